@@ -7,6 +7,7 @@ use crate::codecs::{
 pub struct H264Depacketizer<'a> {
     buf_mut: UnsafeBufMut<'a>,
     is_aggregating: bool,
+    nalu_offsets: Option<&'a mut Vec<usize>>,
 }
 
 impl<'a> Depacketizer for H264Depacketizer<'a> {
@@ -17,6 +18,7 @@ impl<'a> Depacketizer for H264Depacketizer<'a> {
         H264Depacketizer {
             buf_mut: UnsafeBufMut::new(output),
             is_aggregating: false,
+            nalu_offsets: None,
         }
     }
 
@@ -58,6 +60,7 @@ impl<'a> Depacketizer for H264Depacketizer<'a> {
                         let fragmented_nalu_type = b1 & NALU_TYPE_BITMASK;
 
                         if self.buf_mut.remaining_mut() >= ANNEXB_NALUSTART_CODE.len() + 1 {
+                            self.record_nalu_offset();
                             // SAFETY: Checked that the buffer has enough space
                             unsafe {
                                 self.buf_mut.put_slice(ANNEXB_NALUSTART_CODE);
@@ -96,15 +99,52 @@ impl<'a> Depacketizer for H264Depacketizer<'a> {
     fn finish(self) -> usize {
         self.buf_mut.num_bytes_written()
     }
+
+    /// An IDR or SPS/PPS, either as a single NALU, the first NALU of a STAP-A, or the start of
+    /// a FU-A.
+    fn is_keyframe_start(payload: &[u8]) -> bool {
+        let is_keyframe_nalu_type = |nalu_type: u8| {
+            nalu_type == IDR_NALU_TYPE || nalu_type == SPS_NALU_TYPE || nalu_type == PPS_NALU_TYPE
+        };
+
+        match payload {
+            [b0, rest @ ..] => match b0 & NALU_TYPE_BITMASK {
+                STAPA_NALU_TYPE => rest
+                    .get(STAPA_NALU_LENGTH_SIZE)
+                    .is_some_and(|b| is_keyframe_nalu_type(b & NALU_TYPE_BITMASK)),
+                FUA_NALU_TYPE => rest.first().is_some_and(|b1| {
+                    b1 & FU_START_BITMASK != 0 && is_keyframe_nalu_type(b1 & NALU_TYPE_BITMASK)
+                }),
+                nalu_type => is_keyframe_nalu_type(nalu_type),
+            },
+            [] => false,
+        }
+    }
 }
 
 impl<'a> H264Depacketizer<'a> {
+    /// Record the offset in the output buffer of the start code of each NALU written, e.g., to
+    /// iterate over the NALUs aggregated in a STAP-A. The offsets are appended to `nalu_offsets`
+    /// so a NALU spans from its offset up to the next one or the end of the output.
+    pub fn with_nalu_offsets(mut self, nalu_offsets: &'a mut Vec<usize>) -> Self {
+        self.nalu_offsets = Some(nalu_offsets);
+        self
+    }
+
+    #[inline]
+    fn record_nalu_offset(&mut self) {
+        if let Some(nalu_offsets) = &mut self.nalu_offsets {
+            nalu_offsets.push(self.buf_mut.num_bytes_written());
+        }
+    }
+
     #[cold]
     fn single_nalu(&mut self, payload: &[u8]) -> Result<(), DepacketizerError> {
         if self.is_aggregating {
             return Err(DepacketizerError::AggregationInterrupted);
         }
         if self.buf_mut.remaining_mut() >= ANNEXB_NALUSTART_CODE.len() + payload.len() {
+            self.record_nalu_offset();
             // SAFETY: Checked that the buffer has enough space
             unsafe {
                 self.buf_mut.put_slice(ANNEXB_NALUSTART_CODE);
@@ -140,6 +180,7 @@ impl<'a> H264Depacketizer<'a> {
                 .ok_or(DepacketizerError::PayloadTooShort)?;
 
             if self.buf_mut.remaining_mut() >= ANNEXB_NALUSTART_CODE.len() + nalu.len() {
+                self.record_nalu_offset();
                 // SAFETY: Checked that the buffer has enough space
                 unsafe {
                     self.buf_mut.put_slice(ANNEXB_NALUSTART_CODE);
@@ -164,10 +205,13 @@ impl<'a> H264Depacketizer<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::codecs::util::KeyframeWaiter;
     use bytes::Bytes;
     use webrtc::rtp::{codecs::h264::H264Payloader, packetizer::Payloader};
 
     const TEST_NALU: &[u8] = include_bytes!("nalus/1.h264");
+    /// SPS, PPS, then IDR
+    const TEST_KEYFRAME: &[u8] = include_bytes!("nalus/0.h264");
 
     #[test]
     fn fragment_then_unfragment() {
@@ -193,4 +237,71 @@ mod tests {
         let n = bytes_written.unwrap();
         assert_eq!(&output[..n], TEST_NALU);
     }
+
+    #[test]
+    fn wait_for_keyframe() {
+        let mut payloader = H264Payloader::default();
+        let mut payloads = Vec::new();
+        for nalus in [TEST_NALU, TEST_NALU, TEST_KEYFRAME, TEST_NALU] {
+            payloads.extend(
+                payloader
+                    .payload(1188, &Bytes::copy_from_slice(nalus))
+                    .unwrap(),
+            );
+        }
+
+        let mut waiter = KeyframeWaiter::new(true);
+        let mut output = vec![0u8; 3 * TEST_NALU.len() + TEST_KEYFRAME.len()];
+        let mut total_written = 0;
+        let mut reader = H264Depacketizer::wrap_buffer(&mut output[total_written..]);
+        for payload in payloads {
+            if !waiter.accept::<H264Depacketizer>(&payload) {
+                continue;
+            }
+            match reader.push(&payload) {
+                Ok(()) => {
+                    total_written += reader.finish();
+                    reader = H264Depacketizer::wrap_buffer(&mut output[total_written..]);
+                }
+                Err(DepacketizerError::NeedMoreInput) => continue,
+                Err(e) => panic!("Error processing payloads: {e:?}"),
+            }
+        }
+
+        let expected = [TEST_KEYFRAME, TEST_NALU].concat();
+        assert_eq!(total_written, expected.len());
+        assert_eq!(&output[..total_written], &expected[..]);
+    }
+
+    #[test]
+    fn stapa_nalu_offsets() {
+        const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1f, 0xda];
+        const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+        const IDR: &[u8] = &[0x65, 0x88, 0x84, 0x00, 0x33, 0xff];
+
+        let mut stapa = vec![0x78];
+        for nalu in [SPS, PPS, IDR] {
+            stapa.extend_from_slice(&(nalu.len() as u16).to_be_bytes());
+            stapa.extend_from_slice(nalu);
+        }
+
+        let mut output = vec![0u8; 64];
+        let mut nalu_offsets = Vec::new();
+        let mut reader =
+            H264Depacketizer::wrap_buffer(&mut output).with_nalu_offsets(&mut nalu_offsets);
+        reader.push(&stapa).unwrap();
+        let n = reader.finish();
+
+        assert_eq!(nalu_offsets.len(), 3);
+        let ends = nalu_offsets.iter().skip(1).copied().chain([n]);
+        let nalus: Vec<_> = nalu_offsets
+            .iter()
+            .zip(ends)
+            .map(|(&start, end)| &output[start..end])
+            .collect();
+        for (nalu, expected) in nalus.into_iter().zip([SPS, PPS, IDR]) {
+            assert_eq!(&nalu[..ANNEXB_NALUSTART_CODE.len()], ANNEXB_NALUSTART_CODE);
+            assert_eq!(&nalu[ANNEXB_NALUSTART_CODE.len()..], expected);
+        }
+    }
 }
@@ -5,7 +5,10 @@ mod profile;
 mod sample_sender;
 
 pub use self::{
-    depacketizer::H264Depacketizer, profile::H264Profile, sample_sender::H264SampleSender,
+    depacketizer::H264Depacketizer,
+    parameter_set::{ParameterSetChange, ParameterSetMonitor},
+    profile::{check_compatibility, H264Incompatibility, H264Profile},
+    sample_sender::H264SampleSender,
 };
 use super::{supported_video_rtcp_feedbacks, Codec, CodecType, MIME_TYPE_H264};
 use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters};
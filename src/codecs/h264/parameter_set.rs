@@ -1,4 +1,9 @@
-use crate::codecs::h264::constants::SPS_NALU_TYPE;
+#[cfg(test)]
+use crate::codecs::util::bit_string::BitString;
+use crate::codecs::{
+    h264::constants::{PPS_NALU_TYPE, SPS_NALU_TYPE},
+    util::nalu_chunks,
+};
 use exp_golomb::ExpGolombDecoder;
 
 const NALU_TYPE_BITMASK: u8 = 0x1F;
@@ -41,12 +46,18 @@ pub fn parse_parameter_sets_for_resolution(buf: &[u8]) -> Option<(usize, usize)>
     // Skip seq_parameter_set_id
     exp_golomb.skip_next();
 
+    // 4:2:0 unless signaled otherwise
+    let mut chroma_array_type = 1;
     if let 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 13 = profile_idc {
         let chroma_format_idc = exp_golomb.next_unsigned()?;
+        chroma_array_type = chroma_format_idc;
 
         if chroma_format_idc == 3 {
-            // Skip separate_colour_plane_flag
-            exp_golomb.next_bit()?;
+            let separate_colour_plane_flag = exp_golomb.next_bit()?;
+            // Each color plane is coded as monochrome
+            if separate_colour_plane_flag == 1 {
+                chroma_array_type = 0;
+            }
         }
 
         // Skip bit_depth_luma_minus8
@@ -117,17 +128,94 @@ pub fn parse_parameter_sets_for_resolution(buf: &[u8]) -> Option<(usize, usize)>
         frame_crop_bottom_offset = exp_golomb.next_unsigned()?;
     }
 
-    let width = 16 * (pic_width_in_mbs_minus1 + 1)
-        - frame_crop_right_offset * 2
-        - frame_crop_left_offset * 2;
+    // The crop offsets are in units of chroma samples, and of field lines if not progressive
+    let (sub_width_c, sub_height_c) = match chroma_array_type {
+        0 | 3 => (1, 1),
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => return None,
+    };
+    let crop_unit_x = sub_width_c;
+    let crop_unit_y = sub_height_c * (2 - frame_mbs_only_flag as u64);
+
+    let width = (16 * (pic_width_in_mbs_minus1 + 1))
+        .checked_sub(crop_unit_x * (frame_crop_left_offset + frame_crop_right_offset))?;
 
-    let height = 16 * (2 - frame_mbs_only_flag as u64) * (pic_height_in_map_units_minus1 + 1)
-        - frame_crop_top_offset * 2
-        - frame_crop_bottom_offset * 2;
+    let height = (16 * (2 - frame_mbs_only_flag as u64) * (pic_height_in_map_units_minus1 + 1))
+        .checked_sub(crop_unit_y * (frame_crop_top_offset + frame_crop_bottom_offset))?;
 
     return Some((width as usize, height as usize));
 }
 
+/// Change in the parameter sets of a H.264 stream that requires re-initializing the decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterSetChange {
+    /// The SPS changed the resolution of the stream to (width, height).
+    Resolution(usize, usize),
+    /// The SPS/PPS changed but the resolution either stayed the same or could not be parsed.
+    ParameterSets,
+}
+
+/// Watches the depacketized NALUs for new SPS/PPS, e.g., when the sender changes the resolution
+/// mid-stream.
+#[derive(Debug, Clone, Default)]
+pub struct ParameterSetMonitor {
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+    resolution: Option<(usize, usize)>,
+}
+
+impl ParameterSetMonitor {
+    /// Create a new `ParameterSetMonitor` that has not seen any parameter sets.
+    pub fn new() -> ParameterSetMonitor {
+        ParameterSetMonitor::default()
+    }
+
+    /// Check the NALUs in `buf`, i.e., the output of `H264Depacketizer`. Returns a change if the
+    /// SPS or PPS differs from the previous one, which includes the first ones seen.
+    pub fn update(&mut self, buf: &[u8]) -> Option<ParameterSetChange> {
+        let mut sps_changed = false;
+        let mut pps_changed = false;
+
+        for nalu in nalu_chunks(buf) {
+            match nalu.first().map(|b| b & NALU_TYPE_BITMASK) {
+                Some(SPS_NALU_TYPE) if nalu != self.sps => {
+                    self.sps.clear();
+                    self.sps.extend_from_slice(nalu);
+                    sps_changed = true;
+                }
+                Some(PPS_NALU_TYPE) if nalu != self.pps => {
+                    self.pps.clear();
+                    self.pps.extend_from_slice(nalu);
+                    pps_changed = true;
+                }
+                _ => (),
+            }
+        }
+
+        if sps_changed {
+            // The SPS without a NAL delimiter is parsed starting from the first byte
+            if let Some(resolution) = parse_parameter_sets_for_resolution(&self.sps) {
+                if self.resolution != Some(resolution) {
+                    self.resolution = Some(resolution);
+                    return Some(ParameterSetChange::Resolution(resolution.0, resolution.1));
+                }
+            }
+        }
+
+        if sps_changed || pps_changed {
+            Some(ParameterSetChange::ParameterSets)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the (width, height) from the latest SPS.
+    pub fn resolution(&self) -> Option<(usize, usize)> {
+        self.resolution
+    }
+}
+
 #[test]
 fn sps_parse() {
     const NALU: &'static [u8] = include_bytes!("nalus/csd.bin");
@@ -136,3 +224,120 @@ fn sps_parse() {
         Some((1920, 1080))
     );
 }
+
+/// SPS with the given size in macroblocks and frame cropping offsets, as (left, right, top,
+/// bottom). `chroma_format_idc` is only coded for the High profiles.
+#[cfg(test)]
+fn sps(
+    profile_idc: u8,
+    chroma_format_idc: Option<usize>,
+    size_in_mbs: (usize, usize),
+    crop: [usize; 4],
+) -> Vec<u8> {
+    let mut bits = BitString::default();
+    bits.push_ue(0); // seq_parameter_set_id
+    if let Some(chroma_format_idc) = chroma_format_idc {
+        bits.push_ue(chroma_format_idc as u64);
+        if chroma_format_idc == 3 {
+            bits.push("0"); // separate_colour_plane_flag
+        }
+        bits.push_ue(0); // bit_depth_luma_minus8
+        bits.push_ue(0); // bit_depth_chroma_minus8
+        bits.push("0"); // qpprime_y_zero_transform_bypass_flag
+        bits.push("0"); // seq_scaling_matrix_present_flag
+    }
+    bits.push_ue(0); // log2_max_frame_num_minus4
+    bits.push_ue(2); // pic_order_cnt_type
+    bits.push_ue(1); // max_num_ref_frames
+    bits.push("0"); // gaps_in_frame_num_value_allowed_flag
+    bits.push_ue(size_in_mbs.0 as u64 - 1); // pic_width_in_mbs_minus1
+    bits.push_ue(size_in_mbs.1 as u64 - 1); // pic_height_in_map_units_minus1
+    bits.push("1"); // frame_mbs_only_flag
+    bits.push("1"); // direct_8x8_inference_flag
+    if crop == [0; 4] {
+        bits.push("0"); // frame_cropping_flag
+    } else {
+        bits.push("1");
+        for offset in crop {
+            bits.push_ue(offset as u64);
+        }
+    }
+    bits.push("0"); // vui_parameters_present_flag
+    bits.push("1"); // RBSP stop bit
+
+    // NALU header, profile_idc, constraint_set0_flag and constraint_set1_flag, level_idc=31
+    let mut sps = vec![0x67, profile_idc, 0xc0, 0x1f];
+    sps.extend(bits.to_bytes());
+    sps
+}
+
+/// Baseline profile SPS with the given resolution in macroblock multiples.
+#[cfg(test)]
+fn baseline_sps(width: usize, height: usize) -> Vec<u8> {
+    sps(66, None, (width / 16, height / 16), [0; 4])
+}
+
+#[test]
+fn crop_units_by_chroma_format() {
+    // 1080p is coded as 1088 lines, cropping 8 lines at the bottom
+    let sps_420 = sps(100, Some(1), (120, 68), [0, 0, 0, 4]);
+    assert_eq!(
+        parse_parameter_sets_for_resolution(&sps_420),
+        Some((1920, 1080))
+    );
+
+    // Vertical chroma resolution is the same as luma for 4:2:2
+    let sps_422 = sps(122, Some(2), (120, 68), [0, 1, 0, 8]);
+    assert_eq!(
+        parse_parameter_sets_for_resolution(&sps_422),
+        Some((1918, 1080))
+    );
+
+    // Crop offsets are in luma samples for 4:4:4, allowing odd dimensions
+    let sps_444 = sps(244, Some(3), (80, 45), [0, 1, 0, 1]);
+    assert_eq!(
+        parse_parameter_sets_for_resolution(&sps_444),
+        Some((1279, 719))
+    );
+}
+
+#[test]
+fn resolution_change() {
+    const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84, 0x00];
+    const NON_IDR: &[u8] = &[0x41, 0x9a, 0x02, 0x10];
+
+    let annexb = |nalus: &[&[u8]]| -> Vec<u8> {
+        nalus
+            .iter()
+            .flat_map(|nalu| [&[0, 0, 0, 1], *nalu].concat())
+            .collect()
+    };
+
+    let sps_720p = baseline_sps(1280, 720);
+    let sps_480p = baseline_sps(640, 480);
+    let stream = [
+        annexb(&[&sps_720p, PPS, IDR]),
+        annexb(&[NON_IDR]),
+        // Same parameter sets repeated with the next keyframe
+        annexb(&[&sps_720p, PPS, IDR]),
+        annexb(&[NON_IDR]),
+        annexb(&[&sps_480p, PPS, IDR]),
+        annexb(&[NON_IDR]),
+    ];
+
+    let mut monitor = ParameterSetMonitor::new();
+    let changes: Vec<_> = stream.iter().map(|buf| monitor.update(buf)).collect();
+    assert_eq!(
+        changes,
+        [
+            Some(ParameterSetChange::Resolution(1280, 720)),
+            None,
+            None,
+            None,
+            Some(ParameterSetChange::Resolution(640, 480)),
+            None,
+        ]
+    );
+    assert_eq!(monitor.resolution(), Some((640, 480)));
+}
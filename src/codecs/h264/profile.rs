@@ -1,4 +1,7 @@
+use crate::codecs::{fmtp_params, Codec};
+
 /// H.264 codec profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum H264Profile {
     ConstrainedBaseline,
@@ -169,3 +172,149 @@ impl H264Profile {
         Err(())
     }
 }
+
+/// Reasons why a remote H.264 codec is not compatible with ours, from [check_compatibility].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum H264Incompatibility {
+    /// The profile-level-id of either codec is not a valid one. Holds the invalid value.
+    InvalidProfileLevelId(String),
+    /// The codecs have different profiles.
+    ProfileMismatch {
+        local: H264Profile,
+        remote: H264Profile,
+    },
+    /// The remote codec has a higher level than ours. The levels are `level_idc`s, with level 1b
+    /// as 9.
+    LevelTooHigh { local: u8, remote: u8 },
+}
+
+/// Checks if the H.264 codec with the `a=fmtp` line `remote_sdp_fmtp_line` is compatible with
+/// `local`, i.e., they have the same profile and the level of the remote codec is at most ours.
+/// A missing profile-level-id is Baseline profile at level 1, as in [RFC6184][RFC6184].
+///
+/// [RFC6184]: https://www.rfc-editor.org/rfc/rfc6184#section-8.1
+pub fn check_compatibility(
+    local: &Codec,
+    remote_sdp_fmtp_line: &str,
+) -> Result<(), H264Incompatibility> {
+    let remote_profile_level_id = fmtp_params(remote_sdp_fmtp_line)
+        .find(|(k, _)| *k == "profile-level-id")
+        .map(|(_, v)| v);
+    let (local_profile, local_level) =
+        parse_profile_level_id(local.fmtp_param("profile-level-id"))?;
+    let (remote_profile, remote_level) = parse_profile_level_id(remote_profile_level_id)?;
+
+    if local_profile != remote_profile {
+        return Err(H264Incompatibility::ProfileMismatch {
+            local: local_profile,
+            remote: remote_profile,
+        });
+    }
+    if level_order(remote_level) > level_order(local_level) {
+        return Err(H264Incompatibility::LevelTooHigh {
+            local: local_level,
+            remote: remote_level,
+        });
+    }
+    Ok(())
+}
+
+/// `level_idc` of level 1b in the High profiles. The other profiles signal it with level 1.1 and
+/// the constraint_set3_flag.
+const LEVEL_1B: u8 = 9;
+
+fn parse_profile_level_id(
+    profile_level_id: Option<&str>,
+) -> Result<(H264Profile, u8), H264Incompatibility> {
+    let profile_level_id = profile_level_id.unwrap_or("42000a");
+    let invalid = || H264Incompatibility::InvalidProfileLevelId(profile_level_id.to_owned());
+    if profile_level_id.len() != 6 || !profile_level_id.is_ascii() {
+        return Err(invalid());
+    }
+
+    let profile = H264Profile::from_str(&profile_level_id[..4]).map_err(|_| invalid())?;
+    let iop = u8::from_str_radix(&profile_level_id[2..4], 16).map_err(|_| invalid())?;
+    let level_idc = u8::from_str_radix(&profile_level_id[4..], 16).map_err(|_| invalid())?;
+
+    const CONSTRAINT_SET3_FLAG: u8 = 0b00010000;
+    let is_level_1b = match profile {
+        H264Profile::ConstrainedBaseline
+        | H264Profile::Baseline
+        | H264Profile::Main
+        | H264Profile::Extended => level_idc == 11 && iop & CONSTRAINT_SET3_FLAG != 0,
+        _ => false,
+    };
+    Ok((profile, if is_level_1b { LEVEL_1B } else { level_idc }))
+}
+
+/// Level 1b is between levels 1 and 1.1 even though its `level_idc` is less than both.
+fn level_order(level_idc: u8) -> u16 {
+    match level_idc {
+        LEVEL_1B => 105,
+        _ => level_idc as u16 * 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::h264::H264Codec;
+
+    #[test]
+    fn compatible_profile_levels() {
+        // Constrained Baseline at level 3.1
+        let local: Codec = H264Codec::constrained_baseline().into();
+
+        let fmtp = "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f";
+        assert_eq!(check_compatibility(&local, fmtp), Ok(()));
+        // Lower level, and the same profile through the profile_idc of Main
+        assert_eq!(
+            check_compatibility(&local, "packetization-mode=1;profile-level-id=4de015"),
+            Ok(())
+        );
+        // Level 1b through the constraint_set3_flag
+        let local: Codec = H264Codec::new(H264Profile::Baseline).with_level(11).into();
+        assert_eq!(
+            check_compatibility(&local, "profile-level-id=42100b"),
+            Ok(())
+        );
+        // Defaults to Baseline at level 1
+        assert_eq!(check_compatibility(&local, "packetization-mode=1"), Ok(()));
+    }
+
+    #[test]
+    fn incompatible_profile_levels() {
+        let local: Codec = H264Codec::constrained_baseline().into();
+
+        assert_eq!(
+            check_compatibility(&local, "profile-level-id=64001f"),
+            Err(H264Incompatibility::ProfileMismatch {
+                local: H264Profile::ConstrainedBaseline,
+                remote: H264Profile::High,
+            })
+        );
+        assert_eq!(
+            check_compatibility(&local, "profile-level-id=42e034"),
+            Err(H264Incompatibility::LevelTooHigh {
+                local: 0x1f,
+                remote: 0x34,
+            })
+        );
+        assert_eq!(
+            check_compatibility(&local, "profile-level-id=42e0"),
+            Err(H264Incompatibility::InvalidProfileLevelId(
+                "42e0".to_owned()
+            ))
+        );
+
+        // Level 1b is higher than level 1
+        let local: Codec = H264Codec::new(H264Profile::High).with_level(10).into();
+        assert_eq!(
+            check_compatibility(&local, "profile-level-id=640009"),
+            Err(H264Incompatibility::LevelTooHigh {
+                local: 10,
+                remote: LEVEL_1B,
+            })
+        );
+    }
+}
@@ -16,9 +16,22 @@ use webrtc::{
 pub struct H264SampleSender {
     sps_nalu: Option<Bytes>,
     pps_nalu: Option<Bytes>,
+    // Last SPS and PPS sent, kept for repeating before IDRs
+    sent_parameter_sets: Option<(Bytes, Bytes)>,
+    repeat_parameter_sets: bool,
+    // Whether the parameter sets were sent as part of the current payload
+    parameter_sets_in_payload: bool,
 }
 
 impl H264SampleSender {
+    /// Send the last SPS and PPS again before every IDR that does not come with its own, so that
+    /// receivers that joined late or lost the original parameter sets can start decoding at the
+    /// next keyframe. Disabled by default.
+    pub fn with_repeated_parameter_sets(mut self, repeat: bool) -> H264SampleSender {
+        self.repeat_parameter_sets = repeat;
+        self
+    }
+
     #[cold]
     async fn emit_single_nalu<T>(
         header: &mut Header,
@@ -208,6 +221,10 @@ impl H264SampleSender {
 
         if self.sps_nalu.is_some() && self.pps_nalu.is_some() {
             if let (Some(sps_nalu), Some(pps_nalu)) = (self.sps_nalu.take(), self.pps_nalu.take()) {
+                if self.repeat_parameter_sets {
+                    self.sent_parameter_sets = Some((sps_nalu.clone(), pps_nalu.clone()));
+                }
+                self.parameter_sets_in_payload = true;
                 Self::emit_parameter_sets(header, sps_nalu, pps_nalu, mtu, writer).await?;
             } else {
                 // `sps_nalu` and `pps_nalu` were already checked using `is_some`
@@ -223,6 +240,24 @@ impl H264SampleSender {
         Ok(())
     }
 
+    #[cold]
+    async fn emit_sent_parameter_sets<T>(
+        &mut self,
+        header: &mut Header,
+        mtu: usize,
+        writer: &T,
+    ) -> Result<(), webrtc::Error>
+    where
+        T: TrackLocalWriter,
+    {
+        // Only once for IDRs of multiple slices
+        self.parameter_sets_in_payload = true;
+        if let Some((sps_nalu, pps_nalu)) = self.sent_parameter_sets.clone() {
+            Self::emit_parameter_sets(header, sps_nalu, pps_nalu, mtu, writer).await?;
+        }
+        Ok(())
+    }
+
     #[inline]
     async fn emit<T>(
         &mut self,
@@ -261,6 +296,12 @@ impl H264SampleSender {
             )
             .await
         } else {
+            if nalu_type == IDR_NALU_TYPE
+                && self.repeat_parameter_sets
+                && !self.parameter_sets_in_payload
+            {
+                self.emit_sent_parameter_sets(header, mtu, writer).await?;
+            }
             if nalu.len() <= mtu {
                 Self::emit_single_nalu(header, nalu, mtu, writer).await
             } else {
@@ -287,6 +328,7 @@ impl H264SampleSender {
         }
 
         header.marker = false;
+        self.parameter_sets_in_payload = false;
 
         for nalu in nalu_chunks(payload) {
             self.emit(header, nalu, mtu, writer).await?;
@@ -389,6 +431,71 @@ mod tests {
         unsafe { std::mem::transmute(FakeTrackLocalContext::new(write_stream, capability)) }
     }
 
+    /// Whether the packet has the start of an IDR NALU.
+    fn starts_idr(packet: &Packet) -> bool {
+        let payload = &packet.payload;
+        match payload[0] & NALU_TYPE_BITMASK {
+            IDR_NALU_TYPE => true,
+            FUA_NALU_TYPE => {
+                payload[1] & FU_START_BITMASK != 0
+                    && payload[1] & NALU_TYPE_BITMASK == IDR_NALU_TYPE
+            }
+            _ => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_parameter_sets() {
+        async fn send(mut sender: H264SampleSender, payloads: &[&[u8]]) -> Vec<Packet> {
+            let write_stream = PacketVec::new();
+            let mut header = Header::default();
+            for payload in payloads {
+                sender
+                    .send_payload(1200 - 12, &mut header, payload, &write_stream)
+                    .await
+                    .unwrap();
+            }
+            write_stream.clone_inner()
+        }
+
+        // Sequence of the parameter set (S) and IDR start (I) packets
+        fn outline(packets: &[Packet]) -> String {
+            packets
+                .iter()
+                .filter_map(|p| match p.payload[0] & NALU_TYPE_BITMASK {
+                    STAPA_NALU_TYPE => Some('S'),
+                    _ if starts_idr(p) => Some('I'),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        // SPS, PPS, then IDR
+        let keyframe = std::fs::read("src/codecs/h264/nalus/0.h264").unwrap();
+        let idr = nalu_chunks(&keyframe).nth(2).unwrap();
+        let idr_only = [ANNEXB_NALUSTART_CODE, idr].concat();
+        let two_slice_idr = [&idr_only[..], &idr_only].concat();
+        let p_frame = std::fs::read("src/codecs/h264/nalus/1.h264").unwrap();
+        let payloads = [&keyframe, &p_frame, &idr_only, &p_frame, &two_slice_idr].map(|p| &p[..]);
+
+        let packets = send(
+            H264SampleSender::default().with_repeated_parameter_sets(true),
+            &payloads,
+        )
+        .await;
+        // Once per IDR even if it has multiple slices
+        assert_eq!(outline(&packets), "SISISII");
+        let stap_a = &packets[0].payload;
+        assert!(packets
+            .iter()
+            .filter(|p| p.payload[0] & NALU_TYPE_BITMASK == STAPA_NALU_TYPE)
+            .all(|p| p.payload == stap_a));
+
+        // Only with the first IDR by default
+        let packets = send(H264SampleSender::default(), &payloads).await;
+        assert_eq!(outline(&packets), "SIIII");
+    }
+
     #[tokio::test]
     async fn h264_sender() {
         let capability = RTCRtpCodecCapability {
@@ -1,5 +1,9 @@
 mod constants;
 mod depacketizer;
+mod parameter_set;
 mod sample_sender;
 
-pub use self::{depacketizer::H265Depacketizer, sample_sender::H265SampleSender};
+pub use self::{
+    depacketizer::H265Depacketizer, parameter_set::parse_sps_for_resolution,
+    sample_sender::H265SampleSender,
+};
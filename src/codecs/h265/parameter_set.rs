@@ -0,0 +1,201 @@
+use crate::codecs::{
+    h265::constants::{SPS_NALU_TYPE, TRUNCATED_NALU_TYPE_MASK},
+    util::nalu_chunks,
+};
+use exp_golomb::ExpGolombDecoder;
+
+// NALU header size of H.265
+const NALU_HEADER_SIZE: usize = 2;
+
+/// Read the (width, height) of the video stream from the SPS, after cropping to the conformance
+/// window. `buf` is either the SPS itself or NALUs with NALU delimiters, one of which is the SPS.
+pub fn parse_sps_for_resolution(buf: &[u8]) -> Option<(usize, usize)> {
+    let sps = nalu_chunks(buf)
+        .find(|nalu| nalu_type(nalu) == Some(SPS_NALU_TYPE))
+        .unwrap_or(buf);
+    if nalu_type(sps) != Some(SPS_NALU_TYPE) {
+        return None;
+    }
+
+    let rbsp = remove_emulation_prevention(sps.get(NALU_HEADER_SIZE..)?);
+    let mut exp_golomb = ExpGolombDecoder::new(&rbsp, 0)?;
+
+    // Skip sps_video_parameter_set_id
+    next_bits(&mut exp_golomb, 4)?;
+    let sps_max_sub_layers_minus1 = next_bits(&mut exp_golomb, 3)? as usize;
+    // Skip sps_temporal_id_nesting_flag
+    exp_golomb.next_bit()?;
+
+    skip_profile_tier_level(&mut exp_golomb, sps_max_sub_layers_minus1)?;
+
+    // Skip sps_seq_parameter_set_id
+    exp_golomb.skip_next();
+
+    let chroma_format_idc = exp_golomb.next_unsigned()?;
+    if chroma_format_idc == 3 {
+        // Skip separate_colour_plane_flag
+        exp_golomb.next_bit()?;
+    }
+
+    let pic_width_in_luma_samples = exp_golomb.next_unsigned()?;
+    let pic_height_in_luma_samples = exp_golomb.next_unsigned()?;
+    let conformance_window_flag = exp_golomb.next_bit()?;
+
+    // These are interpreted as 0 if conformance_window_flag == 0
+    let mut conf_win_left_offset = 0;
+    let mut conf_win_right_offset = 0;
+    let mut conf_win_top_offset = 0;
+    let mut conf_win_bottom_offset = 0;
+    if conformance_window_flag == 1 {
+        conf_win_left_offset = exp_golomb.next_unsigned()?;
+        conf_win_right_offset = exp_golomb.next_unsigned()?;
+        conf_win_top_offset = exp_golomb.next_unsigned()?;
+        conf_win_bottom_offset = exp_golomb.next_unsigned()?;
+    }
+
+    // The offsets are in units of chroma samples, i.e., SubWidthC and SubHeightC
+    let (sub_width_c, sub_height_c) = match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    };
+
+    let width = pic_width_in_luma_samples
+        .checked_sub(sub_width_c * (conf_win_left_offset + conf_win_right_offset))?;
+    let height = pic_height_in_luma_samples
+        .checked_sub(sub_height_c * (conf_win_top_offset + conf_win_bottom_offset))?;
+
+    Some((width as usize, height as usize))
+}
+
+fn nalu_type(nalu: &[u8]) -> Option<u8> {
+    nalu.first().map(|b| (b & TRUNCATED_NALU_TYPE_MASK) >> 1)
+}
+
+/// Remove the 0x03 bytes inserted after every two zero bytes so the payload can't be mistaken
+/// for a NALU delimiter. The all-zero flags of profile_tier_level almost always have them.
+fn remove_emulation_prevention(ebsp: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(ebsp.len());
+    let mut zeroes = 0;
+    for &byte in ebsp {
+        if zeroes >= 2 && byte == 3 {
+            zeroes = 0;
+            continue;
+        }
+        rbsp.push(byte);
+        if byte == 0 {
+            zeroes += 1;
+        } else {
+            zeroes = 0;
+        }
+    }
+    rbsp
+}
+
+/// Read `n` bits as an unsigned integer.
+fn next_bits(exp_golomb: &mut ExpGolombDecoder, n: u32) -> Option<u64> {
+    let mut value = 0;
+    for _ in 0..n {
+        value = (value << 1) | exp_golomb.next_bit()? as u64;
+    }
+    Some(value)
+}
+
+fn skip_profile_tier_level(
+    exp_golomb: &mut ExpGolombDecoder,
+    max_sub_layers_minus1: usize,
+) -> Option<()> {
+    // Skip general_profile_space, general_tier_flag, general_profile_idc,
+    // general_profile_compatibility_flags, the 48 bits of source and constraint flags, and
+    // general_level_idc
+    for _ in 0..3 {
+        next_bits(exp_golomb, 32)?;
+    }
+
+    // sub_layer_profile_present_flag and sub_layer_level_present_flag of each sub-layer
+    let mut sub_layer_present_flags = [(0, 0); 7];
+    let sub_layer_present_flags = &mut sub_layer_present_flags[..max_sub_layers_minus1];
+    for flags in sub_layer_present_flags.iter_mut() {
+        *flags = (exp_golomb.next_bit()?, exp_golomb.next_bit()?);
+    }
+    if max_sub_layers_minus1 > 0 {
+        // Skip reserved_zero_2bits
+        for _ in max_sub_layers_minus1..8 {
+            next_bits(exp_golomb, 2)?;
+        }
+    }
+
+    for &mut (profile_present_flag, level_present_flag) in sub_layer_present_flags {
+        if profile_present_flag == 1 {
+            // Same as the general profile without the level
+            next_bits(exp_golomb, 32)?;
+            next_bits(exp_golomb, 32)?;
+            next_bits(exp_golomb, 24)?;
+        }
+        if level_present_flag == 1 {
+            // Skip sub_layer_level_idc
+            next_bits(exp_golomb, 8)?;
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::util::bit_string::BitString;
+
+    // Main profile SPS from x265 at 1920x1080
+    const SPS_1080P: &[u8] = &[
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x03, 0x00, 0x78, 0xa0, 0x03, 0xc0, 0x80, 0x10, 0xe5, 0x96, 0x56, 0x69, 0x24, 0xca, 0xf0,
+        0x16, 0x9c, 0x20, 0x00, 0x00, 0x03, 0x00, 0x20, 0x00, 0x00, 0x03, 0x03, 0x21,
+    ];
+
+    #[test]
+    fn sps_parse() {
+        assert_eq!(parse_sps_for_resolution(SPS_1080P), Some((1920, 1080)));
+
+        // Also found after the VPS
+        const VPS: &[u8] = &[0x40, 0x01, 0x0c, 0x01, 0xff, 0xff];
+        let annexb = [&[0, 0, 0, 1], VPS, &[0, 0, 0, 1], SPS_1080P].concat();
+        assert_eq!(parse_sps_for_resolution(&annexb), Some((1920, 1080)));
+        assert_eq!(parse_sps_for_resolution(&annexb[..10]), None);
+    }
+
+    /// 4:2:0 SPS of the given coded size with a sub-layer, cropped by the conformance window.
+    fn cropped_sps(width: u64, height: u64, crop_right: u64, crop_bottom: u64) -> Vec<u8> {
+        // sps_video_parameter_set_id, sps_max_sub_layers_minus1=1, sps_temporal_id_nesting_flag
+        let mut bits = BitString::default();
+        bits.push("00000011");
+        // General profile and level
+        bits.push(&"1".repeat(96));
+        // The sub-layer has a level but no profile, then reserved_zero_2bits
+        bits.push("01");
+        bits.push(&"00".repeat(7));
+        bits.push("01011101"); // sub_layer_level_idc
+        bits.push_ue(0); // sps_seq_parameter_set_id
+        bits.push_ue(1); // chroma_format_idc
+        bits.push_ue(width);
+        bits.push_ue(height);
+        bits.push("1"); // conformance_window_flag
+        bits.push_ue(0);
+        bits.push_ue(crop_right / 2);
+        bits.push_ue(0);
+        bits.push_ue(crop_bottom / 2);
+        bits.push("1"); // Stand-in for the rest of the SPS
+
+        let mut sps = vec![0x42, 0x01];
+        sps.extend(bits.to_bytes());
+        sps
+    }
+
+    #[test]
+    fn conformance_window() {
+        let sps = cropped_sps(1280, 736, 0, 16);
+        assert_eq!(parse_sps_for_resolution(&sps), Some((1280, 720)));
+
+        let sps = cropped_sps(648, 480, 8, 0);
+        assert_eq!(parse_sps_for_resolution(&sps), Some((640, 480)));
+    }
+}
@@ -3,6 +3,8 @@ pub mod h264;
 pub mod h265;
 pub mod util;
 
+use crate::network::data_rate::DataRate;
+use std::time::Duration;
 use webrtc::{
     api::media_engine::MediaEngine,
     rtp_transceiver::{
@@ -11,12 +13,24 @@ use webrtc::{
     },
 };
 
-const MIME_TYPE_H264: &str = "video/H264";
+pub(crate) const MIME_TYPE_H264: &str = "video/H264";
 const MIME_TYPE_OPUS: &str = "audio/opus";
+const MIME_TYPE_ULPFEC: &str = "video/ulpfec";
+const MIME_TYPE_PCMU: &str = "audio/PCMU";
+const MIME_TYPE_PCMA: &str = "audio/PCMA";
+const MIME_TYPE_G722: &str = "audio/G722";
 
-// TODO H265:
+/// Format parameters that Chrome reads to bound its encoder, in kbps.
+const FMTP_MAX_BITRATE: &str = "x-google-max-bitrate";
+const FMTP_MIN_BITRATE: &str = "x-google-min-bitrate";
+const FMTP_START_BITRATE: &str = "x-google-start-bitrate";
+
+/// Start of the range of payload types that are assigned dynamically.
+pub(crate) const DYNAMIC_PAYLOAD_TYPE_START: u8 = 96;
+
+// TODO H265: Codec for the SDP parameters
 // See https://www.rfc-editor.org/rfc/rfc7798#section-7.1
-// const MIME_TYPE_H265: &str = "video/H265";
+pub(crate) const MIME_TYPE_H265: &str = "video/H265";
 
 /// The type of a [Codec].
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -34,11 +48,27 @@ impl Into<RTPCodecType> for CodecType {
     }
 }
 
+/// Errors that [Codec::with_clock_rate] can return.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClockRateError {
+    /// The RTP payload format of the codec mandates this clock rate.
+    FixedByStandard(u32),
+    /// The clock rate is outside of what is sensible for the [CodecType].
+    OutOfRange,
+}
+
+/// Error returned when converting `RTCRtpCodecParameters` whose MIME type is neither `audio/*`
+/// nor `video/*` into a [Codec].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownMimeType(pub String);
+
 /// Abstraction of a media codec for registering in a [MediaEngine].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Codec {
     parameters: RTCRtpCodecParameters,
     codec_type: CodecType,
+    // Whether `rtcp_feedback` was set through `with_rtcp_feedback`
+    custom_rtcp_feedback: bool,
 }
 
 impl Codec {
@@ -47,19 +77,193 @@ impl Codec {
         Codec {
             parameters,
             codec_type,
+            custom_rtcp_feedback: false,
         }
     }
 
+    /// Returns the underlying [RTCRtpCodecParameters] of the [Codec].
+    pub fn parameters(&self) -> &RTCRtpCodecParameters {
+        &self.parameters
+    }
+
     /// Returns the type (audio/video) of the [Codec].
     pub fn codec_type(&self) -> CodecType {
         self.codec_type
     }
 
+    /// Returns the MIME type of the [Codec], e.g., `video/H264`.
+    pub fn mime_type(&self) -> &str {
+        &self.parameters.capability.mime_type
+    }
+
+    /// Returns the clock rate of the [Codec] in Hz.
+    pub fn clock_rate(&self) -> u32 {
+        self.parameters.capability.clock_rate
+    }
+
+    /// Change the clock rate of the [Codec]. Fails if the payload format fixes the clock rate
+    /// (e.g., 90 kHz for H.264 and H.265, 48 kHz for Opus, and 8 kHz for G.711) or if the clock
+    /// rate is not within 8 - 192 kHz for audio or at least 1 kHz for video.
+    pub fn with_clock_rate(mut self, clock_rate: u32) -> Result<Codec, ClockRateError> {
+        let fixed_clock_rate = [
+            (MIME_TYPE_H264, 90000),
+            (MIME_TYPE_H265, 90000),
+            (MIME_TYPE_ULPFEC, 90000),
+            (MIME_TYPE_OPUS, 48000),
+            (MIME_TYPE_PCMU, 8000),
+            (MIME_TYPE_PCMA, 8000),
+            // G.722 samples at 16 kHz but keeps the 8 kHz RTP clock of RFC 3551
+            (MIME_TYPE_G722, 8000),
+        ]
+        .into_iter()
+        .find(|(mime_type, _)| self.mime_type().eq_ignore_ascii_case(mime_type))
+        .map(|(_, fixed_clock_rate)| fixed_clock_rate);
+        if let Some(fixed_clock_rate) = fixed_clock_rate {
+            if clock_rate != fixed_clock_rate {
+                return Err(ClockRateError::FixedByStandard(fixed_clock_rate));
+            }
+        }
+
+        let valid = match self.codec_type {
+            CodecType::Audio => (8000..=192000).contains(&clock_rate),
+            CodecType::Video => clock_rate >= 1000,
+        };
+        if !valid {
+            return Err(ClockRateError::OutOfRange);
+        }
+
+        self.parameters.capability.clock_rate = clock_rate;
+        Ok(self)
+    }
+
+    /// Returns the number of audio channels of the [Codec]. This is 0 for video codecs.
+    pub fn channels(&self) -> u16 {
+        self.parameters.capability.channels
+    }
+
+    /// Returns the `a=fmtp` line of the [Codec] without the payload type.
+    pub fn sdp_fmtp_line(&self) -> &str {
+        &self.parameters.capability.sdp_fmtp_line
+    }
+
+    /// Returns the value of the format parameter `key`, e.g., `profile-level-id` for H.264.
+    /// Returns [None] if `key` is not in the `a=fmtp` line or has no value.
+    pub fn fmtp_param(&self, key: &str) -> Option<&str> {
+        fmtp_params(self.sdp_fmtp_line())
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Set the `x-google-max-bitrate` format parameter, which Chrome uses as the upper bound of
+    /// its encoder bitrate. Rounded down to kbps.
+    pub fn with_max_bitrate(self, max_bitrate: DataRate) -> Codec {
+        self.with_bitrate_param(FMTP_MAX_BITRATE, max_bitrate)
+    }
+
+    /// Set the `x-google-min-bitrate` format parameter, which Chrome uses as the lower bound of
+    /// its encoder bitrate. Rounded down to kbps.
+    pub fn with_min_bitrate(self, min_bitrate: DataRate) -> Codec {
+        self.with_bitrate_param(FMTP_MIN_BITRATE, min_bitrate)
+    }
+
+    /// Set the `x-google-start-bitrate` format parameter, which Chrome uses as the initial bitrate
+    /// of its encoder. Rounded down to kbps.
+    pub fn with_start_bitrate(self, start_bitrate: DataRate) -> Codec {
+        self.with_bitrate_param(FMTP_START_BITRATE, start_bitrate)
+    }
+
+    /// Returns the `x-google-max-bitrate` format parameter.
+    pub fn max_bitrate(&self) -> Option<DataRate> {
+        bitrate_param(self.sdp_fmtp_line(), FMTP_MAX_BITRATE)
+    }
+
+    /// Returns the `x-google-min-bitrate` format parameter.
+    pub fn min_bitrate(&self) -> Option<DataRate> {
+        bitrate_param(self.sdp_fmtp_line(), FMTP_MIN_BITRATE)
+    }
+
+    /// Returns the `x-google-start-bitrate` format parameter.
+    pub fn start_bitrate(&self) -> Option<DataRate> {
+        bitrate_param(self.sdp_fmtp_line(), FMTP_START_BITRATE)
+    }
+
+    /// Replaces the format parameter `key` if already present, otherwise appends it.
+    fn with_bitrate_param(mut self, key: &str, bitrate: DataRate) -> Codec {
+        let kbps = bitrate.bits_per_sec() / 1000;
+        let mut params: Vec<String> = fmtp_params(self.sdp_fmtp_line())
+            .filter(|(k, _)| *k != key)
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect();
+        params.push(format!("{key}={kbps}"));
+        self.parameters.capability.sdp_fmtp_line = params.join(";");
+        self
+    }
+
+    /// Negotiate exactly `rtcp_feedback` on the transceiver of every encoder that supports the
+    /// [Codec], instead of the feedback that every codec of its type gets, i.e., NACK, PLI, and
+    /// transport-cc if configured. Useful for tracks that want different feedback than the rest,
+    /// e.g., a track without NACKs next to video that retransmits.
+    ///
+    /// The other codecs of the same encoder then also negotiate only their own `rtcp_feedback`.
+    ///
+    /// Leaving out transport-cc only removes its `a=rtcp-fb` line. The transport-wide-cc header
+    /// extension is negotiated for every media section of the same type, since webrtc-rs does not
+    /// negotiate header extensions per transceiver. The packets of the track then still carry
+    /// transport-wide sequence numbers, and the remote peer may still send TWCC feedback for
+    /// them.
+    pub fn with_rtcp_feedback(mut self, rtcp_feedback: Vec<RTCPFeedback>) -> Codec {
+        self.parameters.capability.rtcp_feedback = rtcp_feedback;
+        self.custom_rtcp_feedback = true;
+        self
+    }
+
+    /// Returns the RTCP feedback of the [Codec] besides the ones added for every codec of its
+    /// type.
+    pub fn rtcp_feedback(&self) -> &[RTCPFeedback] {
+        &self.parameters.capability.rtcp_feedback
+    }
+
+    /// Whether the RTCP feedback was set through [Codec::with_rtcp_feedback].
+    pub fn has_custom_rtcp_feedback(&self) -> bool {
+        self.custom_rtcp_feedback
+    }
+
+    /// Returns the payload type of the [Codec].
+    pub fn payload_type(&self) -> u8 {
+        self.parameters.payload_type
+    }
+
     /// Modifies the payload type of the [Codec].
     pub fn set_payload_type(&mut self, payload_type: u8) {
         self.parameters.payload_type = payload_type;
     }
 
+    /// Returns the payload type that the [Codec] must be registered with instead of a dynamically
+    /// assigned one. This is either the static payload type of [RFC3551][RFC3551] or a payload
+    /// type below the dynamic range set through [Codec::set_payload_type].
+    ///
+    /// [RFC3551]: https://www.rfc-editor.org/rfc/rfc3551#section-6
+    pub fn fixed_payload_type(&self) -> Option<u8> {
+        let capability = &self.parameters.capability;
+        if capability.clock_rate == 8000 && capability.channels <= 1 {
+            for (mime_type, payload_type) in [
+                (MIME_TYPE_PCMU, 0),
+                (MIME_TYPE_PCMA, 8),
+                (MIME_TYPE_G722, 9),
+            ] {
+                if capability.mime_type.eq_ignore_ascii_case(mime_type) {
+                    return Some(payload_type);
+                }
+            }
+        }
+
+        // Zero is the default, i.e., not set
+        match self.parameters.payload_type {
+            payload_type @ 1..=95 => Some(payload_type),
+            _ => None,
+        }
+    }
+
     /// Checks if the [Codec] has the same [RTCRtpCodecCapability] as `codec_capability`.
     pub fn capability_matches(&self, codec_capability: &RTCRtpCodecCapability) -> bool {
         // All parameters except `rtcp_feedback` should match
@@ -73,6 +277,57 @@ impl Codec {
         codec_matches(&self.parameters.capability, codec_capability)
     }
 
+    /// Checks if the [Codec] can handle `codec_capability` even if the `a=fmtp` lines differ, and
+    /// if so, how specific the match is. The more format parameters with the same value, the
+    /// higher the score.
+    ///
+    /// The MIME type, clock rate, and channels must match. For H.264, the packetization-mode and
+    /// the profile part of profile-level-id must also match but the level can differ, as in
+    /// [RFC6184][RFC6184].
+    ///
+    /// [RFC6184]: https://www.rfc-editor.org/rfc/rfc6184#section-8.2.2
+    pub fn capability_match_score(
+        &self,
+        codec_capability: &RTCRtpCodecCapability,
+    ) -> Option<usize> {
+        let capability = &self.parameters.capability;
+        if !capability
+            .mime_type
+            .eq_ignore_ascii_case(&codec_capability.mime_type)
+            || capability.clock_rate != codec_capability.clock_rate
+            || capability.channels != codec_capability.channels
+        {
+            return None;
+        }
+
+        let other_param = |key: &str| {
+            fmtp_params(&codec_capability.sdp_fmtp_line)
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v)
+        };
+
+        if capability.mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+            // Both default to 0 and Baseline profile
+            let packetization_mode = |param: Option<&str>| param.unwrap_or("0").to_owned();
+            let profile = |param: Option<&str>| {
+                let profile_level_id = param.unwrap_or("420010");
+                profile_level_id.get(..4).map(str::to_ascii_lowercase)
+            };
+            if packetization_mode(self.fmtp_param("packetization-mode"))
+                != packetization_mode(other_param("packetization-mode"))
+                || profile(self.fmtp_param("profile-level-id"))
+                    != profile(other_param("profile-level-id"))
+            {
+                return None;
+            }
+        }
+
+        let num_equal = fmtp_params(self.sdp_fmtp_line())
+            .filter(|(k, v)| other_param(k) == Some(v))
+            .count();
+        Some(num_equal)
+    }
+
     /// Create an [RFC4588][RFC4588] retransmission [Codec] from a base video [Codec]. Returns
     /// [None] if `base_codec` is of type [CodecType::Audio].
     ///
@@ -101,7 +356,7 @@ impl Codec {
     pub fn ulpfec() -> Codec {
         let parameters = RTCRtpCodecParameters {
             capability: RTCRtpCodecCapability {
-                mime_type: "video/ulpfec".to_owned(),
+                mime_type: MIME_TYPE_ULPFEC.to_owned(),
                 clock_rate: 90000,
                 channels: 0,
                 sdp_fmtp_line: "".to_owned(),
@@ -127,14 +382,95 @@ impl Codec {
         };
         Codec::new(parameters, CodecType::Audio)
     }
+
+    /// Create a G.711 μ-law [Codec]. It always uses the static payload type 0.
+    pub fn pcmu() -> Codec {
+        Codec::g711(MIME_TYPE_PCMU, 0)
+    }
+
+    /// Create a G.711 A-law [Codec]. It always uses the static payload type 8.
+    pub fn pcma() -> Codec {
+        Codec::g711(MIME_TYPE_PCMA, 8)
+    }
+
+    fn g711(mime_type: &str, payload_type: u8) -> Codec {
+        let parameters = RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: mime_type.to_owned(),
+                clock_rate: 8000,
+                channels: 0,
+                sdp_fmtp_line: "".to_owned(),
+                rtcp_feedback: Vec::new(),
+            },
+            payload_type,
+            ..Default::default()
+        };
+        Codec::new(parameters, CodecType::Audio)
+    }
+}
+
+impl TryFrom<RTCRtpCodecParameters> for Codec {
+    type Error = UnknownMimeType;
+
+    /// Wrap existing codec parameters, e.g., from `TrackRemote::codec`. The [CodecType] is taken
+    /// from the MIME type. Use [Codec::new] if it is already known.
+    fn try_from(parameters: RTCRtpCodecParameters) -> Result<Codec, UnknownMimeType> {
+        let mime_type = parameters.capability.mime_type.to_ascii_lowercase();
+        let codec_type = if mime_type.starts_with("audio/") {
+            CodecType::Audio
+        } else if mime_type.starts_with("video/") {
+            CodecType::Video
+        } else {
+            return Err(UnknownMimeType(parameters.capability.mime_type));
+        };
+        Ok(Codec::new(parameters, codec_type))
+    }
+}
+
+impl From<Codec> for RTCRtpCodecParameters {
+    fn from(codec: Codec) -> RTCRtpCodecParameters {
+        codec.parameters
+    }
+}
+
+/// Splits an `a=fmtp` line into its key-value pairs.
+fn fmtp_params(sdp_fmtp_line: &str) -> impl Iterator<Item = (&str, &str)> {
+    sdp_fmtp_line
+        .split(';')
+        .filter_map(|param| param.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+}
+
+/// Reads a bitrate format parameter in kbps, e.g., `x-google-max-bitrate`.
+fn bitrate_param(sdp_fmtp_line: &str, key: &str) -> Option<DataRate> {
+    let kbps: u64 = fmtp_params(sdp_fmtp_line)
+        .find(|(k, _)| *k == key)?
+        .1
+        .parse()
+        .ok()?;
+    Some(DataRate::from_bits_per_sec(kbps.saturating_mul(1000)))
+}
+
+/// Converts `duration` to RTP timestamp ticks at `clock_rate` Hz. Encoders should use the clock
+/// rate of the negotiated codec rather than assume the usual 90 kHz or 48 kHz. Wraps around like
+/// RTP timestamps.
+pub fn rtp_timestamp_ticks(duration: Duration, clock_rate: u32) -> u32 {
+    // duration [us] * clock_rate [1/s] / 1_000_000 [us/s]
+    (duration.as_micros() * clock_rate as u128 / 1_000_000) as u32
 }
 
 /// RTCP feedbacks that can be handled either by this crate or natively by webrtc-rs.
 pub(crate) fn supported_video_rtcp_feedbacks() -> Vec<RTCPFeedback> {
-    vec![RTCPFeedback {
-        typ: "ccm".to_owned(),
-        parameter: "fir".to_owned(),
-    }]
+    vec![
+        RTCPFeedback {
+            typ: "ccm".to_owned(),
+            parameter: "fir".to_owned(),
+        },
+        RTCPFeedback {
+            typ: "goog-remb".to_owned(),
+            parameter: "".to_owned(),
+        },
+    ]
 }
 
 /// Helper trait for adding methods to [MediaEngine].
@@ -149,3 +485,196 @@ impl MediaEngineExt for MediaEngine {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use h264::{H264Codec, H264Profile};
+
+    #[test]
+    fn h264_fmtp_params() {
+        let mut codec: Codec = H264Codec::constrained_baseline().with_level(0x34).into();
+        codec.set_payload_type(102);
+
+        assert_eq!(codec.mime_type(), MIME_TYPE_H264);
+        assert_eq!(codec.clock_rate(), 90000);
+        assert_eq!(codec.channels(), 0);
+        assert_eq!(codec.payload_type(), 102);
+
+        assert_eq!(codec.fmtp_param("profile-level-id"), Some("42e034"));
+        assert_eq!(codec.fmtp_param("packetization-mode"), Some("1"));
+        assert_eq!(codec.fmtp_param("level-asymmetry-allowed"), Some("1"));
+        assert_eq!(codec.fmtp_param("sprop-parameter-sets"), None);
+    }
+
+    #[test]
+    fn opus_fmtp_params() {
+        let codec = Codec::opus();
+        assert_eq!(codec.channels(), 2);
+        assert_eq!(codec.fmtp_param("useinbandfec"), Some("1"));
+        assert_eq!(codec.fmtp_param("minptime"), Some("10"));
+        assert_eq!(codec.fmtp_param("stereo"), None);
+    }
+
+    #[test]
+    fn bitrate_fmtp_params() {
+        let codec: Codec = H264Codec::constrained_baseline().into();
+        assert!(codec.max_bitrate().is_none());
+
+        let codec = codec
+            .with_max_bitrate(DataRate::from_bits_per_sec(2_500_000))
+            .with_min_bitrate(DataRate::from_bits_per_sec(300_000))
+            .with_start_bitrate(DataRate::from_bits_per_sec(1_000_000))
+            // Replaces the previous value
+            .with_max_bitrate(DataRate::from_bits_per_sec(4_000_500));
+        assert_eq!(
+            codec.sdp_fmtp_line(),
+            "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f;\
+            x-google-min-bitrate=300;x-google-start-bitrate=1000;x-google-max-bitrate=4000"
+        );
+        assert_eq!(codec.fmtp_param("profile-level-id"), Some("42e01f"));
+
+        let bits_per_sec = |bitrate: Option<DataRate>| bitrate.map(|b| b.bits_per_sec());
+        assert_eq!(bits_per_sec(codec.max_bitrate()), Some(4_000_000));
+        assert_eq!(bits_per_sec(codec.min_bitrate()), Some(300_000));
+        assert_eq!(bits_per_sec(codec.start_bitrate()), Some(1_000_000));
+    }
+
+    #[test]
+    fn h264_capability_match_score() {
+        let capability = |codec: H264Codec| {
+            let codec: Codec = codec.into();
+            codec.parameters.capability
+        };
+        let baseline: Codec = H264Codec::new(H264Profile::Baseline).into();
+        let high: Codec = H264Codec::new(H264Profile::High).into();
+
+        // Only the level differs
+        let remote = capability(H264Codec::new(H264Profile::Baseline).with_level(0x34));
+        assert!(!baseline.capability_matches(&remote));
+        assert_eq!(baseline.capability_match_score(&remote), Some(2));
+        assert_eq!(high.capability_match_score(&remote), None);
+
+        let remote = capability(H264Codec::new(H264Profile::High));
+        assert_eq!(baseline.capability_match_score(&remote), None);
+        assert_eq!(high.capability_match_score(&remote), Some(3));
+
+        // Constrained Baseline is a different profile-level-id
+        let remote = capability(H264Codec::constrained_baseline());
+        assert_eq!(baseline.capability_match_score(&remote), None);
+
+        let mut remote = capability(H264Codec::new(H264Profile::Baseline));
+        remote.sdp_fmtp_line = remote.sdp_fmtp_line.replace("packetization-mode=1", "");
+        assert_eq!(baseline.capability_match_score(&remote), None);
+
+        assert_eq!(
+            Codec::opus().capability_match_score(&baseline.parameters.capability),
+            None
+        );
+    }
+
+    #[test]
+    fn fixed_clock_rates() {
+        let h264: Codec = H264Codec::constrained_baseline().into();
+        assert_eq!(
+            h264.clone().with_clock_rate(48000),
+            Err(ClockRateError::FixedByStandard(90000))
+        );
+        assert!(h264.with_clock_rate(90000).is_ok());
+        // MIME types are case-insensitive
+        for mime_type in ["VIDEO/H264", "video/h265"] {
+            let mut codec: Codec = H264Codec::constrained_baseline().into();
+            codec.parameters.capability.mime_type = mime_type.to_owned();
+            assert_eq!(
+                codec.with_clock_rate(1000),
+                Err(ClockRateError::FixedByStandard(90000))
+            );
+        }
+        assert_eq!(
+            Codec::opus().with_clock_rate(16000),
+            Err(ClockRateError::FixedByStandard(48000))
+        );
+        for codec in [Codec::pcmu(), Codec::pcma()] {
+            assert_eq!(
+                codec.clone().with_clock_rate(16000),
+                Err(ClockRateError::FixedByStandard(8000))
+            );
+            // Still uses its static payload type
+            assert!(codec
+                .with_clock_rate(8000)
+                .unwrap()
+                .fixed_payload_type()
+                .is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_clock_rate() {
+        let parameters = RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: "audio/L16".to_owned(),
+                clock_rate: 44100,
+                channels: 1,
+                sdp_fmtp_line: "".to_owned(),
+                rtcp_feedback: Vec::new(),
+            },
+            payload_type: 96,
+            ..Default::default()
+        };
+        let codec = Codec::new(parameters, CodecType::Audio);
+        assert_eq!(
+            codec.clone().with_clock_rate(1000),
+            Err(ClockRateError::OutOfRange)
+        );
+        let codec = codec.with_clock_rate(16000).unwrap();
+
+        let mut media_engine = MediaEngine::default();
+        media_engine.register_custom_codec(codec.clone()).unwrap();
+        let api = webrtc::api::APIBuilder::new()
+            .with_media_engine(media_engine)
+            .build();
+        let pc = api.new_peer_connection(Default::default()).await.unwrap();
+        pc.add_transceiver_from_kind(RTPCodecType::Audio, &[])
+            .await
+            .unwrap();
+        let offer = pc.create_offer(None).await.unwrap();
+        assert!(offer.sdp.contains("a=rtpmap:96 L16/16000/1\r\n"));
+
+        // 20 ms of audio
+        let ticks = rtp_timestamp_ticks(Duration::from_millis(20), codec.clock_rate());
+        assert_eq!(ticks, 320);
+
+        pc.close().await.unwrap();
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let mut codec: Codec = H264Codec::constrained_baseline().into();
+        codec.set_payload_type(102);
+
+        // What `TrackRemote::codec` returns for the negotiated codec
+        let parameters: RTCRtpCodecParameters = codec.clone().into();
+        let negotiated = Codec::try_from(parameters.clone()).unwrap();
+        assert_eq!(negotiated, codec);
+        assert_eq!(negotiated.codec_type(), CodecType::Video);
+        assert!(codec.capability_matches(&negotiated.parameters().capability));
+        assert_eq!(
+            Codec::retransmission(&negotiated).unwrap().sdp_fmtp_line(),
+            "apt=102"
+        );
+        assert_eq!(RTCRtpCodecParameters::from(negotiated), parameters);
+
+        let opus: RTCRtpCodecParameters = Codec::opus().into();
+        assert_eq!(
+            Codec::try_from(opus).unwrap().codec_type(),
+            CodecType::Audio
+        );
+
+        let mut unknown = parameters;
+        unknown.capability.mime_type = "application/x-foo".to_owned();
+        assert_eq!(
+            Codec::try_from(unknown),
+            Err(UnknownMimeType("application/x-foo".to_owned()))
+        );
+    }
+}
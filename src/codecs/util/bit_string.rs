@@ -0,0 +1,38 @@
+//! Bitstreams written out as strings of '0's and '1's, for building test inputs such as parameter
+//! sets.
+
+/// Bits appended one field at a time, then packed into bytes.
+#[derive(Debug, Default)]
+pub(crate) struct BitString(String);
+
+impl BitString {
+    /// Append `bits`, a string of '0's and '1's.
+    pub(crate) fn push(&mut self, bits: &str) {
+        self.0.push_str(bits);
+    }
+
+    /// Append `value` as an unsigned Exp-Golomb code, i.e., `ue(v)`.
+    pub(crate) fn push_ue(&mut self, value: u64) {
+        let coded = format!("{:b}", value + 1);
+        self.0.push_str(&"0".repeat(coded.len() - 1));
+        self.0.push_str(&coded);
+    }
+
+    /// Pack the bits into bytes, padding the end with zeroes.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bits = self.0.clone();
+        bits.push_str(&"0".repeat((8 - bits.len() % 8) % 8));
+        (0..bits.len())
+            .step_by(8)
+            .map(|i| u8::from_str_radix(&bits[i..i + 8], 2).unwrap())
+            .collect()
+    }
+}
+
+/// Packs a string of '0's and '1's into bytes, padding the end with zeroes. Spaces are ignored so
+/// that they can separate the fields.
+pub(crate) fn pack(bits: &str) -> Vec<u8> {
+    let mut bit_string = BitString::default();
+    bit_string.push(&bits.replace(' ', ""));
+    bit_string.to_bytes()
+}
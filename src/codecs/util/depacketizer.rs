@@ -1,3 +1,5 @@
+use webrtc::rtp;
+
 /// Errors that `Depacketizer` can return.
 ///
 /// `NeedMoreInput` is non-fatal and must be treated as a request for more payload.
@@ -22,6 +24,11 @@ pub trait Depacketizer {
     /// Create a new `Depacketizer` by wrapping an existing buffer.
     fn wrap_buffer<'a>(output: &'a mut [u8]) -> Self::WrapOutput<'a>;
 
+    /// Called with the header extensions of each packet right before its payload is pushed, for
+    /// codecs whose depacketization depends on them, e.g., AV1 with the dependency descriptor.
+    /// Ignored by default.
+    fn push_extensions(&mut self, _extensions: &[rtp::header::Extension]) {}
+
     /// Add a payload to be depacketized. This method can return `DepacketizerError::NeedMoreInput`
     /// signaling that the depacketizer needs more packets to complete the data.
     ///
@@ -30,4 +37,43 @@ pub trait Depacketizer {
 
     /// Consume the `Depacketizer` and return the number of bytes written.
     fn finish(self) -> usize;
+
+    /// Checks if the payload is the start of a keyframe, i.e., something a decoder can start
+    /// decoding from. Defaults to `true` for codecs that cannot detect keyframes.
+    fn is_keyframe_start(_payload: &[u8]) -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
+}
+
+/// Drops payloads until the start of a keyframe so that decoding starts cleanly, e.g., when
+/// joining an existing stream.
+#[derive(Debug, Clone)]
+pub struct KeyframeWaiter {
+    synced: bool,
+}
+
+impl KeyframeWaiter {
+    /// Create a new `KeyframeWaiter`. If `wait_for_keyframe` is false, every payload is accepted.
+    pub fn new(wait_for_keyframe: bool) -> KeyframeWaiter {
+        KeyframeWaiter {
+            synced: !wait_for_keyframe,
+        }
+    }
+
+    /// Returns `true` if the payload should be passed to the depacketizer `D`.
+    pub fn accept<D: Depacketizer>(&mut self, payload: &[u8]) -> bool {
+        if !self.synced && D::is_keyframe_start(payload) {
+            self.synced = true;
+        }
+        self.synced
+    }
+
+    /// Start dropping payloads again until the next keyframe, e.g., after unrecoverable packet
+    /// loss.
+    pub fn reset(&mut self) {
+        self.synced = false;
+    }
 }
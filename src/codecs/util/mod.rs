@@ -1,10 +1,12 @@
+#[cfg(test)]
+pub(crate) mod bit_string;
 mod depacketizer;
 mod ext_traits;
 mod nalu_chunks;
 mod unsafe_buf;
 
 pub use self::{
-    depacketizer::{Depacketizer, DepacketizerError},
+    depacketizer::{Depacketizer, DepacketizerError, KeyframeWaiter},
     ext_traits::*,
     nalu_chunks::nalu_chunks,
     unsafe_buf::UnsafeBufMut,
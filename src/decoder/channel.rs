@@ -0,0 +1,317 @@
+//! Decoder that hands the received frames to the application through a channel instead of
+//! decoding them, for applications without a decoder of their own to plug in.
+
+use super::DecoderBuilder;
+use crate::{
+    codecs::{
+        h264::H264Depacketizer,
+        h265::H265Depacketizer,
+        util::{Depacketizer, DepacketizerError},
+        Codec, CodecType, MIME_TYPE_H264, MIME_TYPE_H265,
+    },
+    network::reorder_buffer::{BufferedTrackRemote, ReorderBufferError},
+    WebRtcPeer,
+};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use webrtc::{rtp_transceiver::rtp_receiver::RTCRtpReceiver, track::track_remote::TrackRemote};
+
+/// Number of packets the reorder buffer holds while waiting for a missing one.
+const REORDER_BUFFER_SIZE: usize = 128;
+
+/// Largest NALU that can be reassembled.
+const MAX_NALU_SIZE: usize = 1 << 20;
+
+/// A complete frame of the received track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedFrame {
+    /// NALUs of the frame in Annex-B format, i.e., each preceded by a start code.
+    pub data: Vec<u8>,
+    /// RTP timestamp of the frame.
+    pub timestamp: u32,
+}
+
+/// Builds a decoder that reassembles the frames of a H.264 or H.265 track and sends them through
+/// a channel, leaving the actual decoding to the application.
+///
+/// Frames end at a packet with the marker bit set or, for senders that do not set it, when the RTP
+/// timestamp changes. Partial frames from packet loss are dropped, including a frame whose packet
+/// with the marker bit is lost.
+pub struct ChannelDecoderBuilder {
+    codecs: Vec<Codec>,
+    frames: mpsc::Sender<EncodedFrame>,
+}
+
+impl ChannelDecoderBuilder {
+    /// Create a builder for a track of `codec`. Returns the receiving end of the channel for the
+    /// frames, which buffers up to `capacity` frames before the track stops being read.
+    pub fn new(
+        codec: Codec,
+        capacity: usize,
+    ) -> (ChannelDecoderBuilder, mpsc::Receiver<EncodedFrame>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let builder = ChannelDecoderBuilder {
+            codecs: vec![codec],
+            frames: tx,
+        };
+        (builder, rx)
+    }
+}
+
+impl DecoderBuilder for ChannelDecoderBuilder {
+    fn supported_codecs(&self) -> &[Codec] {
+        &self.codecs
+    }
+
+    fn codec_type(&self) -> CodecType {
+        self.codecs[0].codec_type()
+    }
+
+    fn build(
+        self: Box<Self>,
+        track: Arc<TrackRemote>,
+        rtp_receiver: Arc<RTCRtpReceiver>,
+        _peer: Arc<WebRtcPeer>,
+    ) {
+        // The RTCP needs to be read for the interceptors to process it
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 1500];
+            while rtp_receiver.read(&mut buf).await.is_ok() {}
+        });
+
+        tokio::spawn(async move {
+            let mime_type = track.codec().await.capability.mime_type;
+            let mut buffered_track = BufferedTrackRemote::new(track, REORDER_BUFFER_SIZE);
+            let result = if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+                forward_frames::<H264Depacketizer>(&mut buffered_track, &self.frames).await
+            } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_H265) {
+                forward_frames::<H265Depacketizer>(&mut buffered_track, &self.frames).await
+            } else {
+                log::error!("Cannot reassemble the frames of {mime_type}");
+                return;
+            };
+            if let Err(e) = result {
+                log::debug!("Stopped reading the track: {e:?}");
+            }
+        });
+    }
+}
+
+/// Send the frames read from `track` through `frames` until either is closed.
+async fn forward_frames<D>(
+    track: &mut BufferedTrackRemote,
+    frames: &mpsc::Sender<EncodedFrame>,
+) -> Result<(), ReorderBufferError>
+where
+    D: Depacketizer,
+{
+    let mut buf = vec![0u8; MAX_NALU_SIZE];
+    let mut frame: Option<EncodedFrame> = None;
+    // Timestamp of the frame that lost packets, whose remaining NALUs are dropped
+    let mut damaged: Option<u32> = None;
+
+    loop {
+        let mut depacketizer = D::wrap_buffer(&mut buf);
+        let nalu = loop {
+            let (payload, timestamp) = match track.recv().await {
+                Ok(received) => received,
+                // A late duplicate, or nothing received for a while
+                Err(
+                    ReorderBufferError::UnorderablePacketReceived
+                    | ReorderBufferError::TrackRemoteReadTimeout,
+                ) => continue,
+                Err(
+                    ReorderBufferError::BufferFull | ReorderBufferError::MaxReorderDelayExceeded,
+                ) => {
+                    let prev_marker = track.marker();
+                    break Err(track.take_packet_loss().and_then(|loss| {
+                        match loss.prev_timestamp {
+                            // Lost the rest of the frame, including its marker bit. The gap is
+                            // assumed to end there unless the packet after it is still part of the
+                            // same frame.
+                            Some(prev_timestamp) if !prev_marker => {
+                                (loss.next_timestamp == prev_timestamp).then_some(prev_timestamp)
+                            }
+                            // Lost the start of the frame after a complete one
+                            _ => Some(loss.next_timestamp),
+                        }
+                    }));
+                }
+                Err(
+                    ReorderBufferError::PacketTooShort | ReorderBufferError::HeaderParsingError,
+                ) => break Err(None),
+                Err(e) => return Err(e),
+            };
+            match depacketizer.push(payload) {
+                Ok(()) => break Ok((timestamp, track.marker())),
+                Err(DepacketizerError::NeedMoreInput) => continue,
+                Err(_) => break Err(Some(timestamp)),
+            }
+        };
+
+        let (timestamp, marker) = match nalu {
+            Ok(nalu) => nalu,
+            Err(damaged_timestamp) => {
+                let in_progress = frame.as_ref().map(|frame| frame.timestamp);
+                damaged = damaged_timestamp.or(in_progress);
+                if in_progress == damaged {
+                    frame = None;
+                }
+                continue;
+            }
+        };
+        let len = depacketizer.finish();
+
+        if damaged == Some(timestamp) {
+            continue;
+        }
+        damaged = None;
+
+        match frame.take() {
+            // The packet with the marker bit of the previous frame was lost
+            Some(previous) if previous.timestamp != timestamp => {
+                if frames.send(previous).await.is_err() {
+                    return Ok(());
+                }
+            }
+            current => frame = current,
+        }
+
+        let current = frame.get_or_insert_with(|| EncodedFrame {
+            data: Vec::new(),
+            timestamp,
+        });
+        current.data.extend_from_slice(&buf[..len]);
+
+        if marker {
+            if let Some(current) = frame.take() {
+                if frames.send(current).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::reorder_buffer::tests::DummyTrackRemote;
+    use bytes::Bytes;
+    use webrtc::{
+        rtp::{codecs::h264::H264Payloader, header::Header, packet::Packet, packetizer::Payloader},
+        util::Marshal,
+    };
+
+    const FRAME_INTERVAL_TICKS: u32 = 3000;
+
+    /// SPS/PPS then three frames of a single slice each.
+    fn frames() -> Vec<Vec<u8>> {
+        const CSD: &[u8] = include_bytes!("../codecs/h264/nalus/csd.bin");
+        const NALUS: [&[u8]; 3] = [
+            include_bytes!("../codecs/h264/nalus/1.h264"),
+            include_bytes!("../codecs/h264/nalus/2.h264"),
+            include_bytes!("../codecs/h264/nalus/3.h264"),
+        ];
+        vec![
+            [CSD, NALUS[0]].concat(),
+            NALUS[1].to_vec(),
+            NALUS[2].to_vec(),
+        ]
+    }
+
+    /// Packetizes the frames, setting the marker bit on the last packet of each except for the
+    /// frame at `no_marker`.
+    fn packetize(frames: &[Vec<u8>], no_marker: usize) -> Vec<Bytes> {
+        let mut payloader = H264Payloader::default();
+        let mut packets = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            let payloads = payloader
+                .payload(1188, &Bytes::copy_from_slice(frame))
+                .unwrap();
+            let num_payloads = payloads.len();
+            for (j, payload) in payloads.into_iter().enumerate() {
+                let packet = Packet {
+                    header: Header {
+                        marker: j + 1 == num_payloads && i != no_marker,
+                        sequence_number: packets.len() as u16,
+                        timestamp: FRAME_INTERVAL_TICKS * i as u32,
+                        ..Default::default()
+                    },
+                    payload,
+                };
+                packets.push(packet.marshal().unwrap());
+            }
+        }
+        packets
+    }
+
+    #[tokio::test]
+    async fn complete_frames_in_order() {
+        let frames = frames();
+        let mut packets = packetize(&frames, 1);
+        // Reordered in transit
+        packets.swap(1, 2);
+
+        let track = DummyTrackRemote::new(packets.into());
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), REORDER_BUFFER_SIZE);
+        let (tx, mut rx) = mpsc::channel(frames.len());
+        // The dummy track errors out after the last packet
+        assert!(matches!(
+            forward_frames::<H264Depacketizer>(&mut buffered_track, &tx).await,
+            Err(ReorderBufferError::TrackRemoteReadError)
+        ));
+        drop(tx);
+
+        // The second frame without the marker bit ends at the start of the third
+        for (i, data) in frames.into_iter().enumerate() {
+            let frame = rx.recv().await.unwrap();
+            assert_eq!(frame.timestamp, FRAME_INTERVAL_TICKS * i as u32);
+            assert!(frame.data == data);
+        }
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn lost_marker() {
+        let mut frames = frames();
+        // Enough packets after the loss for the buffer to give up on it
+        frames[2] = frames[2].repeat(2);
+        let mut packets = packetize(&frames, usize::MAX);
+        // The last packet of the second frame never arrives
+        packets.remove(packetize(&frames[..2], usize::MAX).len() - 1);
+
+        let track = DummyTrackRemote::new(packets.into());
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), 8);
+        let (tx, mut rx) = mpsc::channel(frames.len());
+        let _ = forward_frames::<H264Depacketizer>(&mut buffered_track, &tx).await;
+
+        // The truncated frame is dropped but not the intact one after it
+        let received: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let timestamps: Vec<_> = received.iter().map(|frame| frame.timestamp).collect();
+        assert_eq!(timestamps, [0, 2 * FRAME_INTERVAL_TICKS]);
+        assert!(received[1].data == frames[2]);
+    }
+
+    #[tokio::test]
+    async fn damaged_frame_dropped() {
+        let mut frames = frames();
+        // A second slice that is complete even though the first one is not
+        frames[1] = frames[1].repeat(2);
+        let mut packets = packetize(&frames, usize::MAX);
+        // A packet in the middle of the first slice of the second frame never arrives
+        let second_frame_start = packetize(&frames[..1], usize::MAX).len();
+        packets.remove(second_frame_start + 5);
+
+        // Small enough for the buffer to give up on the missing packet
+        let track = DummyTrackRemote::new(packets.into());
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), 8);
+        let (tx, mut rx) = mpsc::channel(frames.len());
+        let _ = forward_frames::<H264Depacketizer>(&mut buffered_track, &tx).await;
+
+        let timestamps: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|frame| frame.timestamp)
+            .collect();
+        assert_eq!(timestamps, [0, 2 * FRAME_INTERVAL_TICKS]);
+    }
+}
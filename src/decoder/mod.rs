@@ -0,0 +1,225 @@
+mod channel;
+
+pub use self::channel::{ChannelDecoderBuilder, EncodedFrame};
+use crate::{
+    codecs::{h264, CodecType, MIME_TYPE_H264},
+    Codec, WebRtcPeer,
+};
+use std::sync::Arc;
+use webrtc::{
+    rtp_transceiver::{rtp_codec::RTCRtpCodecCapability, rtp_receiver::RTCRtpReceiver},
+    track::track_remote::TrackRemote,
+};
+
+/// Encapsulates a builder that produces a decoder.
+pub trait DecoderBuilder: Send {
+    /// Lists all the supported codecs of the decoder.
+    fn supported_codecs(&self) -> &[Codec];
+
+    /// Whether the builder is for an audio or video codec.
+    fn codec_type(&self) -> CodecType;
+
+    /// URIs of the RTP header extensions the decoder relies on, e.g., `urn:3gpp:video-orientation`.
+    /// These are registered in the media engine so that they get negotiated.
+    fn required_extensions(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Consumes the builder to produce a decoder.
+    ///
+    /// Data from the encoder is received through `track` while the `rtp_receiver` is used to send
+    /// RTCP messages. The chosen codec is identified through `TrackRemote::codec` of `track`.
+    ///
+    /// This function will be invoked inside a Tokio runtime such that implementations could assume
+    /// that `tokio::runtime::Handle` would not panic.
+    fn build(
+        self: Box<Self>,
+        track: Arc<TrackRemote>,
+        rtp_receiver: Arc<RTCRtpReceiver>,
+        peer: Arc<WebRtcPeer>,
+    );
+
+    /// Checks if the decoder supports the given codec parameters. H.264 is matched through
+    /// [Codec::capability_match_score], which allows a different level, while other codecs need
+    /// the same `a=fmtp` line.
+    fn is_codec_supported(&self, codec_capability: &RTCRtpCodecCapability) -> bool {
+        if codec_capability
+            .mime_type
+            .eq_ignore_ascii_case(MIME_TYPE_H264)
+        {
+            self.codec_match_score(codec_capability).is_some()
+        } else {
+            self.supported_codecs()
+                .iter()
+                .any(|codec| codec.capability_matches(codec_capability))
+        }
+    }
+
+    /// How specifically the decoder supports the given codec parameters, through
+    /// [Codec::capability_match_score]. A received track goes to the decoder with the highest
+    /// score among those where `is_codec_supported` is true.
+    fn codec_match_score(&self, codec_capability: &RTCRtpCodecCapability) -> Option<usize> {
+        self.supported_codecs()
+            .iter()
+            .filter_map(|codec| codec.capability_match_score(codec_capability))
+            .max()
+    }
+}
+
+/// Index of the decoder that the track with `codec_capability` should go to. Ties go to the
+/// decoder added first.
+pub(crate) fn select_decoder(
+    decoders: &[Box<dyn DecoderBuilder>],
+    codec_capability: &RTCRtpCodecCapability,
+) -> Option<usize> {
+    let mut selected: Option<(usize, usize)> = None;
+    for (index, decoder) in decoders.iter().enumerate() {
+        if !decoder.is_codec_supported(codec_capability) {
+            continue;
+        }
+        let score = decoder.codec_match_score(codec_capability).unwrap_or(0);
+        match selected {
+            Some((_, best)) if score <= best => {}
+            _ => selected = Some((index, score)),
+        }
+    }
+    selected.map(|(index, _)| index)
+}
+
+/// Logs a warning if the H.264 track with `codec_capability` is likely to fail decoding because
+/// its profile or level does not fit the codec of `decoder` that it matched best.
+pub(crate) fn warn_on_h264_incompatibility(
+    decoder: &dyn DecoderBuilder,
+    codec_capability: &RTCRtpCodecCapability,
+) {
+    if !codec_capability
+        .mime_type
+        .eq_ignore_ascii_case(MIME_TYPE_H264)
+    {
+        return;
+    }
+
+    let mut best_match: Option<(&Codec, usize)> = None;
+    for codec in decoder.supported_codecs() {
+        if let Some(score) = codec.capability_match_score(codec_capability) {
+            match best_match {
+                Some((_, best)) if score <= best => {}
+                _ => best_match = Some((codec, score)),
+            }
+        }
+    }
+
+    if let Some((codec, _)) = best_match {
+        if let Err(reason) = h264::check_compatibility(codec, &codec_capability.sdp_fmtp_line) {
+            log::warn!(
+                "Received H.264 track with fmtp \"{}\" may fail to decode: {reason:?}",
+                codec_capability.sdp_fmtp_line
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::h264::{H264Codec, H264Profile};
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecParameters;
+
+    /// Decoder of the given codecs that only takes part in the routing. Building it does nothing.
+    struct ProfileDecoder(Vec<Codec>);
+
+    impl DecoderBuilder for ProfileDecoder {
+        fn supported_codecs(&self) -> &[Codec] {
+            &self.0
+        }
+
+        fn codec_type(&self) -> CodecType {
+            CodecType::Video
+        }
+
+        fn build(
+            self: Box<Self>,
+            _track: Arc<TrackRemote>,
+            _rtp_receiver: Arc<RTCRtpReceiver>,
+            _peer: Arc<WebRtcPeer>,
+        ) {
+        }
+    }
+
+    fn h264_decoder(codecs: Vec<H264Codec>) -> Box<dyn DecoderBuilder> {
+        let codecs = codecs.into_iter().map(Into::into).collect();
+        Box::new(ProfileDecoder(codecs))
+    }
+
+    fn vp9_codec(sdp_fmtp_line: &str) -> Codec {
+        let parameters = RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: "video/VP9".to_owned(),
+                clock_rate: 90000,
+                sdp_fmtp_line: sdp_fmtp_line.to_owned(),
+                ..Default::default()
+            },
+            payload_type: 98,
+            ..Default::default()
+        };
+        Codec::new(parameters, CodecType::Video)
+    }
+
+    fn capability(codec: H264Codec) -> RTCRtpCodecCapability {
+        let codec: Codec = codec.into();
+        codec.parameters().capability.clone()
+    }
+
+    #[test]
+    fn decoder_routing_by_profile() {
+        let decoders = [
+            h264_decoder(vec![H264Codec::new(H264Profile::High)]),
+            h264_decoder(vec![H264Codec::new(H264Profile::Baseline)]),
+            h264_decoder(vec![
+                H264Codec::new(H264Profile::High),
+                H264Codec::new(H264Profile::Baseline),
+            ]),
+        ];
+
+        // Negotiated at a different level than any decoder was added with
+        let baseline = capability(H264Codec::new(H264Profile::Baseline).with_level(0x28));
+        assert_eq!(select_decoder(&decoders, &baseline), Some(1));
+
+        let high = capability(H264Codec::new(H264Profile::High));
+        assert_eq!(select_decoder(&decoders, &high), Some(0));
+
+        let main = capability(H264Codec::new(H264Profile::Main));
+        assert_eq!(select_decoder(&decoders, &main), None);
+    }
+
+    #[test]
+    fn decoder_routing_by_specificity() {
+        let decoders = [
+            h264_decoder(vec![H264Codec::new(H264Profile::Baseline).with_level(0x34)]),
+            h264_decoder(vec![H264Codec::new(H264Profile::Baseline)]),
+            h264_decoder(vec![H264Codec::new(H264Profile::Baseline)]),
+        ];
+
+        // The exact match wins over the partial match added first, then the first of the ties
+        let baseline = capability(H264Codec::new(H264Profile::Baseline));
+        assert_eq!(select_decoder(&decoders, &baseline), Some(1));
+
+        let baseline = capability(H264Codec::new(H264Profile::Baseline).with_level(0x28));
+        assert_eq!(select_decoder(&decoders, &baseline), Some(0));
+    }
+
+    #[test]
+    fn decoder_routing_by_fmtp() {
+        let decoders: [Box<dyn DecoderBuilder>; 2] = [
+            Box::new(ProfileDecoder(vec![vp9_codec("profile-id=0")])),
+            Box::new(ProfileDecoder(vec![vp9_codec("profile-id=2")])),
+        ];
+
+        // Only H.264 tolerates a different fmtp line
+        let profile_2 = vp9_codec("profile-id=2").parameters().capability.clone();
+        assert_eq!(select_decoder(&decoders, &profile_2), Some(1));
+
+        let profile_1 = vp9_codec("profile-id=1").parameters().capability.clone();
+        assert_eq!(select_decoder(&decoders, &profile_1), None);
+    }
+}
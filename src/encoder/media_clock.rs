@@ -0,0 +1,93 @@
+//! RTP timestamps that advance by the frame duration rather than by the wall-clock time between
+//! frames, which jitters with the scheduling of the encoder.
+
+use super::RtpTimestamp;
+
+/// Media clock of a video encoder at a fixed frame rate. Every frame advances the RTP timestamp by
+/// `clock_rate / fps` ticks. If that is not a whole number of ticks, the increments alternate so
+/// that the timestamps never drift from the frame count, e.g., 90 kHz at 7 fps advances by 12857
+/// or 12858 ticks.
+#[derive(Debug, Clone)]
+pub struct MediaClock {
+    clock_rate: u32,
+    fps: u32,
+    start_timestamp: u32,
+    frames: u64,
+}
+
+impl MediaClock {
+    /// Create a new `MediaClock` for a codec with `clock_rate` Hz, e.g., from the
+    /// `codec_capability` passed to [EncoderBuilder::build][a], and a frame rate of `fps`. The
+    /// first frame has a timestamp of 0.
+    ///
+    /// [a]: super::EncoderBuilder::build
+    pub fn new(clock_rate: u32, fps: u32) -> MediaClock {
+        assert!(fps > 0, "Frame rate must be positive");
+        MediaClock {
+            clock_rate,
+            fps,
+            start_timestamp: 0,
+            frames: 0,
+        }
+    }
+
+    /// Start at `start_timestamp` instead of 0, e.g., a random one as recommended by
+    /// [RFC3550][RFC3550].
+    ///
+    /// [RFC3550]: https://www.rfc-editor.org/rfc/rfc3550#section-5.1
+    pub fn with_start_timestamp(mut self, start_timestamp: u32) -> MediaClock {
+        self.start_timestamp = start_timestamp;
+        self
+    }
+
+    /// Returns the timestamp of the next frame and advances the clock by a frame.
+    pub fn next_frame(&mut self) -> u32 {
+        let timestamp = self.timestamp();
+        self.frames += 1;
+        timestamp
+    }
+
+    /// Advances the clock by `frames` without producing them, e.g., for frames the encoder
+    /// dropped, so that the following frames keep their place in time.
+    pub fn skip_frames(&mut self, frames: u64) {
+        self.frames += frames;
+    }
+
+    /// Returns the timestamp of the next frame without advancing the clock.
+    pub fn timestamp(&self) -> u32 {
+        let ticks = self.frames as u128 * self.clock_rate as u128 / self.fps as u128;
+        (RtpTimestamp::new(self.start_timestamp) + ticks as u32).get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_increments() {
+        let mut clock = MediaClock::new(90000, 60).with_start_timestamp(u32::MAX - 2000);
+        let timestamps: Vec<_> = (0..4).map(|_| clock.next_frame()).collect();
+        assert_eq!(
+            timestamps,
+            [u32::MAX - 2000, u32::MAX - 500, 999, 2499],
+            "60 fps is 1500 ticks at 90 kHz"
+        );
+
+        clock.skip_frames(2);
+        assert_eq!(clock.next_frame(), 2499 + 3 * 1500);
+
+        // Whole seconds land exactly on the clock rate
+        let mut clock = MediaClock::new(90000, 7);
+        let increments: Vec<_> = (0..7)
+            .map(|_| clock.next_frame())
+            .collect::<Vec<_>>()
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect();
+        assert!(increments
+            .iter()
+            .all(|&ticks| ticks == 12857 || ticks == 12858));
+        assert_eq!(clock.timestamp(), 90000);
+    }
+}
@@ -0,0 +1,72 @@
+//! Send-side metrics of the encoders, for diagnosing where packets back up before reaching the
+//! network.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use webrtc::{rtp::packet::Packet, track::track_local::TrackLocalWriter};
+
+/// Snapshot of the metrics reported by an encoder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncoderStats {
+    /// Number of packets written.
+    pub num_writes: u64,
+    /// Mean duration of writing a packet to the track.
+    pub mean_write_latency: Duration,
+    /// Longest duration of writing a packet to the track.
+    pub max_write_latency: Duration,
+    /// Packets waiting to be written as of the last report.
+    pub queue_depth: usize,
+    /// Most packets ever waiting to be written.
+    pub max_queue_depth: usize,
+}
+
+/// Handle for an encoder to report how long writing to the track takes and how many packets are
+/// queued up in the encoder waiting for it. Read through [WebRtcPeer::encoder_stats][a].
+///
+/// [a]: crate::peer::WebRtcPeer::encoder_stats
+#[derive(Debug, Clone, Default)]
+pub struct EncoderMetrics(Arc<Mutex<MetricsInner>>);
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    stats: EncoderStats,
+    total_write_latency: Duration,
+}
+
+impl EncoderMetrics {
+    /// Write `packet` to `writer`, recording how long it took.
+    pub async fn timed_write<T>(&self, writer: &T, packet: &Packet) -> Result<usize, webrtc::Error>
+    where
+        T: TrackLocalWriter,
+    {
+        let start = Instant::now();
+        let result = writer.write_rtp(packet).await;
+        self.record_write(start.elapsed());
+        result
+    }
+
+    /// Record the duration of one packet write.
+    pub fn record_write(&self, latency: Duration) {
+        let mut inner = self.0.lock().unwrap();
+        inner.total_write_latency += latency;
+        inner.stats.num_writes += 1;
+        inner.stats.mean_write_latency = Duration::from_secs_f64(
+            inner.total_write_latency.as_secs_f64() / inner.stats.num_writes as f64,
+        );
+        inner.stats.max_write_latency = inner.stats.max_write_latency.max(latency);
+    }
+
+    /// Report the number of packets waiting to be written.
+    pub fn set_queue_depth(&self, queue_depth: usize) {
+        let stats = &mut self.0.lock().unwrap().stats;
+        stats.queue_depth = queue_depth;
+        stats.max_queue_depth = stats.max_queue_depth.max(queue_depth);
+    }
+
+    /// The metrics reported so far.
+    pub fn stats(&self) -> EncoderStats {
+        self.0.lock().unwrap().stats
+    }
+}
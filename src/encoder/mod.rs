@@ -1,9 +1,21 @@
+mod media_clock;
+mod metrics;
+mod passthrough;
+mod rtp_timestamp;
 mod track;
 
-pub use self::track::EncoderTrackLocal;
+pub use self::{
+    media_clock::MediaClock,
+    metrics::{EncoderMetrics, EncoderStats},
+    passthrough::PassthroughEncoderBuilder,
+    rtp_timestamp::RtpTimestamp,
+    track::{
+        EncoderTrackLocal, FlushAck, FlushRequests, KeyframeRequests, TrackBindings, TrackEnabled,
+    },
+};
 use crate::{
     codecs::{Codec, CodecType},
-    interceptor::twcc::TwccBandwidthEstimate,
+    interceptor::twcc::{OutputBitrateReporter, PacketizationMtu, TwccBandwidthEstimate},
     peer::IceConnectionState,
 };
 use std::sync::Arc;
@@ -12,12 +24,80 @@ use webrtc::{
     track::track_local::track_local_static_rtp::TrackLocalStaticRTP,
 };
 
+/// Everything an encoder is built with once its track is bound to a transceiver.
+///
+/// Encoders should drop the handles they do not use, so that the track does not wait on them,
+/// e.g., for a flush, when it is unbound.
+pub struct EncoderContext {
+    /// Track that the RTP packets are written to.
+    pub rtp_track: Arc<TrackLocalStaticRTP>,
+    /// Transceiver of the track. The RTCP of its sender has to be read for the interceptors to
+    /// process it.
+    pub transceiver: Arc<RTCRtpTransceiver>,
+    /// State of the ICE connection. Nothing should be sent before it is connected.
+    pub ice_connection_state: IceConnectionState,
+    /// Capped by the `x-google-max-bitrate` of the chosen codec, if the remote peer set one.
+    pub bandwidth_estimate: TwccBandwidthEstimate,
+    /// Whether the track has been paused through [WebRtcPeer::set_track_enabled][a]. Nothing
+    /// should be sent while it is.
+    ///
+    /// [a]: crate::WebRtcPeer::set_track_enabled
+    pub track_enabled: TrackEnabled,
+    /// Codec chosen in the negotiation.
+    pub codec_capability: RTCRtpCodecCapability,
+    /// SSRC of the stream sent through the transceiver.
+    pub ssrc: u32,
+    /// Payload type of the chosen codec.
+    pub payload_type: u8,
+    /// For reporting the bitrate that the encoder actually produces. Encoders that often send
+    /// well below the bandwidth estimate (e.g., screen sharing) should report it so that the
+    /// estimate is not lowered to match. Dropping it leaves the encoder out of the reported
+    /// total.
+    pub output_bitrate_reporter: OutputBitrateReporter,
+    /// Largest RTP payload to fragment samples into, i.e., the `mtu` of the sample senders. It
+    /// only changes if MTU discovery is enabled through
+    /// [WebRtcBuilder::with_mtu_discovery][a].
+    ///
+    /// [a]: crate::peer::WebRtcBuilder::with_mtu_discovery
+    pub packetization_mtu: PacketizationMtu,
+    /// Requests to flush the encoder, which are sent right before the track is unbound from its
+    /// last transceiver. Stateful encoders should write out any partially sent frame and
+    /// acknowledge the request, or otherwise cleanly reset. Dropping it lets the track be unbound
+    /// immediately.
+    pub flush_requests: FlushRequests,
+    /// Requests for a keyframe, which are sent each time the track is bound after the first,
+    /// e.g., when the same track is relayed to another subscriber that joins late, and when a
+    /// subscriber sends a PLI or FIR. The requests are coalesced and limited to one every
+    /// [WebRtcBuilder::with_min_keyframe_interval][a]. Encoders that can produce keyframes on
+    /// demand should send one.
+    ///
+    /// There is no fallback for encoders that drop it, e.g., [PassthroughEncoderBuilder]. Their
+    /// requests are discarded, and a late subscriber or one that lost a keyframe cannot decode
+    /// anything until the encoder produces its next keyframe on its own.
+    ///
+    /// [a]: crate::peer::WebRtcBuilder::with_min_keyframe_interval
+    pub keyframe_requests: KeyframeRequests,
+    /// For reporting how long writing packets to the track takes and how many are queued up
+    /// waiting for it. The metrics are read through [WebRtcPeer::encoder_stats][a].
+    ///
+    /// [a]: crate::peer::WebRtcPeer::encoder_stats
+    pub metrics: EncoderMetrics,
+    /// Whether the track is bound to a transceiver. Encoders should stop writing while it is
+    /// not, and not give up on a write that failed because it raced an unbind, e.g., when one of
+    /// the subscribers of a shared track leaves.
+    pub track_bindings: TrackBindings,
+}
+
 /// Encapsulates a builder that produces an encoder.
 pub trait EncoderBuilder: Send {
     /// Unique identifier for the track. Used in the `TrackLocal` implementation.
     fn id(&self) -> &str;
 
     /// Group this track belongs to. Used in the `TrackLocal` implementation.
+    ///
+    /// Tracks that return the same stream id, e.g., the audio and video of a camera, are
+    /// advertised as one MediaStream through `a=msid` and share the same CNAME so that the
+    /// receiver plays them in sync. webrtc-rs only supports a single stream per track.
     fn stream_id(&self) -> &str;
 
     /// Whether the builder is for an audio or video codec.
@@ -38,18 +118,13 @@ pub trait EncoderBuilder: Send {
     ///
     /// Encoded samples are to be sent through the `RTCRtpTransceiver`.
     ///
-    /// Implementations need to wait for ICE to be connected via `ice_connection_state` before
-    /// sending data. The chosen codec is found through `codec_capability`.
-    fn build(
-        self: Box<Self>,
-        rtp_track: Arc<TrackLocalStaticRTP>,
-        transceiver: Arc<RTCRtpTransceiver>,
-        ice_connection_state: IceConnectionState,
-        bandwidth_estimate: TwccBandwidthEstimate,
-        codec_capability: RTCRtpCodecCapability,
-        ssrc: u32,
-        payload_type: u8,
-    );
+    /// Implementations need to wait for ICE to be connected via
+    /// [EncoderContext::ice_connection_state] before sending data. The chosen codec is found
+    /// through [EncoderContext::codec_capability].
+    ///
+    /// Implementations should also stop sending while [EncoderContext::track_enabled] reports
+    /// that the track has been paused.
+    fn build(self: Box<Self>, context: EncoderContext);
 
     /// Checks if the encoder supports the given codec parameters.
     fn is_codec_supported(&self, codec_capability: &RTCRtpCodecCapability) -> bool {
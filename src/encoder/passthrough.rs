@@ -0,0 +1,391 @@
+//! Encoder that forwards RTP packets that were already encoded and packetized elsewhere, e.g.,
+//! when relaying the media of another peer.
+
+use super::{EncoderBuilder, EncoderContext, EncoderMetrics, TrackBindings, TrackEnabled};
+use crate::{
+    codecs::{Codec, CodecType},
+    network::frame_transform::{FrameTransform, TransformedTrackWriter},
+};
+use tokio::sync::mpsc;
+use webrtc::{
+    ice_transport::ice_connection_state::RTCIceConnectionState, rtp::packet::Packet,
+    track::track_local::TrackLocalWriter,
+};
+
+/// Builds an encoder that writes the packets received through a channel to the track as is,
+/// except for the SSRC and payload type which are set to the negotiated ones.
+///
+/// Packets are dropped while the track is paused or not bound to any transceiver, and a packet
+/// whose write fails because it raced an unbind is dropped as well. The bandwidth estimate is
+/// ignored since the bitrate is up to the source of the packets, and so are keyframes: the
+/// keyframe requests of the subscribers are not passed on.
+pub struct PassthroughEncoderBuilder {
+    id: String,
+    stream_id: String,
+    codecs: Vec<Codec>,
+    packets: mpsc::Receiver<Packet>,
+    frame_transform: Option<Box<dyn FrameTransform>>,
+}
+
+impl PassthroughEncoderBuilder {
+    /// Create a builder for a track with the given `id` and `stream_id` that forwards packets of
+    /// `codec`. Returns the sending end of the channel for the packets, which buffers up to
+    /// `capacity` packets until ICE is connected.
+    pub fn new(
+        id: &str,
+        stream_id: &str,
+        codec: Codec,
+        capacity: usize,
+    ) -> (PassthroughEncoderBuilder, mpsc::Sender<Packet>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let builder = PassthroughEncoderBuilder {
+            id: id.to_owned(),
+            stream_id: stream_id.to_owned(),
+            codecs: vec![codec],
+            packets: rx,
+            frame_transform: None,
+        };
+        (builder, tx)
+    }
+
+    /// Transform the payload of every packet before it is sent, e.g., to encrypt the media end to
+    /// end. The receiver reverses it through
+    /// [BufferedTrackRemote::with_frame_transform][a].
+    ///
+    /// [a]: crate::network::reorder_buffer::BufferedTrackRemote::with_frame_transform
+    pub fn with_frame_transform(
+        mut self,
+        frame_transform: Box<dyn FrameTransform>,
+    ) -> PassthroughEncoderBuilder {
+        self.frame_transform = Some(frame_transform);
+        self
+    }
+}
+
+impl EncoderBuilder for PassthroughEncoderBuilder {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn stream_id(&self) -> &str {
+        &self.stream_id
+    }
+
+    fn codec_type(&self) -> CodecType {
+        self.codecs[0].codec_type()
+    }
+
+    fn supported_codecs(&self) -> &[Codec] {
+        &self.codecs
+    }
+
+    fn build(self: Box<Self>, context: EncoderContext) {
+        let EncoderContext {
+            rtp_track,
+            transceiver,
+            mut ice_connection_state,
+            track_enabled,
+            metrics,
+            track_bindings: mut bindings,
+            ..
+        } = context;
+        let mut packets = self.packets;
+        let frame_transform = self.frame_transform;
+
+        // The RTCP needs to be read for the interceptors to process it
+        tokio::spawn(async move {
+            if let Some(sender) = transceiver.sender().await {
+                let mut buf = vec![0u8; 1500];
+                while sender.read(&mut buf).await.is_ok() {}
+            }
+        });
+
+        tokio::spawn(async move {
+            while *ice_connection_state.borrow_and_update() != RTCIceConnectionState::Connected {
+                if ice_connection_state.changed().await.is_err() {
+                    return;
+                }
+            }
+
+            let result = match frame_transform {
+                Some(frame_transform) => {
+                    let writer = TransformedTrackWriter::new(rtp_track, frame_transform);
+                    forward_packets(
+                        &mut packets,
+                        &writer,
+                        &track_enabled,
+                        &mut bindings,
+                        &metrics,
+                    )
+                    .await
+                }
+                None => {
+                    forward_packets(
+                        &mut packets,
+                        rtp_track.as_ref(),
+                        &track_enabled,
+                        &mut bindings,
+                        &metrics,
+                    )
+                    .await
+                }
+            };
+            if let Err(e) = result {
+                log::error!("Error forwarding RTP: {e}");
+            }
+        });
+    }
+}
+
+/// Write the packets to `writer` until the channel is closed.
+async fn forward_packets<T>(
+    packets: &mut mpsc::Receiver<Packet>,
+    writer: &T,
+    track_enabled: &TrackEnabled,
+    bindings: &mut TrackBindings,
+    metrics: &EncoderMetrics,
+) -> Result<(), webrtc::Error>
+where
+    T: TrackLocalWriter,
+{
+    while let Some(packet) = packets.recv().await {
+        metrics.set_queue_depth(packets.len());
+        if !track_enabled.is_enabled() || !bindings.is_bound() {
+            continue;
+        }
+        bindings.mark_unchanged();
+        if let Err(e) = metrics.timed_write(writer, &packet).await {
+            if !bindings.has_changed() {
+                return Err(e);
+            }
+            log::debug!("Dropped a packet whose write raced the track being unbound: {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::reorder_buffer::tests::{FakeTrackWriter, XorTransform};
+    use bytes::Bytes;
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::watch;
+    use webrtc::rtp::header::Header;
+
+    #[tokio::test]
+    async fn packets_forwarded_unchanged() {
+        let (builder, tx) =
+            PassthroughEncoderBuilder::new("relay-audio", "relay", Codec::opus(), 16);
+        assert_eq!(builder.codec_type(), CodecType::Audio);
+        let mut packets = builder.packets;
+
+        let sent: Vec<_> = (0..10u16)
+            .map(|i| Packet {
+                header: Header {
+                    version: 2,
+                    marker: i == 9,
+                    payload_type: 111,
+                    sequence_number: 1000 + i,
+                    timestamp: 960 * i as u32,
+                    ssrc: 1234,
+                    ..Default::default()
+                },
+                payload: Bytes::from(vec![i as u8; 100 + i as usize]),
+            })
+            .collect();
+        for packet in &sent {
+            tx.send(packet.clone()).await.unwrap();
+        }
+        drop(tx);
+
+        let (_enabled_tx, enabled_rx) = watch::channel(true);
+        let writer = FakeTrackWriter::default();
+        let metrics = EncoderMetrics::default();
+        forward_packets(
+            &mut packets,
+            &writer,
+            &TrackEnabled(enabled_rx),
+            &mut TrackBindings::default(),
+            &metrics,
+        )
+        .await
+        .unwrap();
+        assert_eq!(metrics.stats().num_writes, sent.len() as u64);
+
+        assert_eq!(writer.written(), sent);
+    }
+
+    #[tokio::test]
+    async fn packets_transformed() {
+        const KEY: u8 = 0x5a;
+
+        let (builder, tx) =
+            PassthroughEncoderBuilder::new("relay-video", "relay", Codec::opus(), 16);
+        let mut packets = builder.packets;
+        let sent: Vec<_> = (0..4u16)
+            .map(|i| Packet {
+                header: Header {
+                    sequence_number: i,
+                    ..Default::default()
+                },
+                payload: Bytes::from(vec![i as u8; 10]),
+            })
+            .collect();
+        for packet in &sent {
+            tx.send(packet.clone()).await.unwrap();
+        }
+        drop(tx);
+
+        let (_enabled_tx, enabled_rx) = watch::channel(true);
+        let recording_writer = Arc::new(FakeTrackWriter::default());
+        let writer =
+            TransformedTrackWriter::new(recording_writer.clone(), Box::new(XorTransform(KEY)));
+        forward_packets(
+            &mut packets,
+            &writer,
+            &TrackEnabled(enabled_rx),
+            &mut TrackBindings::default(),
+            &EncoderMetrics::default(),
+        )
+        .await
+        .unwrap();
+
+        let written = recording_writer.written();
+        assert_eq!(written.len(), sent.len());
+        for (written, sent) in written.iter().zip(&sent) {
+            assert_eq!(written.header, sent.header);
+            assert!(written.payload.iter().all(|b| *b == sent.payload[0] ^ KEY));
+        }
+    }
+
+    #[tokio::test]
+    async fn write_metrics() {
+        const WRITE_LATENCY: Duration = Duration::from_millis(20);
+        const NUM_PACKETS: usize = 5;
+
+        let (builder, tx) =
+            PassthroughEncoderBuilder::new("relay-audio", "relay", Codec::opus(), 16);
+        let mut packets = builder.packets;
+        for _ in 0..NUM_PACKETS {
+            tx.send(Packet::default()).await.unwrap();
+        }
+        drop(tx);
+
+        let (_enabled_tx, enabled_rx) = watch::channel(true);
+        let metrics = EncoderMetrics::default();
+        forward_packets(
+            &mut packets,
+            &FakeTrackWriter::default().with_latency(WRITE_LATENCY),
+            &TrackEnabled(enabled_rx),
+            &mut TrackBindings::default(),
+            &metrics,
+        )
+        .await
+        .unwrap();
+
+        let stats = metrics.stats();
+        assert_eq!(stats.num_writes, NUM_PACKETS as u64);
+        assert!(stats.mean_write_latency >= WRITE_LATENCY);
+        assert!(stats.mean_write_latency < 5 * WRITE_LATENCY);
+        assert!(stats.max_write_latency >= stats.mean_write_latency);
+        // Everything was queued up front
+        assert_eq!(stats.max_queue_depth, NUM_PACKETS - 1);
+        assert_eq!(stats.queue_depth, 0);
+    }
+
+    /// Runs `forward_packets` with `writer` and returns the channel for the packets.
+    fn spawn_forward(
+        writer: Arc<FakeTrackWriter>,
+        mut bindings: TrackBindings,
+    ) -> (
+        mpsc::Sender<Packet>,
+        tokio::task::JoinHandle<Result<(), webrtc::Error>>,
+    ) {
+        let (builder, tx) =
+            PassthroughEncoderBuilder::new("relay-video", "relay", Codec::opus(), 1);
+        let mut packets = builder.packets;
+        let handle = tokio::spawn(async move {
+            let (_enabled_tx, enabled_rx) = watch::channel(true);
+            let metrics = EncoderMetrics::default();
+            forward_packets(
+                &mut packets,
+                writer.as_ref(),
+                &TrackEnabled(enabled_rx),
+                &mut bindings,
+                &metrics,
+            )
+            .await
+        });
+        (tx, handle)
+    }
+
+    fn packet(sequence_number: u16) -> Packet {
+        Packet {
+            header: Header {
+                sequence_number,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn written_seqs(writer: &FakeTrackWriter) -> Vec<u16> {
+        writer
+            .written()
+            .iter()
+            .map(|packet| packet.header.sequence_number)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn writes_resume_after_rebind() {
+        let bindings = Arc::new(watch::channel(1).0);
+        let writer = Arc::new(
+            FakeTrackWriter::default()
+                .with_bindings(bindings.clone())
+                .with_failed_write(2, true),
+        );
+        let (tx, handle) = spawn_forward(writer.clone(), TrackBindings(bindings.subscribe()));
+
+        // Number of bindings set before sending each packet, if changed. The write of 2 races
+        // the unbind and 3 and 4 are sent while unbound. Then the track is rapidly bound and
+        // unbound, e.g., by subscribers coming and going.
+        let schedule = [None; 5]
+            .into_iter()
+            .chain((0..10).map(|i| Some(i % 2)))
+            .chain([Some(2), None]);
+        for (seq, num_bindings) in schedule.enumerate() {
+            if let Some(num_bindings) = num_bindings {
+                bindings.send_replace(num_bindings);
+            }
+            tx.send(packet(seq as u16)).await.unwrap();
+            // The single-threaded runtime only runs the forwarding in between
+            tokio::task::yield_now().await;
+        }
+        drop(tx);
+
+        // None of the failed writes stopped the forwarding
+        handle.await.unwrap().unwrap();
+        assert_eq!(written_seqs(&writer), [0, 1, 6, 8, 10, 12, 14, 15, 16]);
+    }
+
+    #[tokio::test]
+    async fn write_errors_while_bound() {
+        let bindings = Arc::new(watch::channel(1).0);
+        let writer = Arc::new(
+            FakeTrackWriter::default()
+                .with_bindings(bindings.clone())
+                .with_failed_write(1, false),
+        );
+        let (tx, handle) = spawn_forward(writer.clone(), TrackBindings(bindings.subscribe()));
+        for seq in 0..3 {
+            // Nothing is read after the error
+            let _ = tx.send(packet(seq)).await;
+            tokio::task::yield_now().await;
+        }
+
+        assert!(handle.await.unwrap().is_err());
+        assert_eq!(written_seqs(&writer), [0]);
+    }
+}
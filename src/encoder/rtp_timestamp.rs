@@ -0,0 +1,152 @@
+//! RTP timestamps, which wrap around after 2^32 ticks, i.e., about 13 hours at 90 kHz or a day at
+//! 48 kHz. Streams that start at a random timestamp can wrap around at any time.
+
+use crate::codecs::rtp_timestamp_ticks;
+use std::{
+    cmp::Ordering,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    time::Duration,
+};
+
+/// Half of the 32-bit range. Timestamps further apart than this are assumed to have wrapped around.
+const HALF_RANGE: u32 = 1 << 31;
+
+/// A 32-bit RTP timestamp. Adding ticks wraps around and comparisons use serial number arithmetic
+/// from [RFC1982][RFC1982], so that a timestamp right after the wraparound is still later than one
+/// right before it. Two timestamps exactly 2^31 ticks apart are not ordered.
+///
+/// [RFC1982]: https://www.rfc-editor.org/rfc/rfc1982
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(transparent)]
+pub struct RtpTimestamp(u32);
+
+impl RtpTimestamp {
+    /// Create a new `RtpTimestamp` from its raw value, e.g., from an RTP header.
+    pub const fn new(timestamp: u32) -> RtpTimestamp {
+        RtpTimestamp(timestamp)
+    }
+
+    /// Returns the raw value for the RTP header.
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the signed number of ticks from `earlier` to `self`, taking the shorter way around.
+    /// Negative if `earlier` is actually later.
+    pub const fn ticks_since(self, earlier: RtpTimestamp) -> i32 {
+        self.0.wrapping_sub(earlier.0) as i32
+    }
+
+    /// Advance the timestamp by `duration` at `clock_rate` Hz.
+    pub fn add_duration(self, duration: Duration, clock_rate: u32) -> RtpTimestamp {
+        self + rtp_timestamp_ticks(duration, clock_rate)
+    }
+}
+
+impl From<u32> for RtpTimestamp {
+    fn from(timestamp: u32) -> RtpTimestamp {
+        RtpTimestamp(timestamp)
+    }
+}
+
+impl From<RtpTimestamp> for u32 {
+    fn from(timestamp: RtpTimestamp) -> u32 {
+        timestamp.0
+    }
+}
+
+impl Add<u32> for RtpTimestamp {
+    type Output = RtpTimestamp;
+
+    fn add(self, ticks: u32) -> RtpTimestamp {
+        RtpTimestamp(self.0.wrapping_add(ticks))
+    }
+}
+
+impl AddAssign<u32> for RtpTimestamp {
+    fn add_assign(&mut self, ticks: u32) {
+        *self = *self + ticks;
+    }
+}
+
+impl Sub<u32> for RtpTimestamp {
+    type Output = RtpTimestamp;
+
+    fn sub(self, ticks: u32) -> RtpTimestamp {
+        RtpTimestamp(self.0.wrapping_sub(ticks))
+    }
+}
+
+impl SubAssign<u32> for RtpTimestamp {
+    fn sub_assign(&mut self, ticks: u32) {
+        *self = *self - ticks;
+    }
+}
+
+impl Sub for RtpTimestamp {
+    type Output = i32;
+
+    /// Same as [RtpTimestamp::ticks_since].
+    fn sub(self, earlier: RtpTimestamp) -> i32 {
+        self.ticks_since(earlier)
+    }
+}
+
+impl PartialOrd for RtpTimestamp {
+    fn partial_cmp(&self, other: &RtpTimestamp) -> Option<Ordering> {
+        match self.0.wrapping_sub(other.0) {
+            0 => Some(Ordering::Equal),
+            HALF_RANGE => None,
+            delta if delta < HALF_RANGE => Some(Ordering::Greater),
+            _ => Some(Ordering::Less),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn across_wraparound() {
+        let before = RtpTimestamp::new(u32::MAX - 1000);
+        let after = before + 3000;
+        assert_eq!(after.get(), 1999);
+
+        assert!(after > before);
+        assert!(before < after);
+        assert_eq!(after - before, 3000);
+        assert_eq!(before - after, -3000);
+        assert_eq!(after - 3000, before);
+
+        let mut timestamp = before;
+        timestamp += 1001;
+        assert_eq!(timestamp.get(), 0);
+        timestamp -= 1;
+        assert_eq!(timestamp.get(), u32::MAX);
+
+        // 1 s at 90 kHz
+        assert_eq!(
+            before.add_duration(Duration::from_secs(1), 90000).get(),
+            88999
+        );
+    }
+
+    #[test]
+    fn ordering() {
+        let timestamp = RtpTimestamp::new(100);
+        assert_eq!(timestamp.partial_cmp(&timestamp), Some(Ordering::Equal));
+
+        // Up to half the range ahead is later, the rest is earlier
+        let ahead = timestamp + (HALF_RANGE - 1);
+        assert!(ahead > timestamp);
+        assert_eq!(ahead - timestamp, i32::MAX);
+        let behind = timestamp + (HALF_RANGE + 1);
+        assert!(behind < timestamp);
+        assert_eq!(behind - timestamp, i32::MIN + 1);
+
+        let opposite = timestamp + HALF_RANGE;
+        assert_eq!(timestamp.partial_cmp(&opposite), None);
+        assert_eq!(opposite.partial_cmp(&timestamp), None);
+    }
+}
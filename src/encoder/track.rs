@@ -1,10 +1,20 @@
-use super::EncoderBuilder;
-use crate::{codecs::Codec, interceptor::twcc::TwccBandwidthEstimate, peer::IceConnectionState};
+use super::{EncoderBuilder, EncoderContext, EncoderMetrics, EncoderStats};
+use crate::{
+    codecs::Codec,
+    interceptor::{
+        keyframe_request::KeyframeRequestRouter,
+        twcc::{
+            OutputBitrateReporter, PacketizationMtu, TwccBandwidthEstimate, TwccBandwidthSender,
+        },
+    },
+    network::data_rate::DataRate,
+    peer::IceConnectionState,
+};
 use async_trait::async_trait;
-use std::{any::Any, fmt::Debug, sync::Arc};
+use std::{any::Any, collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
 use tokio::sync::{
     mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-    Mutex,
+    oneshot, watch, Mutex,
 };
 use webrtc::{
     peer_connection::RTCPeerConnection,
@@ -19,8 +29,121 @@ use webrtc::{
     Error,
 };
 
+/// Used by the encoders to check if sending has been paused through
+/// [WebRtcPeer::set_track_enabled][a].
+///
+/// [a]: crate::WebRtcPeer::set_track_enabled
+#[derive(Debug, Clone)]
+pub struct TrackEnabled(pub(super) watch::Receiver<bool>);
+
+impl TrackEnabled {
+    /// Returns `true` if the encoder is allowed to send packets.
+    pub fn is_enabled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Blocks until the track is enabled. Returns an error if the track has been dropped.
+    pub async fn wait_until_enabled(&mut self) -> Result<(), watch::error::RecvError> {
+        while !*self.0.borrow_and_update() {
+            self.0.changed().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Used by the encoders to check if the track is bound to a transceiver. Packets written while
+/// it is not go nowhere, and a write that races an unbind can fail once the transport of the
+/// unbound transceiver is closed. See [EncoderContext::track_bindings].
+#[derive(Debug, Clone)]
+pub struct TrackBindings(pub(super) watch::Receiver<usize>);
+
+impl TrackBindings {
+    /// Returns `true` if the track is bound to at least one transceiver.
+    pub fn is_bound(&self) -> bool {
+        *self.0.borrow() > 0
+    }
+
+    /// Blocks until the track is bound. Returns an error if the track has been dropped.
+    pub async fn wait_until_bound(&mut self) -> Result<(), watch::error::RecvError> {
+        while *self.0.borrow_and_update() == 0 {
+            self.0.changed().await?;
+        }
+        Ok(())
+    }
+
+    /// Forget about the binds and unbinds so far, e.g., right before writing a packet.
+    pub fn mark_unchanged(&mut self) {
+        self.0.borrow_and_update();
+    }
+
+    /// Returns `true` if the track was bound or unbound since [TrackBindings::mark_unchanged] or
+    /// [TrackBindings::wait_until_bound]. A write that failed in the meantime most likely raced
+    /// the unbind and is not worth giving up over.
+    pub fn has_changed(&self) -> bool {
+        self.0.has_changed().unwrap_or(false)
+    }
+}
+
+impl Default for TrackBindings {
+    /// Always bound, for encoders that are built without an `EncoderTrackLocal`.
+    fn default() -> Self {
+        TrackBindings(watch::channel(1).1)
+    }
+}
+
+/// `x-google-max-bitrate` of the codecs in the remote description, by payload type.
+pub(crate) type RemoteMaxBitrates = watch::Receiver<HashMap<u8, DataRate>>;
+
+// How long unbinding waits for the encoder to flush
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Used by the encoders to learn that the track is about to be unbound from its last
+/// transceiver, e.g., when the peer connection is closed, so that buffered packets can still be
+/// written. See
+/// [EncoderContext::flush_requests].
+#[derive(Debug)]
+pub struct FlushRequests(UnboundedReceiver<oneshot::Sender<()>>);
+
+impl FlushRequests {
+    /// Waits for the next flush request. Returns [None] if the track has been dropped.
+    pub async fn recv(&mut self) -> Option<FlushAck> {
+        self.0.recv().await.map(FlushAck)
+    }
+}
+
+/// Unbinding continues once this is acknowledged or dropped, or after a timeout.
+#[derive(Debug)]
+pub struct FlushAck(oneshot::Sender<()>);
+
+impl FlushAck {
+    /// Signal that every buffered packet has been written.
+    pub fn done(self) {
+        let _ = self.0.send(());
+    }
+}
+
+/// Used by the encoders to learn that a subscriber needs a keyframe, either because it joined
+/// after the track was first bound or because it sent a PLI or FIR. See
+/// [EncoderContext::keyframe_requests].
+#[derive(Debug)]
+pub struct KeyframeRequests(UnboundedReceiver<()>);
+
+impl KeyframeRequests {
+    /// Waits for the next keyframe request. Returns [None] if the track has been dropped.
+    pub async fn recv(&mut self) -> Option<()> {
+        self.0.recv().await
+    }
+}
+
 pub struct EncoderTrackLocal {
     tx: UnboundedSender<TrackLocalEvent>,
+    flush_tx: UnboundedSender<oneshot::Sender<()>>,
+    keyframe_tx: UnboundedSender<()>,
+    keyframe_router: KeyframeRequestRouter,
+    enabled: watch::Sender<bool>,
+    // Number of transceivers the track is bound to
+    bindings: watch::Sender<usize>,
+    metrics: EncoderMetrics,
     rtp_track: Mutex<Option<Arc<TrackLocalStaticRTP>>>,
     supported_codecs: Vec<Codec>,
     id: String,
@@ -33,7 +156,16 @@ impl TrackLocal for EncoderTrackLocal {
     async fn bind(&self, t: &TrackLocalContext) -> Result<RTCRtpCodecParameters, webrtc::Error> {
         let mut data = self.rtp_track.lock().await;
         match &mut *data {
-            Some(rtp_track) => rtp_track.bind(t).await,
+            Some(rtp_track) => {
+                let bind_result = rtp_track.bind(t).await;
+                if bind_result.is_ok() {
+                    self.bindings.send_modify(|bindings| *bindings += 1);
+                    self.keyframe_router
+                        .register(t.ssrc(), self.keyframe_tx.clone());
+                    self.request_keyframe();
+                }
+                bind_result
+            }
             None => {
                 for codec_params in t.codec_parameters() {
                     for codec in &self.supported_codecs {
@@ -50,6 +182,11 @@ impl TrackLocal for EncoderTrackLocal {
                                 .expect("Error while sending TrackLocalStaticRTP");
 
                             let bind_result = rtp_track.bind(t).await;
+                            if bind_result.is_ok() {
+                                self.bindings.send_modify(|bindings| *bindings += 1);
+                                self.keyframe_router
+                                    .register(t.ssrc(), self.keyframe_tx.clone());
+                            }
                             let mut new_data = Some(rtp_track);
                             std::mem::swap(&mut *data, &mut new_data);
 
@@ -63,8 +200,18 @@ impl TrackLocal for EncoderTrackLocal {
     }
 
     async fn unbind(&self, t: &TrackLocalContext) -> Result<(), webrtc::Error> {
+        self.keyframe_router.unregister(t.ssrc());
         match &mut *self.rtp_track.lock().await {
-            Some(rtp_track) => rtp_track.unbind(t).await,
+            Some(rtp_track) => {
+                // The encoder is shared by every binding, so it keeps going until the last one
+                if *self.bindings.borrow() == 1 {
+                    self.flush_encoder().await;
+                }
+                rtp_track.unbind(t).await?;
+                self.bindings
+                    .send_modify(|bindings| *bindings = bindings.saturating_sub(1));
+                Ok(())
+            }
             None => Err(Error::ErrUnbindFailed),
         }
     }
@@ -90,27 +237,56 @@ impl EncoderTrackLocal {
     pub async fn new(
         encoder_builder: Box<dyn EncoderBuilder>,
         ice_connection_state: IceConnectionState,
-        bandwidth_estimate: TwccBandwidthEstimate,
+        bandwidth_sender: &TwccBandwidthSender,
+        remote_max_bitrates: RemoteMaxBitrates,
+        keyframe_router: KeyframeRequestRouter,
+        min_keyframe_interval: Duration,
     ) -> EncoderTrackLocal {
         let id = encoder_builder.id().to_owned();
         let stream_id = encoder_builder.stream_id().to_owned();
         let kind = encoder_builder.codec_type().into();
         let supported_codecs = encoder_builder.supported_codecs().to_vec();
 
-        let (tx, rx) = unbounded_channel();
+        let (flush_tx, flush_rx) = unbounded_channel();
+
+        let (encoder_keyframe_tx, encoder_keyframe_rx) = unbounded_channel();
+        let (keyframe_tx, keyframe_rx) = unbounded_channel();
+        tokio::spawn(limit_keyframe_requests(
+            keyframe_rx,
+            encoder_keyframe_tx,
+            min_keyframe_interval,
+            id.clone(),
+        ));
+
+        let metrics = EncoderMetrics::default();
+        let (bindings, _) = watch::channel(0);
+        let (enabled, _) = watch::channel(true);
 
+        let handles = EncoderHandles {
+            ice_connection_state,
+            bandwidth_estimate: bandwidth_sender.subscribe_for(encoder_builder.codec_type()),
+            track_enabled: TrackEnabled(enabled.subscribe()),
+            output_bitrate_reporter: bandwidth_sender.output_bitrate_reporter(),
+            packetization_mtu: bandwidth_sender.subscribe_mtu(),
+            flush_requests: FlushRequests(flush_rx),
+            keyframe_requests: KeyframeRequests(encoder_keyframe_rx),
+            metrics: metrics.clone(),
+            track_bindings: TrackBindings(bindings.subscribe()),
+        };
+
+        let (tx, rx) = unbounded_channel();
         tokio::spawn(async move {
-            pending_builder(
-                rx,
-                encoder_builder,
-                ice_connection_state,
-                bandwidth_estimate,
-            )
-            .await;
+            pending_builder(rx, encoder_builder, handles, remote_max_bitrates).await;
         });
 
         EncoderTrackLocal {
             tx,
+            flush_tx,
+            keyframe_tx,
+            keyframe_router,
+            enabled,
+            bindings,
+            metrics,
             rtp_track: Mutex::new(None),
             supported_codecs,
             id,
@@ -122,7 +298,7 @@ impl EncoderTrackLocal {
     pub async fn add_as_transceiver(
         self: Arc<EncoderTrackLocal>,
         pc: &RTCPeerConnection,
-    ) -> Result<(), webrtc::Error> {
+    ) -> Result<Arc<RTCRtpTransceiver>, webrtc::Error> {
         let transceiver = pc
             .add_transceiver_from_track(
                 self.clone(),
@@ -132,10 +308,92 @@ impl EncoderTrackLocal {
                 }],
             )
             .await?;
-        self.tx
-            .send(TrackLocalEvent::RtpTransceiver(transceiver))
-            .expect("Error while sending RTCRtpTransceiver");
-        Ok(())
+        // The encoder was already built if the track is shared with another subscriber
+        let _ = self
+            .tx
+            .send(TrackLocalEvent::RtpTransceiver(transceiver.clone()));
+        Ok(transceiver)
+    }
+
+    /// List of codecs that the encoder supports.
+    pub fn supported_codecs(&self) -> &[Codec] {
+        &self.supported_codecs
+    }
+
+    /// Pause or resume the output of the encoder without touching the transceiver.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.send_if_modified(|current| {
+            if *current == enabled {
+                false
+            } else {
+                *current = enabled;
+                true
+            }
+        });
+    }
+
+    /// Returns `true` if the encoder is allowed to send packets.
+    pub fn is_enabled(&self) -> bool {
+        *self.enabled.borrow()
+    }
+
+    /// The write metrics reported by the encoder.
+    pub fn stats(&self) -> EncoderStats {
+        self.metrics.stats()
+    }
+
+    /// MIME type of the codec the track was bound with, if it was bound.
+    pub(crate) async fn codec_mime_type(&self) -> Option<String> {
+        let rtp_track = self.rtp_track.lock().await;
+        rtp_track
+            .as_ref()
+            .map(|rtp_track| rtp_track.codec().mime_type)
+    }
+
+    /// Asks the encoder for a keyframe for a subscriber that joined late. Without support for
+    /// the requests, the subscriber waits for the next keyframe the encoder produces on its own.
+    fn request_keyframe(&self) {
+        // Only fails once the encoder turned out to not handle the requests
+        let _ = self.keyframe_tx.send(());
+    }
+
+    /// Lets the encoder write out what it has buffered. Returns immediately if the encoder does
+    /// not handle flush requests.
+    async fn flush_encoder(&self) {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        if self.flush_tx.send(ack_tx).is_ok() {
+            let _ = tokio::time::timeout(FLUSH_TIMEOUT, ack_rx).await;
+        }
+    }
+}
+
+/// Forwards the keyframe requests to the encoder, at most one every `min_interval`. Every request
+/// that comes in the meantime, e.g., the PLIs of a decoder that has yet to receive the keyframe,
+/// is coalesced into a single one at the end of the interval.
+async fn limit_keyframe_requests(
+    mut requests: UnboundedReceiver<()>,
+    encoder: UnboundedSender<()>,
+    min_interval: Duration,
+    id: String,
+) {
+    while requests.recv().await.is_some() {
+        loop {
+            // The requests that queued up together only need one keyframe
+            while requests.try_recv().is_ok() {}
+            // No fallback, see `EncoderContext::keyframe_requests`
+            if encoder.send(()).is_err() {
+                log::debug!(
+                    "Encoder of {id} does not handle keyframe requests, waiting for its next \
+                    keyframe"
+                );
+                return;
+            }
+
+            tokio::time::sleep(min_interval).await;
+            if requests.try_recv().is_err() {
+                break;
+            }
+        }
     }
 }
 
@@ -155,11 +413,24 @@ impl Debug for TrackLocalEvent {
     }
 }
 
+/// Parts of the [EncoderContext] that are known before the track is bound.
+struct EncoderHandles {
+    ice_connection_state: IceConnectionState,
+    bandwidth_estimate: TwccBandwidthEstimate,
+    track_enabled: TrackEnabled,
+    output_bitrate_reporter: OutputBitrateReporter,
+    packetization_mtu: PacketizationMtu,
+    flush_requests: FlushRequests,
+    keyframe_requests: KeyframeRequests,
+    metrics: EncoderMetrics,
+    track_bindings: TrackBindings,
+}
+
 async fn pending_builder(
     mut rx: UnboundedReceiver<TrackLocalEvent>,
     encoder_builder: Box<dyn EncoderBuilder>,
-    ice_connection_state: IceConnectionState,
-    bandwidth_estimate: TwccBandwidthEstimate,
+    handles: EncoderHandles,
+    remote_max_bitrates: RemoteMaxBitrates,
 ) {
     let mut rtp_track: Option<Arc<TrackLocalStaticRTP>> = None;
     let mut transceiver: Option<Arc<RTCRtpTransceiver>> = None;
@@ -171,16 +442,28 @@ async fn pending_builder(
             let codec_capability = rtp_track.codec();
             let transceiver = transceiver.unwrap();
             let (ssrc, payload_type) = rtp_params.unwrap();
+            let bandwidth_estimate = cap_bandwidth_estimate(
+                handles.bandwidth_estimate,
+                remote_max_bitrates,
+                payload_type,
+            );
 
-            encoder_builder.build(
+            encoder_builder.build(EncoderContext {
                 rtp_track,
                 transceiver,
-                ice_connection_state,
+                ice_connection_state: handles.ice_connection_state,
                 bandwidth_estimate,
+                track_enabled: handles.track_enabled,
                 codec_capability,
                 ssrc,
                 payload_type,
-            );
+                output_bitrate_reporter: handles.output_bitrate_reporter,
+                packetization_mtu: handles.packetization_mtu,
+                flush_requests: handles.flush_requests,
+                keyframe_requests: handles.keyframe_requests,
+                metrics: handles.metrics,
+                track_bindings: handles.track_bindings,
+            });
             break;
         } else {
             match rx.recv().await {
@@ -199,3 +482,444 @@ async fn pending_builder(
         }
     }
 }
+
+/// Caps `bandwidth_estimate` to the `x-google-max-bitrate` that the remote peer set for the codec
+/// with `payload_type`. Follows both the estimate and the remote descriptions until the encoder
+/// drops the returned receiver.
+fn cap_bandwidth_estimate(
+    mut bandwidth_estimate: TwccBandwidthEstimate,
+    mut remote_max_bitrates: RemoteMaxBitrates,
+    payload_type: u8,
+) -> TwccBandwidthEstimate {
+    let capped_estimate =
+        move |bandwidth_estimate: &mut TwccBandwidthEstimate,
+              remote_max_bitrates: &mut RemoteMaxBitrates| {
+            let estimate = *bandwidth_estimate.borrow_and_update();
+            match remote_max_bitrates.borrow_and_update().get(&payload_type) {
+                Some(max_bitrate) if max_bitrate.bits_per_sec() < estimate.bits_per_sec() => {
+                    *max_bitrate
+                }
+                _ => estimate,
+            }
+        };
+    let (tx, rx) = watch::channel(capped_estimate(
+        &mut bandwidth_estimate,
+        &mut remote_max_bitrates,
+    ));
+
+    tokio::spawn(async move {
+        let mut remote_open = true;
+        loop {
+            tokio::select! {
+                result = bandwidth_estimate.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                // Only fails once the peer is gone, after which the caps stay as they are
+                result = remote_max_bitrates.changed(), if remote_open => {
+                    remote_open = result.is_ok();
+                }
+                _ = tx.closed() => break,
+            }
+            let estimate = capped_estimate(&mut bandwidth_estimate, &mut remote_max_bitrates);
+            tx.send_if_modified(|current| {
+                let modified = *current != estimate;
+                *current = estimate;
+                modified
+            });
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codecs::CodecType, interceptor::twcc::twcc_bandwidth_estimate_channel,
+        network::data_rate::DataRate,
+    };
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use webrtc::{
+        api::{
+            media_engine::{MediaEngine, MIME_TYPE_VP8},
+            APIBuilder,
+        },
+        ice_transport::ice_connection_state::RTCIceConnectionState,
+        peer_connection::configuration::RTCConfiguration,
+        rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication,
+        rtp_transceiver::rtp_codec::RTCRtpCodecCapability,
+    };
+
+    const MIN_KEYFRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+    struct DummyEncoderBuilder;
+
+    impl EncoderBuilder for DummyEncoderBuilder {
+        fn id(&self) -> &str {
+            "dummy-video"
+        }
+
+        fn stream_id(&self) -> &str {
+            "dummy-webrtc"
+        }
+
+        fn codec_type(&self) -> CodecType {
+            CodecType::Video
+        }
+
+        fn supported_codecs(&self) -> &[Codec] {
+            &[]
+        }
+
+        fn build(self: Box<Self>, _context: EncoderContext) {}
+    }
+
+    /// Writes out its buffered packets when flushed.
+    struct BufferingEncoderBuilder {
+        codecs: Vec<Codec>,
+        buffered: usize,
+        written: Arc<AtomicUsize>,
+    }
+
+    impl EncoderBuilder for BufferingEncoderBuilder {
+        fn id(&self) -> &str {
+            "buffering-video"
+        }
+
+        fn stream_id(&self) -> &str {
+            "buffering-webrtc"
+        }
+
+        fn codec_type(&self) -> CodecType {
+            CodecType::Video
+        }
+
+        fn supported_codecs(&self) -> &[Codec] {
+            &self.codecs
+        }
+
+        fn build(self: Box<Self>, context: EncoderContext) {
+            let mut flush_requests = context.flush_requests;
+            let buffered = self.buffered;
+            let written = self.written;
+            tokio::spawn(async move {
+                while let Some(ack) = flush_requests.recv().await {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    written.fetch_add(buffered, Ordering::AcqRel);
+                    ack.done();
+                }
+            });
+        }
+    }
+
+    /// Counts the keyframe requests.
+    struct KeyframeEncoderBuilder {
+        codecs: Vec<Codec>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl EncoderBuilder for KeyframeEncoderBuilder {
+        fn id(&self) -> &str {
+            "keyframe-video"
+        }
+
+        fn stream_id(&self) -> &str {
+            "keyframe-webrtc"
+        }
+
+        fn codec_type(&self) -> CodecType {
+            CodecType::Video
+        }
+
+        fn supported_codecs(&self) -> &[Codec] {
+            &self.codecs
+        }
+
+        fn build(self: Box<Self>, context: EncoderContext) {
+            let mut keyframe_requests = context.keyframe_requests;
+            let requests = self.requests;
+            tokio::spawn(async move {
+                while keyframe_requests.recv().await.is_some() {
+                    requests.fetch_add(1, Ordering::AcqRel);
+                }
+            });
+        }
+    }
+
+    /// Negotiates the track with a new remote peer connection. The track is bound once the
+    /// answer is applied.
+    async fn subscribe(track: Arc<EncoderTrackLocal>) -> [RTCPeerConnection; 2] {
+        let mut media_engine = MediaEngine::default();
+        media_engine.register_default_codecs().unwrap();
+        let api = APIBuilder::new().with_media_engine(media_engine).build();
+
+        let pc = api
+            .new_peer_connection(RTCConfiguration::default())
+            .await
+            .unwrap();
+        track.add_as_transceiver(&pc).await.unwrap();
+        let remote_pc = api
+            .new_peer_connection(RTCConfiguration::default())
+            .await
+            .unwrap();
+
+        let offer = pc.create_offer(None).await.unwrap();
+        pc.set_local_description(offer.clone()).await.unwrap();
+        remote_pc.set_remote_description(offer).await.unwrap();
+        let answer = remote_pc.create_answer(None).await.unwrap();
+        remote_pc
+            .set_local_description(answer.clone())
+            .await
+            .unwrap();
+        pc.set_remote_description(answer).await.unwrap();
+        [pc, remote_pc]
+    }
+
+    /// VP8, which the remote peer connections of [subscribe] accept.
+    fn vp8() -> Codec {
+        let params = RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_VP8.to_owned(),
+                clock_rate: 90000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        Codec::new(params, CodecType::Video)
+    }
+
+    /// Creates a track for the encoder, without an ICE connection or a bandwidth estimate behind
+    /// it.
+    async fn new_track(
+        encoder_builder: impl EncoderBuilder + 'static,
+        keyframe_router: KeyframeRequestRouter,
+    ) -> Arc<EncoderTrackLocal> {
+        let (bandwidth_sender, _) = twcc_bandwidth_estimate_channel(DataRate::default());
+        let track = EncoderTrackLocal::new(
+            Box::new(encoder_builder),
+            watch::channel(RTCIceConnectionState::default()).1,
+            &bandwidth_sender,
+            watch::channel(HashMap::new()).1,
+            keyframe_router,
+            MIN_KEYFRAME_INTERVAL,
+        )
+        .await;
+        Arc::new(track)
+    }
+
+    #[tokio::test]
+    async fn keyframe_on_late_bind() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let builder = KeyframeEncoderBuilder {
+            codecs: vec![vp8()],
+            requests: requests.clone(),
+        };
+        let track = new_track(builder, KeyframeRequestRouter::default()).await;
+
+        // The encoder starts with a keyframe anyway
+        let first = subscribe(track.clone()).await;
+        assert!(track.codec_mime_type().await.is_some());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(requests.load(Ordering::Acquire), 0);
+
+        let second = subscribe(track.clone()).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(requests.load(Ordering::Acquire), 1);
+
+        for pc in first.iter().chain(&second) {
+            pc.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn keyframe_requests_coalesced() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let builder = KeyframeEncoderBuilder {
+            codecs: vec![vp8()],
+            requests: requests.clone(),
+        };
+        let router = KeyframeRequestRouter::default();
+        let track = new_track(builder, router.clone()).await;
+        let pcs = subscribe(track.clone()).await;
+        let sender = pcs[0].get_senders().await.remove(0);
+        let pli = PictureLossIndication {
+            sender_ssrc: 1,
+            media_ssrc: sender.get_parameters().await.encodings[0].ssrc,
+        };
+
+        for _ in 0..10 {
+            router.observe(&pli);
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(requests.load(Ordering::Acquire), 1);
+
+        // A decoder that keeps asking until the keyframe reaches it gets one more at most
+        for _ in 0..10 {
+            router.observe(&pli);
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(requests.load(Ordering::Acquire), 1);
+        tokio::time::sleep(MIN_KEYFRAME_INTERVAL).await;
+        assert_eq!(requests.load(Ordering::Acquire), 2);
+
+        for pc in &pcs {
+            pc.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn bindings_follow_subscribers() {
+        let builder = KeyframeEncoderBuilder {
+            codecs: vec![vp8()],
+            requests: Arc::new(AtomicUsize::new(0)),
+        };
+        let track = new_track(builder, KeyframeRequestRouter::default()).await;
+        let mut bindings = TrackBindings(track.bindings.subscribe());
+        assert!(!bindings.is_bound());
+
+        let first = subscribe(track.clone()).await;
+        let bound = tokio::time::timeout(Duration::from_secs(1), bindings.wait_until_bound()).await;
+        assert!(bound.is_ok());
+        let second = subscribe(track.clone()).await;
+        assert_eq!(*bindings.0.borrow(), 2);
+
+        // Closing the peer connection stops the sender, which unbinds the track
+        bindings.mark_unchanged();
+        for pc in &first {
+            pc.close().await.unwrap();
+        }
+        assert!(bindings.has_changed());
+        assert_eq!(*bindings.0.borrow(), 1);
+        for pc in &second {
+            pc.close().await.unwrap();
+        }
+        assert!(!bindings.is_bound());
+    }
+
+    #[tokio::test]
+    async fn flush_before_unbind() {
+        let written = Arc::new(AtomicUsize::new(0));
+        let builder = BufferingEncoderBuilder {
+            codecs: vec![vp8()],
+            buffered: 3,
+            written: written.clone(),
+        };
+        let track = new_track(builder, KeyframeRequestRouter::default()).await;
+
+        // Closing the peer connection stops the sender, which unbinds the track
+        let pcs = subscribe(track).await;
+        assert_eq!(written.load(Ordering::Acquire), 0);
+        for pc in &pcs {
+            pc.close().await.unwrap();
+        }
+        assert_eq!(written.load(Ordering::Acquire), 3);
+
+        // Encoders that ignore the requests do not hold up unbinding
+        let builder = KeyframeEncoderBuilder {
+            codecs: vec![vp8()],
+            requests: Arc::new(AtomicUsize::new(0)),
+        };
+        let track = new_track(builder, KeyframeRequestRouter::default()).await;
+        let pcs = subscribe(track).await;
+        let closed = tokio::time::timeout(FLUSH_TIMEOUT / 2, pcs[0].close()).await;
+        assert!(closed.is_ok());
+        pcs[1].close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_on_last_unbind() {
+        let written = Arc::new(AtomicUsize::new(0));
+        let builder = BufferingEncoderBuilder {
+            codecs: vec![vp8()],
+            buffered: 3,
+            written: written.clone(),
+        };
+        let track = new_track(builder, KeyframeRequestRouter::default()).await;
+
+        // The remaining subscriber still gets the rest of the frame
+        let first = subscribe(track.clone()).await;
+        let second = subscribe(track).await;
+        for pc in &first {
+            pc.close().await.unwrap();
+        }
+        assert_eq!(written.load(Ordering::Acquire), 0);
+        for pc in &second {
+            pc.close().await.unwrap();
+        }
+        assert_eq!(written.load(Ordering::Acquire), 3);
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume() {
+        let track = new_track(DummyEncoderBuilder, KeyframeRequestRouter::default()).await;
+
+        // Mimics the send loop of an encoder
+        let mut track_enabled = TrackEnabled(track.enabled.subscribe());
+        let num_sent = Arc::new(AtomicUsize::new(0));
+        let num_sent_clone = num_sent.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(1));
+            loop {
+                interval.tick().await;
+                if track_enabled.wait_until_enabled().await.is_err() {
+                    break;
+                }
+                num_sent_clone.fetch_add(1, Ordering::AcqRel);
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(num_sent.load(Ordering::Acquire) > 0);
+
+        track.set_enabled(false);
+        assert!(!track.is_enabled());
+        // Let the loop observe the change
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let paused_count = num_sent.load(Ordering::Acquire);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(num_sent.load(Ordering::Acquire), paused_count);
+
+        track.set_enabled(true);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(num_sent.load(Ordering::Acquire) > paused_count);
+    }
+
+    #[tokio::test]
+    async fn estimate_capped_per_codec() {
+        let (bwe_tx, bwe_rx) = watch::channel(DataRate::from_bits_per_sec(2_000_000));
+        let (max_bitrates_tx, max_bitrates_rx) = watch::channel(HashMap::new());
+        max_bitrates_tx.send_replace(HashMap::from([
+            (96, DataRate::from_bits_per_sec(800_000)),
+            (97, DataRate::from_bits_per_sec(3_000_000)),
+        ]));
+
+        let mut vp8 = cap_bandwidth_estimate(bwe_rx.clone(), max_bitrates_rx.clone(), 96);
+        let h264 = cap_bandwidth_estimate(bwe_rx.clone(), max_bitrates_rx.clone(), 97);
+        let opus = cap_bandwidth_estimate(bwe_rx, max_bitrates_rx, 111);
+        assert_eq!(vp8.borrow().bits_per_sec(), 800_000);
+        assert_eq!(h264.borrow().bits_per_sec(), 2_000_000);
+        assert_eq!(opus.borrow().bits_per_sec(), 2_000_000);
+
+        // Follows the estimate below the cap
+        bwe_tx.send_replace(DataRate::from_bits_per_sec(500_000));
+        let changed = tokio::time::timeout(Duration::from_secs(1), vp8.changed()).await;
+        assert!(changed.is_ok());
+        assert_eq!(vp8.borrow_and_update().bits_per_sec(), 500_000);
+
+        // And a renegotiation that lifts the cap
+        bwe_tx.send_replace(DataRate::from_bits_per_sec(2_000_000));
+        max_bitrates_tx.send_replace(HashMap::new());
+        let lifted = tokio::time::timeout(Duration::from_secs(1), async {
+            while vp8.borrow_and_update().bits_per_sec() != 2_000_000 {
+                vp8.changed().await.unwrap();
+            }
+        })
+        .await;
+        assert!(lifted.is_ok());
+    }
+}
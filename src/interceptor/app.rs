@@ -0,0 +1,243 @@
+//! Application-defined RTCP packets, i.e., RTCP APP from
+//! [RFC3550 Section 6.7](https://www.rfc-editor.org/rfc/rfc3550#section-6.7).
+
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::sync::Arc;
+use webrtc::{
+    interceptor::{
+        self, stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtcp::{self, header::PacketType, raw_packet::RawPacket},
+};
+
+const APP_HEADER_SIZE: usize = 12;
+
+/// Callback invoked for every RTCP APP packet received.
+pub type OnRtcpAppHdlrFn = Box<dyn Fn(ApplicationDefined) + Send + Sync>;
+
+/// An RTCP APP packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplicationDefined {
+    /// 5-bit subtype that can be used for defining a set of APP packets under one name.
+    pub subtype: u8,
+    /// SSRC of the originator of the packet.
+    pub ssrc: u32,
+    /// Four ASCII characters identifying the application.
+    pub name: [u8; 4],
+    /// Application-dependent data. Padded with zeroes to a multiple of 4 bytes when sent.
+    pub data: Bytes,
+}
+
+impl ApplicationDefined {
+    /// Serialize into an [RawPacket] that could be passed to `RTCPeerConnection::write_rtcp`.
+    pub fn to_raw_packet(&self) -> RawPacket {
+        let padding = (4 - self.data.len() % 4) % 4;
+        let total_len = APP_HEADER_SIZE + self.data.len() + padding;
+
+        let mut buf = BytesMut::with_capacity(total_len);
+        // V=2, P=0
+        buf.put_u8(0x80 | (self.subtype & 0x1f));
+        buf.put_u8(PacketType::ApplicationDefined as u8);
+        // Length in 32-bit words minus one
+        buf.put_u16((total_len / 4 - 1) as u16);
+        buf.put_u32(self.ssrc);
+        buf.put_slice(&self.name);
+        buf.put_slice(&self.data);
+        buf.put_bytes(0, padding);
+
+        RawPacket(buf.freeze())
+    }
+
+    /// Parse an APP packet from a [RawPacket]. Returns `None` if it's not an APP packet.
+    pub fn from_raw_packet(raw_packet: &RawPacket) -> Option<ApplicationDefined> {
+        let buf = &raw_packet.0;
+        if buf.len() < APP_HEADER_SIZE || buf[1] != PacketType::ApplicationDefined as u8 {
+            return None;
+        }
+
+        let mut end = 4 * (u16::from_be_bytes([buf[2], buf[3]]) as usize + 1);
+        if end > buf.len() {
+            return None;
+        }
+
+        // Padding bit
+        if buf[0] & 0x20 != 0 {
+            let padding = buf[end - 1] as usize;
+            if padding > end - APP_HEADER_SIZE {
+                return None;
+            }
+            end -= padding;
+        }
+
+        Some(ApplicationDefined {
+            subtype: buf[0] & 0x1f,
+            ssrc: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            name: [buf[8], buf[9], buf[10], buf[11]],
+            data: buf.slice(APP_HEADER_SIZE..end),
+        })
+    }
+}
+
+pub struct RtcpAppStream {
+    handler: Arc<OnRtcpAppHdlrFn>,
+    next_reader: Arc<dyn RTCPReader + Send + Sync>,
+}
+
+#[async_trait]
+impl RTCPReader for RtcpAppStream {
+    async fn read(
+        &self,
+        buf: &mut [u8],
+        attributes: &Attributes,
+    ) -> Result<(usize, Attributes), interceptor::Error> {
+        let (n, attr) = self.next_reader.read(buf, attributes).await?;
+
+        let mut b = &buf[..n];
+        let packets = rtcp::packet::unmarshal(&mut b)?;
+        for packet in packets {
+            if let Some(raw_packet) = packet.as_any().downcast_ref::<RawPacket>() {
+                if let Some(app) = ApplicationDefined::from_raw_packet(raw_packet) {
+                    (self.handler)(app);
+                }
+            }
+        }
+
+        Ok((n, attr))
+    }
+}
+
+pub struct RtcpAppInterceptor {
+    handler: Arc<OnRtcpAppHdlrFn>,
+}
+
+#[async_trait]
+impl Interceptor for RtcpAppInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        Arc::new(RtcpAppStream {
+            handler: self.handler.clone(),
+            next_reader: reader,
+        })
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn bind_local_stream(
+        &self,
+        _info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn unbind_local_stream(&self, _info: &StreamInfo) {}
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        reader
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> Result<(), interceptor::Error> {
+        Ok(())
+    }
+}
+
+pub struct RtcpAppInterceptorBuilder {
+    handler: Arc<OnRtcpAppHdlrFn>,
+}
+
+impl RtcpAppInterceptorBuilder {
+    pub fn new(handler: OnRtcpAppHdlrFn) -> RtcpAppInterceptorBuilder {
+        RtcpAppInterceptorBuilder {
+            handler: Arc::new(handler),
+        }
+    }
+}
+
+impl InterceptorBuilder for RtcpAppInterceptorBuilder {
+    fn build(&self, _id: &str) -> Result<Arc<dyn Interceptor + Send + Sync>, interceptor::Error> {
+        Ok(Arc::new(RtcpAppInterceptor {
+            handler: self.handler.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interceptor::tests::DummyRtcpReader;
+    use std::sync::Mutex;
+    use webrtc::rtcp::source_description::{SourceDescription, SourceDescriptionChunk};
+
+    #[test]
+    fn marshal_unmarshal() {
+        for len in 0..8 {
+            let app = ApplicationDefined {
+                subtype: 3,
+                ssrc: 0xdeadbeef,
+                name: *b"TEST",
+                data: Bytes::from(vec![42u8; len]),
+            };
+            let raw_packet = app.to_raw_packet();
+            assert_eq!(raw_packet.0.len() % 4, 0);
+
+            let parsed = ApplicationDefined::from_raw_packet(&raw_packet).unwrap();
+            assert_eq!(parsed.subtype, app.subtype);
+            assert_eq!(parsed.ssrc, app.ssrc);
+            assert_eq!(parsed.name, app.name);
+            // Padding added when serializing is indistinguishable from the data
+            assert_eq!(&parsed.data[..len], &app.data[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn receive_app_packet() {
+        let app = ApplicationDefined {
+            subtype: 0,
+            ssrc: 1234,
+            name: *b"TEST",
+            data: Bytes::from_static(b"payload!"),
+        };
+
+        let sdes = SourceDescription {
+            chunks: vec![SourceDescriptionChunk {
+                source: 1234,
+                items: Vec::new(),
+            }],
+        };
+        let compound: Vec<Box<dyn rtcp::packet::Packet + Send + Sync>> =
+            vec![Box::new(sdes), Box::new(app.to_raw_packet())];
+        let data = rtcp::packet::marshal(&compound).unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        let builder = RtcpAppInterceptorBuilder::new(Box::new(move |app| {
+            received_clone.lock().unwrap().push(app);
+        }));
+        let interceptor = builder.build("").unwrap();
+        let reader = interceptor
+            .bind_rtcp_reader(Arc::new(DummyRtcpReader::new(data)))
+            .await;
+
+        let mut buf = vec![0u8; 1500];
+        reader.read(&mut buf, &Attributes::new()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0], app);
+    }
+}
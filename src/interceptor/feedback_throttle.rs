@@ -0,0 +1,231 @@
+//! Rate limiting of the outgoing NACKs and keyframe requests. On a very lossy link, the feedback
+//! about the losses can itself add to the congestion of the uplink.
+
+use crate::network::data_rate::DataRate;
+use async_trait::async_trait;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use webrtc::{
+    interceptor::{
+        self, stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtcp::{
+        self,
+        payload_feedbacks::{
+            full_intra_request::FullIntraRequest, picture_loss_indication::PictureLossIndication,
+        },
+        transport_feedbacks::transport_layer_nack::TransportLayerNack,
+    },
+};
+
+/// Unused budget saved up while no feedback is sent, so that the feedback for a single burst of
+/// losses still goes out in full.
+const BURST_DURATION: Duration = Duration::from_millis(250);
+
+/// Token bucket of the feedback bytes that can be sent.
+struct FeedbackBudget {
+    bytes_per_sec: f64,
+    max_bytes: f64,
+    // Goes negative when a packet larger than the remaining budget is let through
+    bytes: f64,
+    last_update: Instant,
+}
+
+impl FeedbackBudget {
+    fn new(max_rate: DataRate, now: Instant) -> FeedbackBudget {
+        let bytes_per_sec = max_rate.bytes_per_sec_f64();
+        let max_bytes = bytes_per_sec * BURST_DURATION.as_secs_f64();
+        FeedbackBudget {
+            bytes_per_sec,
+            max_bytes,
+            bytes: max_bytes,
+            last_update: now,
+        }
+    }
+
+    /// Spend `size` bytes of the budget. Returns `false` if there is none left. A packet is let
+    /// through while any budget is left, even if it is larger, so that no packet is too large to
+    /// ever be sent.
+    fn spend(&mut self, size: usize, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.bytes = (self.bytes + self.bytes_per_sec * elapsed.as_secs_f64()).min(self.max_bytes);
+        self.last_update = now;
+
+        if self.bytes <= 0.0 {
+            return false;
+        }
+        self.bytes -= size as f64;
+        true
+    }
+}
+
+/// Separate budgets for the NACKs and the keyframe requests, so that a flood of NACKs cannot
+/// starve the much rarer keyframe requests.
+struct FeedbackBudgets {
+    nack: FeedbackBudget,
+    keyframe_request: FeedbackBudget,
+}
+
+impl FeedbackBudgets {
+    fn new(max_rate: DataRate, now: Instant) -> FeedbackBudgets {
+        FeedbackBudgets {
+            nack: FeedbackBudget::new(max_rate, now),
+            keyframe_request: FeedbackBudget::new(max_rate, now),
+        }
+    }
+
+    /// The budget that `packet` is sent from, if it is throttled at all.
+    fn budget_for(
+        &mut self,
+        packet: &(dyn rtcp::packet::Packet + Send + Sync),
+    ) -> Option<&mut FeedbackBudget> {
+        let packet = packet.as_any();
+        if packet.is::<TransportLayerNack>() {
+            Some(&mut self.nack)
+        } else if packet.is::<PictureLossIndication>() || packet.is::<FullIntraRequest>() {
+            Some(&mut self.keyframe_request)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct FeedbackThrottleWriter {
+    budgets: Arc<Mutex<FeedbackBudgets>>,
+    next_writer: Arc<dyn RTCPWriter + Send + Sync>,
+}
+
+#[async_trait]
+impl RTCPWriter for FeedbackThrottleWriter {
+    async fn write(
+        &self,
+        pkts: &[Box<dyn rtcp::packet::Packet + Send + Sync>],
+        attributes: &Attributes,
+    ) -> Result<usize, interceptor::Error> {
+        let now = Instant::now();
+        let mut dropped = 0;
+        let allowed: Vec<_> = {
+            let mut budgets = self.budgets.lock().unwrap();
+            pkts.iter()
+                .filter(|packet| {
+                    let allowed = match budgets.budget_for(packet.as_ref()) {
+                        Some(budget) => budget.spend(packet.marshal_size(), now),
+                        None => true,
+                    };
+                    if !allowed {
+                        dropped += 1;
+                    }
+                    allowed
+                })
+                .map(|packet| packet.cloned())
+                .collect()
+        };
+
+        if dropped > 0 {
+            log::trace!("Dropped {dropped} RTCP feedback packets over the rate limit");
+        }
+        if allowed.is_empty() {
+            return Ok(0);
+        }
+        self.next_writer.write(&allowed, attributes).await
+    }
+}
+
+pub struct FeedbackThrottleInterceptor {
+    budgets: Arc<Mutex<FeedbackBudgets>>,
+}
+
+#[async_trait]
+impl Interceptor for FeedbackThrottleInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        reader
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        Arc::new(FeedbackThrottleWriter {
+            budgets: self.budgets.clone(),
+            next_writer: writer,
+        })
+    }
+
+    async fn bind_local_stream(
+        &self,
+        _info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn unbind_local_stream(&self, _info: &StreamInfo) {}
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        reader
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> Result<(), interceptor::Error> {
+        Ok(())
+    }
+}
+
+/// Drops the outgoing NACKs, PLIs, and FIRs that exceed `max_rate`. The NACKs and the keyframe
+/// requests are limited to `max_rate` each. Every other RTCP packet is sent as is.
+pub struct FeedbackThrottleInterceptorBuilder {
+    max_rate: DataRate,
+}
+
+impl FeedbackThrottleInterceptorBuilder {
+    pub fn new(max_rate: DataRate) -> FeedbackThrottleInterceptorBuilder {
+        FeedbackThrottleInterceptorBuilder { max_rate }
+    }
+}
+
+impl InterceptorBuilder for FeedbackThrottleInterceptorBuilder {
+    fn build(&self, _id: &str) -> Result<Arc<dyn Interceptor + Send + Sync>, interceptor::Error> {
+        Ok(Arc::new(FeedbackThrottleInterceptor {
+            budgets: Arc::new(Mutex::new(FeedbackBudgets::new(
+                self.max_rate,
+                Instant::now(),
+            ))),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_refills_at_max_rate() {
+        // 1000 bytes per second, up to 250 bytes saved up
+        let start = Instant::now();
+        let mut budget = FeedbackBudget::new(DataRate::from_bits_per_sec(8000), start);
+
+        // The saved up budget is spent with the last packet going over it
+        assert_eq!((0..4).filter(|_| budget.spend(100, start)).count(), 3);
+
+        // Paying off the 50 bytes overspent takes 50 ms
+        let now = start + Duration::from_millis(40);
+        assert!(!budget.spend(100, now));
+        let now = start + Duration::from_millis(60);
+        assert!(budget.spend(100, now));
+
+        // Idling longer than the burst duration does not save up more
+        let now = now + Duration::from_secs(10);
+        assert_eq!((0..4).filter(|_| budget.spend(100, now)).count(), 3);
+    }
+}
@@ -0,0 +1,95 @@
+//! Routing of the received PLIs and FIRs to the encoders of the streams they ask a keyframe for.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::UnboundedSender;
+use webrtc::rtcp::{
+    self,
+    payload_feedbacks::{
+        full_intra_request::FullIntraRequest, picture_loss_indication::PictureLossIndication,
+    },
+};
+
+/// Where the keyframe requests for each local stream go, by SSRC.
+#[derive(Debug, Clone, Default)]
+pub struct KeyframeRequestRouter(Arc<Mutex<HashMap<u32, UnboundedSender<()>>>>);
+
+impl KeyframeRequestRouter {
+    /// Send the keyframe requests for the stream with `ssrc` through `requests`.
+    pub(crate) fn register(&self, ssrc: u32, requests: UnboundedSender<()>) {
+        self.0.lock().unwrap().insert(ssrc, requests);
+    }
+
+    /// Stop sending the keyframe requests for the stream with `ssrc`.
+    pub(crate) fn unregister(&self, ssrc: u32) {
+        self.0.lock().unwrap().remove(&ssrc);
+    }
+
+    pub(crate) fn observe(&self, packet: &dyn rtcp::packet::Packet) {
+        let packet = packet.as_any();
+        if let Some(pli) = packet.downcast_ref::<PictureLossIndication>() {
+            self.request(pli.media_ssrc);
+        } else if let Some(fir) = packet.downcast_ref::<FullIntraRequest>() {
+            // The media SSRC of a FIR is unused, the streams are in the entries
+            for entry in &fir.fir {
+                self.request(entry.ssrc);
+            }
+        }
+    }
+
+    fn request(&self, ssrc: u32) {
+        if let Some(requests) = self.0.lock().unwrap().get(&ssrc) {
+            let _ = requests.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+    use webrtc::rtcp::payload_feedbacks::full_intra_request::FirEntry;
+
+    const SSRC: u32 = 1234;
+
+    #[test]
+    fn pli_and_fir() {
+        let router = KeyframeRequestRouter::default();
+        let (tx, mut rx) = unbounded_channel();
+        router.register(SSRC, tx);
+
+        router.observe(&PictureLossIndication {
+            sender_ssrc: 1,
+            media_ssrc: SSRC,
+        });
+        router.observe(&FullIntraRequest {
+            sender_ssrc: 1,
+            media_ssrc: 0,
+            fir: vec![
+                FirEntry {
+                    ssrc: SSRC,
+                    sequence_number: 0,
+                },
+                FirEntry {
+                    ssrc: SSRC + 1,
+                    sequence_number: 0,
+                },
+            ],
+        });
+        // Not for a registered stream
+        router.observe(&PictureLossIndication {
+            sender_ssrc: 1,
+            media_ssrc: SSRC + 1,
+        });
+        assert_eq!(std::iter::from_fn(|| rx.try_recv().ok()).count(), 2);
+
+        router.unregister(SSRC);
+        router.observe(&PictureLossIndication {
+            sender_ssrc: 1,
+            media_ssrc: SSRC,
+        });
+        assert!(rx.try_recv().is_err());
+    }
+}
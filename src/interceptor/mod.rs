@@ -1,15 +1,437 @@
+pub mod app;
+pub mod feedback_throttle;
+pub mod keyframe_request;
+pub mod observer;
+pub mod pacer;
+pub mod sender_report;
 pub mod twcc;
 
 use crate::network::data_rate::DataRate;
-use twcc::{twcc_bandwidth_estimate_channel, TwccBandwidthEstimate, TwccInterceptorBuilder};
-use webrtc::{error::Result, interceptor::registry::Registry};
+use app::{OnRtcpAppHdlrFn, RtcpAppInterceptorBuilder};
+use feedback_throttle::FeedbackThrottleInterceptorBuilder;
+use keyframe_request::KeyframeRequestRouter;
+use observer::{OnRtcpHdlrFn, RtcpObserverInterceptorBuilder};
+use pacer::PacerInterceptorBuilder;
+use sender_report::SenderReports;
+use std::time::Duration;
+use twcc::{
+    twcc_bandwidth_estimate_channel, DelayBasedConfig, RembInterceptorBuilder,
+    TwccBandwidthEstimate, TwccBandwidthSender, TwccInterceptorBuilder,
+};
+use webrtc::{
+    api::media_engine::MediaEngine,
+    error::Result,
+    interceptor::{registry::Registry, twcc::receiver::Receiver},
+    rtp_transceiver::{
+        rtp_codec::{RTCRtpHeaderExtensionCapability, RTPCodecType},
+        RTCPFeedback, TYPE_RTCP_FB_TRANSPORT_CC,
+    },
+    sdp,
+};
 
+/// Register the custom TWCC sender-side bandwidth estimator.
+///
+/// This must be called before `webrtc::api::interceptor_registry::configure_twcc` (or anything
+/// else that registers the transport-cc header extension interceptor) so that the extension is
+/// already set when the send time of each packet is recorded.
+///
+/// The bandwidth estimate can be received through [TwccBandwidthSender::subscribe].
 pub fn configure_custom_twcc_sender(
-    mut registry: Registry,
+    registry: Registry,
     init_bandwidth: DataRate,
-) -> Result<(Registry, TwccBandwidthEstimate)> {
-    let (tx, rx) = twcc_bandwidth_estimate_channel(init_bandwidth);
-    let builder = TwccInterceptorBuilder::new(tx);
+    delay_based_config: DelayBasedConfig,
+) -> Result<(Registry, TwccBandwidthSender)> {
+    let (tx, _rx) = twcc_bandwidth_estimate_channel(init_bandwidth);
+    let registry = register_custom_twcc_sender(registry, tx.clone(), delay_based_config)?;
+    Ok((registry, tx))
+}
+
+/// Same as [configure_custom_twcc_sender] but sends the estimate through an existing
+/// [TwccBandwidthSender], e.g., one already passed to the interceptors registered before.
+pub(crate) fn register_custom_twcc_sender(
+    mut registry: Registry,
+    bandwidth_sender: TwccBandwidthSender,
+    delay_based_config: DelayBasedConfig,
+) -> Result<Registry> {
+    let builder = TwccInterceptorBuilder::try_new(bandwidth_sender, delay_based_config)
+        .map_err(|e| webrtc::Error::new(e.to_string()))?;
     registry.add(Box::new(builder));
-    Ok((registry, rx))
+    Ok(registry)
+}
+
+/// Generate TWCC feedback for the remote streams every `feedback_interval`.
+///
+/// Same as `webrtc::api::interceptor_registry::configure_twcc_receiver_only` except for the
+/// interval, which is fixed at 100 ms there. Sparser feedback makes the remote's bandwidth
+/// estimate react slower while denser feedback uses more of the uplink.
+pub fn configure_twcc_receiver(
+    mut registry: Registry,
+    media_engine: &mut MediaEngine,
+    feedback_interval: Duration,
+) -> Result<Registry> {
+    for codec_type in [RTPCodecType::Video, RTPCodecType::Audio] {
+        media_engine.register_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_TRANSPORT_CC.to_owned(),
+                ..Default::default()
+            },
+            codec_type,
+        );
+        media_engine.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+            },
+            codec_type,
+            None,
+        )?;
+    }
+
+    registry.add(Box::new(
+        Receiver::builder().with_interval(feedback_interval),
+    ));
+    Ok(registry)
+}
+
+/// Generate REMB packets for the remote streams that negotiated `goog-remb`.
+pub fn configure_remb_receiver(mut registry: Registry) -> Result<Registry> {
+    registry.add(Box::new(RembInterceptorBuilder::new()));
+    Ok(registry)
+}
+
+/// Invoke `handler` for every RTCP APP packet received.
+pub fn configure_rtcp_app(mut registry: Registry, handler: OnRtcpAppHdlrFn) -> Result<Registry> {
+    registry.add(Box::new(RtcpAppInterceptorBuilder::new(handler)));
+    Ok(registry)
+}
+
+/// Invoke `handler` for every RTCP packet received. The packets are only observed and are still
+/// passed on to the rest of the interceptors, including the bandwidth estimator.
+pub fn configure_rtcp_observer(mut registry: Registry, handler: OnRtcpHdlrFn) -> Result<Registry> {
+    registry.add(Box::new(RtcpObserverInterceptorBuilder::new(handler)));
+    Ok(registry)
+}
+
+/// Limit the outgoing NACKs, PLIs, and FIRs to `max_rate`, dropping the rest. The NACKs and the
+/// keyframe requests have a budget of `max_rate` each, so the NACKs cannot crowd out the PLIs.
+///
+/// This must be called before `webrtc::api::interceptor_registry::configure_nack` since the NACK
+/// generator only writes through the interceptors registered before it. Keyframe requests written
+/// through `RTCPeerConnection::write_rtcp` go through every interceptor.
+pub fn configure_feedback_throttle(mut registry: Registry, max_rate: DataRate) -> Registry {
+    registry.add(Box::new(FeedbackThrottleInterceptorBuilder::new(max_rate)));
+    registry
+}
+
+/// Pace the outgoing RTP packets to `bandwidth_estimate` and send the retransmissions of NACKed
+/// packets ahead of the new packets waiting for the budget, using up to `retransmission_share` of
+/// the estimate for them. Retransmissions over that are paced like new packets. The rate of the
+/// retransmissions that skip ahead and of the RTCP is taken out of the budget for the new packets.
+///
+/// This must be called before `webrtc::api::interceptor_registry::configure_nack` since the NACK
+/// responder only writes the retransmissions through the interceptors registered before it. The
+/// TWCC sender should use [DelayBasedConfig::with_late_send_timestamps] so that the wait in the
+/// pacer is not mistaken for network delay.
+pub fn configure_pacer(
+    mut registry: Registry,
+    bandwidth_estimate: TwccBandwidthEstimate,
+    retransmission_share: f64,
+) -> Registry {
+    registry.add(Box::new(PacerInterceptorBuilder::new(
+        bandwidth_estimate,
+        retransmission_share,
+    )));
+    registry
+}
+
+/// Keep the RTP/NTP timestamp mapping from the latest sender report of each remote stream. The
+/// reports themselves are generated by `webrtc::api::interceptor_registry::configure_rtcp_reports`.
+pub fn configure_sender_reports(mut registry: Registry) -> (Registry, SenderReports) {
+    let sender_reports = SenderReports::default();
+    let observed = sender_reports.clone();
+    registry.add(Box::new(RtcpObserverInterceptorBuilder::new(Box::new(
+        move |packet| observed.observe(packet),
+    ))));
+    (registry, sender_reports)
+}
+
+/// Route the received PLIs and FIRs to the keyframe requests of the local streams registered with
+/// the returned [KeyframeRequestRouter].
+pub fn configure_keyframe_requests(mut registry: Registry) -> (Registry, KeyframeRequestRouter) {
+    let router = KeyframeRequestRouter::default();
+    let observed = router.clone();
+    registry.add(Box::new(RtcpObserverInterceptorBuilder::new(Box::new(
+        move |packet| observed.observe(packet),
+    ))));
+    (registry, router)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    };
+    use tokio::time::Instant;
+    use webrtc::{
+        api::interceptor_registry::configure_nack,
+        interceptor::{
+            self,
+            stream_info::{RTCPFeedback, RTPHeaderExtension, StreamInfo},
+            Attributes, RTCPReader, RTCPWriter, RTPReader, RTPWriter,
+        },
+        rtcp::{
+            self,
+            payload_feedbacks::picture_loss_indication::PictureLossIndication,
+            transport_feedbacks::{
+                transport_layer_cc::TransportLayerCc, transport_layer_nack::TransportLayerNack,
+            },
+        },
+        rtp::{self, extension::transport_cc_extension::TransportCcExtension},
+        util::{Marshal, MarshalSize},
+    };
+
+    const HDR_EXT_ID: u8 = 5;
+
+    /// Returns the marshaled packets once.
+    pub(crate) struct DummyRtcpReader(Mutex<Option<Bytes>>);
+
+    impl DummyRtcpReader {
+        pub(crate) fn new(data: Bytes) -> DummyRtcpReader {
+            DummyRtcpReader(Mutex::new(Some(data)))
+        }
+    }
+
+    #[async_trait]
+    impl RTCPReader for DummyRtcpReader {
+        async fn read(
+            &self,
+            buf: &mut [u8],
+            attributes: &Attributes,
+        ) -> std::result::Result<(usize, Attributes), interceptor::Error> {
+            match self.0.lock().unwrap().take() {
+                Some(data) => {
+                    buf[..data.len()].copy_from_slice(&data);
+                    Ok((data.len(), attributes.clone()))
+                }
+                None => Err(interceptor::Error::ErrIoEOF),
+            }
+        }
+    }
+
+    /// Drops the packets written.
+    pub(crate) struct DiscardRtp;
+
+    #[async_trait]
+    impl RTPWriter for DiscardRtp {
+        async fn write(
+            &self,
+            pkt: &rtp::packet::Packet,
+            _attributes: &Attributes,
+        ) -> std::result::Result<usize, interceptor::Error> {
+            Ok(pkt.payload.len())
+        }
+    }
+
+    /// Records when the TWCC feedback is sent.
+    #[derive(Default)]
+    struct FeedbackTimes(Mutex<Vec<Instant>>);
+
+    #[async_trait]
+    impl RTCPWriter for FeedbackTimes {
+        async fn write(
+            &self,
+            pkts: &[Box<dyn rtcp::packet::Packet + Send + Sync>],
+            _attributes: &Attributes,
+        ) -> std::result::Result<usize, interceptor::Error> {
+            let has_twcc = pkts
+                .iter()
+                .any(|pkt| pkt.as_any().downcast_ref::<TransportLayerCc>().is_some());
+            if has_twcc {
+                self.0.lock().unwrap().push(Instant::now());
+            }
+            Ok(0)
+        }
+    }
+
+    /// Receives a packet with the transport-cc extension every millisecond.
+    #[derive(Default)]
+    struct PacketSource(AtomicU16);
+
+    #[async_trait]
+    impl RTPReader for PacketSource {
+        async fn read(
+            &self,
+            buf: &mut [u8],
+            attributes: &Attributes,
+        ) -> std::result::Result<(usize, Attributes), interceptor::Error> {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+
+            let transport_sequence = self.0.fetch_add(1, Ordering::Relaxed);
+            let mut pkt = rtp::packet::Packet {
+                header: rtp::header::Header {
+                    ssrc: 1234,
+                    sequence_number: transport_sequence,
+                    ..Default::default()
+                },
+                payload: Bytes::from_static(&[0u8; 100]),
+            };
+            let ext = TransportCcExtension { transport_sequence }
+                .marshal()
+                .unwrap();
+            pkt.header.set_extension(HDR_EXT_ID, ext).unwrap();
+
+            let n = pkt.marshal_to(buf)?;
+            Ok((n, attributes.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn twcc_feedback_interval() {
+        const FEEDBACK_INTERVAL: Duration = Duration::from_millis(50);
+
+        let mut media_engine = MediaEngine::default();
+        let registry =
+            configure_twcc_receiver(Registry::new(), &mut media_engine, FEEDBACK_INTERVAL).unwrap();
+        let chain = registry.build("").unwrap();
+
+        let feedback_times = Arc::new(FeedbackTimes::default());
+        chain.bind_rtcp_writer(feedback_times.clone()).await;
+
+        let info = StreamInfo {
+            ssrc: 1234,
+            rtp_header_extensions: vec![RTPHeaderExtension {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+                id: HDR_EXT_ID as isize,
+            }],
+            ..Default::default()
+        };
+        let reader = chain
+            .bind_remote_stream(&info, Arc::new(PacketSource::default()))
+            .await;
+
+        let mut buf = [0u8; 1500];
+        let start = Instant::now();
+        while start.elapsed() < 10 * FEEDBACK_INTERVAL {
+            reader.read(&mut buf, &Attributes::new()).await.unwrap();
+        }
+        chain.close().await.unwrap();
+
+        let feedback_times = feedback_times.0.lock().unwrap();
+        assert!(feedback_times.len() >= 5);
+        let num_intervals = feedback_times.len() as u32 - 1;
+        let average =
+            (feedback_times[feedback_times.len() - 1] - feedback_times[0]) / num_intervals;
+        assert!(average > FEEDBACK_INTERVAL * 4 / 5 && average < FEEDBACK_INTERVAL * 6 / 5);
+    }
+
+    /// Counts the bytes of the NACKs and PLIs sent.
+    #[derive(Default)]
+    struct FeedbackBytes(Mutex<(usize, usize)>);
+
+    #[async_trait]
+    impl RTCPWriter for FeedbackBytes {
+        async fn write(
+            &self,
+            pkts: &[Box<dyn rtcp::packet::Packet + Send + Sync>],
+            _attributes: &Attributes,
+        ) -> std::result::Result<usize, interceptor::Error> {
+            let mut bytes = self.0.lock().unwrap();
+            for pkt in pkts {
+                if pkt.as_any().is::<TransportLayerNack>() {
+                    bytes.0 += pkt.marshal_size();
+                } else if pkt.as_any().is::<PictureLossIndication>() {
+                    bytes.1 += pkt.marshal_size();
+                }
+            }
+            Ok(0)
+        }
+    }
+
+    /// Receives a packet every millisecond but loses every other one.
+    #[derive(Default)]
+    struct LossySource(AtomicU16);
+
+    #[async_trait]
+    impl RTPReader for LossySource {
+        async fn read(
+            &self,
+            buf: &mut [u8],
+            attributes: &Attributes,
+        ) -> std::result::Result<(usize, Attributes), interceptor::Error> {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+
+            let pkt = rtp::packet::Packet {
+                header: rtp::header::Header {
+                    ssrc: 1234,
+                    sequence_number: self.0.fetch_add(2, Ordering::Relaxed),
+                    ..Default::default()
+                },
+                payload: Bytes::from_static(&[0u8; 100]),
+            };
+            let n = pkt.marshal_to(buf)?;
+            Ok((n, attributes.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn feedback_throttled() {
+        const MAX_BYTES_PER_SEC: u64 = 1000;
+        const DURATION: Duration = Duration::from_millis(500);
+
+        let mut media_engine = MediaEngine::default();
+        let registry = configure_feedback_throttle(
+            Registry::new(),
+            DataRate::from_bits_per_sec(8 * MAX_BYTES_PER_SEC),
+        );
+        let registry = configure_nack(registry, &mut media_engine);
+        let chain = registry.build("").unwrap();
+
+        let feedback_bytes = Arc::new(FeedbackBytes::default());
+        let writer = chain.bind_rtcp_writer(feedback_bytes.clone()).await;
+
+        let info = StreamInfo {
+            ssrc: 1234,
+            rtcp_feedback: vec![RTCPFeedback {
+                typ: "nack".to_owned(),
+                parameter: String::new(),
+            }],
+            ..Default::default()
+        };
+        let reader = chain
+            .bind_remote_stream(&info, Arc::new(LossySource::default()))
+            .await;
+
+        let pli = PictureLossIndication {
+            sender_ssrc: 1,
+            media_ssrc: 1234,
+        };
+        let mut buf = [0u8; 1500];
+        let mut num_plis = 0;
+        let start = Instant::now();
+        while start.elapsed() < DURATION {
+            reader.read(&mut buf, &Attributes::new()).await.unwrap();
+            // A naive decoder asking for a keyframe on every loss
+            writer
+                .write(&[Box::new(pli.clone())], &Attributes::new())
+                .await
+                .unwrap();
+            num_plis += 1;
+        }
+        let elapsed = start.elapsed();
+        chain.close().await.unwrap();
+
+        let (nack_bytes, pli_bytes) = *feedback_bytes.0.lock().unwrap();
+        // Only far from every PLI got through, but the NACKs did not take their budget
+        assert!(pli_bytes < num_plis * pli.marshal_size() / 2);
+        assert!(pli_bytes as f64 > MAX_BYTES_PER_SEC as f64 * elapsed.as_secs_f64());
+
+        // The saved up budget of 250 ms plus a packet let through over the budget
+        let max_bytes = MAX_BYTES_PER_SEC as f64 * (elapsed.as_secs_f64() + 0.25) + 200.0;
+        assert!((nack_bytes as f64) < max_bytes);
+        assert!((pli_bytes as f64) < max_bytes);
+        assert!(nack_bytes > 0);
+    }
 }
@@ -0,0 +1,179 @@
+//! Passive observation of the received RTCP packets, e.g., for collecting metrics from sender and
+//! receiver reports.
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use webrtc::{
+    interceptor::{
+        self, stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtcp,
+};
+
+/// Callback invoked for every RTCP packet received. Compound packets are split and the callback
+/// is invoked for each of the packets in it.
+pub type OnRtcpHdlrFn = Box<dyn Fn(&dyn rtcp::packet::Packet) + Send + Sync>;
+
+pub struct RtcpObserverStream {
+    handler: Arc<OnRtcpHdlrFn>,
+    next_reader: Arc<dyn RTCPReader + Send + Sync>,
+}
+
+#[async_trait]
+impl RTCPReader for RtcpObserverStream {
+    async fn read(
+        &self,
+        buf: &mut [u8],
+        attributes: &Attributes,
+    ) -> Result<(usize, Attributes), interceptor::Error> {
+        let (n, attr) = self.next_reader.read(buf, attributes).await?;
+
+        // Packets that fail to parse are left for the rest of the chain to deal with
+        let mut b = &buf[..n];
+        if let Ok(packets) = rtcp::packet::unmarshal(&mut b) {
+            for packet in &packets {
+                (self.handler)(packet.as_ref());
+            }
+        }
+
+        Ok((n, attr))
+    }
+}
+
+pub struct RtcpObserverInterceptor {
+    handler: Arc<OnRtcpHdlrFn>,
+}
+
+#[async_trait]
+impl Interceptor for RtcpObserverInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        Arc::new(RtcpObserverStream {
+            handler: self.handler.clone(),
+            next_reader: reader,
+        })
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn bind_local_stream(
+        &self,
+        _info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn unbind_local_stream(&self, _info: &StreamInfo) {}
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        reader
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> Result<(), interceptor::Error> {
+        Ok(())
+    }
+}
+
+pub struct RtcpObserverInterceptorBuilder {
+    handler: Arc<OnRtcpHdlrFn>,
+}
+
+impl RtcpObserverInterceptorBuilder {
+    pub fn new(handler: OnRtcpHdlrFn) -> RtcpObserverInterceptorBuilder {
+        RtcpObserverInterceptorBuilder {
+            handler: Arc::new(handler),
+        }
+    }
+}
+
+impl InterceptorBuilder for RtcpObserverInterceptorBuilder {
+    fn build(&self, _id: &str) -> Result<Arc<dyn Interceptor + Send + Sync>, interceptor::Error> {
+        Ok(Arc::new(RtcpObserverInterceptor {
+            handler: self.handler.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interceptor::{
+            tests::DummyRtcpReader,
+            twcc::{twcc_bandwidth_estimate_channel, DelayBasedConfig, TwccInterceptorBuilder},
+        },
+        network::data_rate::DataRate,
+    };
+    use std::sync::Mutex;
+    use webrtc::{
+        interceptor::registry::Registry,
+        rtcp::{
+            receiver_report::ReceiverReport, reception_report::ReceptionReport,
+            source_description::SourceDescription,
+        },
+    };
+
+    #[tokio::test]
+    async fn receiver_report_observed() {
+        let rr = ReceiverReport {
+            ssrc: 1234,
+            reports: vec![ReceptionReport {
+                ssrc: 5678,
+                fraction_lost: 26,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let compound: Vec<Box<dyn rtcp::packet::Packet + Send + Sync>> =
+            vec![Box::new(rr.clone()), Box::new(SourceDescription::default())];
+        let data = rtcp::packet::marshal(&compound).unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+
+        // Observer alongside the bandwidth estimator, which also reads receiver reports
+        let (tx, _rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(1_000_000));
+        let mut registry = Registry::new();
+        registry.add(Box::new(TwccInterceptorBuilder::new(
+            tx,
+            DelayBasedConfig::default(),
+        )));
+        registry.add(Box::new(RtcpObserverInterceptorBuilder::new(Box::new(
+            move |packet| {
+                if let Some(rr) = packet.as_any().downcast_ref::<ReceiverReport>() {
+                    received_clone.lock().unwrap().push(rr.clone());
+                }
+            },
+        ))));
+        let chain = registry.build("").unwrap();
+        let reader = chain
+            .bind_rtcp_reader(Arc::new(DummyRtcpReader::new(data.clone())))
+            .await;
+
+        let mut buf = vec![0u8; 1500];
+        let (n, _) = reader.read(&mut buf, &Attributes::new()).await.unwrap();
+        chain.close().await.unwrap();
+
+        // Nothing is consumed or modified for the rest of the chain
+        assert_eq!(&buf[..n], &data[..]);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0], rr);
+    }
+}
@@ -0,0 +1,451 @@
+//! Pacing of the outgoing RTP packets to the bandwidth estimate. While packets wait for the
+//! budget, the retransmissions of NACKed packets go ahead of the new ones, since under congestion
+//! repairing a recent frame usually helps the receiver more than starting on the next one.
+//!
+//! The retransmissions and RTCP share the estimate with the new packets, so the rate they are
+//! measured to use is taken out of the budget of the new packets.
+
+use super::twcc::TwccBandwidthEstimate;
+use async_trait::async_trait;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use webrtc::{
+    interceptor::{
+        self, stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtcp, rtp,
+    util::MarshalSize,
+};
+
+/// Unused budget saved up while idle, so that a short burst still goes out at once.
+const BURST_DURATION: Duration = Duration::from_millis(5);
+
+/// Bounds on how long a waiting packet sleeps before checking the budget again. The upper bound
+/// lets it notice a higher estimate.
+const MIN_WAIT: Duration = Duration::from_millis(1);
+const MAX_WAIT: Duration = Duration::from_millis(5);
+
+/// Window over which the bytes of the retransmissions and RTCP are averaged into a rate.
+const OVERHEAD_WINDOW: Duration = Duration::from_millis(500);
+
+/// New packets get at least this share of the estimate however much of it the retransmissions
+/// use, so that the streams can still move on to frames that do not need the lost packets.
+const MIN_MEDIA_SHARE: f64 = 0.1;
+
+/// Token bucket of the bytes that can be sent, refilled at a rate that can change over time.
+struct SendBudget {
+    // Goes negative when a packet larger than the remaining budget is let through
+    bytes: f64,
+    last_update: Instant,
+}
+
+impl SendBudget {
+    fn new(now: Instant) -> SendBudget {
+        SendBudget {
+            // Filled up to the burst on the first refill
+            bytes: f64::INFINITY,
+            last_update: now,
+        }
+    }
+
+    fn refill(&mut self, bytes_per_sec: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        let max_bytes = bytes_per_sec * BURST_DURATION.as_secs_f64();
+        self.bytes = (self.bytes + bytes_per_sec * elapsed.as_secs_f64()).min(max_bytes);
+        self.last_update = now;
+    }
+
+    /// Spend `size` bytes of the budget. Returns `false` if there is none left. A packet is let
+    /// through while any budget is left, even if it is larger, so that no packet is too large to
+    /// ever be sent.
+    fn spend(&mut self, size: usize) -> bool {
+        if self.bytes <= 0.0 {
+            return false;
+        }
+        self.bytes -= size as f64;
+        true
+    }
+
+    /// How long until there is budget left again at `bytes_per_sec`.
+    fn time_until_available(&self, bytes_per_sec: f64) -> Duration {
+        if self.bytes > 0.0 {
+            Duration::ZERO
+        } else if bytes_per_sec > 0.0 {
+            Duration::from_secs_f64(-self.bytes / bytes_per_sec)
+        } else {
+            MAX_WAIT
+        }
+    }
+}
+
+/// Bytes sent over the last [OVERHEAD_WINDOW].
+struct OverheadRate {
+    sent: VecDeque<(Instant, usize)>,
+    total_bytes: usize,
+}
+
+impl OverheadRate {
+    fn new() -> OverheadRate {
+        OverheadRate {
+            sent: VecDeque::new(),
+            total_bytes: 0,
+        }
+    }
+
+    fn add(&mut self, size: usize, now: Instant) {
+        self.sent.push_back((now, size));
+        self.total_bytes += size;
+        self.evict(now);
+    }
+
+    fn bytes_per_sec(&mut self, now: Instant) -> f64 {
+        self.evict(now);
+        self.total_bytes as f64 / OVERHEAD_WINDOW.as_secs_f64()
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while let Some(&(time, size)) = self.sent.front() {
+            if now.saturating_duration_since(time) < OVERHEAD_WINDOW {
+                break;
+            }
+            self.sent.pop_front();
+            self.total_bytes -= size;
+        }
+    }
+}
+
+struct PacerState {
+    // For the new packets and the retransmissions over the retransmission budget
+    budget: SendBudget,
+    // Limits the retransmissions that go ahead of the new packets
+    retransmission_budget: SendBudget,
+    // Prioritized retransmissions and RTCP, which are not paced but leave less for the budget
+    overhead: OverheadRate,
+}
+
+/// Shared by every local stream of the peer connection.
+struct Pacer {
+    state: Mutex<PacerState>,
+    bandwidth_estimate: TwccBandwidthEstimate,
+    retransmission_share: f64,
+}
+
+impl Pacer {
+    fn new(bandwidth_estimate: TwccBandwidthEstimate, retransmission_share: f64) -> Pacer {
+        let now = Instant::now();
+        Pacer {
+            state: Mutex::new(PacerState {
+                budget: SendBudget::new(now),
+                retransmission_budget: SendBudget::new(now),
+                overhead: OverheadRate::new(),
+            }),
+            bandwidth_estimate,
+            retransmission_share,
+        }
+    }
+
+    fn estimate_bytes_per_sec(&self) -> f64 {
+        self.bandwidth_estimate.borrow().bytes_per_sec_f64()
+    }
+
+    /// Spend the retransmission budget for a retransmission of `size` bytes, in which case it can
+    /// be sent right away. Returns `false` if the retransmission budget is used up and the
+    /// retransmission has to wait its turn with the new packets.
+    fn prioritize(&self, size: usize, now: Instant) -> bool {
+        let bytes_per_sec = self.retransmission_share * self.estimate_bytes_per_sec();
+        let mut state = self.state.lock().unwrap();
+        state.retransmission_budget.refill(bytes_per_sec, now);
+        if !state.retransmission_budget.spend(size) {
+            return false;
+        }
+        state.overhead.add(size, now);
+        true
+    }
+
+    /// Count `size` bytes that were sent without the budget, e.g., RTCP.
+    fn add_overhead(&self, size: usize, now: Instant) {
+        self.state.lock().unwrap().overhead.add(size, now);
+    }
+
+    /// Spend the budget for a packet of `size` bytes. Returns how long to wait before trying again
+    /// if there is none left.
+    fn try_send(&self, size: usize, now: Instant) -> Result<(), Duration> {
+        let estimate = self.estimate_bytes_per_sec();
+        let mut state = self.state.lock().unwrap();
+        let available = estimate - state.overhead.bytes_per_sec(now);
+        let bytes_per_sec = available.max(MIN_MEDIA_SHARE * estimate);
+        state.budget.refill(bytes_per_sec, now);
+
+        if state.budget.spend(size) {
+            Ok(())
+        } else {
+            let wait = state.budget.time_until_available(bytes_per_sec);
+            Err(wait.clamp(MIN_WAIT, MAX_WAIT))
+        }
+    }
+}
+
+pub struct PacedWriter {
+    pacer: Arc<Pacer>,
+    // Highest sequence number written to the stream. Packets that are not newer are
+    // retransmissions.
+    highest_seq_num: Mutex<Option<u16>>,
+    next_writer: Arc<dyn RTPWriter + Send + Sync>,
+}
+
+impl PacedWriter {
+    fn is_retransmission(&self, seq_num: u16) -> bool {
+        let mut highest_seq_num = self.highest_seq_num.lock().unwrap();
+        match *highest_seq_num {
+            // Not newer in RFC1982 serial number arithmetic
+            Some(highest) if (seq_num.wrapping_sub(highest) as i16) <= 0 => true,
+            _ => {
+                *highest_seq_num = Some(seq_num);
+                false
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RTPWriter for PacedWriter {
+    async fn write(
+        &self,
+        pkt: &rtp::packet::Packet,
+        attributes: &Attributes,
+    ) -> Result<usize, interceptor::Error> {
+        let size = pkt.marshal_size();
+        let prioritized = self.is_retransmission(pkt.header.sequence_number)
+            && self.pacer.prioritize(size, Instant::now());
+        if !prioritized {
+            while let Err(wait) = self.pacer.try_send(size, Instant::now()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+
+        self.next_writer.write(pkt, attributes).await
+    }
+}
+
+/// Measures the RTCP written through the interceptors registered after the pacer, which includes
+/// `RTCPeerConnection::write_rtcp`.
+pub struct PacerRtcpWriter {
+    pacer: Arc<Pacer>,
+    next_writer: Arc<dyn RTCPWriter + Send + Sync>,
+}
+
+#[async_trait]
+impl RTCPWriter for PacerRtcpWriter {
+    async fn write(
+        &self,
+        pkts: &[Box<dyn rtcp::packet::Packet + Send + Sync>],
+        attributes: &Attributes,
+    ) -> Result<usize, interceptor::Error> {
+        let size = pkts.iter().map(|packet| packet.marshal_size()).sum();
+        self.pacer.add_overhead(size, Instant::now());
+        self.next_writer.write(pkts, attributes).await
+    }
+}
+
+pub struct PacerInterceptor {
+    pacer: Arc<Pacer>,
+}
+
+#[async_trait]
+impl Interceptor for PacerInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        reader
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        Arc::new(PacerRtcpWriter {
+            pacer: self.pacer.clone(),
+            next_writer: writer,
+        })
+    }
+
+    async fn bind_local_stream(
+        &self,
+        _info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        Arc::new(PacedWriter {
+            pacer: self.pacer.clone(),
+            highest_seq_num: Mutex::new(None),
+            next_writer: writer,
+        })
+    }
+
+    async fn unbind_local_stream(&self, _info: &StreamInfo) {}
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        reader
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> Result<(), interceptor::Error> {
+        Ok(())
+    }
+}
+
+/// Paces the outgoing RTP packets to `bandwidth_estimate`, sending the retransmissions ahead of
+/// the new packets with up to `retransmission_share` of it. The new packets get what the
+/// retransmissions and RTCP leave of the estimate.
+pub struct PacerInterceptorBuilder {
+    bandwidth_estimate: TwccBandwidthEstimate,
+    retransmission_share: f64,
+}
+
+impl PacerInterceptorBuilder {
+    pub fn new(
+        bandwidth_estimate: TwccBandwidthEstimate,
+        retransmission_share: f64,
+    ) -> PacerInterceptorBuilder {
+        PacerInterceptorBuilder {
+            bandwidth_estimate,
+            retransmission_share: retransmission_share.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl InterceptorBuilder for PacerInterceptorBuilder {
+    fn build(&self, _id: &str) -> Result<Arc<dyn Interceptor + Send + Sync>, interceptor::Error> {
+        Ok(Arc::new(PacerInterceptor {
+            pacer: Arc::new(Pacer::new(
+                self.bandwidth_estimate.clone(),
+                self.retransmission_share,
+            )),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interceptor::{tests::DiscardRtp, twcc::twcc_bandwidth_estimate_channel},
+        network::data_rate::DataRate,
+    };
+
+    const PACKET_SIZE: usize = 1000;
+
+    #[test]
+    fn retransmissions_first() {
+        // 100_000 bytes/s, i.e., a packet every 10 ms
+        let (_tx, bandwidth_estimate) =
+            twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(800_000));
+        let pacer = Pacer::new(bandwidth_estimate, 0.5);
+
+        // Uses up the budget, leaving the next new packet waiting
+        let start = Instant::now();
+        assert!(pacer.try_send(PACKET_SIZE, start).is_ok());
+        let now = start + Duration::from_millis(5);
+        assert!(pacer.try_send(PACKET_SIZE, now).is_err());
+
+        // Goes ahead of the new packet still waiting
+        assert!(pacer.prioritize(PACKET_SIZE, now));
+        assert!(pacer.try_send(PACKET_SIZE, now).is_err());
+
+        // Up to half of the estimate, after which the retransmissions wait with the new packets
+        assert!(!pacer.prioritize(PACKET_SIZE, now));
+        let now = start + Duration::from_millis(25);
+        assert!(pacer.prioritize(PACKET_SIZE, now));
+    }
+
+    #[test]
+    fn retransmissions_detected() {
+        let (_tx, bandwidth_estimate) = twcc_bandwidth_estimate_channel(DataRate::default());
+        let writer = PacedWriter {
+            pacer: Arc::new(Pacer::new(bandwidth_estimate, 0.5)),
+            highest_seq_num: Mutex::new(None),
+            next_writer: Arc::new(DiscardRtp),
+        };
+
+        // Across the wraparound
+        let retransmissions: Vec<_> = [u16::MAX, 0, u16::MAX, 1, 0]
+            .into_iter()
+            .map(|seq_num| writer.is_retransmission(seq_num))
+            .collect();
+        assert_eq!(retransmissions, [false, false, true, false, true]);
+    }
+
+    /// Bytes of new packets sent in a second after the overhead window filled up, trying to send a
+    /// packet every millisecond and a retransmission every `retransmission_interval`, along with
+    /// `rtcp_size` bytes of RTCP every millisecond.
+    fn media_bytes_sent(
+        pacer: &Pacer,
+        retransmission_interval: Option<Duration>,
+        rtcp_size: usize,
+    ) -> usize {
+        let start = Instant::now();
+        let measure_from = start + OVERHEAD_WINDOW;
+        let duration = Duration::from_secs(1);
+
+        let mut media_bytes = 0;
+        let mut next_retransmission = start;
+        let mut now = start;
+        while now < measure_from + duration {
+            if let Some(interval) = retransmission_interval {
+                if now >= next_retransmission {
+                    assert!(pacer.prioritize(PACKET_SIZE, now));
+                    next_retransmission += interval;
+                }
+            }
+            if rtcp_size > 0 {
+                pacer.add_overhead(rtcp_size, now);
+            }
+            if pacer.try_send(PACKET_SIZE, now).is_ok() && now >= measure_from {
+                media_bytes += PACKET_SIZE;
+            }
+            now += Duration::from_millis(1);
+        }
+        media_bytes
+    }
+
+    #[test]
+    fn overhead_reduces_media_budget() {
+        // 40_000 bytes/s
+        let estimate = DataRate::from_bits_per_sec(320_000);
+
+        let (_tx, bandwidth_estimate) = twcc_bandwidth_estimate_channel(estimate);
+        let pacer = Pacer::new(bandwidth_estimate, 1.0);
+        let without_overhead = media_bytes_sent(&pacer, None, 0) as f64;
+        assert!((without_overhead - 40_000.0).abs() <= 3.0 * PACKET_SIZE as f64);
+
+        // Retransmissions take a fourth, then half of the estimate
+        for (interval, share) in [(100, 0.25), (50, 0.5)] {
+            let (_tx, bandwidth_estimate) = twcc_bandwidth_estimate_channel(estimate);
+            let pacer = Pacer::new(bandwidth_estimate, 1.0);
+            let interval = Duration::from_millis(interval);
+            let with_overhead = media_bytes_sent(&pacer, Some(interval), 0) as f64;
+
+            let expected = (1.0 - share) * without_overhead;
+            assert!(
+                (with_overhead - expected).abs() <= 3.0 * PACKET_SIZE as f64,
+                "Sent {with_overhead} bytes of media with {share} of the estimate retransmitted"
+            );
+        }
+
+        // The new packets are never starved, even with more RTCP than the estimate
+        let (_tx, bandwidth_estimate) = twcc_bandwidth_estimate_channel(estimate);
+        let pacer = Pacer::new(bandwidth_estimate, 1.0);
+        let starved = media_bytes_sent(&pacer, None, 100) as f64;
+        let expected = MIN_MEDIA_SHARE * without_overhead;
+        assert!((starved - expected).abs() <= 3.0 * PACKET_SIZE as f64);
+    }
+}
@@ -0,0 +1,203 @@
+//! The RTP/NTP timestamp correlation of the received sender reports, for synchronizing the
+//! playout of audio and video from the same sender.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use webrtc::rtcp::{self, sender_report::SenderReport};
+
+// Seconds from the NTP epoch (1900) to the Unix epoch (1970)
+const NTP_UNIX_OFFSET_SECS: u64 = 0x83AA7E80;
+
+/// RTP timestamp of a stream at the sender's wallclock time, from its latest sender report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenderReportMapping {
+    /// 64-bit NTP timestamp, i.e., 32.32 fixed-point seconds since 1900.
+    pub ntp_time: u64,
+    /// RTP timestamp corresponding to `ntp_time`.
+    pub rtp_time: u32,
+}
+
+impl SenderReportMapping {
+    /// The sender's wallclock time when the sample with `rtp_time` was captured, extrapolated
+    /// from the report using the `clock_rate` of the stream. Comparing this between the audio
+    /// and video streams of a sender gives how far apart they are.
+    ///
+    /// Returns `None` if `clock_rate` is zero.
+    pub fn wallclock_time(&self, rtp_time: u32, clock_rate: u32) -> Option<SystemTime> {
+        if clock_rate == 0 {
+            return None;
+        }
+
+        let secs = (self.ntp_time >> 32).saturating_sub(NTP_UNIX_OFFSET_SECS);
+        let nanos = ((self.ntp_time & 0xFFFF_FFFF) * 1_000_000_000) >> 32;
+        let reported = UNIX_EPOCH + Duration::new(secs, nanos as u32);
+
+        // Samples may come before the report so the difference is signed
+        let ticks = rtp_time.wrapping_sub(self.rtp_time) as i32;
+        let offset = Duration::from_secs_f64(ticks.unsigned_abs() as f64 / clock_rate as f64);
+        if ticks >= 0 {
+            Some(reported + offset)
+        } else {
+            Some(reported - offset)
+        }
+    }
+}
+
+/// Latest `SenderReportMapping` of each remote stream, by SSRC.
+#[derive(Debug, Clone, Default)]
+pub struct SenderReports(Arc<Mutex<HashMap<u32, SenderReportMapping>>>);
+
+impl SenderReports {
+    /// Returns the mapping from the latest sender report for the stream with `ssrc`, if any was
+    /// received.
+    pub fn get(&self, ssrc: u32) -> Option<SenderReportMapping> {
+        self.0.lock().unwrap().get(&ssrc).copied()
+    }
+
+    pub(crate) fn observe(&self, packet: &dyn rtcp::packet::Packet) {
+        if let Some(sr) = packet.as_any().downcast_ref::<SenderReport>() {
+            let mapping = SenderReportMapping {
+                ntp_time: sr.ntp_time,
+                rtp_time: sr.rtp_time,
+            };
+            self.0.lock().unwrap().insert(sr.ssrc, mapping);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interceptor::{
+        configure_sender_reports,
+        tests::{DiscardRtp, DummyRtcpReader},
+    };
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use webrtc::{
+        api::interceptor_registry::configure_rtcp_reports,
+        interceptor::{self, registry::Registry, stream_info::StreamInfo, Attributes, RTCPWriter},
+        rtp::{self, extension::abs_send_time_extension::unix2ntp},
+    };
+
+    const SSRC: u32 = 1234;
+    const CLOCK_RATE: u32 = 90000;
+
+    #[derive(Default)]
+    struct SentReports(Mutex<Vec<SenderReport>>);
+
+    #[async_trait]
+    impl RTCPWriter for SentReports {
+        async fn write(
+            &self,
+            pkts: &[Box<dyn rtcp::packet::Packet + Send + Sync>],
+            _attributes: &Attributes,
+        ) -> Result<usize, interceptor::Error> {
+            for pkt in pkts {
+                if let Some(sr) = pkt.as_any().downcast_ref::<SenderReport>() {
+                    self.0.lock().unwrap().push(sr.clone());
+                }
+            }
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn sender_report_correlation() {
+        const RTP_TIME: u32 = 3_000_000;
+
+        let (registry, sender_reports) =
+            configure_sender_reports(configure_rtcp_reports(Registry::new()));
+        let chain = registry.build("").unwrap();
+
+        // Sending side: one packet of a local stream
+        let sent_reports = Arc::new(SentReports::default());
+        chain.bind_rtcp_writer(sent_reports.clone()).await;
+        let info = StreamInfo {
+            ssrc: SSRC,
+            clock_rate: CLOCK_RATE,
+            ..Default::default()
+        };
+        let writer = chain.bind_local_stream(&info, Arc::new(DiscardRtp)).await;
+        let sent_at = SystemTime::now();
+        let pkt = rtp::packet::Packet {
+            header: rtp::header::Header {
+                ssrc: SSRC,
+                timestamp: RTP_TIME,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(&[0u8; 100]),
+        };
+        writer.write(&pkt, &Attributes::new()).await.unwrap();
+
+        // Reports are sent every second
+        let sr = tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let sent_reports = sent_reports.0.lock().unwrap();
+                if let Some(sr) = sent_reports
+                    .iter()
+                    .find(|sr| sr.ntp_time > unix2ntp(sent_at))
+                {
+                    return sr.clone();
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(sr.ssrc, SSRC);
+
+        // The report extrapolates the RTP timestamp of the packet to the time of the report
+        let mapping = SenderReportMapping {
+            ntp_time: sr.ntp_time,
+            rtp_time: sr.rtp_time,
+        };
+        let error = mapping
+            .wallclock_time(RTP_TIME, CLOCK_RATE)
+            .unwrap()
+            .duration_since(sent_at)
+            .unwrap_or_else(|e| e.duration());
+        assert!(error < Duration::from_millis(10));
+
+        // Receiving side: the mapping is kept per SSRC
+        assert_eq!(sender_reports.get(SSRC), None);
+        let data =
+            rtcp::packet::marshal(&[Box::new(sr) as Box<dyn rtcp::packet::Packet + Send + Sync>])
+                .unwrap();
+        let reader = chain
+            .bind_rtcp_reader(Arc::new(DummyRtcpReader::new(data)))
+            .await;
+        let mut buf = vec![0u8; 1500];
+        reader.read(&mut buf, &Attributes::new()).await.unwrap();
+        chain.close().await.unwrap();
+
+        assert_eq!(sender_reports.get(SSRC), Some(mapping));
+    }
+
+    #[test]
+    fn wallclock_time_before_report() {
+        let reported = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mapping = SenderReportMapping {
+            ntp_time: unix2ntp(reported),
+            rtp_time: 1000,
+        };
+
+        // Half a second before the report, across the RTP timestamp wraparound
+        let rtp_time = 1000u32.wrapping_sub(CLOCK_RATE / 2);
+        let wallclock_time = mapping.wallclock_time(rtp_time, CLOCK_RATE).unwrap();
+        let diff = reported.duration_since(wallclock_time).unwrap();
+        assert!(diff.abs_diff(Duration::from_millis(500)) < Duration::from_micros(1));
+    }
+
+    #[test]
+    fn wallclock_time_zero_clock_rate() {
+        let mapping = SenderReportMapping {
+            ntp_time: unix2ntp(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            rtp_time: 1000,
+        };
+        assert_eq!(mapping.wallclock_time(2000, 0), None);
+    }
+}
@@ -0,0 +1,368 @@
+//! Emulated network link for testing the bandwidth estimator end-to-end. Time is virtual so that
+//! a long session can be simulated in an instant.
+
+use crate::network::data_rate::DataRate;
+use async_trait::async_trait;
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use webrtc::{
+    interceptor::{twcc::Recorder, Attributes, Error, RTPWriter},
+    rtcp::transport_feedbacks::transport_layer_cc::TransportLayerCc,
+    rtp::{self, extension::transport_cc_extension::TransportCcExtension},
+    util::{MarshalSize, Unmarshal},
+};
+
+/// Scripted conditions of the emulated link.
+#[derive(Clone, Copy)]
+pub struct NetworkProfile {
+    /// Bottleneck capacity. Packets are queued while the link is busy.
+    pub capacity: DataRate,
+    /// Packets that would wait longer than this in the queue are dropped.
+    pub max_queue_delay: Duration,
+    /// One-way propagation delay.
+    pub base_delay: Duration,
+    /// Extra delay uniformly distributed in `[0, jitter)`. Reorders packets sent close together.
+    pub jitter: Duration,
+    /// Fraction of the packets randomly dropped.
+    pub loss_rate: f64,
+}
+
+/// Virtual time in microseconds shared by the sender and the link.
+#[derive(Default)]
+pub struct VirtualClock(AtomicI64);
+
+impl VirtualClock {
+    pub fn now_us(&self) -> i64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.0
+            .fetch_add(duration.as_micros() as i64, Ordering::AcqRel);
+    }
+}
+
+struct LinkState {
+    rng: u64,
+    link_free_at_us: i64,
+    // (SSRC, transport sequence number, arrival time)
+    in_flight: Vec<(u32, u16, i64)>,
+    recorder: Recorder,
+}
+
+impl LinkState {
+    /// xorshift64, uniformly distributed in `[0, 1)`.
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// `RTPWriter` that passes the packets through an emulated link instead of the network. The
+/// receiving side only records the arrival times of the packets for generating TWCC feedback.
+pub struct EmulatedLink {
+    profile: NetworkProfile,
+    clock: Arc<VirtualClock>,
+    hdr_ext_id: u8,
+    state: Mutex<LinkState>,
+}
+
+impl EmulatedLink {
+    pub fn new(
+        profile: NetworkProfile,
+        clock: Arc<VirtualClock>,
+        hdr_ext_id: u8,
+        seed: u64,
+    ) -> EmulatedLink {
+        EmulatedLink {
+            profile,
+            clock,
+            hdr_ext_id,
+            state: Mutex::new(LinkState {
+                rng: seed.max(1),
+                link_free_at_us: 0,
+                in_flight: Vec::new(),
+                recorder: Recorder::new(0),
+            }),
+        }
+    }
+
+    /// TWCC feedback for the packets that have arrived by now, as received over the wire.
+    pub fn feedback(&self) -> Vec<TransportLayerCc> {
+        let now_us = self.clock.now_us();
+        let mut state = self.state.lock().unwrap();
+
+        let mut i = 0;
+        while i < state.in_flight.len() {
+            let (ssrc, seq_num, arrival_us) = state.in_flight[i];
+            if arrival_us <= now_us {
+                state.in_flight.swap_remove(i);
+                state.recorder.record(ssrc, seq_num, arrival_us);
+            } else {
+                i += 1;
+            }
+        }
+
+        state
+            .recorder
+            .build_feedback_packet()
+            .into_iter()
+            .filter_map(|packet| {
+                // Quantizes the receive deltas to the 250 us resolution of the wire format
+                let data = packet.marshal().ok()?;
+                TransportLayerCc::unmarshal(&mut data.as_ref()).ok()
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl RTPWriter for EmulatedLink {
+    async fn write(
+        &self,
+        pkt: &rtp::packet::Packet,
+        _attributes: &Attributes,
+    ) -> Result<usize, Error> {
+        let size = pkt.marshal_size();
+        let seq_num = match pkt.header.get_extension(self.hdr_ext_id) {
+            Some(mut buf) => TransportCcExtension::unmarshal(&mut buf)?.transport_sequence,
+            None => return Ok(size),
+        };
+
+        let now_us = self.clock.now_us();
+        let capacity_bits_per_sec = self.profile.capacity.bits_per_sec() as i64;
+        let mut state = self.state.lock().unwrap();
+
+        // Drop-tail queue
+        let queue_delay_us = (state.link_free_at_us - now_us).max(0);
+        if queue_delay_us > self.profile.max_queue_delay.as_micros() as i64 {
+            return Ok(size);
+        }
+
+        let transmission_us = size as i64 * 8 * 1_000_000 / capacity_bits_per_sec;
+        state.link_free_at_us = state.link_free_at_us.max(now_us) + transmission_us;
+
+        // Random loss after the bottleneck
+        if state.random() < self.profile.loss_rate {
+            return Ok(size);
+        }
+
+        let jitter_us = (state.random() * self.profile.jitter.as_micros() as f64) as i64;
+        let arrival_us =
+            state.link_free_at_us + self.profile.base_delay.as_micros() as i64 + jitter_us;
+        state.in_flight.push((pkt.header.ssrc, seq_num, arrival_us));
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interceptor::twcc::{
+        estimator::{DelayBasedConfig, TwccBandwidthEstimator},
+        replay::FeedbackLog,
+        sync::TwccSendInfo,
+        time::TwccTime,
+        twcc_bandwidth_estimate_channel,
+    };
+    use bytes::Bytes;
+    use std::time::Instant;
+    use webrtc::util::Marshal;
+
+    const HDR_EXT_ID: u8 = 5;
+    const PAYLOAD_SIZE: usize = 1200;
+    const SEND_INTERVAL: Duration = Duration::from_millis(5);
+    const FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Sends at the estimated bandwidth through the emulated link for `duration`. Returns the
+    /// estimate after every feedback.
+    async fn run(profile: NetworkProfile, duration: Duration) -> Vec<u64> {
+        run_with_encoder(
+            profile,
+            duration,
+            DataRate::from_bits_per_sec(300_000),
+            None,
+        )
+        .await
+    }
+
+    /// Same as [run] but sends at `encoder_output` instead, if given, while reporting it to the
+    /// estimator if `report` is set.
+    async fn run_with_encoder(
+        profile: NetworkProfile,
+        duration: Duration,
+        init_bandwidth: DataRate,
+        encoder_output: Option<(DataRate, bool)>,
+    ) -> Vec<u64> {
+        let mut log = FeedbackLog::default();
+        run_recorded(profile, duration, init_bandwidth, encoder_output, &mut log).await
+    }
+
+    /// Same as [run_with_encoder] while also recording the inputs to the estimator in `log`.
+    async fn run_recorded(
+        profile: NetworkProfile,
+        duration: Duration,
+        init_bandwidth: DataRate,
+        encoder_output: Option<(DataRate, bool)>,
+        log: &mut FeedbackLog,
+    ) -> Vec<u64> {
+        let clock = Arc::new(VirtualClock::default());
+        let link = EmulatedLink::new(profile, clock.clone(), HDR_EXT_ID, 0x2545f4914f6cdd1d);
+
+        let (tx, rx) = twcc_bandwidth_estimate_channel(init_bandwidth);
+        // Held for the whole run since a dropped reporter stops counting
+        let _reporter = match encoder_output {
+            Some((output, true)) => {
+                let reporter = tx.output_bitrate_reporter();
+                reporter.report(output);
+                Some(reporter)
+            }
+            _ => None,
+        };
+        let mut estimator = TwccBandwidthEstimator::new(tx, DelayBasedConfig::default());
+        let send_info = TwccSendInfo::try_new().unwrap();
+
+        let start = Instant::now();
+        let mut estimates = Vec::new();
+        let mut transport_sequence = 0u16;
+        let mut budget_bytes = 0.0;
+        let mut next_feedback = FEEDBACK_INTERVAL;
+        let mut elapsed = Duration::ZERO;
+
+        while elapsed < duration {
+            // Pace the packets at the current estimate or at the encoder output
+            let send_rate = match encoder_output {
+                Some((output, _)) => output,
+                None => *rx.borrow(),
+            };
+            budget_bytes += send_rate.bytes_per_sec_f64() * SEND_INTERVAL.as_secs_f64();
+            while budget_bytes >= PAYLOAD_SIZE as f64 {
+                budget_bytes -= PAYLOAD_SIZE as f64;
+
+                let mut pkt = rtp::packet::Packet {
+                    header: rtp::header::Header::default(),
+                    payload: Bytes::from_static(&[0u8; PAYLOAD_SIZE]),
+                };
+                let ext = TransportCcExtension { transport_sequence }
+                    .marshal()
+                    .unwrap();
+                pkt.header.set_extension(HDR_EXT_ID, ext).unwrap();
+
+                // What `TwccTimestampSenderStream` does but in virtual time
+                send_info.store_send_info(
+                    transport_sequence,
+                    TwccTime::from_raw(clock.now_us()),
+                    PAYLOAD_SIZE as u64,
+                );
+                log.send(transport_sequence, clock.now_us(), PAYLOAD_SIZE as u64);
+                link.write(&pkt, &Attributes::new()).await.unwrap();
+                transport_sequence = transport_sequence.wrapping_add(1);
+            }
+
+            clock.advance(SEND_INTERVAL);
+            elapsed += SEND_INTERVAL;
+
+            if elapsed >= next_feedback {
+                next_feedback += FEEDBACK_INTERVAL;
+                for tcc in link.feedback() {
+                    estimator.process_feedback(&tcc, &send_info);
+                    log.feedback(&tcc);
+                }
+                estimator.estimate(start + elapsed);
+                estimates.push(rx.borrow().bits_per_sec());
+                log.estimate(elapsed.as_micros() as i64, *rx.borrow());
+            }
+        }
+
+        estimates
+    }
+
+    #[tokio::test]
+    async fn converges_near_capacity() {
+        const CAPACITY: u64 = 2_000_000;
+
+        let profile = NetworkProfile {
+            capacity: DataRate::from_bits_per_sec(CAPACITY),
+            max_queue_delay: Duration::from_millis(100),
+            base_delay: Duration::from_millis(20),
+            jitter: Duration::from_millis(2),
+            loss_rate: 0.01,
+        };
+        let estimates = run(profile, Duration::from_secs(60)).await;
+
+        // Average over the last 20 s
+        let settled = &estimates[estimates.len() - 200..];
+        let average = settled.iter().sum::<u64>() / settled.len() as u64;
+        assert!(average > CAPACITY * 3 / 4 && average < CAPACITY * 5 / 4);
+    }
+
+    #[tokio::test]
+    async fn application_limited_encoder() {
+        const ESTIMATE: u64 = 2_000_000;
+        const OUTPUT: u64 = 200_000;
+
+        let profile = NetworkProfile {
+            capacity: DataRate::from_bits_per_sec(10_000_000),
+            max_queue_delay: Duration::from_millis(100),
+            base_delay: Duration::from_millis(20),
+            jitter: Duration::ZERO,
+            loss_rate: 0.0,
+        };
+        let run = |report| {
+            run_with_encoder(
+                profile,
+                Duration::from_secs(10),
+                DataRate::from_bits_per_sec(ESTIMATE),
+                Some((DataRate::from_bits_per_sec(OUTPUT), report)),
+            )
+        };
+
+        // The low received bandwidth is caused by the encoder and not by the network
+        let estimates = run(true).await;
+        assert!(estimates.iter().all(|&estimate| estimate >= ESTIMATE));
+
+        // Otherwise the estimate drops to near what is received
+        let estimates = run(false).await;
+        assert!(*estimates.last().unwrap() < 2 * OUTPUT);
+    }
+
+    /// Regenerates the golden log of `replay::tests::golden_feedback_log` after an intended
+    /// change to the estimator. Run with `cargo test record_feedback_log -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn record_feedback_log() {
+        let profile = NetworkProfile {
+            capacity: DataRate::from_bits_per_sec(1_000_000),
+            max_queue_delay: Duration::from_millis(100),
+            base_delay: Duration::from_millis(20),
+            jitter: Duration::from_millis(2),
+            loss_rate: 0.01,
+        };
+        // Starts above the capacity to also go through overuse
+        let mut log = FeedbackLog::default();
+        run_recorded(
+            profile,
+            Duration::from_secs(15),
+            DataRate::from_bits_per_sec(1_500_000),
+            None,
+            &mut log,
+        )
+        .await;
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/interceptor/twcc/testdata/feedback.log"
+        );
+        let header = "# Emulated 1 Mbps link with 20 ms delay, 2 ms jitter, and 1% loss. \
+            Starts at 1.5 Mbps.\n";
+        std::fs::write(path, format!("{header}{}", log.as_str())).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use super::{ADDITIVE_INCREASE_STD_DEVS, BURST_TIME_US};
+use std::time::Duration;
+
+/// Parameters of the delay-based controller of the bandwidth estimator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelayBasedConfig {
+    burst_time_us: i64,
+    min_media_packet_size: u64,
+    additive_increase_std_devs: f64,
+    additive_increase_tolerance: f64,
+    initial_rtt_ms: f64,
+    late_send_timestamps: bool,
+    group_flush_timeout: Option<Duration>,
+}
+
+impl Default for DelayBasedConfig {
+    fn default() -> Self {
+        DelayBasedConfig {
+            burst_time_us: BURST_TIME_US,
+            min_media_packet_size: 0,
+            additive_increase_std_devs: ADDITIVE_INCREASE_STD_DEVS,
+            additive_increase_tolerance: 0.0,
+            initial_rtt_ms: 0.0,
+            late_send_timestamps: false,
+            group_flush_timeout: None,
+        }
+    }
+}
+
+impl DelayBasedConfig {
+    /// Packets sent within the burst time are treated as a single group. Defaults to 5 ms.
+    pub fn with_burst_time(mut self, burst_time: Duration) -> DelayBasedConfig {
+        self.burst_time_us = burst_time.as_micros() as i64;
+        self
+    }
+
+    /// Burst time in microseconds.
+    pub fn burst_time_us(&self) -> i64 {
+        self.burst_time_us
+    }
+
+    /// Packets smaller than `size_bytes` (e.g., padding or probes) are not counted toward the
+    /// average packet size but still count toward the received bandwidth. Defaults to 0, i.e.,
+    /// every packet is counted.
+    pub fn with_min_media_packet_size(mut self, size_bytes: u64) -> DelayBasedConfig {
+        self.min_media_packet_size = size_bytes;
+        self
+    }
+
+    /// Minimum size in bytes of the packets counted toward the average packet size.
+    pub fn min_media_packet_size(&self) -> u64 {
+        self.min_media_packet_size
+    }
+
+    /// The estimate increases additively instead of multiplicatively while the received
+    /// bandwidth stays within this many standard deviations of its average at the previous
+    /// overuses, i.e., near the rate the estimate already converged to. Lower values are more
+    /// eager to probe higher again. Defaults to 3.
+    pub fn with_additive_increase_std_devs(mut self, std_devs: f64) -> DelayBasedConfig {
+        self.additive_increase_std_devs = std_devs;
+        self
+    }
+
+    /// Number of standard deviations the received bandwidth can be away from its average while
+    /// still increasing additively.
+    pub fn additive_increase_std_devs(&self) -> f64 {
+        self.additive_increase_std_devs
+    }
+
+    /// The received bandwidth is also considered close to its average when within `tolerance`
+    /// of it, relative to the average. Without this, an average that was just reset has no
+    /// variance and only an identical received bandwidth is close to it. Defaults to 0.
+    pub fn with_additive_increase_tolerance(mut self, tolerance: f64) -> DelayBasedConfig {
+        self.additive_increase_tolerance = tolerance;
+        self
+    }
+
+    /// Relative distance from the average received bandwidth that still increases additively.
+    pub fn additive_increase_tolerance(&self) -> f64 {
+        self.additive_increase_tolerance
+    }
+
+    /// RTT to assume until it is measured from the receiver reports, which takes at least a
+    /// sender report interval. A higher RTT slows down the additive increase and softens the
+    /// decrease on overuse. Defaults to 0, which is too aggressive for satellite or
+    /// intercontinental links.
+    pub fn with_initial_rtt(mut self, rtt: Duration) -> DelayBasedConfig {
+        self.initial_rtt_ms = rtt.as_secs_f64() * 1000.0;
+        self
+    }
+
+    /// Initial RTT in milliseconds.
+    pub fn initial_rtt_ms(&self) -> f64 {
+        self.initial_rtt_ms
+    }
+
+    /// Take the send time of a packet once the interceptors after the TWCC interceptor and SRTP
+    /// have written it to the transport, instead of when the TWCC interceptor first sees it. The
+    /// inter-departure times then follow any pacing on the way instead of the bursts the encoders
+    /// write in. Defaults to false.
+    pub fn with_late_send_timestamps(mut self, late_send_timestamps: bool) -> DelayBasedConfig {
+        self.late_send_timestamps = late_send_timestamps;
+        self
+    }
+
+    /// Whether the send time is taken after the packet is written to the transport.
+    pub fn late_send_timestamps(&self) -> bool {
+        self.late_send_timestamps
+    }
+
+    /// Complete the last packet group once no packets arrive for `timeout`, checked whenever the
+    /// estimate is updated. Otherwise a group is only completed by the first packet of the next
+    /// one, so the last group before the sender pauses is left out of the estimate until it
+    /// resumes. Disabled by default.
+    pub fn with_group_flush_timeout(mut self, timeout: Duration) -> DelayBasedConfig {
+        self.group_flush_timeout = Some(timeout);
+        self
+    }
+
+    /// Time without packets after which the last packet group is completed, if enabled.
+    pub fn group_flush_timeout(&self) -> Option<Duration> {
+        self.group_flush_timeout
+    }
+}
@@ -1,18 +1,21 @@
+mod config;
 mod history;
 mod overuse_detector;
 mod packet_group;
 
+pub use self::{config::DelayBasedConfig, packet_group::GroupDelta};
 use self::{
     history::History,
     overuse_detector::{DelayDetector, NetworkCondition},
     packet_group::PacketGroup,
 };
-use super::TwccTime;
+use super::{ReceiveRate, TwccTime};
 use std::{collections::VecDeque, time::Instant};
 
+// Default value of `DelayBasedConfig::burst_time_us`
 const BURST_TIME_US: i64 = 5000;
 
-// Should be within 500 - 1000 ms if packets are grouped by 5 ms burst time
+// Should be within 500 - 1000 ms if packets are grouped by the default 5 ms burst time
 const WINDOW_SIZE: u32 = 100;
 
 const ESTIMATOR_REACTION_TIME_MS: f64 = 100.0;
@@ -24,6 +27,12 @@ const INITIAL_SYSTEM_ERROR_COVARIANCE: f64 = 0.1;
 // Midway between the recommended value of 0.001 - 0.1
 const CHI: f64 = 0.01;
 
+// Intergroup delay variations further than this from the estimate are capped
+const OUTLIER_STD_DEVS: f64 = 3.0;
+
+// Enough samples for the variance to be meaningful before rejecting outliers
+const OUTLIER_MIN_UPDATES: u32 = 10;
+
 const INITIAL_DELAY_THRESHOLD_US: f64 = 12500.0;
 
 const OVERUSE_TIME_THRESHOLD_US: i64 = 10000;
@@ -32,30 +41,51 @@ const K_U: f64 = 0.01;
 
 const K_D: f64 = 0.00018;
 
-const DECREASE_RATE_FACTOR: f64 = 0.85;
+// The bandwidth is multiplied by a factor between these on overuse, depending on the RTT. The
+// feedback of a decrease arrives later on high RTT links so decreasing sharply there overshoots.
+const MIN_DECREASE_RATE_FACTOR: f64 = 0.8;
+const MAX_DECREASE_RATE_FACTOR: f64 = 0.9;
+
+// RTTs at which the decrease rate factor reaches the min/max
+const LOW_RTT_MS: f64 = 20.0;
+const HIGH_RTT_MS: f64 = 300.0;
+
+// Used for the additive increase before any packet group has been received
+const DEFAULT_PACKET_SIZE_BYTES: f64 = 1200.0;
 
 // Exponential moving average smoothing factor
 const ALPHA: f64 = 0.95;
 
+// Default value of `DelayBasedConfig::additive_increase_std_devs`
+const ADDITIVE_INCREASE_STD_DEVS: f64 = 3.0;
+
 struct IncomingBitrateEstimate {
     mean: f64,
     variance: f64,
     converged: bool,
+    std_devs: f64,
+    tolerance: f64,
 }
 
 impl IncomingBitrateEstimate {
-    fn new() -> IncomingBitrateEstimate {
+    fn new(config: &DelayBasedConfig) -> IncomingBitrateEstimate {
         IncomingBitrateEstimate {
             mean: 0.0,
             variance: 0.0,
             converged: false,
+            std_devs: config.additive_increase_std_devs(),
+            tolerance: config.additive_increase_tolerance(),
         }
     }
 
     fn update(&mut self, bytes_per_sec: f64) {
         let diff = bytes_per_sec - self.mean;
-        // Check if sample is beyond 3 stddevs away from the mean
-        if diff * diff > 9.0 * self.variance {
+        // Check if sample is too far away from the mean
+        let max_diff = f64::max(
+            self.std_devs * self.variance.sqrt(),
+            self.tolerance * self.mean,
+        );
+        if diff.abs() > max_diff {
             // Reset the average and go to multiplicative increase
             self.mean = bytes_per_sec;
             self.variance = 0.0;
@@ -78,6 +108,7 @@ impl IncomingBitrateEstimate {
 }
 
 pub struct DelayBasedBandwidthEstimator {
+    config: DelayBasedConfig,
     prev_group: Option<PacketGroup>,
     curr_group: Option<PacketGroup>,
     history: History,
@@ -86,19 +117,28 @@ pub struct DelayBasedBandwidthEstimator {
     last_update: Option<Instant>,
     network_condition: NetworkCondition,
     rtt_ms: f64,
+    application_limited: bool,
+    // Whether a packet was processed since the last estimate
+    packets_since_estimate: bool,
+    // Time of the first estimate after the last processed packet
+    last_packet_estimate: Option<Instant>,
 }
 
 impl DelayBasedBandwidthEstimator {
-    pub fn new() -> DelayBasedBandwidthEstimator {
+    pub fn new(config: DelayBasedConfig) -> DelayBasedBandwidthEstimator {
         DelayBasedBandwidthEstimator {
+            config,
             prev_group: None,
             curr_group: None,
             history: History::new(),
-            incoming_bitrate_estimate: IncomingBitrateEstimate::new(),
+            incoming_bitrate_estimate: IncomingBitrateEstimate::new(&config),
             delay_detector: None,
             last_update: None,
             network_condition: NetworkCondition::Normal,
-            rtt_ms: 0.0,
+            rtt_ms: config.initial_rtt_ms(),
+            application_limited: false,
+            packets_since_estimate: false,
+            last_packet_estimate: None,
         }
     }
 
@@ -109,38 +149,67 @@ impl DelayBasedBandwidthEstimator {
         packet_size: u64,
     ) {
         let mut new_packet_group = false;
+        let is_media = packet_size >= self.config.min_media_packet_size();
+        self.packets_since_estimate = true;
 
         if let Some(curr_group) = &mut self.curr_group {
             // Ignore reordered packets
             if departure_time >= curr_group.earliest_departure_time_us {
-                if curr_group.belongs_to_group(departure_time, arrival_time) {
-                    curr_group.add_packet(departure_time, arrival_time, packet_size);
+                if curr_group.belongs_to_group(
+                    departure_time,
+                    arrival_time,
+                    self.config.burst_time_us(),
+                ) {
+                    curr_group.add_packet(departure_time, arrival_time, packet_size, is_media);
                 } else {
                     new_packet_group = true;
                 }
             }
+        } else if let Some(prev_group) = &self.prev_group {
+            // The current group was flushed, still ignore packets reordered before it
+            new_packet_group = departure_time >= prev_group.earliest_departure_time_us;
         } else {
             new_packet_group = true;
         }
 
         if new_packet_group {
-            self.curr_group_completed(arrival_time);
-
-            std::mem::swap(&mut self.prev_group, &mut self.curr_group);
-            self.curr_group = Some(PacketGroup::new(departure_time, arrival_time, packet_size));
+            if self.curr_group.is_some() {
+                self.curr_group_completed(arrival_time);
+                self.prev_group = self.curr_group.take();
+            }
+            self.curr_group = Some(PacketGroup::new(
+                departure_time,
+                arrival_time,
+                packet_size,
+                is_media,
+            ));
         }
     }
 
+    /// Bandwidth at which the packet groups in the window arrived. Returns `None` until the window
+    /// spans some time.
+    pub fn received_bandwidth_bytes_per_sec(&self) -> Option<f64> {
+        self.history.received_bandwidth_bytes_per_sec()
+    }
+
     pub fn update_rtt(&mut self, rtt_ms: f64) {
         self.rtt_ms = rtt_ms;
     }
 
+    /// Set if the encoders are producing less than the estimate. The received bandwidth is then
+    /// limited by the encoders rather than the network so it says nothing about the available
+    /// bandwidth.
+    pub fn set_application_limited(&mut self, application_limited: bool) {
+        self.application_limited = application_limited;
+    }
+
     fn curr_group_completed(&mut self, arrival_time: TwccTime) {
         if let (Some(curr_group), Some(prev_group)) = (&self.curr_group, &self.prev_group) {
             // Inter-departure time should be >= 0 since we ignore reordered packets
-            let interdeparture_time = curr_group.interdeparture_time(prev_group);
-            let interarrival_time = curr_group.interarrival_time(prev_group);
-            let intergroup_delay = interarrival_time - interdeparture_time;
+            let delta = GroupDelta::between(prev_group, curr_group);
+            let interdeparture_time = delta.interdeparture_time_us;
+            let interarrival_time = delta.interarrival_time_us;
+            let intergroup_delay = delta.intergroup_delay_us();
 
             self.history.add_group(curr_group, interdeparture_time);
 
@@ -159,11 +228,40 @@ impl DelayBasedBandwidthEstimator {
         }
     }
 
+    /// Completes the current group if no packet was processed for the group flush timeout.
+    fn flush_idle_group(&mut self, now: Instant) {
+        let timeout = match self.config.group_flush_timeout() {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        if std::mem::take(&mut self.packets_since_estimate) {
+            self.last_packet_estimate = Some(now);
+            return;
+        }
+
+        let idle = self
+            .last_packet_estimate
+            .is_some_and(|last| now.saturating_duration_since(last) >= timeout);
+        if let Some(arrival_time) = self.curr_group.as_ref().map(|g| g.arrival_time_us) {
+            if idle {
+                self.curr_group_completed(arrival_time);
+                self.prev_group = self.curr_group.take();
+            }
+        }
+    }
+
     pub fn estimate(&mut self, current_bandwidth: f64, now: Instant) -> f64 {
+        self.flush_idle_group(now);
+
         // Underuse - retain current bandwidth (bugged?)
         // Normal - increase bandwidth
         // Overuse - decrease bandwidth
         let mut bandwidth_estimate = match self.network_condition {
+            NetworkCondition::Underuse | NetworkCondition::Normal if self.application_limited => {
+                // Neither probe higher nor cap to the received bandwidth, just hold
+                self.last_update = Some(now);
+                return current_bandwidth;
+            }
             NetworkCondition::Underuse | NetworkCondition::Normal => {
                 let time_since_last_update_ms = self.time_since_last_update(now);
 
@@ -172,7 +270,9 @@ impl DelayBasedBandwidthEstimator {
                         current_bandwidth,
                         time_since_last_update_ms,
                         self.rtt_ms,
-                        self.history.average_packet_size_bytes(),
+                        self.history
+                            .average_packet_size_bytes()
+                            .unwrap_or(DEFAULT_PACKET_SIZE_BYTES),
                     )
                 } else {
                     bandwidth_multiplicative_increase(current_bandwidth, time_since_last_update_ms)
@@ -181,12 +281,12 @@ impl DelayBasedBandwidthEstimator {
             NetworkCondition::Overuse => {
                 if let Some(received_bandwidth) = self.history.received_bandwidth_bytes_per_sec() {
                     self.incoming_bitrate_estimate.update(received_bandwidth);
-                    bandwidth_decrease(received_bandwidth)
+                    bandwidth_decrease(received_bandwidth, self.rtt_ms)
                 } else {
                     // We don't have an estimate of the received bandwidth but we still want to
                     // decrease the sending bandwidth. Use the current sending bandwidth as a proxy
                     // assuming it's near the received bandwidth.
-                    bandwidth_decrease(current_bandwidth)
+                    bandwidth_decrease(current_bandwidth, self.rtt_ms)
                 }
             }
         };
@@ -207,7 +307,7 @@ impl DelayBasedBandwidthEstimator {
         let millis = self
             .last_update
             .map(|t| now.duration_since(t).as_millis() as f64)
-            .unwrap_or((BURST_TIME_US / 1000) as f64);
+            .unwrap_or((self.config.burst_time_us() / 1000) as f64);
         millis
     }
 }
@@ -233,6 +333,236 @@ fn bandwidth_multiplicative_increase(
     current_bandwidth * eta
 }
 
-fn bandwidth_decrease(received_bandwidth: f64) -> f64 {
-    received_bandwidth * DECREASE_RATE_FACTOR
+fn bandwidth_decrease(received_bandwidth: f64, rtt_ms: f64) -> f64 {
+    received_bandwidth * decrease_rate_factor(rtt_ms)
+}
+
+/// Linearly goes from `MIN_DECREASE_RATE_FACTOR` to `MAX_DECREASE_RATE_FACTOR` as the RTT goes
+/// from `LOW_RTT_MS` to `HIGH_RTT_MS`.
+fn decrease_rate_factor(rtt_ms: f64) -> f64 {
+    let t = ((rtt_ms - LOW_RTT_MS) / (HIGH_RTT_MS - LOW_RTT_MS)).clamp(0.0, 1.0);
+    MIN_DECREASE_RATE_FACTOR + t * (MAX_DECREASE_RATE_FACTOR - MIN_DECREASE_RATE_FACTOR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Returns the number of completed groups and their average size in bytes.
+    fn group_packets(config: DelayBasedConfig) -> (usize, f64) {
+        let mut estimator = DelayBasedBandwidthEstimator::new(config);
+        let mut num_groups = 0;
+        let mut total_size = 0;
+
+        // One 1000-byte packet every 1 ms with no queuing delay
+        for i in 0..100 {
+            let timestamp = TwccTime::from_duration(&Duration::from_millis(i));
+            estimator.process_packet(timestamp, timestamp, 1000);
+
+            let curr_group = estimator.curr_group.as_ref().unwrap();
+            if curr_group.num_packets == 1 {
+                if let Some(prev_group) = &estimator.prev_group {
+                    num_groups += 1;
+                    total_size += prev_group.size_bytes;
+                }
+            }
+        }
+        (num_groups, total_size as f64 / num_groups as f64)
+    }
+
+    #[test]
+    fn smaller_burst_time() {
+        let (default_num_groups, default_size) = group_packets(DelayBasedConfig::default());
+        let (num_groups, size) =
+            group_packets(DelayBasedConfig::default().with_burst_time(Duration::from_millis(2)));
+
+        assert_eq!(default_size, 5000.0);
+        assert_eq!(size, 2000.0);
+        assert!(num_groups > default_num_groups);
+    }
+
+    /// Sends a 1000-byte packet every 1 ms for 100 ms, then pauses with the estimate updated every
+    /// 100 ms for 1 s. Returns the received bandwidth before and after the pause.
+    fn received_bandwidth_across_pause(config: DelayBasedConfig) -> (f64, f64) {
+        let mut estimator = DelayBasedBandwidthEstimator::new(config);
+        let start = Instant::now();
+        for i in 0..100 {
+            let timestamp = TwccTime::from_duration(&Duration::from_millis(i));
+            estimator.process_packet(timestamp, timestamp, 1000);
+        }
+        estimator.estimate(1_000_000.0, start);
+        let before = estimator.received_bandwidth_bytes_per_sec().unwrap();
+
+        for i in 1..=10 {
+            estimator.estimate(1_000_000.0, start + Duration::from_millis(100 * i));
+        }
+        let after = estimator.received_bandwidth_bytes_per_sec().unwrap();
+        (before, after)
+    }
+
+    #[test]
+    fn idle_group_flushed() {
+        let (before, after) = received_bandwidth_across_pause(DelayBasedConfig::default());
+        assert_eq!(before, after);
+
+        let config =
+            DelayBasedConfig::default().with_group_flush_timeout(Duration::from_millis(300));
+        let (flushed_before, flushed_after) = received_bandwidth_across_pause(config);
+        assert_eq!(flushed_before, before);
+        // The last 5 ms group is counted once flushed
+        assert_ne!(flushed_after, before);
+
+        // Packets after the pause start a new group, except for those sent before the flushed
+        // group
+        let mut estimator = DelayBasedBandwidthEstimator::new(config);
+        let start = Instant::now();
+        let timestamp = |ms| TwccTime::from_duration(&Duration::from_millis(ms));
+        estimator.process_packet(timestamp(0), timestamp(0), 1000);
+        estimator.process_packet(timestamp(10), timestamp(10), 1000);
+        estimator.estimate(1_000_000.0, start);
+        estimator.estimate(1_000_000.0, start + Duration::from_millis(300));
+        assert!(estimator.curr_group.is_none());
+        assert_eq!(
+            estimator
+                .prev_group
+                .as_ref()
+                .unwrap()
+                .earliest_departure_time_us,
+            timestamp(10)
+        );
+
+        estimator.process_packet(timestamp(5), timestamp(500), 1000);
+        assert!(estimator.curr_group.is_none());
+        estimator.process_packet(timestamp(500), timestamp(500), 1000);
+        assert_eq!(estimator.curr_group.as_ref().unwrap().num_packets, 1);
+        assert_eq!(
+            estimator
+                .prev_group
+                .as_ref()
+                .unwrap()
+                .earliest_departure_time_us,
+            timestamp(10)
+        );
+    }
+
+    /// Returns the average packet size after sending 1000-byte media packets interleaved with
+    /// 50-byte padding packets.
+    fn average_packet_size_with_padding(config: DelayBasedConfig) -> f64 {
+        let mut estimator = DelayBasedBandwidthEstimator::new(config);
+
+        for i in 0..200 {
+            let timestamp = TwccTime::from_duration(&Duration::from_millis(i));
+            let packet_size = if i % 2 == 0 { 1000 } else { 50 };
+            estimator.process_packet(timestamp, timestamp, packet_size);
+        }
+        estimator.history.average_packet_size_bytes().unwrap()
+    }
+
+    #[test]
+    fn exclude_padding_from_average_packet_size() {
+        let size = average_packet_size_with_padding(DelayBasedConfig::default());
+        assert_eq!(size, 525.0);
+
+        let size = average_packet_size_with_padding(
+            DelayBasedConfig::default().with_min_media_packet_size(200),
+        );
+        assert_eq!(size, 1000.0);
+    }
+
+    #[test]
+    fn additive_increase_with_empty_history() {
+        let mut estimator = DelayBasedBandwidthEstimator::new(DelayBasedConfig::default());
+        assert!(estimator.history.average_packet_size_bytes().is_none());
+
+        // Converged without having received any packet group
+        estimator.incoming_bitrate_estimate.update(100_000.0);
+        estimator.incoming_bitrate_estimate.update(100_000.0);
+        assert!(estimator.incoming_bitrate_estimate.has_converged());
+
+        let now = Instant::now();
+        estimator.estimate(100_000.0, now);
+        let bandwidth = estimator.estimate(100_000.0, now + Duration::from_millis(100));
+        assert!(bandwidth.is_finite());
+        assert!(bandwidth > 100_000.0);
+    }
+
+    /// Returns whether each received bandwidth at overuse was close enough to the average for
+    /// the next increase to be additive.
+    fn additive_increases(config: DelayBasedConfig, received_bandwidths: &[f64]) -> Vec<bool> {
+        let mut estimate = IncomingBitrateEstimate::new(&config);
+        received_bandwidths
+            .iter()
+            .map(|&bytes_per_sec| {
+                estimate.update(bytes_per_sec);
+                estimate.has_converged()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn additive_increase_threshold() {
+        let received_bandwidths = [100_000.0, 104_000.0, 109_800.0];
+        let config = DelayBasedConfig::default().with_additive_increase_tolerance(0.05);
+
+        // The last one is beyond both 3 standard deviations and the tolerance
+        let increases = additive_increases(config, &received_bandwidths);
+        assert_eq!(increases, [false, true, false]);
+
+        let increases = additive_increases(
+            config.with_additive_increase_std_devs(8.0),
+            &received_bandwidths,
+        );
+        assert_eq!(increases, [false, true, true]);
+
+        // Only an identical received bandwidth is close to a freshly reset average
+        let increases = additive_increases(
+            DelayBasedConfig::default().with_additive_increase_std_devs(8.0),
+            &received_bandwidths,
+        );
+        assert_eq!(increases, [false, false, false]);
+    }
+
+    /// Returns the first additive increase over a 100 ms update interval.
+    fn first_additive_increase(config: DelayBasedConfig) -> f64 {
+        let mut estimator = DelayBasedBandwidthEstimator::new(config);
+        estimator.incoming_bitrate_estimate.update(100_000.0);
+        estimator.incoming_bitrate_estimate.update(100_000.0);
+
+        let now = Instant::now();
+        let bandwidth = estimator.estimate(100_000.0, now);
+        estimator.estimate(bandwidth, now + Duration::from_millis(100)) - bandwidth
+    }
+
+    #[test]
+    fn initial_rtt() {
+        let default_increase = first_additive_increase(DelayBasedConfig::default());
+        let increase = first_additive_increase(
+            DelayBasedConfig::default().with_initial_rtt(Duration::from_millis(300)),
+        );
+
+        // Half of the default packet size per response time
+        assert_eq!(default_increase, 600.0);
+        assert_eq!(increase, 150.0);
+    }
+
+    /// Returns the estimate after an overuse given the RTT.
+    fn estimate_after_overuse(rtt_ms: f64) -> f64 {
+        let mut estimator = DelayBasedBandwidthEstimator::new(DelayBasedConfig::default());
+        estimator.update_rtt(rtt_ms);
+        estimator.network_condition = NetworkCondition::Overuse;
+        estimator.estimate(100_000.0, Instant::now())
+    }
+
+    #[test]
+    fn decrease_adapts_to_rtt() {
+        let low_rtt = estimate_after_overuse(10.0);
+        let mid_rtt = estimate_after_overuse(160.0);
+        let high_rtt = estimate_after_overuse(1000.0);
+
+        // Sharper decrease on low RTT, more conservative on high RTT
+        assert!(low_rtt < mid_rtt && mid_rtt < high_rtt);
+        assert_eq!(low_rtt, 100_000.0 * MIN_DECREASE_RATE_FACTOR);
+        assert_eq!(high_rtt, 100_000.0 * MAX_DECREASE_RATE_FACTOR);
+    }
 }
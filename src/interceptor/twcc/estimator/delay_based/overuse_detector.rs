@@ -4,6 +4,10 @@ struct ArrivalTimeFilter {
     m_hat: f64,
     e: f64,
     var_v_hat: f64,
+    // Sum of the weights of the exponential average in `var_v_hat`. Used to correct for the
+    // initial bias towards zero when checking for outliers.
+    var_v_hat_weight: f64,
+    num_updates: u32,
 }
 
 impl ArrivalTimeFilter {
@@ -12,6 +16,8 @@ impl ArrivalTimeFilter {
             m_hat: intergroup_delay as f64,
             e: INITIAL_SYSTEM_ERROR_COVARIANCE,
             var_v_hat: 0.0,
+            var_v_hat_weight: 0.0,
+            num_updates: 0,
         }
     }
 
@@ -29,14 +35,29 @@ impl ArrivalTimeFilter {
         let q = STATE_NOISE_COVARIANCE;
 
         // m_hat, intergroup_delay, and z are in microseconds
-        let z = intergroup_delay as f64 - self.m_hat;
+        let z = self.cap_outlier(intergroup_delay as f64 - self.m_hat);
         let z2 = z * z;
 
         self.var_v_hat = f64::max(1.0, alpha * self.var_v_hat + (1.0 - alpha) * z2);
+        self.var_v_hat_weight = alpha * self.var_v_hat_weight + (1.0 - alpha);
         let k = (self.e + q) / (self.var_v_hat + (self.e + q));
         self.e = (1.0 - k) * (self.e + q);
 
         self.m_hat = self.m_hat + z * k;
+        self.num_updates = self.num_updates.saturating_add(1);
+    }
+
+    /// Limits `z` to `OUTLIER_STD_DEVS` standard deviations so that a single delay spike (e.g., a
+    /// scheduler hiccup) can neither shift `m_hat` nor inflate `var_v_hat`, which would lower the
+    /// filter gain for a long time afterwards. A sustained change in delay still gets through since
+    /// the capped `z` also increases `var_v_hat`.
+    fn cap_outlier(&self, z: f64) -> f64 {
+        if self.num_updates < OUTLIER_MIN_UPDATES {
+            return z;
+        }
+
+        let max_z = OUTLIER_STD_DEVS * (self.var_v_hat / self.var_v_hat_weight).sqrt();
+        z.clamp(-max_z, max_z)
     }
 }
 
@@ -128,3 +149,47 @@ impl DelayDetector {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEND_INTERVAL_US: i64 = 5000;
+
+    // Intergroup delays alternating within +-500 us
+    fn jitter(i: i64) -> i64 {
+        [300, -200, 500, -400, 100, -300][i as usize % 6]
+    }
+
+    #[test]
+    fn single_delay_spike() {
+        let mut detector = DelayDetector::new(0);
+
+        let run = |detector: &mut DelayDetector, i: i64, intergroup_delay: i64| {
+            detector.detect_network_condition(
+                intergroup_delay,
+                SEND_INTERVAL_US,
+                SEND_INTERVAL_US + intergroup_delay,
+                TwccTime::from_raw(i * SEND_INTERVAL_US),
+            )
+        };
+
+        for i in 0..200 {
+            run(&mut detector, i, jitter(i));
+        }
+        let m_hat = detector.filter.m_hat();
+        let var_v_hat = detector.filter.var_v_hat;
+
+        // 200 ms spike
+        let condition = run(&mut detector, 200, 200_000);
+        assert!(!matches!(condition, NetworkCondition::Overuse));
+        assert!((detector.filter.m_hat() - m_hat).abs() < 1000.0);
+        // Without the cap, the variance jumps by ~2000x and the filter gain collapses
+        assert!(detector.filter.var_v_hat < 1.5 * var_v_hat);
+
+        for i in 201..250 {
+            let condition = run(&mut detector, i, jitter(i));
+            assert!(matches!(condition, NetworkCondition::Normal));
+        }
+    }
+}
@@ -1,5 +1,45 @@
 use super::*;
 
+/// Differences between the times of the last packets of two consecutive packet groups, the
+/// inputs of the delay-based controller's arrival-time filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupDelta {
+    /// Departure time of the later group minus that of the earlier group, in microseconds.
+    pub interdeparture_time_us: i64,
+    /// Arrival time of the later group minus that of the earlier group, in microseconds.
+    pub interarrival_time_us: i64,
+}
+
+impl GroupDelta {
+    /// Compute the differences given the `(departure_time_us, arrival_time_us)` of the last
+    /// packet of the earlier group and of the later group.
+    ///
+    /// The times are TWCC timestamps, which wrap around every 2^24 * 64 ms (about 12.4 days).
+    /// The groups are assumed to be less than half of that apart, so a difference that is larger
+    /// in either direction is taken to have crossed the wrap-around point.
+    pub fn new(earlier: (i64, i64), later: (i64, i64)) -> GroupDelta {
+        let time = TwccTime::from_micros_wrapping;
+        GroupDelta {
+            interdeparture_time_us: time(later.0).sub_assuming_small_delta(time(earlier.0)),
+            interarrival_time_us: time(later.1).sub_assuming_small_delta(time(earlier.1)),
+        }
+    }
+
+    pub(crate) fn between(earlier: &PacketGroup, later: &PacketGroup) -> GroupDelta {
+        GroupDelta {
+            interdeparture_time_us: later.interdeparture_time(earlier),
+            interarrival_time_us: later.interarrival_time(earlier),
+        }
+    }
+
+    /// Inter-arrival time minus inter-departure time, in microseconds. Positive when the later
+    /// group took longer to arrive than the earlier one, i.e., a queue is building up on the path,
+    /// and negative when the queue drains.
+    pub fn intergroup_delay_us(&self) -> i64 {
+        self.interarrival_time_us - self.interdeparture_time_us
+    }
+}
+
 pub struct PacketGroup {
     pub earliest_departure_time_us: TwccTime,
     pub departure_time_us: TwccTime,
@@ -7,6 +47,8 @@ pub struct PacketGroup {
     pub arrival_time_us: TwccTime,
     pub size_bytes: u64,
     pub num_packets: u64,
+    pub media_size_bytes: u64,
+    pub num_media_packets: u64,
 }
 
 impl PacketGroup {
@@ -14,28 +56,38 @@ impl PacketGroup {
         departure_time_us: TwccTime,
         arrival_time_us: TwccTime,
         packet_size: u64,
+        is_media: bool,
     ) -> PacketGroup {
-        PacketGroup {
+        let mut packet_group = PacketGroup {
             earliest_departure_time_us: departure_time_us,
             departure_time_us,
             earliest_arrival_time_us: arrival_time_us,
             arrival_time_us,
-            size_bytes: packet_size,
-            num_packets: 1,
-        }
+            size_bytes: 0,
+            num_packets: 0,
+            media_size_bytes: 0,
+            num_media_packets: 0,
+        };
+        packet_group.add_packet(departure_time_us, arrival_time_us, packet_size, is_media);
+        packet_group
     }
 
-    pub fn belongs_to_group(&self, departure_time_us: TwccTime, arrival_time_us: TwccTime) -> bool {
+    pub fn belongs_to_group(
+        &self,
+        departure_time_us: TwccTime,
+        arrival_time_us: TwccTime,
+        burst_time_us: i64,
+    ) -> bool {
         let interdeparture_time =
             departure_time_us.sub_assuming_small_delta(self.earliest_departure_time_us);
-        if interdeparture_time < BURST_TIME_US {
+        if interdeparture_time < burst_time_us {
             return true;
         }
 
         let interarrival_time =
             arrival_time_us.sub_assuming_small_delta(self.earliest_arrival_time_us);
         let intergroup_delay = interarrival_time - interdeparture_time;
-        if interarrival_time < BURST_TIME_US && intergroup_delay < 0 {
+        if interarrival_time < burst_time_us && intergroup_delay < 0 {
             return true;
         }
 
@@ -47,9 +99,14 @@ impl PacketGroup {
         departure_time_us: TwccTime,
         arrival_time_us: TwccTime,
         packet_size: u64,
+        is_media: bool,
     ) {
         self.size_bytes += packet_size;
         self.num_packets += 1;
+        if is_media {
+            self.media_size_bytes += packet_size;
+            self.num_media_packets += 1;
+        }
 
         if departure_time_us > self.departure_time_us {
             self.departure_time_us = departure_time_us;
@@ -69,3 +126,62 @@ impl PacketGroup {
             .sub_assuming_small_delta(other.departure_time_us)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRAPAROUND_US: i64 = (1 << 24) * 64000;
+
+    #[test]
+    fn intergroup_delay_sign() {
+        // Arrived 5 ms later than the spacing it was sent with
+        let delta = GroupDelta::new((0, 50_000), (20_000, 75_000));
+        assert_eq!(delta.interdeparture_time_us, 20_000);
+        assert_eq!(delta.interarrival_time_us, 25_000);
+        assert_eq!(delta.intergroup_delay_us(), 5_000);
+
+        // Arrived 5 ms earlier
+        let delta = GroupDelta::new((0, 50_000), (20_000, 65_000));
+        assert_eq!(delta.intergroup_delay_us(), -5_000);
+
+        // Swapping the groups negates everything
+        let swapped = GroupDelta::new((20_000, 65_000), (0, 50_000));
+        assert_eq!(swapped.interdeparture_time_us, -20_000);
+        assert_eq!(swapped.intergroup_delay_us(), 5_000);
+    }
+
+    #[test]
+    fn intergroup_delay_wraparound() {
+        let earlier = (WRAPAROUND_US - 10_000, WRAPAROUND_US - 5_000);
+        let delta = GroupDelta::new(earlier, (10_000, 20_000));
+        assert_eq!(delta.interdeparture_time_us, 20_000);
+        assert_eq!(delta.interarrival_time_us, 25_000);
+        assert_eq!(delta.intergroup_delay_us(), 5_000);
+
+        // Times outside of the domain are wrapped into it
+        assert_eq!(GroupDelta::new((-10_000, -5_000), (10_000, 20_000)), delta);
+        assert_eq!(
+            GroupDelta::new(earlier, (WRAPAROUND_US + 10_000, WRAPAROUND_US + 20_000)),
+            delta
+        );
+    }
+
+    #[test]
+    fn same_as_packet_groups() {
+        let time = TwccTime::from_micros_wrapping;
+        let earlier = PacketGroup::new(time(WRAPAROUND_US - 10_000), time(1_000), 1200, true);
+        let mut later = PacketGroup::new(time(5_000), time(12_000), 1200, true);
+        // Only the last packet of each group counts
+        later.add_packet(time(10_000), time(26_000), 1200, true);
+
+        assert_eq!(
+            GroupDelta::between(&earlier, &later),
+            GroupDelta::new((WRAPAROUND_US - 10_000, 1_000), (10_000, 26_000))
+        );
+        assert_eq!(
+            GroupDelta::between(&earlier, &later).intergroup_delay_us(),
+            5_000
+        );
+    }
+}
@@ -9,6 +9,11 @@ impl LossBasedBandwidthEstimator {
 
     pub fn estimate(&mut self, current_bandwidth: f64, received: u32, lost: u32) -> f64 {
         let total = received + lost;
+        // No feedback since the last estimate
+        if total == 0 {
+            return current_bandwidth;
+        }
+
         let fraction_lost = lost as f64 / total as f64;
         if fraction_lost < 0.02 {
             current_bandwidth * 1.05
@@ -19,3 +24,14 @@ impl LossBasedBandwidthEstimator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_feedback() {
+        let mut estimator = LossBasedBandwidthEstimator::new();
+        assert_eq!(estimator.estimate(125_000.0, 0, 0), 125_000.0);
+    }
+}
@@ -5,58 +5,139 @@ use webrtc::rtcp::transport_feedbacks::transport_layer_cc::{
     PacketStatusChunk, SymbolTypeTcc, TransportLayerCc,
 };
 
-use self::{delay_based::DelayBasedBandwidthEstimator, loss_based::LossBasedBandwidthEstimator};
-use super::{sync::TwccSendInfo, time::TwccTime, TwccBandwidthSender};
-use crate::network::data_rate::DataRate;
-use std::time::Instant;
+pub(super) use self::delay_based::DelayBasedBandwidthEstimator;
+pub use self::delay_based::{DelayBasedConfig, GroupDelta};
+use self::loss_based::LossBasedBandwidthEstimator;
+use super::{receive_rate::ReceiveRate, sync::TwccSendInfo, time::TwccTime, TwccBandwidthSender};
+use crate::network::{data_rate::DataRate, mtu_discovery::MtuDiscovery};
+use std::time::{Duration, Instant};
+
+// The encoders are considered to be limiting the bitrate if they report producing less than this
+// fraction of the estimate
+const APPLICATION_LIMITED_RATIO: f64 = 0.9;
+
+// How long a REMB caps the estimate. Senders repeat them at least every second, so the cap is
+// only lifted after several are missed, e.g., once the remote peer stops sending them.
+const REMB_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub struct TwccBandwidthEstimator {
     estimate_sender: TwccBandwidthSender,
     delay_based_estimator: DelayBasedBandwidthEstimator,
     loss_based_estimator: LossBasedBandwidthEstimator,
+    // Reset every estimate for the loss-based controller. The lifetime totals are kept in
+    // `estimate_sender`.
     received: u32,
     lost: u32,
+    // Created once enabled through `estimate_sender`
+    mtu_discovery: Option<MtuDiscovery>,
+    // Unsmoothed estimate that the controllers continue from, if smoothing is enabled
+    raw_bandwidth: Option<f64>,
+    last_estimate: Option<Instant>,
+    // Bitrate of the latest REMB from the remote peer, in bytes per second, and when it arrived
+    remb_bandwidth: Option<(f64, Instant)>,
 }
 
 impl TwccBandwidthEstimator {
-    pub fn new(estimate_sender: TwccBandwidthSender) -> TwccBandwidthEstimator {
+    pub fn new(
+        estimate_sender: TwccBandwidthSender,
+        delay_based_config: DelayBasedConfig,
+    ) -> TwccBandwidthEstimator {
         TwccBandwidthEstimator {
             estimate_sender,
-            delay_based_estimator: DelayBasedBandwidthEstimator::new(),
+            delay_based_estimator: DelayBasedBandwidthEstimator::new(delay_based_config),
             loss_based_estimator: LossBasedBandwidthEstimator::new(),
             received: 0,
             lost: 0,
+            mtu_discovery: None,
+            raw_bandwidth: None,
+            last_estimate: None,
+            remb_bandwidth: None,
         }
     }
 
     pub fn estimate(&mut self, now: Instant) {
-        let current_bandwidth = self.estimate_sender.borrow().bytes_per_sec_f64();
+        // What the encoders see, which lags behind the controllers with smoothing
+        let sent_bandwidth = self.estimate_sender.current().bytes_per_sec_f64();
+        let smoothing_time_constant = self.estimate_sender.smoothing_time_constant();
+        let current_bandwidth = match smoothing_time_constant {
+            Some(_) => self.raw_bandwidth.unwrap_or(sent_bandwidth),
+            None => sent_bandwidth,
+        };
+        let application_limited =
+            self.estimate_sender
+                .reported_output_bitrate()
+                .is_some_and(|output| {
+                    output.bytes_per_sec_f64() < APPLICATION_LIMITED_RATIO * sent_bandwidth
+                });
+        self.delay_based_estimator
+            .set_application_limited(application_limited);
+
         let a = self.delay_based_estimator.estimate(current_bandwidth, now);
         let b = self
             .loss_based_estimator
             .estimate(current_bandwidth, self.received, self.lost);
-        let bandwidth = f64::min(a, b);
-        self.estimate_sender
-            .send_if_modified(|data_rate: &mut DataRate| {
-                if bandwidth == current_bandwidth {
-                    false
-                } else {
-                    *data_rate = DataRate::from_bytes_per_sec_f64(bandwidth);
-                    true
-                }
-            });
+        let mut bandwidth = f64::min(a, b);
+        if let Some((remb_bandwidth, received_at)) = self.remb_bandwidth {
+            if now.saturating_duration_since(received_at) < REMB_TIMEOUT {
+                bandwidth = bandwidth.min(remb_bandwidth);
+            } else {
+                self.remb_bandwidth = None;
+            }
+        }
+
+        if let Some(time_constant) = smoothing_time_constant {
+            // Capped the same as the sent estimate so it does not run off past the maximum
+            let raw_bandwidth = self
+                .estimate_sender
+                .cap(DataRate::from_bytes_per_sec_f64(bandwidth))
+                .bytes_per_sec_f64();
+            self.raw_bandwidth = Some(raw_bandwidth);
+
+            let elapsed = self
+                .last_estimate
+                .map_or(0.0, |t| now.saturating_duration_since(t).as_secs_f64());
+            bandwidth = smooth_increase(
+                sent_bandwidth,
+                raw_bandwidth,
+                elapsed / time_constant.as_secs_f64(),
+            );
+        }
+        self.last_estimate = Some(now);
+
+        if bandwidth != sent_bandwidth {
+            self.estimate_sender
+                .send(DataRate::from_bytes_per_sec_f64(bandwidth));
+        }
 
         self.received = 0;
         self.lost = 0;
     }
 
     pub fn process_feedback(&mut self, tcc: &TransportLayerCc, send_info: &TwccSendInfo) {
+        let (prev_received, prev_lost) = (self.received, self.lost);
+        if self.mtu_discovery.is_none() {
+            self.mtu_discovery = self.estimate_sender.max_mtu().map(MtuDiscovery::new);
+        }
+
         let mut sequence_number = tcc.base_sequence_number;
         let mut arrival_time = TwccTime::extract_from_rtcp(tcc);
 
         let mut recv_deltas_iter = tcc.recv_deltas.iter();
+        // Status vector chunks are padded past the last packet with `PacketNotReceived`
+        let mut remaining_statuses = tcc.packet_status_count;
 
         let mut with_packet_status = |status: &SymbolTypeTcc| {
+            if remaining_statuses == 0 {
+                return;
+            }
+            remaining_statuses -= 1;
+
+            if let Some(mtu_discovery) = &mut self.mtu_discovery {
+                let (_, packet_size) = send_info.load_send_info(sequence_number);
+                let received = *status != SymbolTypeTcc::PacketNotReceived;
+                mtu_discovery.on_packet_feedback(packet_size as usize, received);
+            }
+
             match status {
                 SymbolTypeTcc::PacketNotReceived => {
                     self.lost += 1;
@@ -97,9 +178,319 @@ impl TwccBandwidthEstimator {
                 }
             }
         }
+
+        self.estimate_sender.record_packets(
+            (self.received - prev_received) as u64,
+            (self.lost - prev_lost) as u64,
+        );
+        if let Some(mtu_discovery) = &self.mtu_discovery {
+            self.estimate_sender.send_mtu(mtu_discovery.packet_size());
+        }
     }
 
     pub fn update_rtt(&mut self, rtt_ms: f64) {
         self.delay_based_estimator.update_rtt(rtt_ms);
     }
+
+    /// Cap the estimate to the bitrate of a REMB from the remote peer that arrived at `now`, for
+    /// endpoints that send REMB alongside the transport-cc feedback. Replaces the previous REMB.
+    /// The cap is lifted if no other REMB arrives within `REMB_TIMEOUT`.
+    pub fn update_remb(&mut self, remb: DataRate, now: Instant) {
+        self.remb_bandwidth = Some((remb.bytes_per_sec_f64(), now));
+    }
+}
+
+/// Moves `smoothed` toward `target` by an exponential moving average over `time_constants`
+/// worth of time if it is an increase. Decreases are followed immediately since holding on to a
+/// higher bitrate than the network can carry builds up queues.
+fn smooth_increase(smoothed: f64, target: f64, time_constants: f64) -> f64 {
+    if target <= smoothed {
+        target
+    } else {
+        smoothed + (target - smoothed) * (1.0 - (-time_constants).exp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codecs::CodecType,
+        interceptor::twcc::{twcc_bandwidth_estimate_channel, TwccPacketStats},
+    };
+    use webrtc::rtcp::transport_feedbacks::transport_layer_cc::{
+        RunLengthChunk, StatusChunkTypeTcc, StatusVectorChunk, SymbolSizeTypeTcc,
+    };
+
+    #[test]
+    fn estimate_without_feedback() {
+        let init_bandwidth = DataRate::from_bits_per_sec(1_000_000);
+        let (tx, rx) = twcc_bandwidth_estimate_channel(init_bandwidth);
+        let mut estimator = TwccBandwidthEstimator::new(tx, DelayBasedConfig::default());
+
+        let start = Instant::now();
+        for i in 1..=10 {
+            estimator.estimate(start + Duration::from_millis(100 * i));
+
+            let bandwidth = rx.borrow().bytes_per_sec_f64();
+            assert!(bandwidth.is_finite());
+            assert_eq!(bandwidth, init_bandwidth.bytes_per_sec_f64());
+        }
+    }
+
+    #[test]
+    fn remb_expires() {
+        let init_bandwidth = DataRate::from_bits_per_sec(1_000_000);
+        let remb = DataRate::from_bits_per_sec(500_000);
+        let (tx, rx) = twcc_bandwidth_estimate_channel(init_bandwidth);
+        let mut estimator = TwccBandwidthEstimator::new(tx, DelayBasedConfig::default());
+
+        // Not followed by another REMB in time
+        let start = Instant::now();
+        estimator.update_remb(remb, start);
+        estimator.estimate(start + REMB_TIMEOUT);
+        assert_eq!(rx.borrow().bits_per_sec(), 1_000_000);
+
+        let now = start + 2 * REMB_TIMEOUT;
+        estimator.update_remb(remb, now);
+        estimator.estimate(now + Duration::from_millis(100));
+        assert_eq!(rx.borrow().bits_per_sec(), 500_000);
+    }
+
+    #[test]
+    fn audio_reservation_under_congestion() {
+        const NUM_PACKETS: u16 = 20;
+
+        let init_bandwidth = DataRate::from_bits_per_sec(2_000_000);
+        let audio_reservation = DataRate::from_bits_per_sec(64_000);
+        let (tx, _rx) = twcc_bandwidth_estimate_channel(init_bandwidth);
+        tx.set_audio_reservation(Some(audio_reservation));
+        let audio_rx = tx.subscribe_for(CodecType::Audio);
+        let video_rx = tx.subscribe_for(CodecType::Video);
+        let mut estimator = TwccBandwidthEstimator::new(tx, DelayBasedConfig::default());
+
+        // Interleaved audio and video packets
+        let send_info = TwccSendInfo::try_new().unwrap();
+        for seq_num in 0..NUM_PACKETS {
+            let packet_size = if seq_num % 2 == 0 { 100 } else { 1000 };
+            send_info.store_send_info(
+                seq_num,
+                TwccTime::from_raw(seq_num as i64 * 1000),
+                packet_size,
+            );
+        }
+
+        // Half of the packets are lost
+        let run_length = |symbol, run_length| {
+            PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                packet_status_symbol: symbol,
+                run_length,
+            })
+        };
+        let tcc = TransportLayerCc {
+            base_sequence_number: 0,
+            packet_status_count: NUM_PACKETS,
+            packet_chunks: vec![
+                run_length(SymbolTypeTcc::PacketReceivedWithoutDelta, NUM_PACKETS / 2),
+                run_length(SymbolTypeTcc::PacketNotReceived, NUM_PACKETS / 2),
+            ],
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        for i in 1..=5 {
+            estimator.process_feedback(&tcc, &send_info);
+            estimator.estimate(start + Duration::from_millis(100 * i));
+        }
+
+        // Video absorbs the congestion
+        let video_bandwidth = video_rx.borrow().bits_per_sec();
+        assert!(video_bandwidth < init_bandwidth.bits_per_sec() / 2);
+        assert_eq!(
+            video_bandwidth,
+            estimator.estimate_sender.current().bits_per_sec() - audio_reservation.bits_per_sec()
+        );
+        assert_eq!(
+            audio_rx.borrow().bits_per_sec(),
+            audio_reservation.bits_per_sec()
+        );
+    }
+
+    #[test]
+    fn cumulative_packet_stats() {
+        const NUM_PACKETS: u16 = 20;
+
+        let (tx, _rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(1_000_000));
+        let stats_tx = tx.clone();
+        let mut estimator = TwccBandwidthEstimator::new(tx, DelayBasedConfig::default());
+
+        let send_info = TwccSendInfo::try_new().unwrap();
+        let tcc = |base_sequence_number, num_received, num_lost| TransportLayerCc {
+            base_sequence_number,
+            packet_status_count: num_received + num_lost,
+            packet_chunks: vec![
+                PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                    type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                    packet_status_symbol: SymbolTypeTcc::PacketReceivedWithoutDelta,
+                    run_length: num_received,
+                }),
+                PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                    type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                    packet_status_symbol: SymbolTypeTcc::PacketNotReceived,
+                    run_length: num_lost,
+                }),
+            ],
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let mut expected = TwccPacketStats::default();
+        for i in 0..5 {
+            // Two feedbacks per estimate with a different loss each time
+            for j in 0..2 {
+                let num_lost = i + j;
+                let num_received = NUM_PACKETS - num_lost;
+                let base_sequence_number = (2 * i + j) * NUM_PACKETS;
+                estimator.process_feedback(
+                    &tcc(base_sequence_number, num_received, num_lost),
+                    &send_info,
+                );
+                expected.received += num_received as u64;
+                expected.lost += num_lost as u64;
+            }
+            estimator.estimate(start + Duration::from_millis(100 * (i as u64 + 1)));
+
+            // Per-interval counters are reset but not the totals
+            assert_eq!((estimator.received, estimator.lost), (0, 0));
+            assert_eq!(stats_tx.packet_stats(), expected);
+        }
+        assert_eq!(
+            expected,
+            TwccPacketStats {
+                received: 175,
+                lost: 25
+            }
+        );
+    }
+
+    #[test]
+    fn status_vector_padding() {
+        let (tx, _rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(1_000_000));
+        let mut estimator = TwccBandwidthEstimator::new(tx, DelayBasedConfig::default());
+
+        // Three packets in a vector chunk of seven symbols, as unmarshaled from the wire
+        let mut symbol_list = vec![SymbolTypeTcc::PacketReceivedWithoutDelta; 3];
+        symbol_list.resize(7, SymbolTypeTcc::PacketNotReceived);
+        let tcc = TransportLayerCc {
+            packet_status_count: 3,
+            packet_chunks: vec![PacketStatusChunk::StatusVectorChunk(StatusVectorChunk {
+                type_tcc: StatusChunkTypeTcc::StatusVectorChunk,
+                symbol_size: SymbolSizeTypeTcc::TwoBit,
+                symbol_list,
+            })],
+            ..Default::default()
+        };
+        estimator.process_feedback(&tcc, &TwccSendInfo::try_new().unwrap());
+
+        assert_eq!((estimator.received, estimator.lost), (3, 0));
+    }
+
+    #[test]
+    fn mtu_discovery() {
+        const DROPPED_ABOVE: usize = 1300;
+        const NUM_PACKETS: u16 = 20;
+
+        let (tx, _rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(1_000_000));
+        tx.enable_mtu_discovery(1500);
+        let mut mtu_rx = tx.subscribe_mtu();
+        let mut estimator = TwccBandwidthEstimator::new(tx, DelayBasedConfig::default());
+
+        let send_info = TwccSendInfo::try_new().unwrap();
+        let mut base_sequence_number = 0u16;
+        for _ in 0..20 {
+            // Packets of the current MTU, dropped by the link if too large
+            let mtu = *mtu_rx.borrow_and_update();
+            for i in 0..NUM_PACKETS {
+                let seq_num = base_sequence_number.wrapping_add(i);
+                send_info.store_send_info(seq_num, TwccTime::from_raw(0), mtu as u64);
+            }
+            let packet_status_symbol = if mtu <= DROPPED_ABOVE {
+                SymbolTypeTcc::PacketReceivedWithoutDelta
+            } else {
+                SymbolTypeTcc::PacketNotReceived
+            };
+            let tcc = TransportLayerCc {
+                base_sequence_number,
+                packet_status_count: NUM_PACKETS,
+                packet_chunks: vec![PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                    type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                    packet_status_symbol,
+                    run_length: NUM_PACKETS,
+                })],
+                ..Default::default()
+            };
+            estimator.process_feedback(&tcc, &send_info);
+            base_sequence_number = base_sequence_number.wrapping_add(NUM_PACKETS);
+        }
+
+        let mtu = *mtu_rx.borrow();
+        assert!(mtu > 1200 && mtu <= DROPPED_ABOVE);
+    }
+
+    #[test]
+    fn smoothing_is_asymmetric() {
+        const NUM_PACKETS: u16 = 20;
+
+        let init_bandwidth = DataRate::from_bits_per_sec(1_000_000);
+        let (raw_tx, raw_rx) = twcc_bandwidth_estimate_channel(init_bandwidth);
+        let (smoothed_tx, smoothed_rx) = twcc_bandwidth_estimate_channel(init_bandwidth);
+        smoothed_tx.enable_smoothing(Duration::from_secs(1));
+        let mut raw_estimator = TwccBandwidthEstimator::new(raw_tx, DelayBasedConfig::default());
+        let mut smoothed_estimator =
+            TwccBandwidthEstimator::new(smoothed_tx, DelayBasedConfig::default());
+
+        let send_info = TwccSendInfo::try_new().unwrap();
+        let tcc = |base_sequence_number, packet_status_symbol| TransportLayerCc {
+            base_sequence_number,
+            packet_status_count: NUM_PACKETS,
+            packet_chunks: vec![PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                packet_status_symbol,
+                run_length: NUM_PACKETS,
+            })],
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let mut base_sequence_number = 0u16;
+        let mut run = |i: u64, packet_status_symbol| {
+            let tcc = tcc(base_sequence_number, packet_status_symbol);
+            base_sequence_number = base_sequence_number.wrapping_add(NUM_PACKETS);
+            let now = start + Duration::from_millis(100 * i);
+            for estimator in [&mut raw_estimator, &mut smoothed_estimator] {
+                estimator.process_feedback(&tcc, &send_info);
+                estimator.estimate(now);
+            }
+            (
+                raw_rx.borrow().bits_per_sec(),
+                smoothed_rx.borrow().bits_per_sec(),
+            )
+        };
+
+        // No loss, the estimate ramps up
+        let mut bandwidths = (0, 0);
+        for i in 1..=20 {
+            bandwidths = run(i, SymbolTypeTcc::PacketReceivedWithoutDelta);
+            assert!(bandwidths.1 <= bandwidths.0);
+        }
+        let (raw, smoothed) = bandwidths;
+        assert!(smoothed > init_bandwidth.bits_per_sec());
+        assert!(smoothed < raw - (raw - init_bandwidth.bits_per_sec()) / 4);
+
+        // Everything lost, the drop is not smoothed
+        let (raw, smoothed) = run(21, SymbolTypeTcc::PacketNotReceived);
+        assert!(raw < init_bandwidth.bits_per_sec());
+        assert_eq!(smoothed, raw);
+    }
 }
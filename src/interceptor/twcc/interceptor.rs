@@ -1,9 +1,13 @@
 use super::{
-    estimator::TwccBandwidthEstimator, sender::TwccTimestampSenderStream, sync::TwccSendInfo,
+    estimator::{DelayBasedConfig, TwccBandwidthEstimator},
+    sender::TwccTimestampSenderStream,
+    sync::TwccSendInfo,
     TwccBandwidthSender,
 };
+use crate::network::data_rate::DataRate;
 use async_trait::async_trait;
 use std::{
+    collections::TryReserveError,
     sync::Arc,
     time::{Instant, SystemTime},
 };
@@ -14,15 +18,19 @@ use webrtc::{
         RTCPWriter, RTPReader, RTPWriter,
     },
     rtcp::{
-        self, receiver_report::ReceiverReport,
-        transport_feedbacks::transport_layer_cc::TransportLayerCc,
+        self,
+        payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate,
+        receiver_report::ReceiverReport, transport_feedbacks::transport_layer_cc::TransportLayerCc,
     },
     rtp::extension::abs_send_time_extension::unix2ntp,
+    sdp,
 };
 
 pub struct TwccStream {
     map: TwccSendInfo,
-    bandwidth_estimator: Mutex<TwccBandwidthEstimator>,
+    // Shared with `TwccBandwidthSender::recompute_estimate`
+    bandwidth_estimator: Arc<Mutex<TwccBandwidthEstimator>>,
+    estimate: TwccBandwidthSender,
     next_reader: Arc<dyn RTCPReader + Send + Sync>,
 }
 
@@ -30,11 +38,18 @@ impl TwccStream {
     pub fn new(
         map: TwccSendInfo,
         estimate: TwccBandwidthSender,
+        delay_based_config: DelayBasedConfig,
         next_reader: Arc<dyn RTCPReader + Send + Sync>,
     ) -> TwccStream {
+        let bandwidth_estimator = Arc::new(Mutex::new(TwccBandwidthEstimator::new(
+            estimate.clone(),
+            delay_based_config,
+        )));
+        estimate.set_estimator(&bandwidth_estimator);
         TwccStream {
             map,
-            bandwidth_estimator: Mutex::new(TwccBandwidthEstimator::new(estimate)),
+            bandwidth_estimator,
+            estimate,
             next_reader,
         }
     }
@@ -49,25 +64,45 @@ impl RTCPReader for TwccStream {
     ) -> Result<(usize, Attributes), interceptor::Error> {
         let (n, attr) = self.next_reader.read(buf, attributes).await?;
 
-        if let Some(byte) = buf.get(1) {
-            // Payload types of TWCC and receiver report
-            if let 205 | 201 = byte {
-                let mut b = &buf[..n];
-                let packets = rtcp::packet::unmarshal(&mut b)?;
-
-                let mut bandwidth_estimator = self.bandwidth_estimator.lock().await;
-
-                for packet in packets {
-                    let packet = packet.as_any();
-                    if let Some(tcc) = packet.downcast_ref::<TransportLayerCc>() {
-                        bandwidth_estimator.process_feedback(tcc, &self.map);
-                    } else if let Some(rr) = packet.downcast_ref::<ReceiverReport>() {
-                        if let Some(rtt_ms) = rtt_from_receiver_report(rr) {
-                            bandwidth_estimator.update_rtt(rtt_ms);
-                        }
+        // Only parse compound packets with something for the bandwidth estimator. Everything is
+        // returned as is for the readers after this one, e.g., the `RTCRtpSender`.
+        let mut used = false;
+        for (packet_type, fmt) in rtcp_headers(&buf[..n]) {
+            if is_used_by_estimator(packet_type, fmt) {
+                used = true;
+            } else {
+                log::trace!("Bandwidth estimator ignored RTCP packet type {packet_type} FMT {fmt}");
+                self.estimate.record_ignored_rtcp_packet();
+            }
+        }
+
+        if used {
+            let mut b = &buf[..n];
+            let packets = rtcp::packet::unmarshal(&mut b)?;
+
+            let mut bandwidth_estimator = self.bandwidth_estimator.lock().await;
+            let now = Instant::now();
+
+            // The rest of the compound packet was already counted as ignored
+            let mut processed = false;
+            for packet in packets {
+                let packet = packet.as_any();
+                if let Some(tcc) = packet.downcast_ref::<TransportLayerCc>() {
+                    bandwidth_estimator.process_feedback(tcc, &self.map);
+                } else if let Some(rr) = packet.downcast_ref::<ReceiverReport>() {
+                    if let Some(rtt_ms) = rtt_from_receiver_report(rr) {
+                        bandwidth_estimator.update_rtt(rtt_ms);
                     }
+                } else if let Some(remb) = packet.downcast_ref::<ReceiverEstimatedMaximumBitrate>()
+                {
+                    bandwidth_estimator
+                        .update_remb(DataRate::from_bits_per_sec(remb.bitrate as u64), now);
+                } else {
+                    continue;
                 }
-                let now = Instant::now();
+                processed = true;
+            }
+            if processed {
                 bandwidth_estimator.estimate(now);
             }
         }
@@ -76,6 +111,23 @@ impl RTCPReader for TwccStream {
     }
 }
 
+/// Payload type and FMT (or report count) of each packet in a compound RTCP packet.
+fn rtcp_headers(mut buf: &[u8]) -> impl Iterator<Item = (u8, u8)> + '_ {
+    std::iter::from_fn(move || {
+        let header = buf.get(..4)?;
+        // The length is in 32-bit words minus one
+        let len = (u16::from_be_bytes([header[2], header[3]]) as usize + 1) * 4;
+        let header = (header[1], header[0] & 0x1f);
+        buf = buf.get(len..).unwrap_or_default();
+        Some(header)
+    })
+}
+
+/// Whether the packet is a receiver report, TWCC feedback, or REMB.
+fn is_used_by_estimator(packet_type: u8, fmt: u8) -> bool {
+    matches!((packet_type, fmt), (201, _) | (205, 15) | (206, 15))
+}
+
 fn rtt_from_receiver_report(rr: &ReceiverReport) -> Option<f64> {
     let now = (unix2ntp(SystemTime::now()) >> 16) as u32;
 
@@ -96,6 +148,7 @@ fn calculate_rtt_ms(now: u32, delay: u32, last_sender_report: u32) -> f64 {
 pub struct TwccInterceptor {
     map: TwccSendInfo,
     estimate_sender: Arc<Mutex<Option<TwccBandwidthSender>>>,
+    delay_based_config: DelayBasedConfig,
     start_time: Instant,
 }
 
@@ -107,7 +160,12 @@ impl Interceptor for TwccInterceptor {
     ) -> Arc<dyn RTCPReader + Send + Sync> {
         let mut lock = self.estimate_sender.lock().await;
         if let Some(sender) = std::mem::take(&mut *lock) {
-            Arc::new(TwccStream::new(self.map.clone(), sender, reader))
+            Arc::new(TwccStream::new(
+                self.map.clone(),
+                sender,
+                self.delay_based_config,
+                reader,
+            ))
         } else {
             reader
         }
@@ -125,12 +183,11 @@ impl Interceptor for TwccInterceptor {
         info: &StreamInfo,
         writer: Arc<dyn RTPWriter + Send + Sync>,
     ) -> Arc<dyn RTPWriter + Send + Sync> {
-        const TRANSPORT_CC_URI: &str =
-            "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01";
-
+        // Audio streams are included as long as the extension was also negotiated for audio, which
+        // `configure_twcc` does
         let mut hdr_ext_id = 0u8;
         for e in &info.rtp_header_extensions {
-            if e.uri == TRANSPORT_CC_URI {
+            if e.uri == sdp::extmap::TRANSPORT_CC_URI {
                 hdr_ext_id = e.id as u8;
                 break;
             }
@@ -138,12 +195,10 @@ impl Interceptor for TwccInterceptor {
         if hdr_ext_id == 0 {
             return writer;
         }
-        Arc::new(TwccTimestampSenderStream::new(
-            self.map.clone(),
-            hdr_ext_id,
-            writer,
-            self.start_time,
-        ))
+        Arc::new(
+            TwccTimestampSenderStream::new(self.map.clone(), hdr_ext_id, writer, self.start_time)
+                .with_late_timestamps(self.delay_based_config.late_send_timestamps()),
+        )
     }
 
     async fn unbind_local_stream(&self, _info: &StreamInfo) {}
@@ -166,14 +221,29 @@ impl Interceptor for TwccInterceptor {
 pub struct TwccInterceptorBuilder {
     map: TwccSendInfo,
     estimate_sender: Arc<Mutex<Option<TwccBandwidthSender>>>,
+    delay_based_config: DelayBasedConfig,
 }
 
 impl TwccInterceptorBuilder {
-    pub fn new(estimate: TwccBandwidthSender) -> TwccInterceptorBuilder {
-        TwccInterceptorBuilder {
-            map: TwccSendInfo::new(),
+    pub fn new(
+        estimate: TwccBandwidthSender,
+        delay_based_config: DelayBasedConfig,
+    ) -> TwccInterceptorBuilder {
+        TwccInterceptorBuilder::try_new(estimate, delay_based_config)
+            .expect("Failed to allocate `TwccSendInfo`")
+    }
+
+    /// Same as [`TwccInterceptorBuilder::new`] but returns an error instead of panicking if the
+    /// ~1 MB needed for storing the send info of the packets could not be allocated.
+    pub fn try_new(
+        estimate: TwccBandwidthSender,
+        delay_based_config: DelayBasedConfig,
+    ) -> Result<TwccInterceptorBuilder, TryReserveError> {
+        Ok(TwccInterceptorBuilder {
+            map: TwccSendInfo::try_new()?,
             estimate_sender: Arc::new(Mutex::new(Some(estimate))),
-        }
+            delay_based_config,
+        })
     }
 }
 
@@ -182,7 +252,213 @@ impl InterceptorBuilder for TwccInterceptorBuilder {
         Ok(Arc::new(TwccInterceptor {
             map: self.map.clone(),
             estimate_sender: self.estimate_sender.clone(),
+            delay_based_config: self.delay_based_config,
             start_time: Instant::now(),
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codecs::{Codec, MediaEngineExt},
+        interceptor::{
+            tests::{DiscardRtp, DummyRtcpReader},
+            twcc::twcc_bandwidth_estimate_channel,
+        },
+        network::data_rate::DataRate,
+    };
+    use bytes::Bytes;
+    use std::time::Duration;
+    use webrtc::{
+        api::{interceptor_registry::configure_twcc, media_engine::MediaEngine, APIBuilder},
+        interceptor::{registry::Registry, stream_info::RTPHeaderExtension},
+        rtcp::{
+            payload_feedbacks::picture_loss_indication::PictureLossIndication,
+            sender_report::SenderReport,
+            transport_feedbacks::transport_layer_cc::{
+                PacketStatusChunk, RunLengthChunk, StatusChunkTypeTcc, SymbolTypeTcc,
+            },
+        },
+        rtp,
+        rtp_transceiver::rtp_codec::RTPCodecType,
+        util::Marshal,
+    };
+
+    #[tokio::test]
+    async fn remb_caps_estimate() {
+        let (tx, rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(2_000_000));
+        let remb = ReceiverEstimatedMaximumBitrate {
+            sender_ssrc: 1,
+            bitrate: 500_000.0,
+            ssrcs: vec![1234],
+        };
+        let stream = TwccStream::new(
+            TwccSendInfo::try_new().unwrap(),
+            tx,
+            DelayBasedConfig::default(),
+            Arc::new(DummyRtcpReader::new(remb.marshal().unwrap())),
+        );
+
+        let mut buf = vec![0u8; 1500];
+        stream.read(&mut buf, &Attributes::new()).await.unwrap();
+        assert_eq!(rx.borrow().bits_per_sec(), 500_000);
+    }
+
+    #[tokio::test]
+    async fn forced_recompute() {
+        const NUM_PACKETS: u16 = 20;
+
+        let (tx, rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(1_000_000));
+        assert!(tx.recompute_estimate().await.is_none());
+
+        // The estimate catches up to the raw one over time
+        tx.enable_smoothing(Duration::from_secs(1));
+        let tcc = TransportLayerCc {
+            sender_ssrc: 1,
+            media_ssrc: 1234,
+            packet_status_count: NUM_PACKETS,
+            packet_chunks: vec![PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                packet_status_symbol: SymbolTypeTcc::PacketReceivedWithoutDelta,
+                run_length: NUM_PACKETS,
+            })],
+            ..Default::default()
+        };
+        let stream = TwccStream::new(
+            TwccSendInfo::try_new().unwrap(),
+            tx.clone(),
+            DelayBasedConfig::default(),
+            Arc::new(DummyRtcpReader::new(tcc.marshal().unwrap())),
+        );
+
+        // Without loss, the raw estimate goes up but the smoothed one does not move yet
+        let mut buf = vec![0u8; 1500];
+        stream.read(&mut buf, &Attributes::new()).await.unwrap();
+        assert_eq!(rx.borrow().bits_per_sec(), 1_000_000);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let estimate = tx.recompute_estimate().await.unwrap();
+        assert!(estimate.bits_per_sec() > 1_000_000);
+        assert_eq!(rx.borrow().bits_per_sec(), estimate.bits_per_sec());
+    }
+
+    #[tokio::test]
+    async fn sender_report_passed_through() {
+        let (tx, rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(2_000_000));
+        let sr = SenderReport {
+            ssrc: 1234,
+            packet_count: 10,
+            octet_count: 10_000,
+            ..Default::default()
+        };
+        let rr = ReceiverReport {
+            ssrc: 1,
+            ..Default::default()
+        };
+        let pli = PictureLossIndication {
+            sender_ssrc: 1,
+            media_ssrc: 1234,
+        };
+        let compound = [sr.marshal().unwrap(), pli.marshal().unwrap()].concat();
+        let stream = TwccStream::new(
+            TwccSendInfo::try_new().unwrap(),
+            tx.clone(),
+            DelayBasedConfig::default(),
+            Arc::new(DummyRtcpReader::new(compound.clone().into())),
+        );
+
+        let mut buf = vec![0u8; 1500];
+        let (n, _) = stream.read(&mut buf, &Attributes::new()).await.unwrap();
+        assert_eq!(buf[..n], compound);
+        assert_eq!(tx.ignored_rtcp_packets(), 2);
+        assert!(!rx.has_changed().unwrap());
+        assert_eq!(rx.borrow().bits_per_sec(), 2_000_000);
+
+        // Only the sender report is ignored when it comes with a receiver report
+        let compound = [sr.marshal().unwrap(), rr.marshal().unwrap()].concat();
+        let stream = TwccStream::new(
+            TwccSendInfo::try_new().unwrap(),
+            tx.clone(),
+            DelayBasedConfig::default(),
+            Arc::new(DummyRtcpReader::new(compound.into())),
+        );
+        stream.read(&mut buf, &Attributes::new()).await.unwrap();
+        assert_eq!(tx.ignored_rtcp_packets(), 3);
+    }
+
+    #[tokio::test]
+    async fn audio_offers_transport_cc() {
+        let mut media_engine = MediaEngine::default();
+        media_engine.register_custom_codec(Codec::opus()).unwrap();
+        let registry = configure_twcc(Registry::new(), &mut media_engine).unwrap();
+
+        let api = APIBuilder::new()
+            .with_media_engine(media_engine)
+            .with_interceptor_registry(registry)
+            .build();
+        let pc = api.new_peer_connection(Default::default()).await.unwrap();
+        pc.add_transceiver_from_kind(RTPCodecType::Audio, &[])
+            .await
+            .unwrap();
+
+        let offer = pc.create_offer(None).await.unwrap();
+        let audio_section = offer.sdp.split("m=audio").nth(1).unwrap();
+        assert!(audio_section.contains(sdp::extmap::TRANSPORT_CC_URI));
+
+        pc.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn audio_send_info_captured() {
+        const HDR_EXT_ID: isize = 3;
+        const NUM_PACKETS: u16 = 10;
+
+        let (tx, _rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(1_000_000));
+        let builder = TwccInterceptorBuilder::new(tx, DelayBasedConfig::default());
+        let map = builder.map.clone();
+
+        // Same order as `WebRtcBuilder`, the transport-cc extension is set before our interceptor
+        // sees the packet
+        let mut registry = Registry::new();
+        registry.add(Box::new(builder));
+        registry.add(Box::new(
+            webrtc::interceptor::twcc::sender::Sender::builder(),
+        ));
+        let chain = registry.build("").unwrap();
+
+        let info = StreamInfo {
+            ssrc: 1234,
+            mime_type: "audio/opus".to_owned(),
+            clock_rate: 48000,
+            channels: 2,
+            rtp_header_extensions: vec![RTPHeaderExtension {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+                id: HDR_EXT_ID,
+            }],
+            ..Default::default()
+        };
+        let writer = chain.bind_local_stream(&info, Arc::new(DiscardRtp)).await;
+
+        for i in 0..NUM_PACKETS {
+            let pkt = rtp::packet::Packet {
+                header: rtp::header::Header {
+                    ssrc: 1234,
+                    sequence_number: i,
+                    ..Default::default()
+                },
+                payload: Bytes::from(vec![0u8; 100 + i as usize]),
+            };
+            writer.write(&pkt, &Attributes::new()).await.unwrap();
+        }
+
+        // Transport-wide sequence numbers start at 0
+        for i in 0..NUM_PACKETS {
+            let (_, packet_size) = map.load_send_info(i);
+            assert_eq!(packet_size, 100 + i as u64);
+        }
+
+        chain.close().await.unwrap();
+    }
+}
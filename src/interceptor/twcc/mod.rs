@@ -1,20 +1,366 @@
+#[cfg(test)]
+mod emulation;
 mod estimator;
 mod interceptor;
+mod receive_rate;
+mod remb;
+#[cfg(test)]
+mod replay;
+mod send_time;
 mod sender;
 mod sync;
 mod time;
 
-use crate::network::data_rate::DataRate;
+use crate::{
+    codecs::CodecType,
+    network::{data_rate::DataRate, mtu_discovery::INITIAL_MTU},
+};
+use estimator::TwccBandwidthEstimator;
+pub use estimator::{DelayBasedConfig, GroupDelta};
 pub use interceptor::TwccInterceptorBuilder;
+pub use remb::RembInterceptorBuilder;
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
 use tokio::sync::watch;
 
 pub type TwccBandwidthEstimate = watch::Receiver<DataRate>;
 
-pub type TwccBandwidthSender = watch::Sender<DataRate>;
+/// Largest RTP payload the encoders should produce, for fragmenting their samples.
+pub type PacketizationMtu = watch::Receiver<usize>;
+
+/// Lifetime packet counts from the TWCC feedback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TwccPacketStats {
+    /// Number of packets reported as received.
+    pub received: u64,
+    /// Number of packets reported as not received.
+    pub lost: u64,
+}
+
+/// Handle for an encoder to report the bitrate it actually produces, which could be well below the
+/// bandwidth estimate, e.g., on a static scene. Otherwise the bandwidth estimator would treat the
+/// resulting drop in the received bandwidth as if the available bandwidth shrank.
+#[derive(Clone)]
+pub struct OutputBitrateReporter(Arc<AtomicU64>);
+
+impl OutputBitrateReporter {
+    // Reserved for encoders that have not reported yet
+    const UNREPORTED: u64 = u64::MAX;
+
+    /// Report the bitrate produced by the encoder, e.g., averaged over the last second.
+    pub fn report(&self, bitrate: DataRate) {
+        let bits_per_sec = bitrate.bits_per_sec().min(Self::UNREPORTED - 1);
+        self.0.store(bits_per_sec, Ordering::Release);
+    }
+}
+
+/// Sending side of the bandwidth estimate. Every estimate sent is capped to the maximum bandwidth
+/// if one is set.
+///
+/// The estimate is also split between the audio and video encoders. By default, both see the
+/// whole estimate. With an audio reservation, audio encoders always get the reserved bandwidth
+/// while video encoders get what remains, so that congestion only affects the video.
+#[derive(Clone)]
+pub struct TwccBandwidthSender {
+    sender: Arc<watch::Sender<DataRate>>,
+    audio_sender: Arc<watch::Sender<DataRate>>,
+    video_sender: Arc<watch::Sender<DataRate>>,
+    max_bits_per_sec: Arc<AtomicU64>,
+    audio_reservation_bits_per_sec: Arc<AtomicU64>,
+    packets_received: Arc<AtomicU64>,
+    packets_lost: Arc<AtomicU64>,
+    ignored_rtcp_packets: Arc<AtomicU64>,
+    // Weak so that the handles dropped by the encoders stop counting
+    output_reporters: Arc<Mutex<Vec<Weak<AtomicU64>>>>,
+    mtu_sender: Arc<watch::Sender<usize>>,
+    // Zero while MTU discovery is disabled
+    max_mtu: Arc<AtomicUsize>,
+    // Zero while smoothing is disabled
+    smoothing_time_constant_us: Arc<AtomicU64>,
+    // Fed by the RTCP reader of the TWCC interceptor once it is bound
+    estimator: Arc<Mutex<Weak<tokio::sync::Mutex<TwccBandwidthEstimator>>>>,
+}
+
+impl TwccBandwidthSender {
+    /// Create a new receiver for the bandwidth estimate.
+    pub fn subscribe(&self) -> TwccBandwidthEstimate {
+        self.sender.subscribe()
+    }
+
+    /// Create a new receiver for the share of the bandwidth estimate given to encoders of
+    /// `codec_type`.
+    pub fn subscribe_for(&self, codec_type: CodecType) -> TwccBandwidthEstimate {
+        match codec_type {
+            CodecType::Audio => self.audio_sender.subscribe(),
+            CodecType::Video => self.video_sender.subscribe(),
+        }
+    }
+
+    /// The last bandwidth estimate sent.
+    pub fn current(&self) -> DataRate {
+        *self.sender.borrow()
+    }
+
+    /// Send a new bandwidth estimate. The receivers are only notified if the estimate changed.
+    pub fn send(&self, estimate: DataRate) {
+        let estimate = self.cap(estimate);
+        send_if_changed(&self.sender, estimate);
+        self.split(estimate);
+    }
+
+    /// Recompute the bandwidth estimate now instead of waiting for the next RTCP feedback, e.g.,
+    /// right before an adaptation decision, and send it if it changed. Returns the estimate, or
+    /// [None] if the TWCC interceptor has not bound its RTCP reader yet.
+    pub async fn recompute_estimate(&self) -> Option<DataRate> {
+        let estimator = self.estimator.lock().unwrap().upgrade()?;
+        estimator.lock().await.estimate(Instant::now());
+        Some(self.current())
+    }
+
+    /// Set an upper bound on the bandwidth estimate, e.g., from the `b=AS` of the remote
+    /// description. The current estimate is capped immediately instead of waiting for the next
+    /// feedback.
+    pub fn set_max_bandwidth(&self, max_bandwidth: Option<DataRate>) {
+        let max_bits_per_sec = max_bandwidth.map_or(u64::MAX, |max| max.bits_per_sec());
+        self.max_bits_per_sec
+            .store(max_bits_per_sec, Ordering::Release);
+        self.send(self.current());
+    }
+
+    /// Reserve bandwidth for the audio encoders that is never given to the video encoders.
+    /// `None` lets every encoder see the whole estimate.
+    pub fn set_audio_reservation(&self, audio_reservation: Option<DataRate>) {
+        let audio_reservation_bits_per_sec = audio_reservation.map_or(0, |r| r.bits_per_sec());
+        self.audio_reservation_bits_per_sec
+            .store(audio_reservation_bits_per_sec, Ordering::Release);
+        self.split(self.current());
+    }
+
+    /// Packet counts from every TWCC feedback processed so far.
+    pub fn packet_stats(&self) -> TwccPacketStats {
+        TwccPacketStats {
+            received: self.packets_received.load(Ordering::Acquire),
+            lost: self.packets_lost.load(Ordering::Acquire),
+        }
+    }
+
+    /// Number of RTCP packets from the remote peer that the bandwidth estimator did not use, i.e.,
+    /// anything other than TWCC feedback, receiver reports, and REMBs. These are still passed on
+    /// to the rest of the interceptors and to the `RTCRtpSender`.
+    pub fn ignored_rtcp_packets(&self) -> u64 {
+        self.ignored_rtcp_packets.load(Ordering::Acquire)
+    }
+
+    /// Create a handle for an encoder to report its output bitrate. The reports are only used once
+    /// every encoder still holding a handle has reported. A handle stops counting once every clone
+    /// of it is dropped.
+    pub fn output_bitrate_reporter(&self) -> OutputBitrateReporter {
+        let reporter =
+            OutputBitrateReporter(Arc::new(AtomicU64::new(OutputBitrateReporter::UNREPORTED)));
+        self.output_reporters
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&reporter.0));
+        reporter
+    }
+
+    /// Total output bitrate reported by the encoders. Returns `None` if there are no reporters or
+    /// if any of them has not reported yet.
+    pub(crate) fn reported_output_bitrate(&self) -> Option<DataRate> {
+        let mut reporters = self.output_reporters.lock().unwrap();
+        reporters.retain(|reporter| reporter.strong_count() > 0);
+        if reporters.is_empty() {
+            return None;
+        }
+
+        let mut total_bits_per_sec = 0u64;
+        for reporter in reporters.iter().filter_map(Weak::upgrade) {
+            match reporter.load(Ordering::Acquire) {
+                OutputBitrateReporter::UNREPORTED => return None,
+                bits_per_sec => {
+                    total_bits_per_sec = total_bits_per_sec.saturating_add(bits_per_sec)
+                }
+            }
+        }
+        Some(DataRate::from_bits_per_sec(total_bits_per_sec))
+    }
+
+    /// Probe for a packetization MTU larger than the initial 1200 bytes, up to `max_mtu`. The
+    /// probing is driven by the loss of the larger packets in the TWCC feedback.
+    pub fn enable_mtu_discovery(&self, max_mtu: usize) {
+        self.max_mtu.store(max_mtu, Ordering::Release);
+    }
+
+    /// Smooth the increases of the estimate with an exponential moving average of the given time
+    /// constant so that the encoders do not thrash between bitrates. Decreases are sent right
+    /// away.
+    pub fn enable_smoothing(&self, time_constant: Duration) {
+        self.smoothing_time_constant_us
+            .store(time_constant.as_micros() as u64, Ordering::Release);
+    }
+
+    /// Time constant of the smoothing, if enabled.
+    pub(crate) fn smoothing_time_constant(&self) -> Option<Duration> {
+        match self.smoothing_time_constant_us.load(Ordering::Acquire) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
+        }
+    }
+
+    /// Create a new receiver for the packetization MTU. Stays at 1200 bytes unless MTU discovery
+    /// is enabled.
+    pub fn subscribe_mtu(&self) -> PacketizationMtu {
+        self.mtu_sender.subscribe()
+    }
+
+    /// Upper bound for MTU discovery, if enabled.
+    pub(crate) fn max_mtu(&self) -> Option<usize> {
+        match self.max_mtu.load(Ordering::Acquire) {
+            0 => None,
+            max_mtu => Some(max_mtu),
+        }
+    }
+
+    pub(crate) fn send_mtu(&self, mtu: usize) {
+        self.mtu_sender.send_if_modified(|current| {
+            if *current == mtu {
+                false
+            } else {
+                *current = mtu;
+                true
+            }
+        });
+    }
+
+    pub(crate) fn record_packets(&self, received: u64, lost: u64) {
+        self.packets_received.fetch_add(received, Ordering::AcqRel);
+        self.packets_lost.fetch_add(lost, Ordering::AcqRel);
+    }
+
+    pub(crate) fn set_estimator(
+        &self,
+        estimator: &Arc<tokio::sync::Mutex<TwccBandwidthEstimator>>,
+    ) {
+        *self.estimator.lock().unwrap() = Arc::downgrade(estimator);
+    }
+
+    pub(crate) fn record_ignored_rtcp_packet(&self) {
+        self.ignored_rtcp_packets.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub(crate) fn cap(&self, estimate: DataRate) -> DataRate {
+        let max_bits_per_sec = self.max_bits_per_sec.load(Ordering::Acquire);
+        if estimate.bits_per_sec() > max_bits_per_sec {
+            DataRate::from_bits_per_sec(max_bits_per_sec)
+        } else {
+            estimate
+        }
+    }
+
+    fn split(&self, estimate: DataRate) {
+        let audio_reservation_bits_per_sec =
+            self.audio_reservation_bits_per_sec.load(Ordering::Acquire);
+        if audio_reservation_bits_per_sec == 0 {
+            send_if_changed(&self.audio_sender, estimate);
+            send_if_changed(&self.video_sender, estimate);
+        } else {
+            let video_bits_per_sec = estimate
+                .bits_per_sec()
+                .saturating_sub(audio_reservation_bits_per_sec);
+            send_if_changed(
+                &self.audio_sender,
+                DataRate::from_bits_per_sec(audio_reservation_bits_per_sec),
+            );
+            send_if_changed(
+                &self.video_sender,
+                DataRate::from_bits_per_sec(video_bits_per_sec),
+            );
+        }
+    }
+}
+
+fn send_if_changed(sender: &watch::Sender<DataRate>, estimate: DataRate) {
+    sender.send_if_modified(|data_rate: &mut DataRate| {
+        if *data_rate == estimate {
+            false
+        } else {
+            *data_rate = estimate;
+            true
+        }
+    });
+}
 
 /// Create a new channel for sending/receiving the bandwidth estimate.
 pub(crate) fn twcc_bandwidth_estimate_channel(
     init: DataRate,
-) -> (watch::Sender<DataRate>, watch::Receiver<DataRate>) {
-    watch::channel(init)
+) -> (TwccBandwidthSender, TwccBandwidthEstimate) {
+    let (tx, rx) = watch::channel(init);
+    let sender = TwccBandwidthSender {
+        sender: Arc::new(tx),
+        audio_sender: Arc::new(watch::channel(init).0),
+        video_sender: Arc::new(watch::channel(init).0),
+        max_bits_per_sec: Arc::new(AtomicU64::new(u64::MAX)),
+        audio_reservation_bits_per_sec: Arc::new(AtomicU64::new(0)),
+        packets_received: Arc::new(AtomicU64::new(0)),
+        packets_lost: Arc::new(AtomicU64::new(0)),
+        ignored_rtcp_packets: Arc::new(AtomicU64::new(0)),
+        output_reporters: Arc::new(Mutex::new(Vec::new())),
+        mtu_sender: Arc::new(watch::channel(INITIAL_MTU).0),
+        max_mtu: Arc::new(AtomicUsize::new(0)),
+        smoothing_time_constant_us: Arc::new(AtomicU64::new(0)),
+        estimator: Arc::new(Mutex::new(Weak::new())),
+    };
+    (sender, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_bandwidth() {
+        let (tx, mut rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(2_000_000));
+
+        // Applied immediately
+        tx.set_max_bandwidth(Some(DataRate::from_bits_per_sec(500_000)));
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(rx.borrow_and_update().bits_per_sec(), 500_000);
+
+        // Estimates are capped
+        tx.send(DataRate::from_bits_per_sec(3_000_000));
+        assert!(!rx.has_changed().unwrap());
+        tx.send(DataRate::from_bits_per_sec(300_000));
+        assert_eq!(rx.borrow_and_update().bits_per_sec(), 300_000);
+
+        tx.set_max_bandwidth(None);
+        tx.send(DataRate::from_bits_per_sec(3_000_000));
+        assert_eq!(rx.borrow_and_update().bits_per_sec(), 3_000_000);
+    }
+
+    #[test]
+    fn dropped_output_reporter() {
+        let (tx, _rx) = twcc_bandwidth_estimate_channel(DataRate::from_bits_per_sec(2_000_000));
+        let reporter = tx.output_bitrate_reporter();
+        // An encoder that does not report, which drops its handle
+        let unused = tx.output_bitrate_reporter();
+
+        reporter.report(DataRate::from_bits_per_sec(300_000));
+        assert!(tx.reported_output_bitrate().is_none());
+
+        drop(unused);
+        let output = tx.reported_output_bitrate().unwrap();
+        assert_eq!(output.bits_per_sec(), 300_000);
+
+        // A clone keeps the handle counting
+        let clone = reporter.clone();
+        drop(reporter);
+        assert!(tx.reported_output_bitrate().is_some());
+        drop(clone);
+        assert!(tx.reported_output_bitrate().is_none());
+    }
 }
@@ -0,0 +1,189 @@
+use super::time::TwccTime;
+use std::collections::VecDeque;
+
+// Gaps in the arrivals longer than this are the sender going silent, e.g., Opus DTX without
+// comfort noise, instead of the link slowing down. Well above the frame interval of low frame rate
+// video, e.g., a 2 fps screen share, whose gaps are part of its bandwidth.
+const MAX_ARRIVAL_GAP_US: i64 = 1_000_000;
+
+struct WindowData {
+    arrival_time_us: TwccTime,
+    // Silence since the previous group that is left out of the timespan
+    excluded_gap_us: i64,
+    size_bytes: u64,
+    num_packets: u64,
+    media_size_bytes: u64,
+    num_media_packets: u64,
+}
+
+/// Sliding window over the arrival time and size of received packet groups. Used for computing
+/// the apparent bandwidth on the receiver side.
+pub struct ReceiveRate {
+    data: VecDeque<WindowData>,
+    capacity: usize,
+    total_packet_size_bytes: u64,
+    num_packets: u64,
+    total_media_size_bytes: u64,
+    num_media_packets: u64,
+    // Sum of `excluded_gap_us` except for the oldest group, whose gap is outside the window
+    excluded_gap_us: i64,
+}
+
+impl ReceiveRate {
+    /// Create a new `ReceiveRate` that keeps at most `capacity` packet groups.
+    pub fn new(capacity: usize) -> ReceiveRate {
+        ReceiveRate {
+            data: VecDeque::with_capacity(capacity + 1),
+            capacity,
+            total_packet_size_bytes: 0,
+            num_packets: 0,
+            total_media_size_bytes: 0,
+            num_media_packets: 0,
+            excluded_gap_us: 0,
+        }
+    }
+
+    /// Add a packet group to the window, evicting the oldest one if the window is full.
+    pub fn push(&mut self, arrival_time_us: TwccTime, size_bytes: u64, num_packets: u64) {
+        self.push_with_media(
+            arrival_time_us,
+            size_bytes,
+            num_packets,
+            size_bytes,
+            num_packets,
+        );
+    }
+
+    /// Same as [`ReceiveRate::push`] but only `media_size_bytes` and `num_media_packets` are
+    /// counted toward the average packet size. The rest of the group (e.g., padding) still counts
+    /// toward the received bandwidth.
+    pub fn push_with_media(
+        &mut self,
+        arrival_time_us: TwccTime,
+        size_bytes: u64,
+        num_packets: u64,
+        media_size_bytes: u64,
+        num_media_packets: u64,
+    ) {
+        // Leave a silence out of the timespan so that it does not dilute the received bandwidth
+        let excluded_gap_us = match self.data.back() {
+            Some(last) => {
+                let gap_us = arrival_time_us.sub_assuming_small_delta(last.arrival_time_us);
+                if gap_us > MAX_ARRIVAL_GAP_US {
+                    gap_us
+                } else {
+                    0
+                }
+            }
+            None => 0,
+        };
+
+        self.total_packet_size_bytes += size_bytes;
+        self.num_packets += num_packets;
+        self.total_media_size_bytes += media_size_bytes;
+        self.num_media_packets += num_media_packets;
+        self.excluded_gap_us += excluded_gap_us;
+        self.data.push_back(WindowData {
+            arrival_time_us,
+            excluded_gap_us,
+            size_bytes,
+            num_packets,
+            media_size_bytes,
+            num_media_packets,
+        });
+
+        if self.data.len() > self.capacity {
+            if let Some(to_remove) = self.data.pop_front() {
+                self.total_packet_size_bytes -= to_remove.size_bytes;
+                self.num_packets -= to_remove.num_packets;
+                self.total_media_size_bytes -= to_remove.media_size_bytes;
+                self.num_media_packets -= to_remove.num_media_packets;
+            }
+            // The gap before the new oldest group is now outside the window
+            if let Some(front) = self.data.front() {
+                self.excluded_gap_us -= front.excluded_gap_us;
+            }
+        }
+    }
+
+    /// Average size of the media packets in the window. Falls back to all the packets if the
+    /// window only has non-media packets. Returns `None` if the window is empty.
+    pub fn average_packet_size_bytes(&self) -> Option<f64> {
+        if self.num_media_packets > 0 {
+            Some(self.total_media_size_bytes as f64 / self.num_media_packets as f64)
+        } else if self.num_packets > 0 {
+            Some(self.total_packet_size_bytes as f64 / self.num_packets as f64)
+        } else {
+            None
+        }
+    }
+
+    /// Received bandwidth over the window, leaving out the silences. Returns `None` if the window
+    /// does not span any time yet.
+    pub fn received_bandwidth_bytes_per_sec(&self) -> Option<f64> {
+        let start = self.data.front()?.arrival_time_us;
+        let end = self.data.back()?.arrival_time_us;
+        let timespan = end.sub_assuming_small_delta(start) - self.excluded_gap_us;
+        if timespan <= 0 {
+            return None;
+        }
+        // Timespan is in microseconds so multiply by 1e6
+        Some(1e6 * self.total_packet_size_bytes as f64 / timespan as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_INTERVAL_US: i64 = 20_000;
+    const FRAME_SIZE_BYTES: u64 = 100;
+
+    fn push_frames(receive_rate: &mut ReceiveRate, arrival_time_us: &mut i64, num_frames: u32) {
+        for _ in 0..num_frames {
+            *arrival_time_us += FRAME_INTERVAL_US;
+            receive_rate.push(TwccTime::from_raw(*arrival_time_us), FRAME_SIZE_BYTES, 1);
+        }
+    }
+
+    #[test]
+    fn dtx_silence() {
+        let mut receive_rate = ReceiveRate::new(100);
+        let mut arrival_time_us = 0;
+        let expected = 1e6 * FRAME_SIZE_BYTES as f64 / FRAME_INTERVAL_US as f64;
+
+        push_frames(&mut receive_rate, &mut arrival_time_us, 20);
+        let before = receive_rate.received_bandwidth_bytes_per_sec().unwrap();
+        assert!((before - expected).abs() < 0.1 * expected);
+
+        // Two seconds of silence, then talking again
+        arrival_time_us += 2_000_000;
+        push_frames(&mut receive_rate, &mut arrival_time_us, 20);
+        let after = receive_rate.received_bandwidth_bytes_per_sec().unwrap();
+        assert!((after - expected).abs() < 0.1 * expected);
+
+        // Still right once the talk before the silence leaves the window
+        push_frames(&mut receive_rate, &mut arrival_time_us, 100);
+        let after = receive_rate.received_bandwidth_bytes_per_sec().unwrap();
+        assert!((after - expected).abs() < 0.1 * expected);
+    }
+
+    #[test]
+    fn low_frame_rate() {
+        // 2 fps screen share, every frame arriving as a single group
+        const SCREEN_FRAME_INTERVAL_US: i64 = 500_000;
+        const SCREEN_FRAME_SIZE_BYTES: u64 = 10_000;
+
+        let mut receive_rate = ReceiveRate::new(100);
+        for i in 0..10 {
+            let arrival_time = TwccTime::from_raw(i * SCREEN_FRAME_INTERVAL_US);
+            receive_rate.push(arrival_time, SCREEN_FRAME_SIZE_BYTES, 10);
+        }
+
+        // Ten frames over nine intervals
+        let expected =
+            1e6 * 10.0 * SCREEN_FRAME_SIZE_BYTES as f64 / (9 * SCREEN_FRAME_INTERVAL_US) as f64;
+        let received = receive_rate.received_bandwidth_bytes_per_sec().unwrap();
+        assert!((received - expected).abs() < 1e-6 * expected);
+    }
+}
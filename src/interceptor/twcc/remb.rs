@@ -0,0 +1,423 @@
+//! Receiver-side REMB generation for remote endpoints that do not support transport-cc.
+
+use super::{
+    estimator::DelayBasedConfig,
+    receive_rate::ReceiveRate,
+    send_time::{SendTimeEstimator, SendTimeSource},
+    time::TwccTime,
+};
+use async_trait::async_trait;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{Mutex, Notify},
+    time::MissedTickBehavior,
+};
+use webrtc::{
+    interceptor::{
+        self, stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate,
+    rtp,
+    rtp_transceiver::TYPE_RTCP_FB_TRANSPORT_CC,
+    util::{MarshalSize, Unmarshal},
+};
+
+/// Packets arriving within this interval are grouped together before being added to the window.
+const GROUP_INTERVAL_US: i64 = 5000;
+
+/// Approx. 1 second of data if the packets are grouped by 5 ms.
+const WINDOW_SIZE: usize = 200;
+
+/// Same headroom used to cap the estimate of the delay-based controller. Advertising exactly the
+/// received bitrate would keep the remote sender from ever ramping up.
+const REMB_HEADROOM: f64 = 1.5;
+
+const REMB_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Same as the TWCC feedback interval so that the delay-based estimates react as fast as the
+/// sender-side ones.
+const ESTIMATE_INTERVAL: Duration = Duration::from_millis(100);
+
+const GOOG_REMB: &str = "goog-remb";
+
+struct PendingGroup {
+    start_us: TwccTime,
+    arrival_time_us: TwccTime,
+    size_bytes: u64,
+    num_packets: u64,
+}
+
+/// Estimates the receive bitrate of the remote streams that negotiated `goog-remb` and builds the
+/// corresponding REMB packets.
+///
+/// If every stream has the send time of its packets in an abs-send-time or transmission offset
+/// extension, the REMB carries the sum of the delay-based estimates of the streams instead, which
+/// backs off when a queue builds up on the path.
+pub struct RembGenerator {
+    receive_rate: ReceiveRate,
+    pending_group: Option<PendingGroup>,
+    ssrcs: Vec<u32>,
+    send_time_estimators: HashMap<u32, SendTimeEstimator>,
+}
+
+impl RembGenerator {
+    pub fn new() -> RembGenerator {
+        RembGenerator {
+            receive_rate: ReceiveRate::new(WINDOW_SIZE),
+            pending_group: None,
+            ssrcs: Vec::new(),
+            send_time_estimators: HashMap::new(),
+        }
+    }
+
+    pub fn add_ssrc(&mut self, ssrc: u32) {
+        if !self.ssrcs.contains(&ssrc) {
+            self.ssrcs.push(ssrc);
+        }
+    }
+
+    /// Estimate the bandwidth of the stream from the send times in `source`.
+    pub fn add_send_time_source(&mut self, ssrc: u32, source: SendTimeSource) {
+        self.send_time_estimators.insert(
+            ssrc,
+            SendTimeEstimator::new(source, DelayBasedConfig::default()),
+        );
+    }
+
+    pub fn remove_ssrc(&mut self, ssrc: u32) {
+        self.ssrcs.retain(|s| *s != ssrc);
+        self.send_time_estimators.remove(&ssrc);
+    }
+
+    /// Record the send time of a packet for the delay-based estimate of its stream, if any.
+    pub fn record_send_time(
+        &mut self,
+        header: &rtp::header::Header,
+        arrival_time_us: TwccTime,
+        payload_size: u64,
+    ) {
+        if let Some(estimator) = self.send_time_estimators.get_mut(&header.ssrc) {
+            estimator.record_packet(header, arrival_time_us, payload_size);
+        }
+    }
+
+    /// Record the arrival of a packet. Only the payload size should be counted.
+    pub fn record_packet(&mut self, arrival_time_us: TwccTime, payload_size: u64) {
+        if let Some(group) = &mut self.pending_group {
+            if arrival_time_us.sub_assuming_small_delta(group.start_us) < GROUP_INTERVAL_US {
+                group.arrival_time_us = arrival_time_us;
+                group.size_bytes += payload_size;
+                group.num_packets += 1;
+                return;
+            }
+        }
+
+        if let Some(group) = self.pending_group.take() {
+            self.receive_rate
+                .push(group.arrival_time_us, group.size_bytes, group.num_packets);
+        }
+        self.pending_group = Some(PendingGroup {
+            start_us: arrival_time_us,
+            arrival_time_us,
+            size_bytes: payload_size,
+            num_packets: 1,
+        });
+    }
+
+    /// Update the delay-based estimates of the streams with send times.
+    pub fn update_estimates(&mut self, now: Instant) {
+        for estimator in self.send_time_estimators.values_mut() {
+            estimator.update(now);
+        }
+    }
+
+    /// Build a REMB packet from the current estimate. Returns `None` if there are no streams to
+    /// report on or not enough data has been received.
+    pub fn build_packet(&self, sender_ssrc: u32) -> Option<ReceiverEstimatedMaximumBitrate> {
+        if self.ssrcs.is_empty() {
+            return None;
+        }
+
+        let bytes_per_sec = match self.send_time_estimate() {
+            Some(bytes_per_sec) => bytes_per_sec,
+            None => REMB_HEADROOM * self.receive_rate.received_bandwidth_bytes_per_sec()?,
+        };
+        if !bytes_per_sec.is_finite() || bytes_per_sec <= 0.0 {
+            return None;
+        }
+
+        Some(ReceiverEstimatedMaximumBitrate {
+            sender_ssrc,
+            bitrate: (8.0 * bytes_per_sec) as f32,
+            ssrcs: self.ssrcs.clone(),
+        })
+    }
+
+    /// Sum of the delay-based estimates of the streams. Returns `None` unless every stream has
+    /// one, since the streams without send times would go unaccounted for.
+    fn send_time_estimate(&self) -> Option<f64> {
+        self.ssrcs
+            .iter()
+            .map(|ssrc| self.send_time_estimators.get(ssrc)?.bandwidth())
+            .sum()
+    }
+}
+
+pub struct RembStream {
+    generator: Arc<Mutex<RembGenerator>>,
+    next_reader: Arc<dyn RTPReader + Send + Sync>,
+    start_time: Instant,
+}
+
+#[async_trait]
+impl RTPReader for RembStream {
+    async fn read(
+        &self,
+        buf: &mut [u8],
+        attributes: &Attributes,
+    ) -> Result<(usize, Attributes), interceptor::Error> {
+        let (n, attr) = self.next_reader.read(buf, attributes).await?;
+
+        let mut b = &buf[..n];
+        let header = rtp::header::Header::unmarshal(&mut b)?;
+        let payload_size = n.saturating_sub(header.marshal_size()) as u64;
+
+        let timestamp = Instant::now().duration_since(self.start_time);
+        let arrival_time_us = TwccTime::from_duration(&timestamp);
+        let mut generator = self.generator.lock().await;
+        generator.record_packet(arrival_time_us, payload_size);
+        generator.record_send_time(&header, arrival_time_us, payload_size);
+
+        Ok((n, attr))
+    }
+}
+
+pub struct RembInterceptor {
+    generator: Arc<Mutex<RembGenerator>>,
+    closed: Arc<Notify>,
+    start_time: Instant,
+    // SSRCs of the local streams, in the order they were bound
+    local_ssrcs: Arc<Mutex<Vec<u32>>>,
+    // Sender SSRC of the REMBs while there is no local stream
+    fallback_ssrc: u32,
+}
+
+impl RembInterceptor {
+    /// SSRC of the first local stream that is still bound, or `fallback_ssrc` if there is none.
+    async fn sender_ssrc(local_ssrcs: &Mutex<Vec<u32>>, fallback_ssrc: u32) -> u32 {
+        local_ssrcs
+            .lock()
+            .await
+            .first()
+            .copied()
+            .unwrap_or(fallback_ssrc)
+    }
+
+    async fn run(
+        generator: Arc<Mutex<RembGenerator>>,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+        closed: Arc<Notify>,
+        local_ssrcs: Arc<Mutex<Vec<u32>>>,
+        fallback_ssrc: u32,
+    ) {
+        let attributes = Attributes::new();
+        let mut ticker = tokio::time::interval(REMB_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut estimate_ticker = tokio::time::interval(ESTIMATE_INTERVAL);
+        estimate_ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = closed.notified() => break,
+                _ = estimate_ticker.tick() => {
+                    generator.lock().await.update_estimates(Instant::now());
+                }
+                _ = ticker.tick() => {
+                    let sender_ssrc = Self::sender_ssrc(&local_ssrcs, fallback_ssrc).await;
+                    let packet = generator.lock().await.build_packet(sender_ssrc);
+                    if let Some(packet) = packet {
+                        if let Err(e) = writer.write(&[Box::new(packet)], &attributes).await {
+                            log::error!("Error writing REMB: {e}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Interceptor for RembInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        reader
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        tokio::spawn(Self::run(
+            self.generator.clone(),
+            writer.clone(),
+            self.closed.clone(),
+            self.local_ssrcs.clone(),
+            self.fallback_ssrc,
+        ));
+        writer
+    }
+
+    async fn bind_local_stream(
+        &self,
+        info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        self.local_ssrcs.lock().await.push(info.ssrc);
+        writer
+    }
+
+    async fn unbind_local_stream(&self, info: &StreamInfo) {
+        self.local_ssrcs
+            .lock()
+            .await
+            .retain(|ssrc| *ssrc != info.ssrc);
+    }
+
+    async fn bind_remote_stream(
+        &self,
+        info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        if !info.rtcp_feedback.iter().any(|fb| fb.typ == GOOG_REMB) {
+            return reader;
+        }
+
+        let mut generator = self.generator.lock().await;
+        generator.add_ssrc(info.ssrc);
+        // Fallback for when the remote peer cannot do the estimate itself through transport-cc
+        let has_transport_cc = info
+            .rtcp_feedback
+            .iter()
+            .any(|fb| fb.typ == TYPE_RTCP_FB_TRANSPORT_CC);
+        if !has_transport_cc {
+            if let Some(source) = SendTimeSource::from_stream_info(info) {
+                generator.add_send_time_source(info.ssrc, source);
+            }
+        }
+        drop(generator);
+
+        Arc::new(RembStream {
+            generator: self.generator.clone(),
+            next_reader: reader,
+            start_time: self.start_time,
+        })
+    }
+
+    async fn unbind_remote_stream(&self, info: &StreamInfo) {
+        self.generator.lock().await.remove_ssrc(info.ssrc);
+    }
+
+    async fn close(&self) -> Result<(), interceptor::Error> {
+        self.closed.notify_one();
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct RembInterceptorBuilder;
+
+impl RembInterceptorBuilder {
+    pub fn new() -> RembInterceptorBuilder {
+        RembInterceptorBuilder {}
+    }
+}
+
+impl InterceptorBuilder for RembInterceptorBuilder {
+    fn build(&self, _id: &str) -> Result<Arc<dyn Interceptor + Send + Sync>, interceptor::Error> {
+        Ok(Arc::new(RembInterceptor {
+            generator: Arc::new(Mutex::new(RembGenerator::new())),
+            closed: Arc::new(Notify::new()),
+            start_time: Instant::now(),
+            local_ssrcs: Arc::new(Mutex::new(Vec::new())),
+            // Only has to differ from the SSRCs of the remote streams, which a random one almost
+            // certainly does
+            fallback_ssrc: RandomState::new().build_hasher().finish() as u32,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interceptor::tests::DiscardRtp;
+
+    #[test]
+    fn remb_bitrate() {
+        const PACKET_SIZE: u64 = 1000;
+        // One packet every 1 ms for 1 second, i.e., 1_000_000 bytes/s
+        const PACKET_INTERVAL_US: u64 = 1000;
+
+        let mut generator = RembGenerator::new();
+        for i in 0..1000 {
+            let timestamp = Duration::from_micros(i * PACKET_INTERVAL_US);
+            generator.record_packet(TwccTime::from_duration(&timestamp), PACKET_SIZE);
+        }
+
+        // No stream negotiated REMB
+        assert!(generator.build_packet(1).is_none());
+
+        generator.add_ssrc(1234);
+        let remb = generator.build_packet(1).unwrap();
+        assert_eq!(remb.sender_ssrc, 1);
+        assert_eq!(remb.ssrcs, vec![1234]);
+
+        // Timespan of the window starts at the last packet of the first group so the estimate is
+        // only approximately 1_000_000 bytes/s
+        let expected = 8.0 * REMB_HEADROOM * 1e6;
+        let error = (remb.bitrate as f64 - expected).abs() / expected;
+        assert!(error < 0.01, "{} != {expected}", remb.bitrate);
+
+        generator.remove_ssrc(1234);
+        assert!(generator.build_packet(1).is_none());
+    }
+
+    #[tokio::test]
+    async fn sender_ssrc_of_local_stream() {
+        let interceptor = RembInterceptor {
+            generator: Arc::new(Mutex::new(RembGenerator::new())),
+            closed: Arc::new(Notify::new()),
+            start_time: Instant::now(),
+            local_ssrcs: Arc::new(Mutex::new(Vec::new())),
+            fallback_ssrc: 1,
+        };
+        let sender_ssrc =
+            || RembInterceptor::sender_ssrc(&interceptor.local_ssrcs, interceptor.fallback_ssrc);
+        assert_eq!(sender_ssrc().await, 1);
+
+        for ssrc in [1234, 5678] {
+            let info = StreamInfo {
+                ssrc,
+                ..Default::default()
+            };
+            interceptor
+                .bind_local_stream(&info, Arc::new(DiscardRtp))
+                .await;
+        }
+        assert_eq!(sender_ssrc().await, 1234);
+
+        let info = StreamInfo {
+            ssrc: 1234,
+            ..Default::default()
+        };
+        interceptor.unbind_local_stream(&info).await;
+        assert_eq!(sender_ssrc().await, 5678);
+    }
+}
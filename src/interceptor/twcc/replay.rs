@@ -0,0 +1,175 @@
+//! Text log of the inputs to the bandwidth estimator, for replaying them in regression tests.
+//!
+//! Each line is one of:
+//!
+//! ```text
+//! s <transport sequence number> <departure time [us]> <payload size [bytes]>
+//! f <base64 of the marshaled transport-cc feedback>
+//! e <time [us]> <estimate [bps]>
+//! ```
+//!
+//! Lines starting with `#` are comments. The estimate is the one recorded after running the
+//! estimator at that time.
+
+use super::{
+    estimator::{DelayBasedConfig, TwccBandwidthEstimator},
+    sync::TwccSendInfo,
+    time::TwccTime,
+    twcc_bandwidth_estimate_channel, TwccBandwidthEstimate,
+};
+use crate::network::data_rate::DataRate;
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+use webrtc::{
+    rtcp::transport_feedbacks::transport_layer_cc::TransportLayerCc,
+    util::{Marshal, Unmarshal},
+};
+
+/// Records the inputs to the bandwidth estimator in the format that [replay] reads.
+#[derive(Default)]
+pub struct FeedbackLog(String);
+
+impl FeedbackLog {
+    pub fn send(&mut self, seq_num: u16, departure_us: i64, packet_size: u64) {
+        writeln!(self.0, "s {seq_num} {departure_us} {packet_size}").unwrap();
+    }
+
+    pub fn feedback(&mut self, tcc: &TransportLayerCc) {
+        let data = tcc.marshal().unwrap();
+        writeln!(self.0, "f {}", base64::encode(data)).unwrap();
+    }
+
+    pub fn estimate(&mut self, time_us: i64, estimate: DataRate) {
+        writeln!(self.0, "e {time_us} {}", estimate.bits_per_sec()).unwrap();
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Estimate at one point of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayedEstimate {
+    pub time_us: i64,
+    /// What the estimator produced when the log was recorded.
+    pub recorded_bits_per_sec: u64,
+    /// What the estimator produces now.
+    pub replayed_bits_per_sec: u64,
+}
+
+/// Runs the estimator through `log`, starting at `init_bandwidth`. Returns the estimate
+/// trajectory or the number of the first line that could not be parsed.
+pub fn replay(
+    log: &str,
+    init_bandwidth: DataRate,
+    delay_based_config: DelayBasedConfig,
+) -> Result<Vec<ReplayedEstimate>, usize> {
+    let (tx, estimate) = twcc_bandwidth_estimate_channel(init_bandwidth);
+    let mut replayer = Replayer {
+        estimator: TwccBandwidthEstimator::new(tx, delay_based_config),
+        estimate,
+        send_info: TwccSendInfo::try_new().unwrap(),
+        start: Instant::now(),
+        trajectory: Vec::new(),
+    };
+
+    for (i, line) in log.lines().enumerate() {
+        if replayer.replay_line(line).is_none() {
+            return Err(i + 1);
+        }
+    }
+
+    Ok(replayer.trajectory)
+}
+
+struct Replayer {
+    estimator: TwccBandwidthEstimator,
+    estimate: TwccBandwidthEstimate,
+    send_info: TwccSendInfo,
+    start: Instant,
+    trajectory: Vec<ReplayedEstimate>,
+}
+
+impl Replayer {
+    fn replay_line(&mut self, line: &str) -> Option<()> {
+        let mut fields = line.split_ascii_whitespace();
+        match fields.next() {
+            None => {}
+            Some(comment) if comment.starts_with('#') => {}
+            Some("s") => {
+                let seq_num = fields.next()?.parse().ok()?;
+                let departure_us = fields.next()?.parse().ok()?;
+                let packet_size = fields.next()?.parse().ok()?;
+                self.send_info.store_send_info(
+                    seq_num,
+                    TwccTime::from_raw(departure_us),
+                    packet_size,
+                );
+            }
+            Some("f") => {
+                let data = base64::decode(fields.next()?).ok()?;
+                let tcc = TransportLayerCc::unmarshal(&mut data.as_slice()).ok()?;
+                self.estimator.process_feedback(&tcc, &self.send_info);
+            }
+            Some("e") => {
+                let time_us: i64 = fields.next()?.parse().ok()?;
+                let recorded_bits_per_sec = fields.next()?.parse().ok()?;
+                let elapsed = Duration::from_micros(time_us.try_into().ok()?);
+                self.estimator.estimate(self.start + elapsed);
+                self.trajectory.push(ReplayedEstimate {
+                    time_us,
+                    recorded_bits_per_sec,
+                    replayed_bits_per_sec: self.estimate.borrow().bits_per_sec(),
+                });
+            }
+            Some(_) => return None,
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recorded from the emulated link by `emulation::tests::record_feedback_log`.
+    const GOLDEN_LOG: &str = include_str!("testdata/feedback.log");
+
+    // Allowed relative difference from the recorded estimates
+    const TOLERANCE: f64 = 0.05;
+
+    #[test]
+    fn golden_feedback_log() {
+        let trajectory = replay(
+            GOLDEN_LOG,
+            DataRate::from_bits_per_sec(1_500_000),
+            DelayBasedConfig::default(),
+        )
+        .unwrap();
+        assert!(trajectory.len() >= 100);
+
+        for estimate in trajectory {
+            let recorded = estimate.recorded_bits_per_sec as f64;
+            let replayed = estimate.replayed_bits_per_sec as f64;
+            assert!(
+                (replayed - recorded).abs() <= TOLERANCE * recorded,
+                "Estimate at {} us changed from {recorded} bps to {replayed} bps",
+                estimate.time_us
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_line() {
+        let log = "# comment\ns 0 0 1200\nf not-base64!\n";
+        let result = replay(
+            log,
+            DataRate::from_bits_per_sec(300_000),
+            DelayBasedConfig::default(),
+        );
+        assert_eq!(result, Err(3));
+    }
+}
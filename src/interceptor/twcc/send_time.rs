@@ -0,0 +1,245 @@
+//! Receiver-side delay-based estimate for remote streams that did not negotiate transport-cc but
+//! have the send time of each packet in an RTP header extension.
+
+use super::{
+    estimator::{DelayBasedBandwidthEstimator, DelayBasedConfig},
+    time::TwccTime,
+};
+use crate::network::transmission_offset::{TransmissionOffset, TRANSMISSION_OFFSET_URI};
+use std::time::Instant;
+use webrtc::{
+    interceptor::stream_info::StreamInfo,
+    rtp::{self, extension::abs_send_time_extension::AbsSendTimeExtension},
+    sdp::extmap::ABS_SEND_TIME_URI,
+    util::Unmarshal,
+};
+
+/// abs-send-time is a 24-bit 6.18 fixed point number of seconds.
+const ABS_SEND_TIME_BITS: u32 = 24;
+const ABS_SEND_TIME_TICKS_PER_SEC: f64 = (1 << 18) as f64;
+
+/// Header extension that the send time of the packets of a remote stream is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendTimeSource {
+    /// The abs-send-time extension with the negotiated ID.
+    AbsSendTime { id: u8 },
+    /// The transmission time offset extension with the negotiated ID. The send times are in RTP
+    /// timestamp ticks so they are only comparable within the stream.
+    TransmissionOffset { id: u8, clock_rate: u32 },
+}
+
+impl SendTimeSource {
+    /// Pick the send time extension negotiated for the stream, preferring abs-send-time.
+    pub fn from_stream_info(info: &StreamInfo) -> Option<SendTimeSource> {
+        let id = |uri: &str| {
+            info.rtp_header_extensions
+                .iter()
+                .find(|ext| ext.uri == uri)
+                .map(|ext| ext.id as u8)
+        };
+
+        if let Some(id) = id(ABS_SEND_TIME_URI) {
+            Some(SendTimeSource::AbsSendTime { id })
+        } else if info.clock_rate > 0 {
+            id(TRANSMISSION_OFFSET_URI).map(|id| SendTimeSource::TransmissionOffset {
+                id,
+                clock_rate: info.clock_rate,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the wrapping send time of the packet, its number of bits, and the ticks per
+    /// second.
+    fn read(&self, header: &rtp::header::Header) -> Option<(u64, u32, f64)> {
+        match *self {
+            SendTimeSource::AbsSendTime { id } => {
+                let mut buf = header.get_extension(id)?;
+                let abs_send_time = AbsSendTimeExtension::unmarshal(&mut buf).ok()?;
+                Some((
+                    abs_send_time.timestamp,
+                    ABS_SEND_TIME_BITS,
+                    ABS_SEND_TIME_TICKS_PER_SEC,
+                ))
+            }
+            SendTimeSource::TransmissionOffset { id, clock_rate } => {
+                let transmission_offset = TransmissionOffset::from_header(header, id)?;
+                Some((
+                    transmission_offset.send_time(header.timestamp) as u64,
+                    u32::BITS,
+                    clock_rate as f64,
+                ))
+            }
+        }
+    }
+}
+
+/// Delay-based estimate of the bandwidth of a single remote stream, with the departure times of
+/// the packets taken from their send time extension instead of the TWCC send history.
+pub struct SendTimeEstimator {
+    source: SendTimeSource,
+    delay_based_estimator: DelayBasedBandwidthEstimator,
+    // Send time of the last packet before unwrapping, and the unwrapped send time in ticks
+    last_send_time: Option<(u64, i64)>,
+    bandwidth: Option<f64>,
+}
+
+impl SendTimeEstimator {
+    pub fn new(source: SendTimeSource, delay_based_config: DelayBasedConfig) -> SendTimeEstimator {
+        SendTimeEstimator {
+            source,
+            delay_based_estimator: DelayBasedBandwidthEstimator::new(delay_based_config),
+            last_send_time: None,
+            bandwidth: None,
+        }
+    }
+
+    /// Record the arrival of a packet. Packets without the send time extension are ignored.
+    pub fn record_packet(
+        &mut self,
+        header: &rtp::header::Header,
+        arrival_time: TwccTime,
+        packet_size: u64,
+    ) {
+        let (send_time, bits, ticks_per_sec) = match self.source.read(header) {
+            Some(send_time) => send_time,
+            None => return,
+        };
+
+        // Reordered packets unwrap to an earlier send time, which the estimator then ignores
+        let unwrapped = match self.last_send_time {
+            Some((last, last_unwrapped)) => {
+                let modulus = 1i64 << bits;
+                let mut delta = (send_time as i64 - last as i64).rem_euclid(modulus);
+                if delta >= modulus / 2 {
+                    delta -= modulus;
+                }
+                last_unwrapped + delta
+            }
+            None => send_time as i64,
+        };
+        self.last_send_time = Some((send_time, unwrapped));
+
+        let departure_time_us = (1e6 * unwrapped as f64 / ticks_per_sec) as i64;
+        self.delay_based_estimator.process_packet(
+            TwccTime::from_micros_wrapping(departure_time_us),
+            arrival_time,
+            packet_size,
+        );
+    }
+
+    /// Update the estimate. It starts from the received bandwidth once enough packets with the
+    /// send time extension have arrived.
+    pub fn update(&mut self, now: Instant) {
+        let current_bandwidth = match self.bandwidth {
+            Some(bandwidth) => bandwidth,
+            None => match self
+                .delay_based_estimator
+                .received_bandwidth_bytes_per_sec()
+            {
+                Some(received_bandwidth) => received_bandwidth,
+                None => return,
+            },
+        };
+        self.bandwidth = Some(self.delay_based_estimator.estimate(current_bandwidth, now));
+    }
+
+    /// Latest estimate in bytes per second.
+    pub fn bandwidth(&self) -> Option<f64> {
+        self.bandwidth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use webrtc::util::Marshal;
+
+    const ID: u8 = 3;
+    const PACKET_SIZE: u64 = 1000;
+
+    /// Sends a packet every 1 ms for 3 s, i.e., 1_000_000 bytes/s, with the abs-send-time starting
+    /// just before it wraps around. Each packet leaves the link `link_interval_us` after the
+    /// previous one and arrives with up to 2 ms of jitter. Returns the estimate every 100 ms.
+    fn abs_send_time_estimates(link_interval_us: i64) -> Vec<f64> {
+        let mut estimator = SendTimeEstimator::new(
+            SendTimeSource::AbsSendTime { id: ID },
+            DelayBasedConfig::default(),
+        );
+        let start = Instant::now();
+        let wraparound_us = 64_000_000;
+
+        let mut estimates = Vec::new();
+        let mut link_free_at_us = 0;
+        // Deterministic jitter
+        let mut rng = 1u64;
+        for i in 0..3000 {
+            let send_time_us = wraparound_us - 500_000 + 1000 * i;
+            let abs_send_time = AbsSendTimeExtension {
+                timestamp: (send_time_us as f64 * ABS_SEND_TIME_TICKS_PER_SEC / 1e6) as u64
+                    & 0xffffff,
+            };
+            let mut header = rtp::header::Header::default();
+            header
+                .set_extension(ID, abs_send_time.marshal().unwrap())
+                .unwrap();
+
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            let jitter_us = (rng % 2000) as i64;
+            link_free_at_us = link_free_at_us.max(1000 * i) + link_interval_us;
+            let arrival_time = TwccTime::from_raw(20_000 + link_free_at_us + jitter_us);
+            estimator.record_packet(&header, arrival_time, PACKET_SIZE);
+
+            if (i + 1) % 100 == 0 {
+                estimator.update(start + Duration::from_millis(i as u64));
+                estimates.extend(estimator.bandwidth());
+            }
+        }
+        estimates
+    }
+
+    #[test]
+    fn abs_send_time_estimate() {
+        // The link is faster than the packets are sent
+        let estimates = abs_send_time_estimates(500);
+        assert_eq!(estimates.len(), 30);
+        for &estimate in &estimates {
+            assert!(
+                (0.9e6..=1.5e6).contains(&estimate),
+                "{estimate} is implausible"
+            );
+        }
+
+        // The link only delivers 125_000 bytes/s so a queue builds up. The estimate drops below
+        // what a cap on the received bandwidth alone would give.
+        let estimates = abs_send_time_estimates(8000);
+        let lowest = estimates.iter().copied().fold(f64::INFINITY, f64::min);
+        assert!(lowest < 0.125e6, "{lowest} did not back off");
+        assert!(lowest > 0.075e6, "{lowest} backed off too much");
+        let last = *estimates.last().unwrap();
+        assert!(last < 1.5 * 0.13e6, "{last} is implausible");
+    }
+
+    #[test]
+    fn transmission_offset_read() {
+        let source = SendTimeSource::TransmissionOffset {
+            id: ID,
+            clock_rate: 90000,
+        };
+        let mut header = rtp::header::Header {
+            timestamp: 9000,
+            ..Default::default()
+        };
+        assert!(source.read(&header).is_none());
+
+        let transmission_offset = TransmissionOffset::new(450);
+        header
+            .set_extension(ID, transmission_offset.marshal().unwrap())
+            .unwrap();
+        assert_eq!(source.read(&header), Some((9450, 32, 90000.0)));
+    }
+}
@@ -1,6 +1,12 @@
 use super::{sync::TwccSendInfo, time::TwccTime};
 use async_trait::async_trait;
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 use webrtc::{
     interceptor::{Attributes, Error, RTPWriter},
     rtp::{self, extension::transport_cc_extension::TransportCcExtension},
@@ -12,6 +18,8 @@ pub struct TwccTimestampSenderStream {
     hdr_ext_id: u8,
     next_writer: Arc<dyn RTPWriter + Send + Sync>,
     start_time: Instant,
+    late_timestamps: bool,
+    warned_missing_ext: AtomicBool,
 }
 
 impl TwccTimestampSenderStream {
@@ -26,8 +34,26 @@ impl TwccTimestampSenderStream {
             hdr_ext_id,
             next_writer,
             start_time,
+            late_timestamps: false,
+            warned_missing_ext: AtomicBool::new(false),
         }
     }
+
+    /// Record the send time after the rest of the chain, i.e., the remaining interceptors and
+    /// SRTP, has written the packet instead of before.
+    pub fn with_late_timestamps(mut self, late_timestamps: bool) -> TwccTimestampSenderStream {
+        self.late_timestamps = late_timestamps;
+        self
+    }
+
+    fn store_send_info(&self, transport_sequence: u16, payload_size: u64) {
+        let timestamp = Instant::now().duration_since(self.start_time);
+        self.map.store_send_info(
+            transport_sequence,
+            TwccTime::from_duration(&timestamp),
+            payload_size,
+        );
+    }
 }
 
 #[async_trait]
@@ -37,20 +63,227 @@ impl RTPWriter for TwccTimestampSenderStream {
         pkt: &rtp::packet::Packet,
         attributes: &Attributes,
     ) -> Result<usize, Error> {
-        // `TwccExtensionCapturerStream` must run after `TransportCcExtension` has been set
-        if let Some(mut buf) = pkt.header.get_extension(self.hdr_ext_id) {
-            // Incoming bitrate measured, R_hat, only considers payload size:
-            // https://datatracker.ietf.org/doc/html/draft-ietf-rmcat-gcc-02#section-5.5
-            let payload_size = pkt.payload.len() as u64;
-
-            let tcc_ext = TransportCcExtension::unmarshal(&mut buf)?;
-            let timestamp = Instant::now().duration_since(self.start_time);
-            self.map.store_send_info(
-                tcc_ext.transport_sequence,
-                TwccTime::from_duration(&timestamp),
-                payload_size,
-            );
-        }
-        self.next_writer.write(pkt, attributes).await
+        // `TwccTimestampSenderStream` must run after `TransportCcExtension` has been set, i.e.,
+        // `TwccInterceptorBuilder` must be registered before the transport-cc header extension
+        // interceptor. Packets are still forwarded if that is not the case.
+        //
+        // The extensions are already parsed from either the one-byte or two-byte form (RFC 8285)
+        // so `get_extension` works for both. IDs above 14 only occur in the two-byte form.
+        let transport_sequence = match pkt.header.get_extension(self.hdr_ext_id) {
+            Some(mut buf) => match TransportCcExtension::unmarshal(&mut buf) {
+                Ok(tcc_ext) => Some(tcc_ext.transport_sequence),
+                Err(e) => {
+                    log::error!("Invalid transport-cc extension: {e}");
+                    None
+                }
+            },
+            None => {
+                if !self.warned_missing_ext.swap(true, Ordering::Relaxed) {
+                    log::warn!(
+                        "Packet without transport-cc extension. The TWCC interceptor may have \
+                        been registered after the transport-cc header extension interceptor."
+                    );
+                }
+                None
+            }
+        };
+
+        // Incoming bitrate measured, R_hat, only considers payload size:
+        // https://datatracker.ietf.org/doc/html/draft-ietf-rmcat-gcc-02#section-5.5
+        let payload_size = pkt.payload.len() as u64;
+
+        if let (Some(transport_sequence), false) = (transport_sequence, self.late_timestamps) {
+            self.store_send_info(transport_sequence, payload_size);
+        }
+        let result = self.next_writer.write(pkt, attributes).await;
+        if let (Some(transport_sequence), true) = (transport_sequence, self.late_timestamps) {
+            self.store_send_info(transport_sequence, payload_size);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::{sync::atomic::AtomicUsize, time::Duration};
+    use webrtc::{
+        rtp::header::EXTENSION_PROFILE_TWO_BYTE,
+        util::{Marshal, Unmarshal},
+    };
+
+    const HDR_EXT_ID: u8 = 5;
+
+    #[derive(Default)]
+    struct CountingWriter(AtomicUsize);
+
+    #[async_trait]
+    impl RTPWriter for CountingWriter {
+        async fn write(
+            &self,
+            pkt: &rtp::packet::Packet,
+            _attributes: &Attributes,
+        ) -> Result<usize, Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(pkt.payload.len())
+        }
+    }
+
+    fn packet(transport_sequence: Option<u16>) -> rtp::packet::Packet {
+        let mut pkt = rtp::packet::Packet {
+            header: rtp::header::Header::default(),
+            payload: Bytes::from_static(&[0u8; 100]),
+        };
+        if let Some(transport_sequence) = transport_sequence {
+            let ext = TransportCcExtension { transport_sequence }
+                .marshal()
+                .unwrap();
+            pkt.header.set_extension(HDR_EXT_ID, ext).unwrap();
+        }
+        pkt
+    }
+
+    #[tokio::test]
+    async fn missing_extension() {
+        let map = TwccSendInfo::try_new().unwrap();
+        let next_writer = Arc::new(CountingWriter::default());
+        let stream = TwccTimestampSenderStream::new(
+            map.clone(),
+            HDR_EXT_ID,
+            next_writer.clone(),
+            Instant::now(),
+        );
+
+        // Packets without the extension are forwarded untouched
+        for _ in 0..3 {
+            stream
+                .write(&packet(None), &Attributes::new())
+                .await
+                .unwrap();
+        }
+        assert_eq!(next_writer.0.load(Ordering::Relaxed), 3);
+        assert_eq!(map.load_send_info(0).1, 0);
+
+        stream
+            .write(&packet(Some(7)), &Attributes::new())
+            .await
+            .unwrap();
+        assert_eq!(next_writer.0.load(Ordering::Relaxed), 4);
+        assert_eq!(map.load_send_info(7).1, 100);
+    }
+
+    /// Sends a packet every `PACING_INTERVAL` no matter how fast they are written.
+    struct PacedWriter(tokio::sync::Mutex<tokio::time::Instant>);
+
+    const PACING_INTERVAL: Duration = Duration::from_millis(10);
+
+    #[async_trait]
+    impl RTPWriter for PacedWriter {
+        async fn write(
+            &self,
+            pkt: &rtp::packet::Packet,
+            _attributes: &Attributes,
+        ) -> Result<usize, Error> {
+            let mut next_slot = self.0.lock().await;
+            tokio::time::sleep_until(*next_slot).await;
+            *next_slot = tokio::time::Instant::now().max(*next_slot) + PACING_INTERVAL;
+            Ok(pkt.payload.len())
+        }
+    }
+
+    /// Returns the standard deviation in microseconds of the inter-departure times recorded for
+    /// bursts of packets that get paced out.
+    async fn inter_departure_jitter(late_timestamps: bool) -> f64 {
+        const BURST_SIZE: u16 = 5;
+        const NUM_BURSTS: u16 = 4;
+
+        let map = TwccSendInfo::try_new().unwrap();
+        let next_writer = Arc::new(PacedWriter(tokio::sync::Mutex::new(
+            tokio::time::Instant::now(),
+        )));
+        let stream = Arc::new(
+            TwccTimestampSenderStream::new(map.clone(), HDR_EXT_ID, next_writer, Instant::now())
+                .with_late_timestamps(late_timestamps),
+        );
+
+        for burst in 0..NUM_BURSTS {
+            let writes: Vec<_> = (0..BURST_SIZE)
+                .map(|i| {
+                    let stream = stream.clone();
+                    let pkt = packet(Some(burst * BURST_SIZE + i));
+                    tokio::spawn(async move { stream.write(&pkt, &Attributes::new()).await })
+                })
+                .collect();
+            for write in writes {
+                write.await.unwrap().unwrap();
+            }
+        }
+
+        let mut timestamps: Vec<_> = (0..BURST_SIZE * NUM_BURSTS)
+            .map(|seq_num| map.load_send_info(seq_num).0.as_raw())
+            .collect();
+        timestamps.sort_unstable();
+        let deltas: Vec<_> = timestamps
+            .windows(2)
+            .map(|w| (w[1] - w[0]) as f64)
+            .collect();
+        let mean = deltas.iter().sum::<f64>() / deltas.len() as f64;
+        let variance = deltas.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / deltas.len() as f64;
+        variance.sqrt()
+    }
+
+    #[tokio::test]
+    async fn late_timestamps_follow_pacing() {
+        let early_jitter = inter_departure_jitter(false).await;
+        let late_jitter = inter_departure_jitter(true).await;
+
+        // Written in bursts but sent evenly
+        assert!(early_jitter > PACING_INTERVAL.as_micros() as f64);
+        assert!(late_jitter < early_jitter / 4.0);
+    }
+
+    #[tokio::test]
+    async fn two_byte_extension_header() {
+        // Only representable in the two-byte form
+        const TWO_BYTE_HDR_EXT_ID: u8 = 20;
+        const TRANSPORT_SEQUENCE: u16 = 0xabcd;
+
+        let mut header = rtp::header::Header {
+            extension: true,
+            extension_profile: EXTENSION_PROFILE_TWO_BYTE,
+            ..Default::default()
+        };
+        // An extension too long for the one-byte form
+        header
+            .set_extension(1, Bytes::from_static(&[0xff; 20]))
+            .unwrap();
+        let ext = TransportCcExtension {
+            transport_sequence: TRANSPORT_SEQUENCE,
+        }
+        .marshal()
+        .unwrap();
+        header.set_extension(TWO_BYTE_HDR_EXT_ID, ext).unwrap();
+
+        // Round-trip through the wire format
+        let raw = rtp::packet::Packet {
+            header,
+            payload: Bytes::from_static(&[0u8; 100]),
+        }
+        .marshal()
+        .unwrap();
+        let pkt = rtp::packet::Packet::unmarshal(&mut &raw[..]).unwrap();
+        assert_eq!(pkt.header.extension_profile, EXTENSION_PROFILE_TWO_BYTE);
+
+        let map = TwccSendInfo::try_new().unwrap();
+        let stream = TwccTimestampSenderStream::new(
+            map.clone(),
+            TWO_BYTE_HDR_EXT_ID,
+            Arc::new(CountingWriter::default()),
+            Instant::now(),
+        );
+        stream.write(&pkt, &Attributes::new()).await.unwrap();
+        assert_eq!(map.load_send_info(TRANSPORT_SEQUENCE).1, 100);
+        assert_eq!(map.load_send_info(TRANSPORT_SEQUENCE.wrapping_add(1)).1, 0);
     }
 }
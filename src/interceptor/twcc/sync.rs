@@ -1,7 +1,10 @@
 use super::time::TwccTime;
-use std::sync::{
-    atomic::{AtomicI64, AtomicU64, Ordering},
-    Arc,
+use std::{
+    collections::TryReserveError,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 /// Exact sized needed to be able to index in the range [0, u16::MAX]
@@ -10,23 +13,31 @@ const TWCC_ARRAY_SIZE: usize = (u16::MAX as usize) + 1;
 /// TWCC data structure for storing the timestamp and size of each packet sent.
 ///
 // Box<[T; N]> is used instead of Vec<T> or Box<[T]> to help the compiler to elide-out the bounds
-// check when indexing with a u16. `TwccSendInfo` requires approx. ~1 MB of heap memory.
+// check when indexing with a u16. `TwccSendInfo` requires approx. ~1 MB of heap memory. The box is
+// wrapped instead of converted to an `Arc<[T; N]>`, which would copy it into a new allocation that
+// cannot fail.
 #[derive(Clone)]
 #[repr(transparent)]
+#[allow(clippy::redundant_allocation)]
 pub struct TwccSendInfo(Arc<Box<[(AtomicI64, AtomicU64); TWCC_ARRAY_SIZE]>>);
 
 impl TwccSendInfo {
-    /// Create a new `TwccSendInfo`.
-    pub fn new() -> TwccSendInfo {
+    /// Create a new `TwccSendInfo`. Returns an error if the allocation fails.
+    pub fn try_new() -> Result<TwccSendInfo, TryReserveError> {
         let mut vec = Vec::new();
-        vec.reserve_exact(TWCC_ARRAY_SIZE);
+        vec.try_reserve_exact(TWCC_ARRAY_SIZE)?;
 
         for _ in 0..TWCC_ARRAY_SIZE {
             vec.push(Default::default());
         }
 
-        let boxed_array = TryFrom::try_from(vec.into_boxed_slice()).unwrap();
-        TwccSendInfo(Arc::new(boxed_array))
+        // Neither reallocates since the capacity is exactly `TWCC_ARRAY_SIZE`, and the conversion
+        // cannot fail since the length is too
+        let boxed_array = match TryFrom::try_from(vec.into_boxed_slice()) {
+            Ok(boxed_array) => boxed_array,
+            Err(_) => unreachable!(),
+        };
+        Ok(TwccSendInfo(Arc::new(boxed_array)))
     }
 
     /// Stores the timestamp and packet size of the packet.
@@ -45,3 +56,27 @@ impl TwccSendInfo {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn across_wrap() {
+        let send_info = TwccSendInfo::try_new().unwrap();
+
+        // Sequence numbers from 65500 wrapping around to 100
+        let seq_nums: Vec<u16> = (0..137u16).map(|i| 65500u16.wrapping_add(i)).collect();
+        assert_eq!(*seq_nums.last().unwrap(), 100);
+
+        for (i, seq_num) in seq_nums.iter().enumerate() {
+            let timestamp = TwccTime::from_raw(1000 * i as i64);
+            send_info.store_send_info(*seq_num, timestamp, 1000 + i as u64);
+        }
+        for (i, seq_num) in seq_nums.iter().enumerate() {
+            let (timestamp, packet_size) = send_info.load_send_info(*seq_num);
+            assert_eq!(timestamp.as_raw(), 1000 * i as i64);
+            assert_eq!(packet_size, 1000 + i as u64);
+        }
+    }
+}
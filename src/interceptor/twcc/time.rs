@@ -82,6 +82,11 @@ impl TwccTime {
     pub(crate) fn from_raw(val: i64) -> TwccTime {
         TwccTime(val)
     }
+
+    /// Create a `TwccTime` from a timestamp in microseconds, wrapping it into the domain.
+    pub(crate) fn from_micros_wrapping(micros: i64) -> TwccTime {
+        TwccTime(micros.rem_euclid(REFERENCE_TIME_WRAPAROUND))
+    }
 }
 
 // Impl'ed for readability in the delay-based control.
@@ -5,6 +5,7 @@ pub mod interceptor;
 pub mod network;
 pub mod peer;
 pub mod signaling;
+pub mod stats;
 
 pub use self::{
     codecs::Codec,
@@ -12,4 +13,5 @@ pub use self::{
     encoder::EncoderBuilder,
     peer::{WebRtcBuilder, WebRtcPeer},
     signaling::{Message, Signaler},
+    stats::PeerStats,
 };
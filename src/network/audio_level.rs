@@ -0,0 +1,100 @@
+//! The [audio level][rfc6464] RTP header extension. The sender reports the level of each audio
+//! packet so the receiver can tell who is speaking without decoding the audio.
+//!
+//! [rfc6464]: https://www.rfc-editor.org/rfc/rfc6464
+
+use webrtc::{
+    rtp::{
+        self,
+        extension::{audio_level_extension::AudioLevelExtension, HeaderExtension},
+    },
+    rtp_transceiver::rtp_receiver::RTCRtpReceiver,
+    util::Unmarshal,
+};
+
+pub const AUDIO_LEVEL_URI: &str = webrtc::sdp::extmap::AUDIO_LEVEL_URI;
+
+/// Largest value that fits in 7 bits, i.e., silence.
+const MAX_LEVEL: u8 = 0x7f;
+
+///    0                   1
+///    0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5
+///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///   |  ID   | len=0 |V| level       |
+///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioLevel {
+    level: u8,
+    voice_activity: bool,
+}
+
+impl AudioLevel {
+    /// Create a new `AudioLevel`. `level` is in -dBov and capped at 127.
+    pub fn new(level: u8, voice_activity: bool) -> AudioLevel {
+        AudioLevel {
+            level: level.min(MAX_LEVEL),
+            voice_activity,
+        }
+    }
+
+    /// Level of the audio in -dBov, from 0 for the loudest to 127 for silence.
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Whether the sender's voice activity detection thinks the packet contains speech.
+    pub fn voice_activity(&self) -> bool {
+        self.voice_activity
+    }
+
+    /// Wraps the `AudioLevel` so that it can be passed to
+    /// `TrackLocalStaticRTP::write_rtp_with_extensions`, which takes care of using the negotiated
+    /// extension ID.
+    pub fn to_header_extension(self) -> HeaderExtension {
+        HeaderExtension::AudioLevel(AudioLevelExtension {
+            level: self.level,
+            voice: self.voice_activity,
+        })
+    }
+
+    /// Read the `AudioLevel` from an RTP header given the negotiated extension ID.
+    pub fn from_header(header: &rtp::header::Header, id: u8) -> Option<AudioLevel> {
+        let mut buf = header.get_extension(id)?;
+        let extension = AudioLevelExtension::unmarshal(&mut buf).ok()?;
+        Some(AudioLevel::new(extension.level, extension.voice))
+    }
+
+    /// Returns the negotiated extension ID of the audio level extension on the receiver's side.
+    pub async fn extension_id(rtp_receiver: &RTCRtpReceiver) -> Option<u8> {
+        rtp_receiver
+            .get_parameters()
+            .await
+            .header_extensions
+            .iter()
+            .find(|ext| ext.uri == AUDIO_LEVEL_URI)
+            .map(|ext| ext.id as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn read_from_header() {
+        const ID: u8 = 3;
+
+        let mut header = rtp::header::Header::default();
+        header
+            .set_extension(ID, Bytes::from_static(&[0x80 | 30]))
+            .unwrap();
+
+        let audio_level = AudioLevel::from_header(&header, ID).unwrap();
+        assert_eq!(audio_level.level(), 30);
+        assert!(audio_level.voice_activity());
+
+        assert!(AudioLevel::from_header(&header, ID + 1).is_none());
+        assert_eq!(AudioLevel::new(200, false).level(), 127);
+    }
+}
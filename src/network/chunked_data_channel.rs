@@ -0,0 +1,132 @@
+//! Sending application messages larger than the SCTP max message size over a data channel by
+//! splitting them into chunks.
+
+use bytes::{BufMut, BytesMut};
+use std::sync::Arc;
+use webrtc::data::{data_channel::DataChannel, Error};
+
+/// Largest SCTP user message that webrtc-rs sends or accepts. webrtc-rs 0.6 does not negotiate
+/// `a=max-message-size` with the remote peer and always uses this limit, so larger messages
+/// written directly to a data channel fail with `ErrOutboundPacketTooLarge`.
+pub const SCTP_MAX_MESSAGE_SIZE: usize = 65536;
+
+/// Each chunk starts with a byte telling whether it is the last one of the message.
+const CHUNK_HEADER_SIZE: usize = 1;
+const MORE_CHUNKS: u8 = 0;
+const LAST_CHUNK: u8 = 1;
+
+/// Default limit on the size of the reassembled messages.
+pub const DEFAULT_MAX_RECEIVED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Wraps a detached data channel to send and receive messages of any size. Messages are split
+/// into chunks of up to the max message size and reassembled on the other side, so both ends
+/// need to use a `ChunkedDataChannel`.
+///
+/// The data channel has to be ordered and reliable, which is the default for
+/// `RTCPeerConnection::create_data_channel`.
+pub struct ChunkedDataChannel {
+    data_channel: Arc<DataChannel>,
+    max_message_size: usize,
+    read_buf: Vec<u8>,
+    max_received_size: usize,
+    // Chunks received so far of the message being reassembled
+    message: Vec<u8>,
+    // Set when the message being received got too large, until its last chunk
+    discarding: bool,
+}
+
+impl ChunkedDataChannel {
+    /// Wrap a data channel returned by `RTCDataChannel::detach`.
+    pub fn new(data_channel: Arc<DataChannel>) -> ChunkedDataChannel {
+        ChunkedDataChannel {
+            data_channel,
+            max_message_size: SCTP_MAX_MESSAGE_SIZE,
+            // Fits any chunk regardless of the max message size of the other side
+            read_buf: vec![0u8; SCTP_MAX_MESSAGE_SIZE],
+            max_received_size: DEFAULT_MAX_RECEIVED_SIZE,
+            message: Vec::new(),
+            discarding: false,
+        }
+    }
+
+    /// Limit the SCTP messages sent to `size_bytes`, including the chunk header, for remote peers
+    /// that accept less than [SCTP_MAX_MESSAGE_SIZE]. Capped at [SCTP_MAX_MESSAGE_SIZE], which
+    /// is also the default.
+    pub fn with_max_message_size(mut self, size_bytes: usize) -> ChunkedDataChannel {
+        self.max_message_size = size_bytes.clamp(CHUNK_HEADER_SIZE + 1, SCTP_MAX_MESSAGE_SIZE);
+        self
+    }
+
+    /// Max size in bytes of the SCTP messages sent.
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Reject the received messages larger than `size_bytes` instead of reassembling them.
+    /// Defaults to [DEFAULT_MAX_RECEIVED_SIZE].
+    pub fn with_max_received_size(mut self, size_bytes: usize) -> ChunkedDataChannel {
+        self.max_received_size = size_bytes;
+        self
+    }
+
+    /// Max size in bytes of the messages received.
+    pub fn max_received_size(&self) -> usize {
+        self.max_received_size
+    }
+
+    /// Send `message`, split into as many SCTP messages as needed.
+    pub async fn send(&self, message: &[u8]) -> Result<(), Error> {
+        let max_chunk_size = self.max_message_size - CHUNK_HEADER_SIZE;
+        let mut start = 0;
+        // An empty message is still sent as a single chunk
+        loop {
+            let end = message.len().min(start + max_chunk_size);
+            let is_last = end == message.len();
+
+            let mut chunk = BytesMut::with_capacity(CHUNK_HEADER_SIZE + end - start);
+            chunk.put_u8(if is_last { LAST_CHUNK } else { MORE_CHUNKS });
+            chunk.put_slice(&message[start..end]);
+            self.data_channel.write(&chunk.freeze()).await?;
+
+            if is_last {
+                return Ok(());
+            }
+            start = end;
+        }
+    }
+
+    /// Receive the next message once all of its chunks arrived. Returns [None] when the data
+    /// channel is closed.
+    ///
+    /// A message larger than [max_received_size][Self::max_received_size] fails with an error as
+    /// soon as it goes over. Its remaining chunks are dropped and the next call receives the
+    /// message after it.
+    pub async fn recv(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            let n = self.data_channel.read(&mut self.read_buf).await?;
+            // Every chunk has at least the header
+            if n < CHUNK_HEADER_SIZE {
+                return Ok(None);
+            }
+            let is_last = self.read_buf[0] == LAST_CHUNK;
+            if self.discarding {
+                self.discarding = !is_last;
+                continue;
+            }
+
+            let chunk = &self.read_buf[CHUNK_HEADER_SIZE..n];
+            if self.message.len() + chunk.len() > self.max_received_size {
+                self.message = Vec::new();
+                self.discarding = !is_last;
+                return Err(Error::new(format!(
+                    "Received message larger than {} bytes",
+                    self.max_received_size
+                )));
+            }
+            self.message.extend_from_slice(chunk);
+            if is_last {
+                return Ok(Some(std::mem::take(&mut self.message)));
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! The [dependency descriptor][dd] RTP header extension. Carries the layer structure of AV1 and
+//! VP9 SVC streams so that forwarders and decoders know the spatial and temporal layer of each
+//! packet without parsing the payload.
+//!
+//! [dd]: https://aomediacodec.github.io/av1-rtp-spec/#dependency-descriptor-rtp-header-extension
+
+use exp_golomb::ExpGolombDecoder;
+use webrtc::{rtp, rtp_transceiver::rtp_receiver::RTCRtpReceiver};
+
+pub const DEPENDENCY_DESCRIPTOR_URI: &str =
+    "https://aomediacodec.github.io/av1-rtp-spec/#dependency-descriptor-rtp-header-extension";
+
+/// Size of start_of_frame, end_of_frame, frame_dependency_template_id, and frame_number.
+const MANDATORY_FIELDS_SIZE: usize = 3;
+
+/// Template IDs are 6 bits.
+const MAX_TEMPLATES: usize = 64;
+
+/// Fields of a dependency descriptor, with the layer resolved through its frame dependency
+/// template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DependencyDescriptor {
+    /// Whether the packet is the first of the frame.
+    pub start_of_frame: bool,
+    /// Whether the packet is the last of the frame.
+    pub end_of_frame: bool,
+    /// Wrapping 16-bit frame number.
+    pub frame_number: u16,
+    /// Spatial layer of the frame, 0 being the base layer.
+    pub spatial_id: u8,
+    /// Temporal layer of the frame, 0 being the base layer.
+    pub temporal_id: u8,
+}
+
+/// Reads dependency descriptors. The template dependency structure needed for resolving the
+/// layers is only sent in some of the descriptors, usually those of keyframes, so it is kept
+/// for the ones after.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyDescriptorReader {
+    template_id_offset: usize,
+    // (spatial_id, temporal_id) of each template
+    template_layers: Vec<(u8, u8)>,
+}
+
+impl DependencyDescriptorReader {
+    pub fn new() -> DependencyDescriptorReader {
+        DependencyDescriptorReader::default()
+    }
+
+    /// Parse the extension data. Returns [None] if it is malformed or if no template dependency
+    /// structure has been received yet.
+    pub fn read(&mut self, data: &[u8]) -> Option<DependencyDescriptor> {
+        if data.len() < MANDATORY_FIELDS_SIZE {
+            return None;
+        }
+        let mut reader = ExpGolombDecoder::new(data, 0)?;
+
+        let start_of_frame = reader.next_bit()? == 1;
+        let end_of_frame = reader.next_bit()? == 1;
+        let template_id = next_bits(&mut reader, 6)? as usize;
+        let frame_number = next_bits(&mut reader, 16)? as u16;
+
+        if data.len() > MANDATORY_FIELDS_SIZE {
+            let template_dependency_structure_present_flag = reader.next_bit()?;
+            // Skip active_decode_targets_present_flag, custom_dtis_flag, custom_fdiffs_flag, and
+            // custom_chains_flag
+            next_bits(&mut reader, 4)?;
+            if template_dependency_structure_present_flag == 1 {
+                self.read_template_dependency_structure(&mut reader)?;
+            }
+        }
+
+        let template_index =
+            (template_id + MAX_TEMPLATES - self.template_id_offset) % MAX_TEMPLATES;
+        let &(spatial_id, temporal_id) = self.template_layers.get(template_index)?;
+        Some(DependencyDescriptor {
+            start_of_frame,
+            end_of_frame,
+            frame_number,
+            spatial_id,
+            temporal_id,
+        })
+    }
+
+    /// Read the dependency descriptor from an RTP header given the negotiated extension ID.
+    pub fn read_from_header(
+        &mut self,
+        header: &rtp::header::Header,
+        id: u8,
+    ) -> Option<DependencyDescriptor> {
+        let data = header.get_extension(id)?;
+        self.read(&data)
+    }
+
+    /// Returns the negotiated extension ID of the dependency descriptor extension on the
+    /// receiver's side.
+    pub async fn extension_id(rtp_receiver: &RTCRtpReceiver) -> Option<u8> {
+        rtp_receiver
+            .get_parameters()
+            .await
+            .header_extensions
+            .iter()
+            .find(|ext| ext.uri == DEPENDENCY_DESCRIPTOR_URI)
+            .map(|ext| ext.id as u8)
+    }
+
+    /// Only reads up to the layers of the templates. The decode targets, chains, and resolutions
+    /// after them are not needed for the layers.
+    fn read_template_dependency_structure(&mut self, reader: &mut ExpGolombDecoder) -> Option<()> {
+        let template_id_offset = next_bits(reader, 6)? as usize;
+        // Skip dt_cnt_minus_one
+        next_bits(reader, 5)?;
+
+        let mut template_layers = Vec::new();
+        let (mut spatial_id, mut temporal_id) = (0, 0);
+        loop {
+            if template_layers.len() == MAX_TEMPLATES {
+                return None;
+            }
+            template_layers.push((spatial_id, temporal_id));
+            match next_bits(reader, 2)? {
+                // Same layer
+                0 => {}
+                1 => temporal_id += 1,
+                2 => {
+                    temporal_id = 0;
+                    spatial_id += 1;
+                }
+                // No more templates
+                _ => break,
+            }
+        }
+
+        self.template_id_offset = template_id_offset;
+        self.template_layers = template_layers;
+        Some(())
+    }
+}
+
+/// Read `n` bits as an unsigned integer.
+fn next_bits(reader: &mut ExpGolombDecoder, n: u32) -> Option<u64> {
+    let mut value = 0;
+    for _ in 0..n {
+        value = (value << 1) | reader.next_bit()? as u64;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::util::bit_string::pack;
+
+    #[test]
+    fn l2t2_layers() {
+        // Keyframe with start_of_frame, template 5, and frame 1000, followed by the structure
+        // with template ID offset 5 and four templates going through S0T0, S0T1, S1T0, and S1T1
+        let keyframe = pack(
+            "1 0 000101 0000001111101000 \
+            1 0000 \
+            000101 00011 \
+            01 10 01 11",
+        );
+        let mut reader = DependencyDescriptorReader::new();
+        assert_eq!(
+            reader.read(&keyframe),
+            Some(DependencyDescriptor {
+                start_of_frame: true,
+                end_of_frame: false,
+                frame_number: 1000,
+                spatial_id: 0,
+                temporal_id: 0,
+            })
+        );
+
+        // Later frames only have the mandatory fields
+        let layers: Vec<_> = (5..9)
+            .map(|template_id| {
+                let data = pack(&format!("01{template_id:06b}{:016b}", 1001));
+                let descriptor = reader.read(&data).unwrap();
+                assert!(descriptor.end_of_frame);
+                (descriptor.spatial_id, descriptor.temporal_id)
+            })
+            .collect();
+        assert_eq!(layers, [(0, 0), (0, 1), (1, 0), (1, 1)]);
+
+        // Beyond the templates
+        assert!(reader.read(&pack("01001001 00000011 11101001")).is_none());
+    }
+
+    #[test]
+    fn no_structure_yet() {
+        let mut reader = DependencyDescriptorReader::new();
+        assert!(reader.read(&pack("11000000 00000000 00000001")).is_none());
+        assert!(reader.read(&[0x80]).is_none());
+
+        // A single template
+        let data = pack("1 1 000000 0000000000000001 1 0000 000000 00000 11");
+        let mut header = rtp::header::Header::default();
+        header.set_extension(4, data.into()).unwrap();
+        let descriptor = reader.read_from_header(&header, 4).unwrap();
+        assert_eq!((descriptor.spatial_id, descriptor.temporal_id), (0, 0));
+    }
+}
@@ -0,0 +1,117 @@
+//! Frame dropping for encoders that cannot lower their output as fast as the bandwidth estimate
+//! drops. Sending those frames anyway would only queue them up along the path and add latency.
+
+use crate::interceptor::twcc::TwccBandwidthEstimate;
+use std::time::{Duration, Instant};
+
+/// Leaky bucket drained at the bandwidth estimate. Frames are dropped before they are encoded
+/// while the bucket holds more than the target latency, since dropping an encoded frame would
+/// break the frames that reference it until the next keyframe.
+pub struct FrameDropper {
+    bandwidth_estimate: TwccBandwidthEstimate,
+    target_latency: Duration,
+    // Bytes sent but not yet drained at the estimated bandwidth
+    queued_bytes: f64,
+    last_update: Option<Instant>,
+}
+
+impl FrameDropper {
+    /// Create a `FrameDropper` that keeps the sent frames within `target_latency` worth of the
+    /// bandwidth estimate.
+    pub fn new(
+        bandwidth_estimate: TwccBandwidthEstimate,
+        target_latency: Duration,
+    ) -> FrameDropper {
+        FrameDropper {
+            bandwidth_estimate,
+            target_latency,
+            queued_bytes: 0.0,
+            last_update: None,
+        }
+    }
+
+    /// Returns `false` if the frame captured at `now` should be skipped instead of encoded. A
+    /// keyframe due on a skipped frame should be encoded on the next frame that is not.
+    pub fn should_encode(&mut self, now: Instant) -> bool {
+        let bytes_per_sec = self.drain(now);
+        let budget = bytes_per_sec * self.target_latency.as_secs_f64();
+        self.queued_bytes < budget
+    }
+
+    /// Count the `frame_size` bytes of a frame encoded at `now` against the budget. Every encoded
+    /// frame should be passed here, including keyframes.
+    pub fn frame_encoded(&mut self, frame_size: usize, now: Instant) {
+        self.drain(now);
+        self.queued_bytes += frame_size as f64;
+    }
+
+    /// Drain the bucket up to `now` and return the bandwidth estimate it drained at.
+    fn drain(&mut self, now: Instant) -> f64 {
+        let bytes_per_sec = self.bandwidth_estimate.borrow().bytes_per_sec_f64();
+        if let Some(last_update) = self.last_update {
+            let elapsed = now.saturating_duration_since(last_update).as_secs_f64();
+            self.queued_bytes = (self.queued_bytes - elapsed * bytes_per_sec).max(0.0);
+        }
+        self.last_update = Some(now);
+        bytes_per_sec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::data_rate::DataRate;
+    use tokio::sync::watch;
+
+    const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+    const FRAME_SIZE: usize = 5000;
+    const KEYFRAME_SIZE: usize = 20000;
+    const KEYFRAME_INTERVAL: usize = 30;
+
+    #[test]
+    fn drops_frames_after_bitrate_drop() {
+        let (estimate_tx, estimate_rx) = watch::channel(DataRate::from_bits_per_sec(2_000_000));
+        let mut frame_dropper = FrameDropper::new(estimate_rx, Duration::from_millis(200));
+
+        let start = Instant::now();
+        // Returns the encoded frames and keyframes, and the skipped frames
+        let capture_frames = |frame_dropper: &mut FrameDropper, offset: usize| {
+            let mut encoded = [0usize; 2];
+            let mut skipped = 0;
+            let mut keyframe_due = false;
+            for i in 0..3 * KEYFRAME_INTERVAL {
+                keyframe_due |= i % KEYFRAME_INTERVAL == 0;
+                let now = start + FRAME_INTERVAL * (offset + i) as u32;
+                if !frame_dropper.should_encode(now) {
+                    skipped += 1;
+                    continue;
+                }
+                let frame_size = if keyframe_due {
+                    KEYFRAME_SIZE
+                } else {
+                    FRAME_SIZE
+                };
+                frame_dropper.frame_encoded(frame_size, now);
+                encoded[keyframe_due as usize] += 1;
+                keyframe_due = false;
+            }
+            (encoded, skipped)
+        };
+
+        // Encoder output of about 1.4 Mbps fits
+        let (_, skipped) = capture_frames(&mut frame_dropper, 0);
+        assert_eq!(skipped, 0);
+
+        // The encoder does not follow the drop to 300 kbps
+        estimate_tx.send_replace(DataRate::from_bits_per_sec(300_000));
+        let (encoded, skipped) = capture_frames(&mut frame_dropper, 3 * KEYFRAME_INTERVAL);
+        // Every keyframe is still encoded, only later
+        assert_eq!(encoded[1], 3);
+        assert!(skipped > encoded[0]);
+
+        // What got through is close to the estimate
+        let sent_bits = 8 * (encoded[0] * FRAME_SIZE + encoded[1] * KEYFRAME_SIZE);
+        let duration = (FRAME_INTERVAL * 3 * KEYFRAME_INTERVAL as u32).as_secs_f64();
+        assert!(sent_bits as f64 / duration < 1.2 * 300_000.0);
+    }
+}
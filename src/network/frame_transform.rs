@@ -0,0 +1,66 @@
+//! Insertable-streams-style hooks for transforming the encoded media, e.g., for end-to-end
+//! encryption or watermarking. SRTP only protects each hop so a relay in between sees the media
+//! unless it is transformed on top of that.
+
+use async_trait::async_trait;
+use std::ops::Deref;
+use webrtc::{rtp::packet::Packet, track::track_local::TrackLocalWriter, util::Unmarshal};
+
+/// Transforms the encoded media carried in each RTP payload. The sender transforms the payloads
+/// after packetization through [TransformedTrackWriter], e.g., with
+/// [PassthroughEncoderBuilder::with_frame_transform][a], while the receiver reverses it before
+/// depacketization through [BufferedTrackRemote::with_frame_transform][b].
+///
+/// [a]: crate::encoder::PassthroughEncoderBuilder::with_frame_transform
+/// [b]: crate::network::reorder_buffer::BufferedTrackRemote::with_frame_transform
+pub trait FrameTransform: Send + Sync {
+    /// Write the transformed `payload` to `output`, which is empty.
+    fn transform(&self, payload: &[u8], output: &mut Vec<u8>);
+}
+
+/// `TrackLocalWriter` that transforms the payload of every packet before writing it to the
+/// wrapped writer, which is behind a pointer such as the `Arc<TrackLocalStaticRTP>` passed to
+/// [EncoderBuilder::build][a].
+///
+/// [a]: crate::encoder::EncoderBuilder::build
+pub struct TransformedTrackWriter<T> {
+    writer: T,
+    transform: Box<dyn FrameTransform>,
+}
+
+impl<T> TransformedTrackWriter<T> {
+    /// Wrap `writer`, e.g., the `Arc<TrackLocalStaticRTP>` that an encoder writes to.
+    pub fn new(writer: T, transform: Box<dyn FrameTransform>) -> TransformedTrackWriter<T> {
+        TransformedTrackWriter { writer, transform }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for TransformedTrackWriter<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransformedTrackWriter")
+            .field("writer", &self.writer)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<T> TrackLocalWriter for TransformedTrackWriter<T>
+where
+    T: Deref + std::fmt::Debug + Send + Sync,
+    T::Target: TrackLocalWriter + Send + Sync,
+{
+    async fn write_rtp(&self, p: &Packet) -> Result<usize, webrtc::Error> {
+        let mut payload = Vec::with_capacity(p.payload.len());
+        self.transform.transform(&p.payload, &mut payload);
+        let packet = Packet {
+            header: p.header.clone(),
+            payload: payload.into(),
+        };
+        self.writer.write_rtp(&packet).await
+    }
+
+    async fn write(&self, mut b: &[u8]) -> Result<usize, webrtc::Error> {
+        let packet = Packet::unmarshal(&mut b)?;
+        self.write_rtp(&packet).await
+    }
+}
@@ -1,2 +1,10 @@
+pub mod audio_level;
+pub mod chunked_data_channel;
 pub mod data_rate;
+pub mod dependency_descriptor;
+pub mod frame_dropper;
+pub mod frame_transform;
+pub mod mtu_discovery;
+pub mod playout_delay;
 pub mod reorder_buffer;
+pub mod transmission_offset;
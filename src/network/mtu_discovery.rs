@@ -0,0 +1,106 @@
+//! Packetization MTU discovery driven by the packet loss reported in the TWCC feedback.
+//!
+//! Packets are never larger than the confirmed MTU except for the probes, so a path that drops
+//! large packets only costs the probes. The sizes here are of the RTP payload, which is what the
+//! `mtu` of the sample senders limits.
+
+/// Packetization MTU that is safe on practically every path, even with TURN and VPN overhead.
+pub const INITIAL_MTU: usize = 1200;
+
+// Feedback on this many packets decides whether a probe succeeded
+const PROBE_PACKETS: u32 = 10;
+// A probe with more loss than this is assumed to have been dropped for its size
+const MAX_PROBE_LOSS_RATIO: f64 = 0.5;
+// Stop probing once the MTU is known to within this many bytes
+const CONVERGENCE_BYTES: usize = 8;
+
+/// Binary search for the largest packet size that is not dropped by the path.
+pub struct MtuDiscovery {
+    // Largest size known to get through
+    confirmed: usize,
+    // Smallest size known to be dropped
+    failed: usize,
+    received: u32,
+    lost: u32,
+}
+
+impl MtuDiscovery {
+    /// Start probing from [INITIAL_MTU] up to `max_mtu`.
+    pub fn new(max_mtu: usize) -> MtuDiscovery {
+        MtuDiscovery {
+            confirmed: INITIAL_MTU.min(max_mtu),
+            failed: max_mtu.max(INITIAL_MTU) + 1,
+            received: 0,
+            lost: 0,
+        }
+    }
+
+    /// Size the packets should be fragmented to. This is the probe size while probing.
+    pub fn packet_size(&self) -> usize {
+        if self.is_converged() {
+            self.confirmed
+        } else {
+            (self.confirmed + self.failed) / 2
+        }
+    }
+
+    /// Whether probing has finished.
+    pub fn is_converged(&self) -> bool {
+        self.failed - self.confirmed <= CONVERGENCE_BYTES
+    }
+
+    /// Update with the TWCC feedback on a packet with the given payload size. Only packets larger
+    /// than the confirmed MTU are relevant.
+    pub fn on_packet_feedback(&mut self, size: usize, received: bool) {
+        if self.is_converged() || size <= self.confirmed {
+            return;
+        }
+
+        let probe_size = self.packet_size();
+        if received && size >= probe_size {
+            self.received += 1;
+        } else if !received && size <= probe_size {
+            self.lost += 1;
+        } else {
+            // Says nothing about the current probe, e.g., a lost leftover from a larger probe
+            return;
+        }
+
+        let total = self.received + self.lost;
+        if total >= PROBE_PACKETS {
+            if self.lost as f64 / total as f64 > MAX_PROBE_LOSS_RATIO {
+                self.failed = probe_size;
+            } else {
+                self.confirmed = probe_size;
+            }
+            self.received = 0;
+            self.lost = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stabilizes_below_dropped_size() {
+        const DROPPED_ABOVE: usize = 1300;
+
+        let mut mtu_discovery = MtuDiscovery::new(1500);
+        assert_eq!(mtu_discovery.packet_size(), INITIAL_MTU + 150);
+
+        // Link that drops every packet larger than 1300 bytes, with a full and a partial
+        // fragment per frame
+        for _ in 0..100 {
+            let packet_size = mtu_discovery.packet_size();
+            for size in [packet_size, packet_size / 2] {
+                mtu_discovery.on_packet_feedback(size, size <= DROPPED_ABOVE);
+            }
+        }
+
+        assert!(mtu_discovery.is_converged());
+        let mtu = mtu_discovery.packet_size();
+        assert!(mtu <= DROPPED_ABOVE && mtu > DROPPED_ABOVE - CONVERGENCE_BYTES);
+    }
+}
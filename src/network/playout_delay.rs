@@ -0,0 +1,157 @@
+//! The [playout-delay][pd] RTP header extension. Lets the sender hint the minimum and maximum
+//! delay the receiver should apply before rendering a frame.
+//!
+//! [pd]: http://www.webrtc.org/experiments/rtp-hdrext/playout-delay
+
+use bytes::{Buf, BufMut};
+use std::time::Duration;
+use webrtc::{
+    rtp::{self, extension::HeaderExtension},
+    rtp_transceiver::rtp_receiver::RTCRtpReceiver,
+    util::{self, Marshal, MarshalSize, Unmarshal},
+};
+
+pub const PLAYOUT_DELAY_URI: &str = "http://www.webrtc.org/experiments/rtp-hdrext/playout-delay";
+
+const PLAYOUT_DELAY_EXTENSION_SIZE: usize = 3;
+
+/// Delays are sent in multiples of 10 ms.
+const GRANULARITY_MS: u64 = 10;
+
+/// Largest value that fits in 12 bits.
+const MAX_DELAY: u16 = 0xfff;
+
+///    0                   1                   2                   3
+///    0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///   |  ID   | len=2 |       MIN delay       |       MAX delay       |
+///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayoutDelay {
+    min_delay: u16,
+    max_delay: u16,
+}
+
+impl PlayoutDelay {
+    /// Create a new `PlayoutDelay`. The delays are rounded down to a multiple of 10 ms and capped
+    /// at 40.95 s.
+    pub fn new(min: Duration, max: Duration) -> PlayoutDelay {
+        let to_units =
+            |delay: Duration| (delay.as_millis() / GRANULARITY_MS as u128).min(MAX_DELAY as u128);
+        let min_delay = to_units(min) as u16;
+        let max_delay = to_units(max) as u16;
+
+        PlayoutDelay {
+            min_delay,
+            max_delay: max_delay.max(min_delay),
+        }
+    }
+
+    /// Minimum delay before rendering a frame.
+    pub fn min(&self) -> Duration {
+        Duration::from_millis(self.min_delay as u64 * GRANULARITY_MS)
+    }
+
+    /// Maximum delay before rendering a frame.
+    pub fn max(&self) -> Duration {
+        Duration::from_millis(self.max_delay as u64 * GRANULARITY_MS)
+    }
+
+    /// Wraps the `PlayoutDelay` so that it can be passed to
+    /// `TrackLocalStaticRTP::write_rtp_with_extensions`, which takes care of using the negotiated
+    /// extension ID.
+    pub fn to_header_extension(self) -> HeaderExtension {
+        HeaderExtension::Custom {
+            uri: PLAYOUT_DELAY_URI.into(),
+            extension: Box::new(self),
+        }
+    }
+
+    /// Read the `PlayoutDelay` from an RTP header given the negotiated extension ID.
+    pub fn from_header(header: &rtp::header::Header, id: u8) -> Option<PlayoutDelay> {
+        let mut buf = header.get_extension(id)?;
+        PlayoutDelay::unmarshal(&mut buf).ok()
+    }
+
+    /// Returns the negotiated extension ID of the playout-delay extension on the receiver's side.
+    pub async fn extension_id(rtp_receiver: &RTCRtpReceiver) -> Option<u8> {
+        rtp_receiver
+            .get_parameters()
+            .await
+            .header_extensions
+            .iter()
+            .find(|ext| ext.uri == PLAYOUT_DELAY_URI)
+            .map(|ext| ext.id as u8)
+    }
+}
+
+impl MarshalSize for PlayoutDelay {
+    fn marshal_size(&self) -> usize {
+        PLAYOUT_DELAY_EXTENSION_SIZE
+    }
+}
+
+impl Marshal for PlayoutDelay {
+    fn marshal_to(&self, mut buf: &mut [u8]) -> util::Result<usize> {
+        if buf.remaining_mut() < PLAYOUT_DELAY_EXTENSION_SIZE {
+            return Err(rtp::Error::ErrBufferTooSmall.into());
+        }
+
+        let packed = (self.min_delay as u32) << 12 | self.max_delay as u32;
+        buf.put_uint(packed as u64, PLAYOUT_DELAY_EXTENSION_SIZE);
+        Ok(PLAYOUT_DELAY_EXTENSION_SIZE)
+    }
+}
+
+impl Unmarshal for PlayoutDelay {
+    fn unmarshal<B>(buf: &mut B) -> util::Result<Self>
+    where
+        Self: Sized,
+        B: Buf,
+    {
+        if buf.remaining() < PLAYOUT_DELAY_EXTENSION_SIZE {
+            return Err(rtp::Error::ErrBufferTooSmall.into());
+        }
+
+        let packed = buf.get_uint(PLAYOUT_DELAY_EXTENSION_SIZE) as u32;
+        Ok(PlayoutDelay {
+            min_delay: (packed >> 12) as u16,
+            max_delay: (packed & MAX_DELAY as u32) as u16,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_then_read() {
+        const ID: u8 = 7;
+
+        let playout_delay = PlayoutDelay::new(Duration::from_millis(100), Duration::from_secs(2));
+        let data = playout_delay.marshal().unwrap();
+        assert_eq!(&data[..], &[0x00, 0xa0, 0xc8]);
+
+        let mut header = rtp::header::Header::default();
+        header.set_extension(ID, data).unwrap();
+
+        let parsed = PlayoutDelay::from_header(&header, ID).unwrap();
+        assert_eq!(parsed, playout_delay);
+        assert_eq!(parsed.min(), Duration::from_millis(100));
+        assert_eq!(parsed.max(), Duration::from_secs(2));
+
+        assert!(PlayoutDelay::from_header(&header, ID + 1).is_none());
+    }
+
+    #[test]
+    fn clamped() {
+        let playout_delay = PlayoutDelay::new(Duration::from_millis(15), Duration::from_secs(60));
+        assert_eq!(playout_delay.min(), Duration::from_millis(10));
+        assert_eq!(playout_delay.max(), Duration::from_millis(40950));
+
+        // Max is never less than min
+        let playout_delay = PlayoutDelay::new(Duration::from_millis(500), Duration::ZERO);
+        assert_eq!(playout_delay.max(), Duration::from_millis(500));
+    }
+}
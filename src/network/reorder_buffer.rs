@@ -1,17 +1,26 @@
+use super::{
+    audio_level::AudioLevel,
+    dependency_descriptor::{DependencyDescriptor, DependencyDescriptorReader},
+    frame_transform::FrameTransform,
+};
+use crate::codecs::util::{Depacketizer, DepacketizerError, KeyframeWaiter};
 use bytes::Buf;
-use std::{collections::BTreeMap, sync::Arc, time::Duration};
-use tokio::time::timeout;
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+use tokio::time::{timeout, Instant};
 use webrtc::{rtp, util::Unmarshal};
 
 const MAX_MTU: usize = 1500;
 const READ_TIMEOUT: Duration = Duration::from_millis(5000);
 const MIN_RTP_HEADER_SIZE: usize = 12;
 
+/// Interval of the NACK generator registered by `configure_nack`.
+const NACK_INTERVAL: Duration = Duration::from_millis(100);
+
 #[cfg(not(test))]
 type TrackRemote = webrtc::track::track_remote::TrackRemote;
 
 #[cfg(test)]
-type TrackRemote = dyn tests::DummyTrackRemoteTrait;
+type TrackRemote = dyn tests::DummyTrackRemoteTrait + Send + Sync;
 
 #[derive(Debug)]
 pub enum ReorderBufferError {
@@ -20,14 +29,66 @@ pub enum ReorderBufferError {
     TrackRemoteReadError,
     PacketTooShort,
     BufferFull,
+    /// A missing packet was skipped after waiting for the max reorder delay.
+    MaxReorderDelayExceeded,
     UnorderablePacketReceived,
+    Depacketizer(DepacketizerError),
+    /// The read was cancelled through `BufferedTrackRemote::recv_cancellable`.
+    Cancelled,
 }
 
+/// Packets skipped by `BufferedTrackRemote` after `ReorderBufferError::BufferFull` or
+/// `ReorderBufferError::MaxReorderDelayExceeded`.
+///
+/// An Opus packet carries the FEC data of the previous frame, so the packet at `next_timestamp`
+/// can be decoded with FEC enabled to recover the last lost frame before decoding it normally.
+/// Any other lost frames have to be concealed with PLC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketLoss {
+    /// Sequence number of the first lost packet.
+    pub first_seq_num: u16,
+    /// Number of consecutive packets lost.
+    pub num_lost: u16,
+    /// RTP timestamp of the last packet returned before the loss, if any.
+    pub prev_timestamp: Option<u32>,
+    /// RTP timestamp of the packet after the loss, i.e., the next packet returned by `recv`.
+    pub next_timestamp: u32,
+}
+
+/// Payload of a received packet, its RTP timestamp, and its header extensions.
+pub type ReceivedPayload<'a> = (&'a [u8], u32, &'a [rtp::header::Extension]);
+
 pub struct BufferedTrackRemote {
     track: Arc<TrackRemote>,
     expected_seq_num: Option<SequenceNumber>,
     packets: BTreeMap<SequenceNumber, RawPacket>,
     buffers: Vec<PacketBuffer>,
+    // Limit on the bytes of the packets waiting in `packets` instead of on their count
+    byte_budget: Option<usize>,
+    buffered_bytes: usize,
+    // The last returned packet if it was stored in `PacketStorage::Compact`
+    compact_packet: Box<[u8]>,
+    max_reorder_delay: Option<Duration>,
+    // When the buffer started waiting for `expected_seq_num`
+    gap_start: Option<Instant>,
+    prev_timestamp: Option<u32>,
+    packet_loss: Option<PacketLoss>,
+    frame_transform: Option<Box<dyn FrameTransform>>,
+    audio_level_id: Option<u8>,
+    // Audio level of the last returned packet
+    audio_level: Option<AudioLevel>,
+    dependency_descriptor_id: Option<u8>,
+    dependency_descriptor_reader: DependencyDescriptorReader,
+    // Dependency descriptor of the last returned packet
+    dependency_descriptor: Option<DependencyDescriptor>,
+    // Header extensions of the last returned packet
+    extensions: Vec<rtp::header::Extension>,
+    // Output of `frame_transform` for the last returned packet
+    transformed: Vec<u8>,
+    // Marker bit of the last returned packet
+    marker: bool,
+    // Drops the payloads before the first keyframe in `recv_into_sink`
+    keyframe_waiter: KeyframeWaiter,
 }
 
 impl BufferedTrackRemote {
@@ -39,35 +100,202 @@ impl BufferedTrackRemote {
             expected_seq_num: None,
             packets: BTreeMap::new(),
             buffers,
+            byte_budget: None,
+            buffered_bytes: 0,
+            compact_packet: Box::default(),
+            max_reorder_delay: None,
+            gap_start: None,
+            prev_timestamp: None,
+            packet_loss: None,
+            frame_transform: None,
+            audio_level_id: None,
+            audio_level: None,
+            dependency_descriptor_id: None,
+            dependency_descriptor_reader: DependencyDescriptorReader::new(),
+            dependency_descriptor: None,
+            extensions: Vec::new(),
+            transformed: Vec::new(),
+            marker: false,
+            keyframe_waiter: KeyframeWaiter::new(false),
+        }
+    }
+
+    /// Same as `new` but bounds the out-of-order packets waiting for a missing one by the total of
+    /// their sizes instead of their number. The packets are copied to allocations of their own
+    /// size so small packets, e.g., audio, don't each hold on to a whole MTU.
+    ///
+    /// Packets are still read into an MTU-sized buffer, which also takes the first packet that
+    /// doesn't fit `byte_budget`. The next one after that gives `ReorderBufferError::BufferFull`.
+    pub fn new_with_byte_budget(
+        track: Arc<TrackRemote>,
+        byte_budget: usize,
+    ) -> BufferedTrackRemote {
+        BufferedTrackRemote {
+            byte_budget: Some(byte_budget),
+            ..BufferedTrackRemote::new(track, 1)
+        }
+    }
+
+    /// Skip a missing packet if it has not arrived within `max_reorder_delay` instead of waiting
+    /// for the buffer to fill up. `recv` returns `ReorderBufferError::MaxReorderDelayExceeded`
+    /// when that happens and resumes with the next packet on the following call.
+    ///
+    /// The delay is at least `NACK_INTERVAL` so that the missing packet is NACKed before giving
+    /// up. It should also cover the round-trip time for the retransmission to arrive.
+    pub fn with_max_reorder_delay(mut self, max_reorder_delay: Duration) -> BufferedTrackRemote {
+        self.max_reorder_delay = Some(max_reorder_delay.max(NACK_INTERVAL));
+        self
+    }
+
+    /// Reverse the transform the sender applied to the payloads, e.g., decrypt them, before `recv`
+    /// returns them.
+    pub fn with_frame_transform(
+        mut self,
+        frame_transform: Box<dyn FrameTransform>,
+    ) -> BufferedTrackRemote {
+        self.frame_transform = Some(frame_transform);
+        self
+    }
+
+    /// Read the audio level extension with the negotiated extension ID `id` from every packet,
+    /// which can be found through [AudioLevel::extension_id].
+    pub fn with_audio_level(mut self, id: u8) -> BufferedTrackRemote {
+        self.audio_level_id = Some(id);
+        self
+    }
+
+    /// Make `recv_into_sink` drop the payloads until the start of a keyframe, as detected by
+    /// [Depacketizer::is_keyframe_start], so that decoding starts cleanly when joining an
+    /// existing stream. `recv` and `recv_with_extensions` are unaffected.
+    pub fn with_wait_for_keyframe(mut self) -> BufferedTrackRemote {
+        self.keyframe_waiter = KeyframeWaiter::new(true);
+        self
+    }
+
+    /// Start dropping the payloads in `recv_into_sink` again until the next keyframe, e.g., after
+    /// a [PacketLoss] the decoder cannot conceal.
+    pub fn wait_for_keyframe(&mut self) {
+        self.keyframe_waiter.reset();
+    }
+
+    /// Returns the audio level of the packet last returned by `recv`, e.g., for voice activity
+    /// detection before or without decoding it. Always [None] without `with_audio_level`.
+    pub fn audio_level(&self) -> Option<AudioLevel> {
+        self.audio_level
+    }
+
+    /// Read the dependency descriptor extension with the negotiated extension ID `id` from every
+    /// packet, which can be found through [DependencyDescriptorReader::extension_id].
+    pub fn with_dependency_descriptor(mut self, id: u8) -> BufferedTrackRemote {
+        self.dependency_descriptor_id = Some(id);
+        self
+    }
+
+    /// Returns the dependency descriptor of the packet last returned by `recv`, e.g., for
+    /// dropping the layers above the ones being decoded. Always [None] without
+    /// `with_dependency_descriptor`.
+    pub fn dependency_descriptor(&self) -> Option<DependencyDescriptor> {
+        self.dependency_descriptor
+    }
+
+    /// Whether the last packet returned by `recv` had the marker bit set. For video, this marks
+    /// the last packet of a frame.
+    pub fn marker(&self) -> bool {
+        self.marker
+    }
+
+    /// Returns the packets skipped by the last `ReorderBufferError::BufferFull` or
+    /// `ReorderBufferError::MaxReorderDelayExceeded`, if not yet taken.
+    pub fn take_packet_loss(&mut self) -> Option<PacketLoss> {
+        self.packet_loss.take()
+    }
+
+    /// Gives up on the missing packets, forcing the first buffered packet to be returned next.
+    fn skip_to_first_buffered(&mut self) -> bool {
+        let (first_seq_num, packet) = match self.packets.first_key_value() {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        if let Some(expected_seq_num) = self.expected_seq_num {
+            self.packet_loss = Some(PacketLoss {
+                first_seq_num: expected_seq_num.0,
+                num_lost: first_seq_num.0.wrapping_sub(expected_seq_num.0),
+                prev_timestamp: self.prev_timestamp,
+                next_timestamp: packet.get_timestamp(),
+            });
         }
+
+        self.expected_seq_num = Some(*first_seq_num);
+        self.gap_start = None;
+        true
     }
 
     #[cold]
-    fn track_read_timeout(&self) -> Result<(&[u8], u32), ReorderBufferError> {
+    fn track_read_timeout(&self) -> Result<ReceivedPayload<'_>, ReorderBufferError> {
         Err(ReorderBufferError::TrackRemoteReadTimeout)
     }
 
     #[cold]
-    fn track_read_error(&self) -> Result<(&[u8], u32), ReorderBufferError> {
+    fn track_read_error(&self) -> Result<ReceivedPayload<'_>, ReorderBufferError> {
         Err(ReorderBufferError::TrackRemoteReadError)
     }
 
     // SAFETY:
-    // `self.buffers` should not be empty and `len` should be <= `MAX_MTU`
+    // If `compact`, `self.compact_packet` should be the packet. Otherwise, `self.buffers` should
+    // not be empty and `len` should be <= `MAX_MTU`.
     #[inline]
-    unsafe fn last_buffer_payload(&mut self, len: usize) -> Result<(&[u8], u32), ReorderBufferError> {
-        let last_buffer = self.buffers.last().unwrap_unchecked();
-        let mut b: &[u8] = last_buffer.get_unchecked(..len);
+    unsafe fn last_buffer_payload(
+        &mut self,
+        len: usize,
+        compact: bool,
+    ) -> Result<ReceivedPayload<'_>, ReorderBufferError> {
+        let mut b: &[u8] = if compact {
+            &self.compact_packet
+        } else {
+            let last_buffer = self.buffers.last().unwrap_unchecked();
+            last_buffer.get_unchecked(..len)
+        };
 
         // Unmarshaling the header would move `b` to point to the payload
         match unmarshal_header(&mut b) {
-            Some(header) => Ok((b, header.timestamp)),
+            Some(header) => {
+                self.prev_timestamp = Some(header.timestamp);
+                self.marker = header.marker;
+                self.audio_level = self
+                    .audio_level_id
+                    .and_then(|id| AudioLevel::from_header(&header, id));
+                if let Some(id) = self.dependency_descriptor_id {
+                    self.dependency_descriptor = self
+                        .dependency_descriptor_reader
+                        .read_from_header(&header, id);
+                }
+                self.extensions = header.extensions;
+                match &self.frame_transform {
+                    Some(frame_transform) => {
+                        self.transformed.clear();
+                        frame_transform.transform(b, &mut self.transformed);
+                        Ok((&self.transformed, header.timestamp, &self.extensions))
+                    }
+                    None => Ok((b, header.timestamp, &self.extensions)),
+                }
+            }
             None => Err(ReorderBufferError::HeaderParsingError),
         }
     }
 
     #[inline]
     pub async fn recv(&mut self) -> Result<(&[u8], u32), ReorderBufferError> {
+        let (payload, timestamp, _) = self.recv_with_extensions().await?;
+        Ok((payload, timestamp))
+    }
+
+    /// Same as `recv` but also returns the header extensions of the packet, e.g., for the
+    /// extensions that a depacketizer needs or that have no reader of their own here.
+    #[inline]
+    pub async fn recv_with_extensions(
+        &mut self,
+    ) -> Result<ReceivedPayload<'_>, ReorderBufferError> {
         loop {
             if let Some(first_entry) = self.packets.first_entry() {
                 // SAFETY:
@@ -77,26 +305,55 @@ impl BufferedTrackRemote {
 
                 if first_entry.key() == expected_seq_num {
                     let packet = first_entry.remove();
-                    let RawPacket { buffer, len } = packet;
+                    let RawPacket { storage, len } = packet;
 
-                    // Reuse the buffer, adding it to the last spot
-                    self.buffers.push(buffer);
+                    let compact = match storage {
+                        PacketStorage::Pooled(buffer) => {
+                            // Reuse the buffer, adding it to the last spot
+                            self.buffers.push(buffer);
+                            false
+                        }
+                        PacketStorage::Compact(data) => {
+                            self.buffered_bytes -= len;
+                            self.compact_packet = data;
+                            true
+                        }
+                    };
 
                     // Advance the expected sequence number regardless of errors in the next steps
                     *expected_seq_num = expected_seq_num.next();
 
-                    // SAFETY: A buffer was just pushed and we trust the number of bytes retured
-                    // by `TrackRemote::read`
-                    return unsafe { self.last_buffer_payload(len) };
+                    // Restart the wait for the next missing packet
+                    if self.max_reorder_delay.is_some() {
+                        self.gap_start = if self.packets.is_empty() {
+                            None
+                        } else {
+                            Some(Instant::now())
+                        };
+                    }
+
+                    // SAFETY: Either the packet was just moved to `self.compact_packet` or its
+                    // buffer was just pushed and we trust the number of bytes returned by
+                    // `TrackRemote::read`
+                    return unsafe { self.last_buffer_payload(len, compact) };
+                }
+            }
+
+            // Waited too long for the missing packet
+            let deadline = self
+                .max_reorder_delay
+                .zip(self.gap_start)
+                .map(|(max_reorder_delay, gap_start)| gap_start + max_reorder_delay);
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline && self.skip_to_first_buffered() {
+                    return Err(ReorderBufferError::MaxReorderDelayExceeded);
                 }
             }
 
             let last_buffer = match self.buffers.last_mut() {
                 Some(b) => b,
                 None => {
-                    if let Some((first_seq_num, _)) = self.packets.first_key_value() {
-                        // Force the first entry to be returned next
-                        self.expected_seq_num = Some(*first_seq_num);
+                    if self.skip_to_first_buffered() {
                         return Err(ReorderBufferError::BufferFull);
                     } else {
                         // `self.buffers.is_empty()` implies `!self.packets.is_empty()`
@@ -105,8 +362,19 @@ impl BufferedTrackRemote {
                 }
             };
 
-            let track_read = timeout(READ_TIMEOUT, self.track.read(last_buffer)).await;
+            // Wake up at the deadline to skip the missing packet
+            let read_timeout = match deadline {
+                Some(deadline) => {
+                    READ_TIMEOUT.min(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => READ_TIMEOUT,
+            };
+
+            // Dropping the read here leaves every packet in place since `last_buffer` is only
+            // taken out of `self.buffers` after a packet is read into it
+            let track_read = timeout(read_timeout, self.track.read(last_buffer)).await;
             match track_read {
+                Err(_) if read_timeout < READ_TIMEOUT => continue,
                 Err(_) => {
                     return self.track_read_timeout();
                 }
@@ -120,26 +388,22 @@ impl BufferedTrackRemote {
 
                     let seq_num = last_buffer.get_sequence_number();
 
-                    let expected_seq_num = match self.expected_seq_num.as_mut() {
-                        Some(expected_seq_num) => expected_seq_num,
-                        None => {
-                            self.expected_seq_num = Some(seq_num);
-                            // rustc should be able to optimize out the `unwrap`
-                            self.expected_seq_num.as_mut().unwrap()
-                        }
-                    };
+                    // The first packet received is the one expected
+                    let expected_seq_num = *self.expected_seq_num.get_or_insert(seq_num);
 
-                    match seq_num.cmp(expected_seq_num) {
-                        std::cmp::Ordering::Equal if self.packets.is_empty() => {
-                            // Advance the expected sequence number regardless of errors in the
-                            // next steps
-                            *expected_seq_num = expected_seq_num.next();
+                    // Fast path for the dominant in-order case, skipping the RFC1982 comparison
+                    // and the `BTreeMap` altogether
+                    if self.packets.is_empty() && expected_seq_num == seq_num {
+                        // Advance the expected sequence number regardless of errors in the next
+                        // steps
+                        self.expected_seq_num = Some(seq_num.next());
 
-                            // SAFETY: `self.buffers.last_mut()` returned a `Some` and we trust the
-                            // number of bytes retured by `TrackRemote::read`
-                            return unsafe { self.last_buffer_payload(len) };
-                        }
+                        // SAFETY: `self.buffers.last_mut()` returned a `Some` and we trust the
+                        // number of bytes returned by `TrackRemote::read`
+                        return unsafe { self.last_buffer_payload(len, false) };
+                    }
 
+                    match seq_num.cmp(&expected_seq_num) {
                         std::cmp::Ordering::Less => {
                             return Err(ReorderBufferError::UnorderablePacketReceived)
                         }
@@ -150,13 +414,26 @@ impl BufferedTrackRemote {
                         //    saved packets, in which case the packet needs to be pushed to the
                         //    `BTreeMap` to try to empty them on the next loop
                         _ => {
-                            let packet = RawPacket {
+                            if self.max_reorder_delay.is_some() && self.packets.is_empty() {
+                                self.gap_start = Some(Instant::now());
+                            }
+
+                            let fits_budget = self
+                                .byte_budget
+                                .map(|byte_budget| self.buffered_bytes + len <= byte_budget);
+                            let storage = if fits_budget == Some(true) {
+                                self.buffered_bytes += len;
+                                PacketStorage::Compact(last_buffer[..len].into())
+                            } else {
                                 // rustc should be able to optimize out the `unwrap`
-                                buffer: self.buffers.pop().unwrap(),
-                                len,
+                                PacketStorage::Pooled(self.buffers.pop().unwrap())
                             };
+                            let packet = RawPacket { storage, len };
                             if let Some(packet) = self.packets.insert(seq_num, packet) {
-                                self.buffers.push(packet.buffer);
+                                match packet.storage {
+                                    PacketStorage::Pooled(buffer) => self.buffers.push(buffer),
+                                    PacketStorage::Compact(_) => self.buffered_bytes -= packet.len,
+                                }
                             }
                             continue;
                         }
@@ -167,6 +444,70 @@ impl BufferedTrackRemote {
     }
 }
 
+impl BufferedTrackRemote {
+    /// Same as `recv` but returns `ReorderBufferError::Cancelled` once `cancel` completes, e.g.,
+    /// `Notify::notified` of a decoder that is shutting down, instead of waiting for the track
+    /// for up to the read timeout.
+    ///
+    /// Only the pending read of the track is abandoned. The buffered packets are kept so the
+    /// `BufferedTrackRemote` can still be read from afterward.
+    pub async fn recv_cancellable<F>(
+        &mut self,
+        cancel: F,
+    ) -> Result<(&[u8], u32), ReorderBufferError>
+    where
+        F: Future<Output = ()>,
+    {
+        tokio::select! {
+            biased;
+            _ = cancel => Err(ReorderBufferError::Cancelled),
+            result = self.recv() => result,
+        }
+    }
+
+    /// Depacketizes the received payloads using `D` and invokes `sink` with each complete NALU or
+    /// frame and its RTP timestamp. `buf` is used as the scratch space of the depacketizer. Keeps
+    /// reading until `sink` returns `false` or an error occurs.
+    ///
+    /// Useful for feeding a decoder directly instead of copying the output from a buffer.
+    ///
+    /// With [with_wait_for_keyframe][Self::with_wait_for_keyframe], the payloads before the start
+    /// of the first keyframe are dropped without reaching `D`.
+    pub async fn recv_into_sink<D, F>(
+        &mut self,
+        buf: &mut [u8],
+        mut sink: F,
+    ) -> Result<(), ReorderBufferError>
+    where
+        D: Depacketizer,
+        F: FnMut(&[u8], u32) -> bool,
+    {
+        // The payloads borrow `self` so the waiter is updated through a copy
+        let mut keyframe_waiter = self.keyframe_waiter.clone();
+        loop {
+            let mut depacketizer = D::wrap_buffer(buf);
+            let (len, timestamp) = loop {
+                let (payload, timestamp, extensions) = self.recv_with_extensions().await?;
+                if !keyframe_waiter.accept::<D>(payload) {
+                    continue;
+                }
+                depacketizer.push_extensions(extensions);
+                let pushed = depacketizer.push(payload);
+                self.keyframe_waiter = keyframe_waiter.clone();
+                match pushed {
+                    Ok(()) => break (depacketizer.finish(), timestamp),
+                    Err(DepacketizerError::NeedMoreInput) => continue,
+                    Err(e) => return Err(ReorderBufferError::Depacketizer(e)),
+                }
+            };
+
+            if !sink(&buf[..len], timestamp) {
+                return Ok(());
+            }
+        }
+    }
+}
+
 #[inline]
 fn unmarshal_header(buffer: &mut &[u8]) -> Option<rtp::header::Header> {
     // TODO: The header itself is not needed, modify the unmarshal method
@@ -264,22 +605,42 @@ impl PacketBuffer {
     }
 }
 
+enum PacketStorage {
+    /// MTU-sized buffer from `BufferedTrackRemote::buffers`, returned there once read.
+    Pooled(PacketBuffer),
+    /// Copy of the packet counted against `BufferedTrackRemote::byte_budget`.
+    Compact(Box<[u8]>),
+}
+
 pub struct RawPacket {
-    buffer: PacketBuffer,
+    storage: PacketStorage,
     len: usize,
 }
 
+impl RawPacket {
+    fn get_timestamp(&self) -> u32 {
+        let b = match &self.storage {
+            PacketStorage::Pooled(buffer) => &buffer[..],
+            PacketStorage::Compact(data) => &data[..],
+        };
+        u32::from_be_bytes([b[4], b[5], b[6], b[7]])
+    }
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::{codecs::h264::H264Depacketizer, network::frame_transform::TransformedTrackWriter};
     use bytes::{Buf, BufMut, Bytes, BytesMut};
     use std::{
         collections::{HashMap, VecDeque},
         sync::Mutex,
     };
+    use tokio::sync::{watch, Notify};
     use webrtc::{
-        rtp::{header::Header, packet::Packet},
-        util::Marshal,
+        rtp::{codecs::h264::H264Payloader, header::Header, packet::Packet, packetizer::Payloader},
+        track::track_local::TrackLocalWriter,
+        util::{Marshal, MarshalSize},
     };
 
     const NUM_PACKETS_TO_BUFFER: usize = 128;
@@ -292,14 +653,24 @@ mod tests {
         ) -> Result<(usize, std::collections::HashMap<usize, usize>), webrtc::Error>;
     }
 
-    struct DummyTrackRemote {
+    pub(crate) struct DummyTrackRemote {
         packets: Mutex<VecDeque<Bytes>>,
+        stall_when_empty: bool,
     }
 
     impl DummyTrackRemote {
-        fn new(packets: VecDeque<Bytes>) -> DummyTrackRemote {
+        pub(crate) fn new(packets: VecDeque<Bytes>) -> DummyTrackRemote {
             DummyTrackRemote {
                 packets: Mutex::new(packets),
+                stall_when_empty: false,
+            }
+        }
+
+        /// Blocks forever instead of returning an error once all the packets have been read.
+        fn stalling(packets: VecDeque<Bytes>) -> DummyTrackRemote {
+            DummyTrackRemote {
+                packets: Mutex::new(packets),
+                stall_when_empty: true,
             }
         }
     }
@@ -310,17 +681,102 @@ mod tests {
             &self,
             b: &mut [u8],
         ) -> Result<(usize, HashMap<usize, usize>), webrtc::Error> {
-            let mut lock = self.packets.lock().unwrap();
-            if let Some(packet) = lock.pop_front() {
+            let packet = self.packets.lock().unwrap().pop_front();
+            if let Some(packet) = packet {
                 let min_len = usize::min(packet.len(), b.len());
                 b[..min_len].copy_from_slice(&packet[..min_len]);
                 Ok((min_len, HashMap::new()))
+            } else if self.stall_when_empty {
+                std::future::pending().await
             } else {
                 Err(webrtc::Error::ErrUnknownType)
             }
         }
     }
 
+    // Lets the users of `BufferedTrackRemote` pass it their tracks in tests
+    #[async_trait::async_trait]
+    impl DummyTrackRemoteTrait for webrtc::track::track_remote::TrackRemote {
+        async fn read(
+            &self,
+            b: &mut [u8],
+        ) -> Result<(usize, HashMap<usize, usize>), webrtc::Error> {
+            webrtc::track::track_remote::TrackRemote::read(self, b).await
+        }
+    }
+
+    /// `TrackLocalWriter` that records the packets written to it.
+    #[derive(Debug, Default)]
+    pub(crate) struct FakeTrackWriter {
+        latency: Duration,
+        bindings: Option<Arc<watch::Sender<usize>>>,
+        fail_seq: Option<u16>,
+        unbind_on_failure: bool,
+        written: Mutex<Vec<Packet>>,
+    }
+
+    impl FakeTrackWriter {
+        /// Make each write take `latency`.
+        pub(crate) fn with_latency(mut self, latency: Duration) -> FakeTrackWriter {
+            self.latency = latency;
+            self
+        }
+
+        /// Fail every write while `bindings` is 0, like the closed transport of an unbound
+        /// transceiver.
+        pub(crate) fn with_bindings(
+            mut self,
+            bindings: Arc<watch::Sender<usize>>,
+        ) -> FakeTrackWriter {
+            self.bindings = Some(bindings);
+            self
+        }
+
+        /// Fail the write of the packet with `sequence_number` as well, setting the bindings to 0
+        /// first if `unbind` is set.
+        pub(crate) fn with_failed_write(
+            mut self,
+            sequence_number: u16,
+            unbind: bool,
+        ) -> FakeTrackWriter {
+            self.fail_seq = Some(sequence_number);
+            self.unbind_on_failure = unbind;
+            self
+        }
+
+        /// Returns the packets written so far.
+        pub(crate) fn written(&self) -> Vec<Packet> {
+            self.written.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl TrackLocalWriter for FakeTrackWriter {
+        async fn write_rtp(&self, p: &Packet) -> Result<usize, webrtc::Error> {
+            if !self.latency.is_zero() {
+                tokio::time::sleep(self.latency).await;
+            }
+            if let Some(bindings) = &self.bindings {
+                if *bindings.borrow() == 0 {
+                    return Err(webrtc::Error::ErrClosedPipe);
+                }
+            }
+            if self.fail_seq == Some(p.header.sequence_number) {
+                if let Some(bindings) = self.bindings.as_ref().filter(|_| self.unbind_on_failure) {
+                    bindings.send_replace(0);
+                }
+                return Err(webrtc::Error::ErrClosedPipe);
+            }
+            self.written.lock().unwrap().push(p.clone());
+            Ok(p.marshal_size())
+        }
+
+        async fn write(&self, mut b: &[u8]) -> Result<usize, webrtc::Error> {
+            let packet = Packet::unmarshal(&mut b)?;
+            self.write_rtp(&packet).await
+        }
+    }
+
     #[test]
     fn sequence_number_sort() {
         const START: u16 = 65500;
@@ -337,8 +793,8 @@ mod tests {
         }
     }
 
-    async fn reorder_buffer_test(mut seq_nums: Vec<SequenceNumber>) {
-        let packets: VecDeque<_> = seq_nums
+    fn packets_with_seq_num_payload(seq_nums: &[SequenceNumber]) -> VecDeque<Bytes> {
+        seq_nums
             .iter()
             .map(|seq_num| {
                 let mut payload = BytesMut::new();
@@ -352,7 +808,11 @@ mod tests {
                 };
                 packet.marshal().unwrap()
             })
-            .collect();
+            .collect()
+    }
+
+    async fn reorder_buffer_test(mut seq_nums: Vec<SequenceNumber>) {
+        let packets = packets_with_seq_num_payload(&seq_nums);
 
         seq_nums.sort();
 
@@ -397,6 +857,144 @@ mod tests {
         reorder_buffer_test(seq_nums).await;
     }
 
+    #[tokio::test]
+    async fn inorder_fast_path_matches_general_path() {
+        const START: u16 = 65500;
+        const N: u16 = 100;
+        let packets: Vec<_> = (0..N)
+            .map(|offset| {
+                let packet = Packet {
+                    header: Header {
+                        sequence_number: START.wrapping_add(offset),
+                        timestamp: 3000 * (offset as u32 / 3),
+                        marker: offset % 3 == 2,
+                        ..Default::default()
+                    },
+                    payload: Bytes::from(vec![offset as u8; 1 + offset as usize]),
+                };
+                packet.marshal().unwrap()
+            })
+            .collect();
+
+        async fn recv_all(packets: VecDeque<Bytes>) -> Vec<(Vec<u8>, u32, bool)> {
+            let track = DummyTrackRemote::new(packets);
+            let mut buffered_track =
+                BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER);
+            let mut received = Vec::new();
+            for _ in 0..N {
+                let (payload, timestamp) = buffered_track.recv().await.unwrap();
+                let payload = payload.to_vec();
+                received.push((payload, timestamp, buffered_track.marker()));
+            }
+            assert!(buffered_track.packets.is_empty());
+            assert_eq!(buffered_track.buffers.len(), NUM_PACKETS_TO_BUFFER);
+            received
+        }
+
+        // Every packet after the first takes the fast path
+        let fast_path = recv_all(packets.iter().cloned().collect()).await;
+
+        // Holding back the second packet until the end routes the rest through the `BTreeMap`
+        let mut reordered: VecDeque<_> = packets.iter().cloned().collect();
+        let second = reordered.remove(1).unwrap();
+        reordered.push_back(second);
+        let general_path = recv_all(reordered).await;
+
+        assert_eq!(fast_path, general_path);
+    }
+
+    #[tokio::test]
+    async fn sink_invoked_per_nalu() {
+        const NALUS: [&[u8]; 3] = [
+            include_bytes!("../codecs/h264/nalus/1.h264"),
+            include_bytes!("../codecs/h264/nalus/2.h264"),
+            include_bytes!("../codecs/h264/nalus/3.h264"),
+        ];
+
+        let mut payloader = H264Payloader::default();
+        let mut seq_num = 0u16;
+        let mut packets = VecDeque::new();
+        for (timestamp, nalu) in NALUS.iter().enumerate() {
+            let payloads = payloader
+                .payload(1188, &Bytes::copy_from_slice(nalu))
+                .unwrap();
+            for payload in payloads {
+                let packet = Packet {
+                    header: Header {
+                        sequence_number: seq_num,
+                        timestamp: timestamp as u32,
+                        ..Default::default()
+                    },
+                    payload,
+                };
+                packets.push_back(packet.marshal().unwrap());
+                seq_num = seq_num.wrapping_add(1);
+            }
+        }
+
+        let track = DummyTrackRemote::new(packets);
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER);
+
+        let mut buf = vec![0u8; 1 << 16];
+        let mut received = Vec::new();
+        buffered_track
+            .recv_into_sink::<H264Depacketizer, _>(&mut buf, |nalu, timestamp| {
+                received.push((nalu.to_vec(), timestamp));
+                received.len() < NALUS.len()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(received.len(), NALUS.len());
+        for (i, (nalu, timestamp)) in received.iter().enumerate() {
+            assert_eq!(&nalu[..], NALUS[i]);
+            assert_eq!(*timestamp, i as u32);
+        }
+    }
+
+    #[tokio::test]
+    async fn sink_waits_for_keyframe() {
+        const NALU: &[u8] = include_bytes!("../codecs/h264/nalus/1.h264");
+        /// SPS, PPS, then IDR
+        const KEYFRAME: &[u8] = include_bytes!("../codecs/h264/nalus/0.h264");
+
+        let mut payloader = H264Payloader::default();
+        let mut seq_num = 0u16;
+        let mut packets = VecDeque::new();
+        for nalus in [NALU, NALU, KEYFRAME, NALU] {
+            let payloads = payloader
+                .payload(1188, &Bytes::copy_from_slice(nalus))
+                .unwrap();
+            for payload in payloads {
+                let packet = Packet {
+                    header: Header {
+                        sequence_number: seq_num,
+                        ..Default::default()
+                    },
+                    payload,
+                };
+                packets.push_back(packet.marshal().unwrap());
+                seq_num = seq_num.wrapping_add(1);
+            }
+        }
+
+        let track = DummyTrackRemote::new(packets);
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER)
+            .with_wait_for_keyframe();
+
+        let mut buf = vec![0u8; KEYFRAME.len()];
+        let mut received = Vec::new();
+        // Ends with an error once the track runs out of packets
+        let _ = buffered_track
+            .recv_into_sink::<H264Depacketizer, _>(&mut buf, |nalu, _| {
+                received.extend_from_slice(nalu);
+                true
+            })
+            .await;
+
+        assert!(received == [KEYFRAME, NALU].concat());
+    }
+
     #[tokio::test]
     async fn reorder_buffer_large_window() {
         const START: u16 = 65500;
@@ -409,4 +1007,439 @@ mod tests {
 
         reorder_buffer_test(seq_nums).await;
     }
+
+    #[tokio::test]
+    async fn byte_budget() {
+        // 12-byte header and 2-byte payload
+        const PACKET_SIZE: usize = 14;
+        const NUM_FIT: u16 = 8;
+
+        // Packet 1 is late, arriving after the budget runs out
+        let mut seq_nums = vec![SequenceNumber(0)];
+        seq_nums.extend((2..NUM_FIT + 4).map(SequenceNumber));
+        seq_nums.push(SequenceNumber(1));
+        let track = DummyTrackRemote::new(packets_with_seq_num_payload(&seq_nums));
+        let mut buffered_track = BufferedTrackRemote::new_with_byte_budget(
+            Arc::new(track),
+            NUM_FIT as usize * PACKET_SIZE,
+        );
+
+        let (mut b, _) = buffered_track.recv().await.unwrap();
+        assert_eq!(b.get_u16(), 0);
+
+        // One more than the budget fits in the read buffer
+        assert!(matches!(
+            buffered_track.recv().await,
+            Err(ReorderBufferError::BufferFull)
+        ));
+        assert_eq!(buffered_track.packets.len(), NUM_FIT as usize + 1);
+        assert_eq!(
+            buffered_track.buffered_bytes,
+            NUM_FIT as usize * PACKET_SIZE
+        );
+        assert_eq!(
+            buffered_track.take_packet_loss(),
+            Some(PacketLoss {
+                first_seq_num: 1,
+                num_lost: 1,
+                prev_timestamp: Some(0),
+                next_timestamp: 0,
+            })
+        );
+
+        // The late packet is rejected since the missing one was given up on
+        for seq_num in 2..NUM_FIT + 4 {
+            let (mut b, _) = buffered_track.recv().await.unwrap();
+            assert_eq!(b.get_u16(), seq_num);
+        }
+        assert!(matches!(
+            buffered_track.recv().await,
+            Err(ReorderBufferError::UnorderablePacketReceived)
+        ));
+        assert!(buffered_track.packets.is_empty());
+        assert_eq!(buffered_track.buffered_bytes, 0);
+        assert_eq!(buffered_track.buffers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn audio_level() {
+        const ID: u8 = 1;
+
+        let levels = [
+            Some(AudioLevel::new(30, true)),
+            None,
+            Some(AudioLevel::new(127, false)),
+        ];
+        let packets: VecDeque<_> = levels
+            .iter()
+            .enumerate()
+            .map(|(seq_num, audio_level)| {
+                let mut header = Header {
+                    sequence_number: seq_num as u16,
+                    ..Default::default()
+                };
+                if let Some(audio_level) = audio_level {
+                    let voice = if audio_level.voice_activity() {
+                        0x80
+                    } else {
+                        0
+                    };
+                    let data = Bytes::from(vec![voice | audio_level.level()]);
+                    header.set_extension(ID, data).unwrap();
+                }
+                let packet = Packet {
+                    header,
+                    payload: Bytes::from_static(&[0xfc, 0xff, 0xfe]),
+                };
+                packet.marshal().unwrap()
+            })
+            .collect();
+
+        let track = DummyTrackRemote::new(packets);
+        let mut buffered_track =
+            BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER).with_audio_level(ID);
+        for expected in levels {
+            let (b, _) = buffered_track.recv().await.unwrap();
+            assert_eq!(b, &[0xfc, 0xff, 0xfe]);
+            assert_eq!(buffered_track.audio_level(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn dependency_descriptor() {
+        const ID: u8 = 2;
+
+        // Keyframe with an L1T2 structure then a delta frame on each template
+        let descriptors: [&[u8]; 3] = [
+            &[0x80, 0x00, 0x00, 0x80, 0x00, 0x70],
+            &[0x80, 0x00, 0x01],
+            &[0x81, 0x00, 0x02],
+        ];
+        let packets: VecDeque<_> = descriptors
+            .iter()
+            .enumerate()
+            .map(|(seq_num, descriptor)| {
+                let mut header = Header {
+                    sequence_number: seq_num as u16,
+                    ..Default::default()
+                };
+                header
+                    .set_extension(ID, Bytes::copy_from_slice(descriptor))
+                    .unwrap();
+                let packet = Packet {
+                    header,
+                    payload: Bytes::from_static(&[0u8; 10]),
+                };
+                packet.marshal().unwrap()
+            })
+            .collect();
+
+        let track = DummyTrackRemote::new(packets);
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER)
+            .with_dependency_descriptor(ID);
+        for (frame_number, temporal_id) in [(0, 0), (1, 0), (2, 1)] {
+            buffered_track.recv().await.unwrap();
+            let descriptor = buffered_track.dependency_descriptor().unwrap();
+            assert_eq!(descriptor.frame_number, frame_number);
+            assert_eq!(
+                (descriptor.spatial_id, descriptor.temporal_id),
+                (0, temporal_id)
+            );
+        }
+    }
+
+    /// Outputs the data of the extension with `ID` ahead of the payload, like a depacketizer
+    /// that needs the extension to make sense of the payload.
+    struct ExtensionDepacketizer<'a> {
+        output: &'a mut [u8],
+        len: usize,
+    }
+
+    impl ExtensionDepacketizer<'_> {
+        const ID: u8 = 3;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), DepacketizerError> {
+            let output = self
+                .output
+                .get_mut(self.len..self.len + data.len())
+                .ok_or(DepacketizerError::OutputBufferFull)?;
+            output.copy_from_slice(data);
+            self.len += data.len();
+            Ok(())
+        }
+    }
+
+    impl<'a> Depacketizer for ExtensionDepacketizer<'a> {
+        type WrapOutput<'b> = ExtensionDepacketizer<'b>;
+
+        fn wrap_buffer<'b>(output: &'b mut [u8]) -> Self::WrapOutput<'b> {
+            ExtensionDepacketizer { output, len: 0 }
+        }
+
+        fn push_extensions(&mut self, extensions: &[rtp::header::Extension]) {
+            if let Some(extension) = extensions.iter().find(|ext| ext.id == Self::ID) {
+                self.write(&extension.payload).unwrap();
+            }
+        }
+
+        fn push(&mut self, payload: &[u8]) -> Result<(), DepacketizerError> {
+            self.write(payload)
+        }
+
+        fn finish(self) -> usize {
+            self.len
+        }
+    }
+
+    #[tokio::test]
+    async fn extensions_to_depacketizer() {
+        let extensions: [Option<&[u8]>; 2] = [Some(&[0xab, 0xcd]), None];
+        let packets: VecDeque<_> = extensions
+            .iter()
+            .enumerate()
+            .map(|(seq_num, extension)| {
+                let mut header = Header {
+                    sequence_number: seq_num as u16,
+                    ..Default::default()
+                };
+                if let Some(data) = extension {
+                    header
+                        .set_extension(ExtensionDepacketizer::ID, Bytes::copy_from_slice(data))
+                        .unwrap();
+                }
+                let packet = Packet {
+                    header,
+                    payload: Bytes::from_static(&[1, 2, 3]),
+                };
+                packet.marshal().unwrap()
+            })
+            .collect();
+
+        let track = DummyTrackRemote::new(packets);
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER);
+        let mut buf = vec![0u8; MAX_MTU];
+        let mut outputs = Vec::new();
+        buffered_track
+            .recv_into_sink::<ExtensionDepacketizer, _>(&mut buf, |output, _| {
+                outputs.push(output.to_vec());
+                outputs.len() < extensions.len()
+            })
+            .await
+            .unwrap();
+        assert_eq!(outputs, [vec![0xab, 0xcd, 1, 2, 3], vec![1, 2, 3]]);
+    }
+
+    #[tokio::test]
+    async fn skip_permanently_missing_packet() {
+        const MAX_REORDER_DELAY: Duration = Duration::from_millis(200);
+
+        // Packet 2 never arrives
+        let seq_nums: Vec<_> = [0, 1, 3, 4, 5].into_iter().map(SequenceNumber).collect();
+        let track = DummyTrackRemote::stalling(packets_with_seq_num_payload(&seq_nums));
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER)
+            .with_max_reorder_delay(MAX_REORDER_DELAY);
+
+        for seq_num in [0, 1] {
+            let (mut b, _) = buffered_track.recv().await.unwrap();
+            assert_eq!(b.get_u16(), seq_num);
+        }
+
+        let start = Instant::now();
+        assert!(matches!(
+            buffered_track.recv().await,
+            Err(ReorderBufferError::MaxReorderDelayExceeded)
+        ));
+        assert!(start.elapsed() >= MAX_REORDER_DELAY);
+        assert!(start.elapsed() < READ_TIMEOUT);
+
+        // Playout resumes after the missing packet
+        for seq_num in [3, 4, 5] {
+            let (mut b, _) = buffered_track.recv().await.unwrap();
+            assert_eq!(b.get_u16(), seq_num);
+        }
+    }
+
+    #[tokio::test]
+    async fn cancel_pending_recv() {
+        // Packet 1 arrives late
+        let seq_nums: Vec<_> = [0, 2].into_iter().map(SequenceNumber).collect();
+        let track = Arc::new(DummyTrackRemote::stalling(packets_with_seq_num_payload(
+            &seq_nums,
+        )));
+        let mut buffered_track = BufferedTrackRemote::new(track.clone(), NUM_PACKETS_TO_BUFFER);
+
+        let (mut b, _) = buffered_track.recv().await.unwrap();
+        assert_eq!(b.get_u16(), 0);
+
+        // Packet 2 gets buffered before the read of packet 1 is cancelled
+        let cancel = Arc::new(Notify::new());
+        let cancel_clone = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            cancel_clone.notify_one();
+        });
+        let start = Instant::now();
+        assert!(matches!(
+            buffered_track.recv_cancellable(cancel.notified()).await,
+            Err(ReorderBufferError::Cancelled)
+        ));
+        assert!(start.elapsed() < READ_TIMEOUT);
+        assert_eq!(buffered_track.packets.len(), 1);
+
+        let late = packets_with_seq_num_payload(&[SequenceNumber(1)]);
+        track.packets.lock().unwrap().extend(late);
+        for seq_num in [1, 2] {
+            let (mut b, _) = buffered_track.recv().await.unwrap();
+            assert_eq!(b.get_u16(), seq_num);
+        }
+        assert_eq!(buffered_track.buffers.len(), NUM_PACKETS_TO_BUFFER);
+    }
+
+    #[tokio::test]
+    async fn opus_packet_loss() {
+        // 20 ms Opus frames
+        const SAMPLES_PER_FRAME: u32 = 960;
+
+        // Packet 2 is dropped
+        let packets: VecDeque<_> = [0u16, 1, 3, 4]
+            .into_iter()
+            .map(|seq_num| {
+                let packet = Packet {
+                    header: Header {
+                        sequence_number: seq_num,
+                        timestamp: seq_num as u32 * SAMPLES_PER_FRAME,
+                        ..Default::default()
+                    },
+                    payload: Bytes::from_static(&[0xfc, 0xff, 0xfe]),
+                };
+                packet.marshal().unwrap()
+            })
+            .collect();
+        let track = DummyTrackRemote::stalling(packets);
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER)
+            .with_max_reorder_delay(NACK_INTERVAL);
+
+        for _ in 0..2 {
+            buffered_track.recv().await.unwrap();
+        }
+        assert!(buffered_track.take_packet_loss().is_none());
+
+        assert!(matches!(
+            buffered_track.recv().await,
+            Err(ReorderBufferError::MaxReorderDelayExceeded)
+        ));
+        assert_eq!(
+            buffered_track.take_packet_loss(),
+            Some(PacketLoss {
+                first_seq_num: 2,
+                num_lost: 1,
+                prev_timestamp: Some(SAMPLES_PER_FRAME),
+                next_timestamp: 3 * SAMPLES_PER_FRAME,
+            })
+        );
+        assert!(buffered_track.take_packet_loss().is_none());
+
+        // The packet carrying the FEC data of the lost frame is next
+        let (_, timestamp) = buffered_track.recv().await.unwrap();
+        assert_eq!(timestamp, 3 * SAMPLES_PER_FRAME);
+    }
+
+    #[tokio::test]
+    async fn opus_dtx_gap() {
+        const SAMPLES_PER_FRAME: u32 = 960;
+        // Two seconds of silence at 48 kHz
+        const DTX_GAP: u32 = 2 * 48000;
+
+        // Consecutive sequence numbers across the silence, only the timestamps jump
+        let timestamps: Vec<_> = (0..10u32)
+            .map(|i| {
+                let gap = if i >= 5 { DTX_GAP } else { 0 };
+                i * SAMPLES_PER_FRAME + gap
+            })
+            .collect();
+        let packets: VecDeque<_> = timestamps
+            .iter()
+            .enumerate()
+            .map(|(seq_num, &timestamp)| {
+                let packet = Packet {
+                    header: Header {
+                        sequence_number: seq_num as u16,
+                        timestamp,
+                        ..Default::default()
+                    },
+                    payload: Bytes::from_static(&[0xfc, 0xff, 0xfe]),
+                };
+                packet.marshal().unwrap()
+            })
+            .collect();
+        let track = DummyTrackRemote::stalling(packets);
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER)
+            .with_max_reorder_delay(NACK_INTERVAL);
+
+        for expected in timestamps {
+            let (_, timestamp) = buffered_track.recv().await.unwrap();
+            assert_eq!(timestamp, expected);
+            assert!(buffered_track.take_packet_loss().is_none());
+        }
+    }
+
+    /// Stand-in for encryption that XORs every byte with the key.
+    pub(crate) struct XorTransform(pub(crate) u8);
+
+    impl FrameTransform for XorTransform {
+        fn transform(&self, payload: &[u8], output: &mut Vec<u8>) {
+            output.extend(payload.iter().map(|b| b ^ self.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn frame_transform_round_trip() {
+        const KEY: u8 = 0x5a;
+
+        let payloads: Vec<Vec<u8>> = (0..20u8)
+            .map(|i| {
+                (0..100)
+                    .map(|j| i.wrapping_mul(31).wrapping_add(j))
+                    .collect()
+            })
+            .collect();
+
+        let recording_writer = Arc::new(FakeTrackWriter::default());
+        let writer =
+            TransformedTrackWriter::new(recording_writer.clone(), Box::new(XorTransform(KEY)));
+        for (seq_num, payload) in payloads.iter().enumerate() {
+            let packet = Packet {
+                header: Header {
+                    sequence_number: seq_num as u16,
+                    timestamp: seq_num as u32,
+                    ..Default::default()
+                },
+                payload: Bytes::copy_from_slice(payload),
+            };
+            writer.write_rtp(&packet).await.unwrap();
+        }
+
+        let sent = recording_writer.written();
+        for (packet, payload) in sent.iter().zip(&payloads) {
+            assert_ne!(&packet.payload[..], &payload[..]);
+        }
+        let mut packets: VecDeque<_> = sent
+            .iter()
+            .map(|packet| packet.marshal().unwrap())
+            .collect();
+
+        // Reordered on the way
+        for i in (2..packets.len()).step_by(3) {
+            packets.swap(i, i - 1);
+        }
+
+        let track = DummyTrackRemote::new(packets);
+        let mut buffered_track = BufferedTrackRemote::new(Arc::new(track), NUM_PACKETS_TO_BUFFER)
+            .with_frame_transform(Box::new(XorTransform(KEY)));
+        for (i, payload) in payloads.iter().enumerate() {
+            let (b, timestamp) = buffered_track.recv().await.unwrap();
+            assert_eq!(b, &payload[..]);
+            assert_eq!(timestamp, i as u32);
+        }
+    }
 }
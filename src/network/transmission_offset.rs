@@ -0,0 +1,140 @@
+//! The [transmission time offset][rfc5450] RTP header extension. Carries the offset of the actual
+//! send time of a packet from its RTP timestamp, so that the receiver can tell how much of the
+//! jitter is from the sender pacing its packets rather than from the network.
+//!
+//! [rfc5450]: https://www.rfc-editor.org/rfc/rfc5450
+
+use bytes::{Buf, BufMut};
+use webrtc::{
+    rtp::{self, extension::HeaderExtension},
+    rtp_transceiver::rtp_receiver::RTCRtpReceiver,
+    util::{self, Marshal, MarshalSize, Unmarshal},
+};
+
+pub const TRANSMISSION_OFFSET_URI: &str = "urn:ietf:params:rtp-hdrext:toffset";
+
+const TRANSMISSION_OFFSET_EXTENSION_SIZE: usize = 3;
+
+/// Range of a 24-bit signed integer.
+const MAX_OFFSET: i32 = (1 << 23) - 1;
+const MIN_OFFSET: i32 = -(1 << 23);
+
+///    0                   1                   2                   3
+///    0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///   |  ID   | len=2 |              transmission offset              |
+///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransmissionOffset {
+    offset: i32,
+}
+
+impl TransmissionOffset {
+    /// Create a new `TransmissionOffset` from the offset in RTP timestamp ticks, clamped to 24
+    /// bits.
+    pub fn new(offset_ticks: i32) -> TransmissionOffset {
+        TransmissionOffset {
+            offset: offset_ticks.clamp(MIN_OFFSET, MAX_OFFSET),
+        }
+    }
+
+    /// Offset of the send time from the RTP timestamp in RTP timestamp ticks.
+    pub fn offset_ticks(&self) -> i32 {
+        self.offset
+    }
+
+    /// Send time of the packet in RTP timestamp ticks.
+    pub fn send_time(&self, timestamp: u32) -> u32 {
+        timestamp.wrapping_add(self.offset as u32)
+    }
+
+    /// Wraps the `TransmissionOffset` so that it can be passed to
+    /// `TrackLocalStaticRTP::write_rtp_with_extensions`, which takes care of using the negotiated
+    /// extension ID.
+    pub fn to_header_extension(self) -> HeaderExtension {
+        HeaderExtension::Custom {
+            uri: TRANSMISSION_OFFSET_URI.into(),
+            extension: Box::new(self),
+        }
+    }
+
+    /// Read the `TransmissionOffset` from an RTP header given the negotiated extension ID.
+    pub fn from_header(header: &rtp::header::Header, id: u8) -> Option<TransmissionOffset> {
+        let mut buf = header.get_extension(id)?;
+        TransmissionOffset::unmarshal(&mut buf).ok()
+    }
+
+    /// Returns the negotiated extension ID of the transmission offset extension on the receiver's
+    /// side.
+    pub async fn extension_id(rtp_receiver: &RTCRtpReceiver) -> Option<u8> {
+        rtp_receiver
+            .get_parameters()
+            .await
+            .header_extensions
+            .iter()
+            .find(|ext| ext.uri == TRANSMISSION_OFFSET_URI)
+            .map(|ext| ext.id as u8)
+    }
+}
+
+impl MarshalSize for TransmissionOffset {
+    fn marshal_size(&self) -> usize {
+        TRANSMISSION_OFFSET_EXTENSION_SIZE
+    }
+}
+
+impl Marshal for TransmissionOffset {
+    fn marshal_to(&self, mut buf: &mut [u8]) -> util::Result<usize> {
+        if buf.remaining_mut() < TRANSMISSION_OFFSET_EXTENSION_SIZE {
+            return Err(rtp::Error::ErrBufferTooSmall.into());
+        }
+
+        buf.put_int(self.offset as i64, TRANSMISSION_OFFSET_EXTENSION_SIZE);
+        Ok(TRANSMISSION_OFFSET_EXTENSION_SIZE)
+    }
+}
+
+impl Unmarshal for TransmissionOffset {
+    fn unmarshal<B>(buf: &mut B) -> util::Result<Self>
+    where
+        Self: Sized,
+        B: Buf,
+    {
+        if buf.remaining() < TRANSMISSION_OFFSET_EXTENSION_SIZE {
+            return Err(rtp::Error::ErrBufferTooSmall.into());
+        }
+
+        Ok(TransmissionOffset {
+            offset: buf.get_int(TRANSMISSION_OFFSET_EXTENSION_SIZE) as i32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_then_read() {
+        const ID: u8 = 5;
+
+        let transmission_offset = TransmissionOffset::new(-900);
+        let data = transmission_offset.marshal().unwrap();
+        assert_eq!(&data[..], &[0xff, 0xfc, 0x7c]);
+
+        let mut header = rtp::header::Header::default();
+        header.set_extension(ID, data).unwrap();
+
+        let parsed = TransmissionOffset::from_header(&header, ID).unwrap();
+        assert_eq!(parsed.offset_ticks(), -900);
+        assert_eq!(parsed.send_time(500), u32::MAX - 399);
+
+        assert!(TransmissionOffset::from_header(&header, ID + 1).is_none());
+    }
+
+    #[test]
+    fn clamped() {
+        assert_eq!(TransmissionOffset::new(i32::MAX).offset_ticks(), MAX_OFFSET);
+        assert_eq!(TransmissionOffset::new(i32::MIN).offset_ticks(), MIN_OFFSET);
+    }
+}
@@ -1,40 +1,126 @@
 use crate::{
-    codecs::{h264::H264Codec, Codec, MediaEngineExt},
-    decoder::DecoderBuilder,
-    encoder::{EncoderBuilder, EncoderTrackLocal},
-    interceptor::{configure_custom_twcc_sender, twcc::TwccBandwidthEstimate},
-    network::data_rate::DataRate,
+    codecs::{h264::H264Codec, Codec, CodecType, MediaEngineExt, DYNAMIC_PAYLOAD_TYPE_START},
+    decoder::{select_decoder, warn_on_h264_incompatibility, DecoderBuilder},
+    encoder::{EncoderBuilder, EncoderStats, EncoderTrackLocal},
+    interceptor::{
+        app::{ApplicationDefined, OnRtcpAppHdlrFn},
+        configure_feedback_throttle, configure_keyframe_requests, configure_pacer,
+        configure_remb_receiver, configure_rtcp_app, configure_rtcp_observer,
+        configure_sender_reports, configure_twcc_receiver,
+        observer::OnRtcpHdlrFn,
+        register_custom_twcc_sender,
+        sender_report::{SenderReportMapping, SenderReports},
+        twcc::{
+            twcc_bandwidth_estimate_channel, DelayBasedConfig, TwccBandwidthSender, TwccPacketStats,
+        },
+    },
+    network::{
+        audio_level::AUDIO_LEVEL_URI, data_rate::DataRate,
+        dependency_descriptor::DEPENDENCY_DESCRIPTOR_URI, playout_delay::PLAYOUT_DELAY_URI,
+        transmission_offset::TRANSMISSION_OFFSET_URI,
+    },
     signaling::{Message, Signaler},
+    stats::PeerStats,
+};
+use bytes::Bytes;
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{watch, Notify},
+    task::JoinHandle,
 };
-use std::{sync::Arc, time::Duration};
-use tokio::sync::{watch, Mutex, Notify};
 use webrtc::{
     api::{
         interceptor_registry::{
-            configure_nack, configure_rtcp_reports, configure_twcc, configure_twcc_receiver_only,
+            configure_nack, configure_rtcp_reports, configure_twcc_sender_only,
         },
         media_engine::MediaEngine,
         setting_engine::SettingEngine,
         APIBuilder,
     },
+    dtls_transport::OnDTLSTransportStateChangeHdlrFn,
     ice::mdns::MulticastDnsMode,
-    ice_transport::{ice_connection_state::RTCIceConnectionState, ice_server::RTCIceServer},
-    interceptor::registry::Registry,
+    ice_transport::{
+        ice_candidate::RTCIceCandidateInit, ice_connection_state::RTCIceConnectionState,
+        ice_gatherer_state::RTCIceGathererState, ice_server::RTCIceServer,
+    },
+    interceptor::{registry::Registry, InterceptorBuilder},
     peer_connection::{
-        configuration::RTCConfiguration, offer_answer_options::RTCOfferOptions,
-        peer_connection_state::RTCPeerConnectionState, sdp::sdp_type::RTCSdpType,
-        signaling_state::RTCSignalingState, OnDataChannelHdlrFn, RTCPeerConnection,
+        configuration::RTCConfiguration,
+        offer_answer_options::RTCOfferOptions,
+        peer_connection_state::RTCPeerConnectionState,
+        sdp::{sdp_type::RTCSdpType, session_description::RTCSessionDescription},
+        signaling_state::RTCSignalingState,
+        OnDataChannelHdlrFn, RTCPeerConnection,
     },
+    rtcp::source_description::{SourceDescription, SourceDescriptionChunk},
     rtp_transceiver::{
-        rtp_receiver::RTCRtpReceiver, rtp_transceiver_direction::RTCRtpTransceiverDirection,
+        rtp_codec::{
+            RTCRtpCodecCapability, RTCRtpCodecParameters, RTCRtpHeaderExtensionCapability,
+            RTPCodecType,
+        },
+        rtp_receiver::RTCRtpReceiver,
+        rtp_transceiver_direction::RTCRtpTransceiverDirection,
         RTCRtpTransceiverInit,
     },
-    track::track_remote::TrackRemote,
+    sdp::{
+        description::{common::Attribute, media::MediaDescription, session::SessionDescription},
+        extmap::ABS_SEND_TIME_URI,
+    },
+    track::{track_local::TrackLocal, track_remote::TrackRemote},
 };
 
 /// Used for querying `RTCIceConnectionState` in the encoders/decoders.
 pub type IceConnectionState = watch::Receiver<RTCIceConnectionState>;
 
+/// Same as the default of `webrtc::interceptor::twcc::receiver::ReceiverBuilder`.
+const DEFAULT_TWCC_FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Keyframes forced more often than this mostly answer requests for a keyframe already on its way.
+const DEFAULT_MIN_KEYFRAME_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Base duration that a peer with `Role::Auto` waits for an offer.
+const AUTO_ROLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Attempts at sending an offer or answer through the signaler before giving up.
+const SDP_SEND_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry of a failed SDP send, doubled on every retry after.
+const SDP_SEND_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Failed receives in a row from the signaler before the peer is closed. The retries back off
+/// the same as those of the SDP sends.
+const SIGNALER_RECV_ATTEMPTS: u32 = 5;
+
+/// Picks the codecs to answer a media section of a remote offer with. Called with the offered
+/// codecs that were also registered, except for RTX and FEC, and returns the ones to answer
+/// with, most preferred first, e.g., to prefer H.264 for hardware decoding. The RTX and FEC
+/// codecs of the chosen ones are answered with as well. Returning none of the offered codecs
+/// leaves the choice to webrtc-rs.
+pub type AnswerCodecPolicy = Box<dyn Fn(&[Codec]) -> Vec<Codec> + Send + Sync>;
+
+/// Where an interceptor added through [WebRtcBuilder::with_interceptor] is registered relative to
+/// the TWCC interceptors. Outgoing packets go through the interceptors in the reverse order of
+/// registration and incoming packets in the order of registration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterceptorPosition {
+    /// Outgoing packets reach the interceptor after the TWCC interceptors, with the transport-cc
+    /// sequence number already set, so packets it adds are not part of the bandwidth estimate.
+    /// Incoming packets reach it before the TWCC receiver.
+    BeforeTwcc,
+    /// Registered after every other interceptor. Outgoing packets reach the interceptor as the
+    /// encoders wrote them, and packets it adds, e.g., FEC, are part of the bandwidth estimate.
+    /// Incoming packets reach it right before the decoders.
+    AfterTwcc,
+}
+
 /// Determines if the peer will offer or wait for an SDP.
 ///
 /// The role of each peer needs to be specified at the start since the `webrtc` crate does not
@@ -45,6 +131,27 @@ pub type IceConnectionState = watch::Receiver<RTCIceConnectionState>;
 pub enum Role {
     Offerer,
     Answerer,
+    /// Becomes the answerer if a message from the remote peer arrives within a randomized
+    /// timeout, otherwise becomes the offerer. Meant for symmetric apps where both ends use
+    /// `Role::Auto`. The randomization makes it unlikely, but not impossible, for both ends to
+    /// time out at the same time and send offers to each other.
+    ///
+    /// `Signaler::recv` must be cancel safe since it is raced against the timeout.
+    Auto,
+}
+
+/// A negotiated transceiver of the peer connection, from [WebRtcPeer::transceivers].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransceiverInfo {
+    /// Media ID of the m-line of the transceiver in the SDP.
+    pub mid: String,
+    pub kind: RTPCodecType,
+    /// Direction as negotiated with the remote peer.
+    pub direction: RTCRtpTransceiverDirection,
+    /// MIME type of the codec of the sent track, or of the received track if the transceiver only
+    /// receives, e.g., `"video/H264"`. [None] until the codec is known, e.g., before the first
+    /// packet of a received track.
+    pub codec: Option<String>,
 }
 
 /// Builder for a `WebRtcPeer`.
@@ -58,7 +165,28 @@ where
     encoders: Vec<Box<dyn EncoderBuilder>>,
     decoders: Vec<Box<dyn DecoderBuilder>>,
     data_channel_handler: Option<OnDataChannelHdlrFn>,
+    rtcp_app_handler: Option<OnRtcpAppHdlrFn>,
+    rtcp_handler: Option<OnRtcpHdlrFn>,
     init_bandwidth: DataRate,
+    delay_based_config: DelayBasedConfig,
+    audio_bandwidth_reservation: Option<DataRate>,
+    twcc_feedback_interval: Duration,
+    max_mtu: Option<usize>,
+    estimate_smoothing: Option<Duration>,
+    ice_gather_timeout: Option<Duration>,
+    negotiation_timeout: Option<Duration>,
+    mdns: Option<bool>,
+    trickle_ice: bool,
+    dependency_descriptor: bool,
+    rtx_ssrc_group: bool,
+    answer_codec_policy: Option<AnswerCodecPolicy>,
+    feedback_rate_limit: Option<DataRate>,
+    retransmission_priority: Option<f64>,
+    min_keyframe_interval: Duration,
+    interceptors: Vec<(
+        InterceptorPosition,
+        Box<dyn InterceptorBuilder + Send + Sync>,
+    )>,
 }
 
 impl<S> WebRtcBuilder<S>
@@ -74,7 +202,25 @@ where
             encoders: Vec::new(),
             decoders: Vec::new(),
             data_channel_handler: None,
+            rtcp_app_handler: None,
+            rtcp_handler: None,
             init_bandwidth: DataRate::from_bits_per_sec(1_000_000), // 1 Mbps
+            delay_based_config: DelayBasedConfig::default(),
+            audio_bandwidth_reservation: None,
+            twcc_feedback_interval: DEFAULT_TWCC_FEEDBACK_INTERVAL,
+            max_mtu: None,
+            estimate_smoothing: None,
+            ice_gather_timeout: None,
+            negotiation_timeout: None,
+            mdns: None,
+            trickle_ice: true,
+            dependency_descriptor: false,
+            rtx_ssrc_group: false,
+            answer_codec_policy: None,
+            feedback_rate_limit: None,
+            retransmission_priority: None,
+            min_keyframe_interval: DEFAULT_MIN_KEYFRAME_INTERVAL,
+            interceptors: Vec::new(),
         }
     }
 
@@ -97,9 +243,12 @@ where
         self
     }
 
-    /// Add a callback for sending/receiving data through a [RTCDataChannel][dc].
+    /// Add a callback for sending/receiving data through a [RTCDataChannel][dc]. Data channels
+    /// are detached, and messages larger than the SCTP max message size can be sent by wrapping
+    /// the detached channel in a [ChunkedDataChannel][cdc].
     ///
     /// [dc]: webrtc::data_channel::RTCDataChannel
+    /// [cdc]: crate::network::chunked_data_channel::ChunkedDataChannel
     pub fn with_data_channel_handler(
         &mut self,
         data_channel_handler: OnDataChannelHdlrFn,
@@ -108,51 +257,345 @@ where
         self
     }
 
+    /// Add a callback for receiving RTCP APP packets.
+    ///
+    /// The packets are only seen when the RTCP of the corresponding `RTCRtpSender` or
+    /// `RTCRtpReceiver` is being read.
+    pub fn with_rtcp_app_handler(&mut self, rtcp_app_handler: OnRtcpAppHdlrFn) -> &mut Self {
+        self.rtcp_app_handler = Some(rtcp_app_handler);
+        self
+    }
+
+    /// Add a callback for observing every received RTCP packet, e.g., sender and receiver reports
+    /// for collecting metrics.
+    ///
+    /// Same as with [WebRtcBuilder::with_rtcp_app_handler], the packets are only seen when the
+    /// RTCP is being read.
+    pub fn with_rtcp_handler(&mut self, rtcp_handler: OnRtcpHdlrFn) -> &mut Self {
+        self.rtcp_handler = Some(rtcp_handler);
+        self
+    }
+
     pub fn initial_bandwidth(&mut self, init_bandwidth: DataRate) -> &mut Self {
         self.init_bandwidth = init_bandwidth;
         self
     }
 
+    /// Tune the delay-based controller of the bandwidth estimator used by the encoders.
+    pub fn with_delay_based_config(&mut self, config: DelayBasedConfig) -> &mut Self {
+        self.delay_based_config = config;
+        self
+    }
+
+    /// Guarantee the audio encoders `reservation` out of the shared bandwidth estimate. The video
+    /// encoders only get what remains so that bursty video cannot starve the audio.
+    pub fn with_audio_bandwidth_reservation(&mut self, reservation: DataRate) -> &mut Self {
+        self.audio_bandwidth_reservation = Some(reservation);
+        self
+    }
+
+    /// Set how often TWCC feedback is sent for the received streams. Defaults to 100 ms.
+    pub fn with_twcc_feedback_interval(&mut self, interval: Duration) -> &mut Self {
+        self.twcc_feedback_interval = interval;
+        self
+    }
+
+    /// Probe for a packetization MTU larger than the conservative 1200 bytes, up to `max_mtu`.
+    /// Encoders are notified of the MTU through [EncoderContext::packetization_mtu][a].
+    /// Disabled by default.
+    ///
+    /// [a]: crate::encoder::EncoderContext::packetization_mtu
+    pub fn with_mtu_discovery(&mut self, max_mtu: usize) -> &mut Self {
+        self.max_mtu = Some(max_mtu);
+        self
+    }
+
+    /// Smooth the increases of the bandwidth estimate seen by the encoders with an exponential
+    /// moving average of `time_constant`, for encoders that do not cope well with frequent
+    /// bitrate changes. Decreases are passed through immediately. Disabled by default.
+    pub fn with_estimate_smoothing(&mut self, time_constant: Duration) -> &mut Self {
+        self.estimate_smoothing = Some(time_constant);
+        self
+    }
+
+    /// Signal the end of the ICE candidates to the remote peer after `timeout` even if gathering
+    /// is still waiting on a slow or unreachable STUN/TURN server, so that the connection proceeds
+    /// with the candidates gathered so far. Candidates gathered afterwards are not sent.
+    ///
+    /// By default gathering ends when webrtc-ice gives up on the servers, which is after 5 s for
+    /// STUN.
+    pub fn with_ice_gather_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.ice_gather_timeout = Some(timeout);
+        self
+    }
+
+    /// Re-send the offer if no answer arrives within `timeout`, e.g., because the signaling
+    /// channel lost the offer or the answer. Otherwise the offerer is stuck, as it ignores the
+    /// offers of the remote peer until its own is answered. webrtc-rs cannot roll back a local
+    /// offer, so the pending offer is re-sent with an incremented session version so that the
+    /// remote peer answers it again instead of skipping it as a duplicate. Only applies to the
+    /// offerer. Disabled by default.
+    pub fn with_negotiation_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.negotiation_timeout = Some(timeout);
+        self
+    }
+
+    /// Enable or disable mDNS, which hides the local IP addresses in the host candidates. Defaults
+    /// to enabled on release builds and to the default mode of webrtc-rs on debug builds, since
+    /// webrtc-rs does not handle mDNS properly when communicating with another webrtc-rs
+    /// instance.
+    pub fn with_mdns(&mut self, mdns: bool) -> &mut Self {
+        self.mdns = Some(mdns);
+        self
+    }
+
+    /// Send the ICE candidates as they are gathered through `Message::IceCandidate`. Otherwise
+    /// the offers and answers are only sent after gathering completes, or times out if configured
+    /// with [with_ice_gather_timeout][a], with the candidates embedded in the SDP. Enabled by
+    /// default.
+    ///
+    /// [a]: WebRtcBuilder::with_ice_gather_timeout
+    pub fn with_trickle_ice(&mut self, trickle_ice: bool) -> &mut Self {
+        self.trickle_ice = trickle_ice;
+        self
+    }
+
+    /// Negotiate the dependency descriptor header extension for video, which carries the
+    /// spatial and temporal layers of AV1 and VP9 SVC streams. Received descriptors can be read
+    /// through [BufferedTrackRemote::with_dependency_descriptor][a]. Disabled by default.
+    ///
+    /// [a]: crate::network::reorder_buffer::BufferedTrackRemote::with_dependency_descriptor
+    pub fn with_dependency_descriptor(&mut self, dependency_descriptor: bool) -> &mut Self {
+        self.dependency_descriptor = dependency_descriptor;
+        self
+    }
+
+    /// Pair every sent video stream with an RTX SSRC through `a=ssrc-group:FID` in the offers and
+    /// answers sent to the remote peer, which some browsers need before they accept RTX from it.
+    /// Disabled by default.
+    ///
+    /// webrtc-rs 0.6 never sends on the advertised RTX SSRC and retransmits on the media SSRC
+    /// instead, so this only helps a remote peer that expects the grouping to be present.
+    pub fn with_rtx_ssrc_group(&mut self, rtx_ssrc_group: bool) -> &mut Self {
+        self.rtx_ssrc_group = rtx_ssrc_group;
+        self
+    }
+
+    /// Choose the codecs of each media section when answering an offer instead of letting
+    /// webrtc-rs pick. Takes precedence over [WebRtcPeer::renegotiate_codecs] for the sections
+    /// where the policy makes a choice. Only used while the peer is the answerer.
+    pub fn with_answer_codec_policy(&mut self, policy: AnswerCodecPolicy) -> &mut Self {
+        self.answer_codec_policy = Some(policy);
+        self
+    }
+
+    /// Limit the NACKs, PLIs, and FIRs sent for the received streams to `max_rate`, dropping the
+    /// ones over it. On very lossy links, e.g., satellite, the feedback about the losses can
+    /// otherwise congest the uplink even further. A few percent of the expected bandwidth is
+    /// usually enough. The NACKs and the keyframe requests are limited separately, so up to twice
+    /// `max_rate` can be sent in total. Disabled by default.
+    pub fn with_feedback_rate_limit(&mut self, max_rate: DataRate) -> &mut Self {
+        self.feedback_rate_limit = Some(max_rate);
+        self
+    }
+
+    /// Pace the outgoing packets to the bandwidth estimate and, while they wait for it, send the
+    /// retransmissions of NACKed packets ahead of the new packets. Up to `retransmission_share`
+    /// (0 - 1) of the estimate goes to the retransmissions that skip ahead. Under congestion,
+    /// repairing a recent frame usually helps the receiver more than starting on the next one.
+    /// The new packets get what the retransmissions and RTCP are measured to leave of the
+    /// estimate. Disabled by default, in which case the packets are sent as soon as they are
+    /// written.
+    ///
+    /// Enables [DelayBasedConfig::with_late_send_timestamps] so that the time the packets wait in
+    /// the pacer is not mistaken for network delay.
+    pub fn with_retransmission_priority(&mut self, retransmission_share: f64) -> &mut Self {
+        self.retransmission_priority = Some(retransmission_share);
+        self
+    }
+
+    /// Force a keyframe at most once every `interval` for each encoder, however many PLIs, FIRs,
+    /// and late subscribers ask for one. The requests in between are coalesced into a single
+    /// keyframe at the end of the interval, so that a storm of requests, e.g., from a decoder
+    /// that keeps asking until the keyframe arrives, does not tank the quality. Defaults to
+    /// 500 ms.
+    pub fn with_min_keyframe_interval(&mut self, interval: Duration) -> &mut Self {
+        self.min_keyframe_interval = interval;
+        self
+    }
+
+    /// Add a custom interceptor, e.g., for stats, logging, or FEC, at `position` relative to the
+    /// TWCC interceptors. Interceptors at the same position are registered in the order they are
+    /// added.
+    pub fn with_interceptor(
+        &mut self,
+        interceptor: Box<dyn InterceptorBuilder + Send + Sync>,
+        position: InterceptorPosition,
+    ) -> &mut Self {
+        self.interceptors.push((position, interceptor));
+        self
+    }
+
     /// Consume the builder and build a `WebRtcPeer`.
+    ///
+    /// Fails if the peer may have to send the offer but has no encoders, decoders, or data
+    /// channel handler, since the offer would have nothing to negotiate. A data channel handler
+    /// alone is enough for a data-channel-only peer. An answerer can be built with nothing and
+    /// takes its media descriptions from the offer.
+    ///
+    /// Also fails if [Signaler::connect] does, e.g., when the signaling server refuses the
+    /// connection.
     pub async fn build(self) -> webrtc::error::Result<Arc<WebRtcPeer>> {
-        let mut media_engine = MediaEngine::default();
+        if self.role != Role::Answerer
+            && self.encoders.is_empty()
+            && self.decoders.is_empty()
+            && self.data_channel_handler.is_none()
         {
-            let mut codecs = Vec::new();
-            for encoder in self.encoders.iter() {
-                codecs.extend_from_slice(encoder.supported_codecs());
+            return Err(webrtc::Error::new(
+                "Nothing to offer without an encoder, a decoder, or a data channel handler"
+                    .to_owned(),
+            ));
+        }
+
+        let codecs = self.available_codecs();
+        check_payload_type_conflicts(&codecs)?;
+
+        if let Err(e) = self.signaler.connect().await {
+            return Err(webrtc::Error::new(format!(
+                "Failed to connect the signaler: {e}"
+            )));
+        }
+
+        let mut media_engine = MediaEngine::default();
+        for codec in &codecs {
+            media_engine.register_custom_codec(codec.clone())?;
+        }
+        // Let encoders hint the receiver's jitter buffer
+        media_engine.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: PLAYOUT_DELAY_URI.to_owned(),
+            },
+            RTPCodecType::Video,
+            None,
+        )?;
+        // Let decoders detect voice activity without decoding
+        media_engine.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: AUDIO_LEVEL_URI.to_owned(),
+            },
+            RTPCodecType::Audio,
+            None,
+        )?;
+        if self.dependency_descriptor {
+            media_engine.register_header_extension(
+                RTCRtpHeaderExtensionCapability {
+                    uri: DEPENDENCY_DESCRIPTOR_URI.to_owned(),
+                },
+                RTPCodecType::Video,
+                None,
+            )?;
+        }
+        // Send times for the delay-based REMB of remote peers without transport-cc
+        if !self.decoders.is_empty() {
+            for uri in [ABS_SEND_TIME_URI, TRANSMISSION_OFFSET_URI] {
+                media_engine.register_header_extension(
+                    RTCRtpHeaderExtensionCapability {
+                        uri: uri.to_owned(),
+                    },
+                    RTPCodecType::Video,
+                    None,
+                )?;
             }
-            for decoder in self.decoders.iter() {
-                codecs.extend_from_slice(decoder.supported_codecs());
+        }
+        // Extensions the decoders rely on
+        for decoder in &self.decoders {
+            for uri in decoder.required_extensions() {
+                media_engine.register_header_extension(
+                    RTCRtpHeaderExtensionCapability {
+                        uri: (*uri).to_owned(),
+                    },
+                    decoder.codec_type().into(),
+                    None,
+                )?;
             }
-
-            Self::register_codecs(codecs, &mut media_engine)?;
         }
 
-        let registry = configure_nack(Registry::new(), &mut media_engine);
+        // Created ahead of the interceptors so that the pacer can follow the estimate
+        let bandwidth_sender = if self.encoders.is_empty() {
+            None
+        } else {
+            Some(twcc_bandwidth_estimate_channel(self.init_bandwidth).0)
+        };
+
+        let mut registry = Registry::new();
+        let mut delay_based_config = self.delay_based_config;
+        // Must come before the NACK generator so that its NACKs also go through the throttle
+        if let Some(max_rate) = self.feedback_rate_limit {
+            registry = configure_feedback_throttle(registry, max_rate);
+        }
+        // Must come before the NACK responder so that its retransmissions also go through the
+        // pacer
+        if let (Some(retransmission_share), Some(bandwidth_sender)) =
+            (self.retransmission_priority, &bandwidth_sender)
+        {
+            registry =
+                configure_pacer(registry, bandwidth_sender.subscribe(), retransmission_share);
+            // Otherwise the wait in the pacer looks like network delay to the estimator
+            delay_based_config = delay_based_config.with_late_send_timestamps(true);
+        }
+        let registry = configure_nack(registry, &mut media_engine);
         let registry = configure_rtcp_reports(registry);
+        let (registry, sender_reports) = configure_sender_reports(registry);
+        let (mut registry, keyframe_router) = configure_keyframe_requests(registry);
+
+        let (before_twcc, after_twcc): (Vec<_>, Vec<_>) = self
+            .interceptors
+            .into_iter()
+            .partition(|(position, _)| *position == InterceptorPosition::BeforeTwcc);
+        for (_, interceptor) in before_twcc {
+            registry.add(interceptor);
+        }
 
-        let (registry, bandwidth_estimate) = Self::init_twcc(
+        // Must come after registering the codecs since the transport-cc feedback is only added to
+        // the codecs already in the `MediaEngine`. The header extension is registered for both
+        // audio and video so audio packets are also included in the bandwidth estimate.
+        let registry = Self::init_twcc(
             registry,
             &mut media_engine,
-            self.init_bandwidth,
-            self.encoders.len() > 0,
-            self.decoders.len() > 0,
+            bandwidth_sender.clone(),
+            delay_based_config,
+            self.twcc_feedback_interval,
+            !self.decoders.is_empty(),
         )?;
 
+        // Fallback for remote peers that only understand REMB
+        let registry = if !self.decoders.is_empty() {
+            configure_remb_receiver(registry)?
+        } else {
+            registry
+        };
+
+        let registry = match self.rtcp_app_handler {
+            Some(rtcp_app_handler) => configure_rtcp_app(registry, rtcp_app_handler)?,
+            None => registry,
+        };
+
+        let mut registry = match self.rtcp_handler {
+            Some(rtcp_handler) => configure_rtcp_observer(registry, rtcp_handler)?,
+            None => registry,
+        };
+
+        for (_, interceptor) in after_twcc {
+            registry.add(interceptor);
+        }
+
         let mut setting_engine = SettingEngine::default();
         setting_engine.detach_data_channels();
 
         // Default is too long
         setting_engine.set_ice_timeouts(None, Some(Duration::from_secs(10)), None);
 
-        // Leave mDNS disabled on debug builds because webrtc-rs does not handle it properly when
-        // communicating with another webrtc-rs instance
-        #[cfg(debug_assertions)]
-        setting_engine.set_ice_multicast_dns_mode(MulticastDnsMode::Unspecified);
-
-        // Enabling mDNS hides local IP addresses
-        #[cfg(not(debug_assertions))]
-        setting_engine.set_ice_multicast_dns_mode(MulticastDnsMode::QueryAndGather);
+        setting_engine.set_ice_multicast_dns_mode(ice_multicast_dns_mode(self.mdns));
 
         let api_builder = APIBuilder::new()
             .with_media_engine(media_engine)
@@ -161,20 +604,75 @@ where
             .build();
 
         let (ice_tx, ice_rx_1) = watch::channel(RTCIceConnectionState::default());
+        let (remote_max_bitrates, _) = watch::channel(HashMap::new());
+
+        let mut encoder_tracks = Vec::with_capacity(self.encoders.len());
+        if let Some(bandwidth_sender) = &bandwidth_sender {
+            bandwidth_sender.set_audio_reservation(self.audio_bandwidth_reservation);
+            if let Some(max_mtu) = self.max_mtu {
+                bandwidth_sender.enable_mtu_discovery(max_mtu);
+            }
+            if let Some(time_constant) = self.estimate_smoothing {
+                bandwidth_sender.enable_smoothing(time_constant);
+            }
+            for encoder_builder in self.encoders {
+                let track = EncoderTrackLocal::new(
+                    encoder_builder,
+                    ice_rx_1.clone(),
+                    bandwidth_sender,
+                    remote_max_bitrates.subscribe(),
+                    keyframe_router.clone(),
+                    self.min_keyframe_interval,
+                )
+                .await;
+                encoder_tracks.push(Arc::new(track));
+            }
+        }
+
+        let pc = api_builder
+            .new_peer_connection(RTCConfiguration {
+                ice_servers: self.ice_servers,
+                ..Default::default()
+            })
+            .await?;
+
+        // Resolved as late as possible so that an offer is sent right after the timeout, leaving
+        // little time for the remote peer to also time out
+        let (role, first_message) = match self.role {
+            Role::Auto => resolve_auto_role(&self.signaler).await,
+            role => (role, None),
+        };
+
         let peer = Arc::new(WebRtcPeer {
-            pc: api_builder
-                .new_peer_connection(RTCConfiguration {
-                    ice_servers: self.ice_servers,
-                    ..Default::default()
-                })
-                .await?,
-            signaler: Box::new(self.signaler),
+            pc,
+            role,
+            signaler: Arc::new(self.signaler),
+            signaler_task: std::sync::Mutex::new(None),
             ice_tx,
             closed: Notify::new(),
+            bye_sent: AtomicBool::new(false),
+            ice_gather_timeout: self.ice_gather_timeout,
+            negotiation_timeout: self.negotiation_timeout,
+            negotiation_generation: AtomicU64::new(0),
+            trickle_ice: self.trickle_ice,
+            gathering_generation: AtomicU64::new(0),
+            end_of_candidates_sent: AtomicBool::new(false),
+            remote_bye: Notify::new(),
+            encoder_tracks,
+            bandwidth_sender,
+            sender_reports,
+            rtx_ssrcs: self
+                .rtx_ssrc_group
+                .then(|| std::sync::Mutex::new(HashMap::new())),
+            remote_max_bitrates,
+            codecs,
+            decoders: std::sync::Mutex::new(self.decoders),
+            decoded_mids: std::sync::Mutex::new(HashSet::new()),
+            answer_codec_policy: self.answer_codec_policy,
         });
 
         // Start the WebRTC negotiation if configured to be the offerer
-        match self.role {
+        match role {
             Role::Offerer => {
                 let weak_ref = Arc::downgrade(&peer);
                 peer.pc.on_negotiation_needed(Box::new(move || {
@@ -182,15 +680,17 @@ where
                     Box::pin(async move {
                         if let Some(peer) = peer.upgrade() {
                             if let Err(e) = peer.start_negotiation(false).await {
-                                panic!("{e}");
+                                log::error!("Failed to start the negotiation: {e}");
                             }
                         }
                     })
                 }));
 
                 // Need to do this else webrtc-rs would not include audio/video in the SDP
-                let codec_types: Vec<_> = self
+                let codec_types: Vec<_> = peer
                     .decoders
+                    .lock()
+                    .unwrap()
                     .iter()
                     .map(|decoder| decoder.codec_type())
                     .collect();
@@ -206,22 +706,64 @@ where
                         .await?;
                 }
             }
-            Role::Answerer => (),
+            Role::Answerer | Role::Auto => (),
         }
 
-        // Sends the ICE candidate to the peer via the signaling channel
+        // Sends the ICE candidate to the peer via the signaling channel. A `None` candidate means
+        // gathering is complete.
         let weak_ref = Arc::downgrade(&peer);
         peer.pc.on_ice_candidate(Box::new(move |candidate| {
             let peer = weak_ref.clone();
             Box::pin(async move {
-                if let (Some(peer), Some(candidate)) = (peer.upgrade(), candidate) {
-                    if let Ok(json) = candidate.to_json() {
-                        let _ = peer.signaler.send(Message::IceCandidate(json)).await;
+                if let Some(peer) = peer.upgrade() {
+                    // Sent as part of the SDP instead
+                    if !peer.trickle_ice {
+                        return;
+                    }
+                    match candidate {
+                        Some(candidate) => {
+                            // Too late if gathering already timed out
+                            if peer.end_of_candidates_sent.load(Ordering::Acquire) {
+                                return;
+                            }
+                            if let Ok(json) = candidate.to_json() {
+                                let _ = peer.signaler.send(Message::IceCandidate(json)).await;
+                            }
+                        }
+                        None => peer.send_end_of_candidates().await,
                     }
                 }
             })
         }));
 
+        // Starts the gathering timeout. Gathering restarts on ICE restarts, which invalidates the
+        // timer of the previous round.
+        let weak_ref = Arc::downgrade(&peer);
+        peer.pc
+            .on_ice_gathering_state_change(Box::new(move |state| {
+                let peer = weak_ref.clone();
+                Box::pin(async move {
+                    if let Some(peer) = peer.upgrade() {
+                        if state == RTCIceGathererState::Gathering {
+                            peer.start_gather_timer();
+                        }
+                    }
+                })
+            }));
+
+        // Starts the negotiation watchdog whenever an offer is waiting for an answer
+        if peer.negotiation_timeout.is_some() {
+            let weak_ref = Arc::downgrade(&peer);
+            peer.pc.on_signaling_state_change(Box::new(move |state| {
+                let peer = weak_ref.clone();
+                Box::pin(async move {
+                    if let Some(peer) = peer.upgrade() {
+                        peer.start_negotiation_watchdog(state);
+                    }
+                })
+            }));
+        }
+
         // Monitors the ICE connection state and sends it to the encoders. Also initiates an ICE
         // restart when the connection fails.
         let weak_ref = Arc::downgrade(&peer);
@@ -230,16 +772,18 @@ where
                 let peer = weak_ref.clone();
                 Box::pin(async move {
                     if let Some(peer) = peer.upgrade() {
-                        let _ = peer.ice_tx.send(state);
+                        // Stored even without receivers so `close_and_wait` sees the latest state
+                        peer.ice_tx.send_replace(state);
                         if state == RTCIceConnectionState::Failed {
-                            match self.role {
+                            match role {
                                 Role::Offerer => {
                                     // TODO: Test ICE restart
                                     if let Err(e) = peer.start_negotiation(true).await {
                                         panic!("{e}");
                                     }
                                 }
-                                Role::Answerer => (), // Offerer should be the one to initiate ICE restart
+                                // Offerer should be the one to initiate ICE restart
+                                Role::Answerer | Role::Auto => (),
                             }
                         }
                     }
@@ -260,11 +804,17 @@ where
                 })
             }));
 
-        // Spawn a task to concurrently handle the messages received from the signaling channel
-        tokio::spawn(Self::signaler_message_handler(peer.clone(), self.role));
+        // Spawn a task to concurrently handle the messages received from the signaling channel.
+        // The task is aborted when the peer is closed or dropped.
+        let signaler_task = tokio::spawn(Self::signaler_message_handler(
+            peer.signaler.clone(),
+            Arc::downgrade(&peer),
+            role,
+            first_message,
+        ));
+        *peer.signaler_task.lock().unwrap() = Some(signaler_task);
 
         // Handle the received track using one of the decoders
-        let decoders = Arc::new(Mutex::new(self.decoders));
         let weak_ref = Arc::downgrade(&peer);
         peer.pc.on_track(Box::new(
             move |track: Option<Arc<TrackRemote>>, receiver: Option<Arc<RTCRtpReceiver>>| {
@@ -273,23 +823,29 @@ where
                     _ => return Box::pin(async {}),
                 };
 
-                let decoders = decoders.clone();
                 let peer = weak_ref.clone();
 
-                // Pick one decoder that can handle the codec of the track
+                // Pick the decoder that most closely matches the codec of the track
                 Box::pin(async move {
                     if let Some(peer) = peer.upgrade() {
                         let codec = track.codec().await;
-                        let mut decoders = decoders.lock().await;
-                        let mut matched_index = None;
-                        for (index, decoder) in decoders.iter().enumerate() {
-                            if decoder.is_codec_supported(&codec.capability) {
-                                matched_index = Some(index);
-                                break;
+                        let decoder = {
+                            let mut decoders = peer.decoders.lock().unwrap();
+                            select_decoder(&decoders, &codec.capability)
+                                .map(|index| decoders.remove(index))
+                        };
+                        if let Some(decoder) = decoder {
+                            // Keeps receiving on a renegotiation even with the decoder used up
+                            for transceiver in peer.pc.get_transceivers().await {
+                                match transceiver.receiver().await {
+                                    Some(r) if Arc::ptr_eq(&r, &receiver) => {
+                                        let mid = transceiver.mid().await;
+                                        peer.decoded_mids.lock().unwrap().insert(mid);
+                                    }
+                                    _ => {}
+                                }
                             }
-                        }
-                        if let Some(index) = matched_index {
-                            let decoder = decoders.swap_remove(index);
+                            warn_on_h264_incompatibility(decoder.as_ref(), &codec.capability);
                             decoder.build(track, receiver, peer);
                         }
                     }
@@ -297,26 +853,22 @@ where
             },
         ));
 
-        for encoder_builder in self.encoders {
-            if let Some(bandwidth_estimate) = &bandwidth_estimate {
-                let track = EncoderTrackLocal::new(
-                    encoder_builder,
-                    ice_rx_1.clone(),
-                    bandwidth_estimate.clone(),
-                )
-                .await;
-                let track = Arc::new(track);
-                track.add_as_transceiver(&peer.pc).await?;
+        for track in peer.encoder_tracks.iter() {
+            let transceiver = track.clone().add_as_transceiver(&peer.pc).await?;
+            if let Some(preferences) =
+                rtcp_feedback_preferences(track.supported_codecs(), track.kind(), &peer.codecs)
+            {
+                transceiver.set_codec_preferences(preferences).await?;
             }
         }
 
         if let Some(mut data_channel_handler) = self.data_channel_handler {
-            match self.role {
+            match role {
                 Role::Offerer => {
                     let data_channel = peer.pc.create_data_channel("channel", None).await?;
                     (data_channel_handler)(data_channel).await;
                 }
-                Role::Answerer => {
+                Role::Answerer | Role::Auto => {
                     peer.pc.on_data_channel(data_channel_handler);
                 }
             }
@@ -325,89 +877,166 @@ where
         Ok(peer)
     }
 
-    fn register_codecs(
-        codecs: Vec<Codec>,
-        media_engine: &mut MediaEngine,
-    ) -> Result<(), webrtc::Error> {
-        const DYNAMIC_PAYLOAD_TYPE_START: u8 = 96u8;
-
-        let mut payload_id = Some(DYNAMIC_PAYLOAD_TYPE_START);
-
-        for mut codec in codecs {
-            if let Some(payload_type) = payload_id {
-                codec.set_payload_type(payload_type);
-                media_engine.register_custom_codec(codec.clone())?;
-                payload_id = payload_type.checked_add(1);
-
-                // Register for retransmission
-                if let Some(mut retransmission) = Codec::retransmission(&codec) {
-                    if let Some(payload_type) = payload_id {
-                        retransmission.set_payload_type(payload_type);
-                        media_engine.register_custom_codec(retransmission)?;
-                        payload_id = payload_type.checked_add(1);
-                    } else {
-                        panic!("Not enough payload type for video retransmission");
-                    }
+    /// Lists every codec that will be registered on `build`, with the payload types that will be
+    /// assigned to them. This includes the RTX, ULPFEC, and fallback H.264 codecs that are added
+    /// automatically.
+    pub fn available_codecs(&self) -> Vec<Codec> {
+        let mut codecs: Vec<Codec> = Vec::new();
+        let supported_codecs = self
+            .encoders
+            .iter()
+            .flat_map(|encoder| encoder.supported_codecs())
+            .chain(
+                self.decoders
+                    .iter()
+                    .flat_map(|decoder| decoder.supported_codecs()),
+            );
+        for codec in supported_codecs {
+            // The same codec for both sending and receiving is only registered once
+            let duplicate = codecs.iter().position(|registered| {
+                registered.codec_type() == codec.codec_type()
+                    && registered.fixed_payload_type() == codec.fixed_payload_type()
+                    && registered.capability_matches(&codec.parameters().capability)
+            });
+            match duplicate {
+                // Register the RTCP feedback shared by the tracks that did not set their own
+                Some(index)
+                    if codecs[index].has_custom_rtcp_feedback()
+                        && !codec.has_custom_rtcp_feedback() =>
+                {
+                    codecs[index] = codec.clone();
                 }
-            } else {
-                panic!("Registered too many codecs");
+                Some(_) => {}
+                None => codecs.push(codec.clone()),
             }
         }
-
-        if let Some(payload_type) = payload_id {
-            // Needed for playback of non-constrained-baseline H264 for some reason
-            let mut ulpfec = Codec::ulpfec();
-            ulpfec.set_payload_type(payload_type);
-            media_engine.register_custom_codec(ulpfec)?;
-        } else {
-            panic!("Not enough payload type for ULPFEC");
-        }
-
-        if let Some(payload_type) = payload_id {
-            // Required for the browser to send TWCC
-            let mut h264: Codec = H264Codec::constrained_baseline().into();
-            h264.set_payload_type(payload_type);
-            media_engine.register_custom_codec(h264)?;
-        } else {
-            panic!("Not enough payload type");
-        }
-
-        Ok(())
+        assign_payload_types(codecs)
     }
 
     // Implements the impolite peer of "perfect negotiation".
     async fn signaler_message_handler(
-        peer: Arc<WebRtcPeer>,
+        signaler: Arc<dyn Signaler + 'static>,
+        peer: Weak<WebRtcPeer>,
         role: Role,
+        mut first_message: Option<Message>,
     ) -> Result<(), webrtc::Error> {
+        // Candidates that arrived ahead of the first remote description, e.g., while it was being
+        // re-sent, which webrtc-rs cannot add yet
+        let mut pending_candidates = Vec::new();
+        let mut recv_backoff = SDP_SEND_INITIAL_BACKOFF;
+        let mut recv_failures = 0;
         loop {
-            if let Ok(msg) = peer.signaler.recv().await {
-                match msg {
-                    Message::Sdp(sdp) => {
-                        let sdp_type = sdp.sdp_type;
-
-                        if role == Role::Offerer
-                            && sdp_type == RTCSdpType::Offer
-                            && peer.pc.signaling_state() != RTCSignalingState::Stable
+            let msg = match first_message.take() {
+                // Already received while resolving `Role::Auto`
+                Some(msg) => Ok(msg),
+                None => signaler.recv().await,
+            };
+            let msg = match msg {
+                Ok(msg) => {
+                    recv_backoff = SDP_SEND_INITIAL_BACKOFF;
+                    recv_failures = 0;
+                    msg
+                }
+                // Backs off since the error may be transient, while a closed channel would
+                // otherwise fail again right away and spin
+                Err(e) => {
+                    recv_failures += 1;
+                    if recv_failures == SIGNALER_RECV_ATTEMPTS {
+                        log::error!(
+                            "Failed to receive from the signaler {recv_failures} times in a row, \
+                            closing the peer: {e}"
+                        );
+                        // Nothing can be received anymore, including the remote's Bye
+                        if let Some(peer) = peer.upgrade() {
+                            peer.close_local();
+                        }
+                        break;
+                    }
+                    log::warn!(
+                        "Failed to receive from the signaler, retrying in {recv_backoff:?}: {e}"
+                    );
+                    tokio::time::sleep(recv_backoff).await;
+                    recv_backoff *= 2;
+                    if peer.strong_count() == 0 {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            // Only hold a strong reference while handling a message so the task does not keep the
+            // peer alive
+            let peer = match peer.upgrade() {
+                Some(peer) => peer,
+                None => break,
+            };
+
+            match msg {
+                Message::Sdp(sdp) => {
+                    let sdp_type = sdp.sdp_type;
+
+                    if role == Role::Offerer
+                        && sdp_type == RTCSdpType::Offer
+                        && peer.pc.signaling_state() != RTCSignalingState::Stable
+                    {
+                        continue;
+                    }
+
+                    // The signaling transport re-delivered an SDP that was already applied
+                    if let Some(remote_description) = peer.pc.remote_description().await {
+                        if remote_description.sdp_type == sdp_type
+                            && remote_description.sdp == sdp.sdp
                         {
                             continue;
                         }
+                    }
 
-                        peer.pc.set_remote_description(sdp).await?;
-                        if sdp_type == RTCSdpType::Offer {
-                            let answer = peer.pc.create_answer(None).await?;
-                            peer.pc.set_local_description(answer.clone()).await?;
-                            let _ = peer.signaler.send(Message::Sdp(answer)).await;
+                    // Cap the encoders right away instead of waiting for TWCC to converge
+                    if let Some(bandwidth_sender) = &peer.bandwidth_sender {
+                        if let Some(max_bandwidth) = remote_max_bandwidth(&sdp) {
+                            bandwidth_sender.set_max_bandwidth(Some(max_bandwidth));
                         }
                     }
-                    Message::IceCandidate(candidate) => {
-                        peer.pc.add_ice_candidate(candidate).await?;
+                    // Before applying it, since the encoders are built once their tracks are bound
+                    peer.remote_max_bitrates
+                        .send_replace(remote_max_bitrates(&sdp));
+
+                    peer.pc.set_remote_description(sdp.clone()).await?;
+                    for candidate in pending_candidates.drain(..) {
+                        peer.add_remote_candidate(candidate).await;
                     }
-                    Message::Bye => {
-                        peer.close().await;
-                        break;
+                    if sdp_type == RTCSdpType::Offer {
+                        peer.apply_answer_codec_policy(&sdp).await?;
+                        let answer = peer.pc.create_answer(None).await?;
+                        let answer = peer.apply_local_description(answer).await?;
+                        if let Err(e) = peer.send_sdp(answer).await {
+                            log::error!("Failed to send the answer: {e}");
+                        }
                     }
                 }
+                Message::IceCandidate(candidate) => {
+                    if peer.pc.remote_description().await.is_some() {
+                        peer.add_remote_candidate(candidate).await;
+                    } else {
+                        pending_candidates.push(candidate);
+                    }
+                }
+                Message::EndOfCandidates => {
+                    // An empty candidate is the end-of-candidates marker
+                    let candidate = RTCIceCandidateInit::default();
+                    if peer.pc.remote_description().await.is_some() {
+                        peer.add_remote_candidate(candidate).await;
+                    } else {
+                        pending_candidates.push(candidate);
+                    }
+                }
+                Message::Bye => {
+                    // Wakes up `close_and_wait`. Closing also echoes the Bye if this peer
+                    // has not sent one yet.
+                    peer.remote_bye.notify_one();
+                    peer.close().await;
+                    break;
+                }
             }
         }
         Ok(())
@@ -416,26 +1045,468 @@ where
     fn init_twcc(
         registry: Registry,
         media_engine: &mut MediaEngine,
-        init_bandwidth: DataRate,
-        has_encoder: bool,
+        bandwidth_sender: Option<TwccBandwidthSender>,
+        delay_based_config: DelayBasedConfig,
+        feedback_interval: Duration,
         has_decoder: bool,
-    ) -> Result<(Registry, Option<TwccBandwidthEstimate>), webrtc::Error> {
-        match (has_encoder, has_decoder) {
+    ) -> Result<Registry, webrtc::Error> {
+        match (bandwidth_sender, has_decoder) {
             // Has a sender
-            (true, _) => {
-                let (registry, bandwidth_estimate) =
-                    configure_custom_twcc_sender(registry, init_bandwidth)?;
-                let registry = configure_twcc(registry, media_engine)?;
-                Ok((registry, Some(bandwidth_estimate)))
+            (Some(bandwidth_sender), _) => {
+                // Order matters: the transport-cc extension must be set before the TWCC
+                // interceptor records the send info
+                let registry =
+                    register_custom_twcc_sender(registry, bandwidth_sender, delay_based_config)?;
+                let registry = configure_twcc_sender_only(registry, media_engine)?;
+                configure_twcc_receiver(registry, media_engine, feedback_interval)
             }
             // Only receiver
-            (false, true) => {
-                let registry = configure_twcc_receiver_only(registry, media_engine)?;
-                Ok((registry, None))
+            (None, true) => configure_twcc_receiver(registry, media_engine, feedback_interval),
+            (None, false) => Ok(registry),
+        }
+    }
+}
+
+/// Payload types and capabilities of the codecs in a media section, in the order of the `m=`
+/// line. Payload types without an `a=rtpmap` are skipped.
+fn offered_codecs(media: &MediaDescription) -> Vec<(u8, RTCRtpCodecCapability)> {
+    let kind = &media.media_name.media;
+    media
+        .media_name
+        .formats
+        .iter()
+        .filter_map(|format| {
+            let payload_type = format.parse().ok()?;
+            // The value of the attribute for this payload type, e.g., `VP8/90000` of
+            // `a=rtpmap:96 VP8/90000`
+            let value_of = |key: &str| {
+                media
+                    .attributes
+                    .iter()
+                    .filter(|attribute| attribute.key == key)
+                    .find_map(|attribute| {
+                        attribute
+                            .value
+                            .as_deref()?
+                            .strip_prefix(format.as_str())?
+                            .strip_prefix(' ')
+                    })
+            };
+
+            let mut rtpmap = value_of("rtpmap")?.split('/');
+            let encoding_name = rtpmap.next()?;
+            let clock_rate = rtpmap.next()?.parse().ok()?;
+            let channels = rtpmap.next().and_then(|c| c.parse().ok()).unwrap_or(0);
+            let capability = RTCRtpCodecCapability {
+                mime_type: format!("{kind}/{encoding_name}"),
+                clock_rate,
+                channels,
+                sdp_fmtp_line: value_of("fmtp").unwrap_or_default().to_owned(),
+                rtcp_feedback: Vec::new(),
+            };
+            Some((payload_type, capability))
+        })
+        .collect()
+}
+
+/// Whether the codec only protects the media of other codecs, i.e., RTX, RED, or FEC.
+fn is_repair_codec(codec: &Codec) -> bool {
+    let mime_type = codec.mime_type();
+    ["video/rtx", "video/red", "video/ulpfec", "video/flexfec-03"]
+        .iter()
+        .any(|repair| mime_type.eq_ignore_ascii_case(repair))
+}
+
+/// Codec preferences for the transceiver of an encoder that negotiate the RTCP feedback of its
+/// `supported` codecs as is, with the payload types of the `registered` codecs. Returns [None]
+/// unless the feedback of one of them was set through [Codec::with_rtcp_feedback], in which case
+/// the feedback that the `MediaEngine` adds to every codec is left out. The header extensions,
+/// including transport-wide-cc, are not affected.
+fn rtcp_feedback_preferences(
+    supported: &[Codec],
+    kind: RTPCodecType,
+    registered: &[Codec],
+) -> Option<Vec<RTCRtpCodecParameters>> {
+    if !supported.iter().any(Codec::has_custom_rtcp_feedback) {
+        return None;
+    }
+
+    let chosen: Vec<_> = supported
+        .iter()
+        .filter_map(|codec| {
+            let registered = registered.iter().find(|registered| {
+                registered.codec_type() == codec.codec_type()
+                    && registered.capability_matches(&codec.parameters().capability)
+            })?;
+            let mut parameters = registered.parameters().clone();
+            parameters.capability.rtcp_feedback = codec.rtcp_feedback().to_vec();
+            Some(parameters)
+        })
+        .collect();
+
+    // RTX codecs are tied to their base codec through the apt parameter
+    let chosen_payload_types: Vec<_> = chosen
+        .iter()
+        .map(|parameters| parameters.payload_type.to_string())
+        .collect();
+    let repair = registered
+        .iter()
+        .filter(|codec| kind == codec.codec_type().into() && is_repair_codec(codec))
+        .filter(|codec| match codec.fmtp_param("apt") {
+            Some(apt) => chosen_payload_types.iter().any(|pt| pt == apt),
+            None => true,
+        })
+        .map(|codec| codec.parameters().clone());
+
+    Some(chosen.into_iter().chain(repair).collect())
+}
+
+/// mDNS mode of the ICE agent. See [WebRtcBuilder::with_mdns] for the default.
+fn ice_multicast_dns_mode(mdns: Option<bool>) -> MulticastDnsMode {
+    match mdns {
+        Some(true) => MulticastDnsMode::QueryAndGather,
+        Some(false) => MulticastDnsMode::Disabled,
+        // Leave mDNS to webrtc-rs on debug builds because it does not handle it properly when
+        // communicating with another webrtc-rs instance
+        None if cfg!(debug_assertions) => MulticastDnsMode::Unspecified,
+        // Enabling mDNS hides local IP addresses
+        None => MulticastDnsMode::QueryAndGather,
+    }
+}
+
+/// Waits for the first message from the remote peer, becoming the answerer if one arrives.
+async fn resolve_auto_role<S: Signaler>(signaler: &S) -> (Role, Option<Message>) {
+    let wait = AUTO_ROLE_TIMEOUT + random_duration(AUTO_ROLE_TIMEOUT);
+    match tokio::time::timeout(wait, signaler.recv()).await {
+        Ok(Ok(msg)) => (Role::Answerer, Some(msg)),
+        _ => (Role::Offerer, None),
+    }
+}
+
+/// Random duration in `[0, max)`.
+fn random_duration(max: Duration) -> Duration {
+    // `RandomState` is randomly seeded
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos(random % max.as_nanos().max(1) as u64)
+}
+
+/// Reads the smallest `b=AS` of the session and the video media descriptions.
+fn remote_max_bandwidth(sdp: &RTCSessionDescription) -> Option<DataRate> {
+    let parsed = sdp.unmarshal().ok()?;
+
+    parsed
+        .bandwidth
+        .iter()
+        .chain(
+            parsed
+                .media_descriptions
+                .iter()
+                .filter(|media| media.media_name.media == "video")
+                .flat_map(|media| media.bandwidth.iter()),
+        )
+        .filter(|bandwidth| !bandwidth.experimental && bandwidth.bandwidth_type == "AS")
+        .map(|bandwidth| bandwidth.bandwidth)
+        .min()
+        // `b=AS` is in kbps
+        .map(|kbps| DataRate::from_bits_per_sec(kbps * 1000))
+}
+
+/// Reads the `x-google-max-bitrate` of every codec in `sdp`, by payload type. These only bound
+/// the encoders that end up sending with that codec.
+fn remote_max_bitrates(sdp: &RTCSessionDescription) -> HashMap<u8, DataRate> {
+    let parsed = match sdp.unmarshal() {
+        Ok(parsed) => parsed,
+        Err(_) => return HashMap::new(),
+    };
+    parsed
+        .media_descriptions
+        .iter()
+        .flat_map(|media| {
+            let codec_type = match media.media_name.media.as_str() {
+                "audio" => CodecType::Audio,
+                _ => CodecType::Video,
+            };
+            offered_codecs(media)
+                .into_iter()
+                .map(move |(payload_type, capability)| (payload_type, capability, codec_type))
+        })
+        .filter_map(|(payload_type, capability, codec_type)| {
+            let parameters = RTCRtpCodecParameters {
+                capability,
+                ..Default::default()
+            };
+            let max_bitrate = Codec::new(parameters, codec_type).max_bitrate()?;
+            Some((payload_type, max_bitrate))
+        })
+        .collect()
+}
+
+/// Direction attribute of the media section with `mid` in `sdp`, if any.
+fn media_direction(
+    sdp: Option<&SessionDescription>,
+    mid: &str,
+) -> Option<RTCRtpTransceiverDirection> {
+    let media = sdp?
+        .media_descriptions
+        .iter()
+        .find(|media| media.attribute("mid") == Some(Some(mid)))?;
+    media
+        .attributes
+        .iter()
+        .map(|attribute| RTCRtpTransceiverDirection::from(attribute.key.as_str()))
+        .find(|direction| *direction != RTCRtpTransceiverDirection::Unspecified)
+}
+
+/// Stops the remote peer from sending media that would be dropped, by answering the media sections
+/// where `is_decodable` is false for every answered codec as not receiving: `sendrecv` becomes
+/// `sendonly` and `recvonly` becomes `inactive`. webrtc-rs accepts these as long as the codec is
+/// registered at all, e.g., for an encoder or as the fallback H.264. Offers are returned as is.
+///
+/// `is_decodable` is called with the mid of the media section and one of its codecs.
+fn reject_undecodable_media(
+    sdp: RTCSessionDescription,
+    is_decodable: impl Fn(&str, &RTCRtpCodecCapability) -> bool,
+) -> RTCSessionDescription {
+    if sdp.sdp_type != RTCSdpType::Answer {
+        return sdp;
+    }
+    let mut parsed = match sdp.unmarshal() {
+        Ok(parsed) => parsed,
+        Err(_) => return sdp,
+    };
+
+    let mut modified = false;
+    for media in parsed.media_descriptions.iter_mut() {
+        if !matches!(media.media_name.media.as_str(), "audio" | "video") {
+            continue;
+        }
+        // Already rejected by webrtc-rs
+        if media.media_name.port.value == 0 {
+            continue;
+        }
+
+        let mid = media
+            .attribute("mid")
+            .flatten()
+            .unwrap_or_default()
+            .to_owned();
+        let decodable = offered_codecs(media)
+            .iter()
+            .any(|(_, capability)| is_decodable(&mid, capability));
+        if decodable {
+            continue;
+        }
+
+        for attribute in media.attributes.iter_mut() {
+            let direction = match attribute.key.as_str() {
+                "sendrecv" => "sendonly",
+                "recvonly" => "inactive",
+                _ => continue,
+            };
+            *attribute = Attribute::new(direction.to_owned(), None);
+            modified = true;
+        }
+    }
+
+    if !modified {
+        return sdp;
+    }
+    RTCSessionDescription::answer(parsed.marshal()).unwrap_or(sdp)
+}
+
+/// Increments the session version of the `o=` line by `increment`, marking the description as
+/// changed.
+fn with_session_version_incremented(
+    sdp: RTCSessionDescription,
+    increment: u64,
+) -> RTCSessionDescription {
+    let mut parsed = match sdp.unmarshal() {
+        Ok(parsed) => parsed,
+        Err(_) => return sdp,
+    };
+    parsed.origin.session_version = parsed.origin.session_version.wrapping_add(increment);
+
+    let modified = match sdp.sdp_type {
+        RTCSdpType::Offer => RTCSessionDescription::offer(parsed.marshal()),
+        RTCSdpType::Answer => RTCSessionDescription::answer(parsed.marshal()),
+        _ => return sdp,
+    };
+    modified.unwrap_or(sdp)
+}
+
+/// Associates an RTX SSRC with the SSRC of every stream sent in a media section that has an `rtx`
+/// codec through `a=ssrc-group:FID` ([RFC4588][RFC4588]), which some browsers need to pair the
+/// retransmissions with the media stream. The RTX SSRCs are kept in `rtx_ssrcs` so that they stay
+/// the same across renegotiations.
+///
+/// The RTX SSRCs are only advertised. webrtc-rs 0.6 still sends the retransmissions on the media
+/// SSRC.
+///
+/// [RFC4588]: https://www.rfc-editor.org/rfc/rfc4588#section-8.3
+fn add_rtx_ssrc_groups(
+    sdp: RTCSessionDescription,
+    rtx_ssrcs: &mut HashMap<u32, u32>,
+) -> RTCSessionDescription {
+    let mut parsed = match sdp.unmarshal() {
+        Ok(parsed) => parsed,
+        Err(_) => return sdp,
+    };
+
+    for media in parsed.media_descriptions.iter_mut() {
+        let has_rtx = media.attributes.iter().any(|attribute| {
+            attribute.key == "rtpmap"
+                && attribute
+                    .value
+                    .as_deref()
+                    .and_then(|rtpmap| rtpmap.split(' ').nth(1))
+                    .is_some_and(|encoding| encoding.to_ascii_lowercase().starts_with("rtx/"))
+        });
+        let has_ssrc_group = media
+            .attributes
+            .iter()
+            .any(|attribute| attribute.key == "ssrc-group");
+        if !has_rtx || has_ssrc_group {
+            continue;
+        }
+
+        let ssrc_of = |attribute: &Attribute| -> Option<u32> {
+            if attribute.key != "ssrc" {
+                return None;
+            }
+            attribute.value.as_deref()?.split(' ').next()?.parse().ok()
+        };
+        let media_ssrc = match media.attributes.iter().find_map(ssrc_of) {
+            Some(ssrc) => ssrc,
+            // Not sending anything
+            None => continue,
+        };
+        let rtx_ssrc = *rtx_ssrcs.entry(media_ssrc).or_insert_with(|| loop {
+            let ssrc = RandomState::new().build_hasher().finish() as u32;
+            if ssrc != 0 && ssrc != media_ssrc {
+                break ssrc;
+            }
+        });
+
+        // Same `cname`, `msid`, etc. as the media stream
+        let media_ssrc_prefix = format!("{media_ssrc} ");
+        let rtx_attributes: Vec<_> = media
+            .attributes
+            .iter()
+            .filter(|attribute| ssrc_of(attribute) == Some(media_ssrc))
+            .filter_map(|attribute| {
+                let value = attribute
+                    .value
+                    .as_deref()?
+                    .strip_prefix(&media_ssrc_prefix)?;
+                Some(Attribute::new(
+                    "ssrc".to_owned(),
+                    Some(format!("{rtx_ssrc} {value}")),
+                ))
+            })
+            .collect();
+
+        let first = media
+            .attributes
+            .iter()
+            .position(|attribute| ssrc_of(attribute).is_some())
+            .unwrap_or(media.attributes.len());
+        let last = media
+            .attributes
+            .iter()
+            .rposition(|attribute| ssrc_of(attribute) == Some(media_ssrc))
+            .map_or(first, |last| last + 1);
+        media.attributes.splice(last..last, rtx_attributes);
+        media.attributes.insert(
+            first,
+            Attribute::new(
+                "ssrc-group".to_owned(),
+                Some(format!("FID {media_ssrc} {rtx_ssrc}")),
+            ),
+        );
+    }
+
+    // Also updates the parsed description
+    let modified = match sdp.sdp_type {
+        RTCSdpType::Offer => RTCSessionDescription::offer(parsed.marshal()),
+        RTCSdpType::Answer => RTCSessionDescription::answer(parsed.marshal()),
+        _ => return sdp,
+    };
+    modified.unwrap_or(sdp)
+}
+
+/// Returns an error if two different codecs ended up with the same payload type, e.g., two codecs
+/// with the same fixed payload type.
+fn check_payload_type_conflicts(codecs: &[Codec]) -> Result<(), webrtc::Error> {
+    for (i, codec) in codecs.iter().enumerate() {
+        if let Some(other) = codecs[..i]
+            .iter()
+            .find(|other| other.payload_type() == codec.payload_type())
+        {
+            return Err(webrtc::Error::new(format!(
+                "Codecs {} and {} both use the payload type {}",
+                other.mime_type(),
+                codec.mime_type(),
+                codec.payload_type()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Assigns dynamic payload types to `codecs`, except for those with a fixed payload type, adding
+/// an RTX codec for each video codec, then ULPFEC and a constrained baseline H.264 at the end.
+fn assign_payload_types(codecs: Vec<Codec>) -> Vec<Codec> {
+    let mut payload_id = Some(DYNAMIC_PAYLOAD_TYPE_START);
+    let mut assigned = Vec::new();
+
+    for mut codec in codecs {
+        if let Some(payload_type) = codec.fixed_payload_type() {
+            codec.set_payload_type(payload_type);
+            assigned.push(codec);
+            continue;
+        }
+
+        if let Some(payload_type) = payload_id {
+            codec.set_payload_type(payload_type);
+            payload_id = payload_type.checked_add(1);
+
+            // Register for retransmission
+            let retransmission = Codec::retransmission(&codec);
+            assigned.push(codec);
+            if let Some(mut retransmission) = retransmission {
+                if let Some(payload_type) = payload_id {
+                    retransmission.set_payload_type(payload_type);
+                    assigned.push(retransmission);
+                    payload_id = payload_type.checked_add(1);
+                } else {
+                    panic!("Not enough payload type for video retransmission");
+                }
             }
-            (false, false) => Ok((registry, None)),
+        } else {
+            panic!("Registered too many codecs");
         }
     }
+
+    if let Some(payload_type) = payload_id {
+        // Needed for playback of non-constrained-baseline H264 for some reason
+        let mut ulpfec = Codec::ulpfec();
+        ulpfec.set_payload_type(payload_type);
+        assigned.push(ulpfec);
+        payload_id = payload_type.checked_add(1);
+    } else {
+        panic!("Not enough payload type for ULPFEC");
+    }
+
+    if let Some(payload_type) = payload_id {
+        // Required for the browser to send TWCC
+        let mut h264: Codec = H264Codec::constrained_baseline().into();
+        h264.set_payload_type(payload_type);
+        assigned.push(h264);
+    } else {
+        panic!("Not enough payload type");
+    }
+
+    assigned
 }
 
 /// Struct representing a WebRTC connection.
@@ -444,9 +1515,36 @@ where
 /// builder.
 pub struct WebRtcPeer {
     pc: RTCPeerConnection,
-    signaler: Box<dyn Signaler + 'static>,
+    role: Role,
+    signaler: Arc<dyn Signaler + 'static>,
+    signaler_task: std::sync::Mutex<Option<JoinHandle<Result<(), webrtc::Error>>>>,
     ice_tx: watch::Sender<RTCIceConnectionState>,
     closed: Notify,
+    bye_sent: AtomicBool,
+    ice_gather_timeout: Option<Duration>,
+    negotiation_timeout: Option<Duration>,
+    // Incremented on every signaling state change
+    negotiation_generation: AtomicU64,
+    trickle_ice: bool,
+    // Incremented on every start of ICE gathering
+    gathering_generation: AtomicU64,
+    // Whether the end of candidates was signaled for the current gathering
+    end_of_candidates_sent: AtomicBool,
+    remote_bye: Notify,
+    encoder_tracks: Vec<Arc<EncoderTrackLocal>>,
+    bandwidth_sender: Option<TwccBandwidthSender>,
+    sender_reports: SenderReports,
+    // Media SSRC to the RTX SSRC advertised for it, if the RTX SSRCs are advertised
+    rtx_ssrcs: Option<std::sync::Mutex<HashMap<u32, u32>>>,
+    // `x-google-max-bitrate` of the remote description, by payload type
+    remote_max_bitrates: watch::Sender<HashMap<u8, DataRate>>,
+    // Registered in the media engine, with their payload types
+    codecs: Vec<Codec>,
+    // Not used by a received track yet
+    decoders: std::sync::Mutex<Vec<Box<dyn DecoderBuilder>>>,
+    // Media sections whose track went to a decoder
+    decoded_mids: std::sync::Mutex<HashSet<String>>,
+    answer_codec_policy: Option<AnswerCodecPolicy>,
 }
 
 impl WebRtcPeer {
@@ -459,11 +1557,190 @@ impl WebRtcPeer {
         WebRtcBuilder::new(signaler, role)
     }
 
+    /// Returns the role of the peer. `Role::Auto` is resolved to either `Role::Offerer` or
+    /// `Role::Answerer` on build.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Returns the description last applied from the remote peer, e.g., for reading its ICE
+    /// credentials or header extensions. [None] until the first offer or answer is received.
+    pub async fn remote_description(&self) -> Option<RTCSessionDescription> {
+        self.pc.remote_description().await
+    }
+
+    /// Returns the local description as sent to the remote peer, i.e., with the RTX SSRC groups
+    /// that webrtc-rs leaves out and, in answers, without receiving the media that no decoder
+    /// supports. [None] until the first offer or answer is created.
+    pub async fn local_description(&self) -> Option<RTCSessionDescription> {
+        let description = self.pc.local_description().await?;
+        Some(self.description_to_send(description))
+    }
+
+    /// Returns the lifetime packet counts from the TWCC feedback of the remote peer. Returns [None]
+    /// if the peer has no encoders and thus no bandwidth estimator.
+    pub fn twcc_packet_stats(&self) -> Option<TwccPacketStats> {
+        self.bandwidth_sender
+            .as_ref()
+            .map(|bandwidth_sender| bandwidth_sender.packet_stats())
+    }
+
+    /// Recompute the bandwidth estimate now instead of waiting for the next RTCP feedback, e.g.,
+    /// to poll a fresh estimate before an adaptation decision. The encoders are notified if it
+    /// changed. Returns [None] if the peer has no encoders and thus no bandwidth estimator, or if
+    /// the estimator is not running yet.
+    pub async fn recompute_estimate(&self) -> Option<DataRate> {
+        self.bandwidth_sender.as_ref()?.recompute_estimate().await
+    }
+
+    /// Gathers the statistics of the connection. See [PeerStats] for what is included.
+    pub async fn stats(&self) -> PeerStats {
+        let report = self.pc.get_stats().await;
+        let bandwidth_estimate = self
+            .bandwidth_sender
+            .as_ref()
+            .map(|bandwidth_sender| bandwidth_sender.current());
+        let ignored_rtcp_packets = self
+            .bandwidth_sender
+            .as_ref()
+            .map(|bandwidth_sender| bandwidth_sender.ignored_rtcp_packets());
+        let mut codecs = Vec::new();
+        for encoder_track in &self.encoder_tracks {
+            codecs.extend(encoder_track.codec_mime_type().await);
+        }
+        for rtp_receiver in self.pc.get_receivers().await {
+            for track in rtp_receiver.tracks().await {
+                codecs.push(track.codec().await.capability.mime_type);
+            }
+        }
+        PeerStats::new(
+            &report,
+            bandwidth_estimate,
+            self.twcc_packet_stats(),
+            ignored_rtcp_packets,
+            codecs,
+        )
+    }
+
+    /// Gathers the statistics of the connection as a JSON object, e.g., for dashboards. The
+    /// schema is documented in [PeerStats].
+    pub async fn stats_json(&self) -> String {
+        // Only plain fields, serialization does not fail
+        serde_json::to_string(&self.stats().await).unwrap()
+    }
+
     /// Close the `WebRtcPeer`.
     pub async fn close(&self) {
-        let _ = self.signaler.send(Message::Bye).await;
+        self.send_bye().await;
+        self.close_local();
+    }
+
+    /// Close the `WebRtcPeer` after the remote peer acknowledged the teardown, either by replying
+    /// with its own `Message::Bye` or by the ICE connection dropping. Returns `false` if neither
+    /// happened within `timeout`, in which case the peer is closed anyway.
+    pub async fn close_and_wait(&self, timeout: Duration) -> bool {
+        let mut ice_rx = self.ice_tx.subscribe();
+        self.send_bye().await;
+
+        let ice_dropped = async move {
+            while !matches!(
+                *ice_rx.borrow_and_update(),
+                RTCIceConnectionState::Disconnected
+                    | RTCIceConnectionState::Failed
+                    | RTCIceConnectionState::Closed
+            ) {
+                if ice_rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        let acknowledged = tokio::time::timeout(timeout, async {
+            tokio::select! {
+                _ = self.remote_bye.notified() => (),
+                _ = ice_dropped => (),
+            }
+        })
+        .await
+        .is_ok();
+
+        self.close_local();
+        acknowledged
+    }
+
+    /// Sends `Message::Bye` at most once so that the remote's reply does not trigger another one.
+    async fn send_bye(&self) {
+        if !self.bye_sent.swap(true, Ordering::AcqRel) {
+            let _ = self.signaler.send(Message::Bye).await;
+        }
+    }
+
+    /// Sends `Message::EndOfCandidates` at most once per gathering, whether gathering completed
+    /// or timed out first.
+    async fn send_end_of_candidates(&self) {
+        if !self.end_of_candidates_sent.swap(true, Ordering::AcqRel) {
+            let _ = self.signaler.send(Message::EndOfCandidates).await;
+        }
+    }
+
+    fn start_gather_timer(self: &Arc<Self>) {
+        let generation = self.gathering_generation.fetch_add(1, Ordering::AcqRel) + 1;
+        self.end_of_candidates_sent.store(false, Ordering::Release);
+
+        // Without trickle ICE the timeout is applied while waiting for the SDP instead
+        if let Some(timeout) = self.ice_gather_timeout.filter(|_| self.trickle_ice) {
+            let peer = Arc::downgrade(self);
+            tokio::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                if let Some(peer) = peer.upgrade() {
+                    if peer.gathering_generation.load(Ordering::Acquire) == generation {
+                        log::debug!("ICE gathering timed out after {timeout:?}");
+                        peer.send_end_of_candidates().await;
+                    }
+                }
+            });
+        }
+    }
+
+    /// Re-sends the pending offer every `negotiation_timeout` until the signaling state changes.
+    fn start_negotiation_watchdog(self: &Arc<Self>, state: RTCSignalingState) {
+        let generation = self.negotiation_generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let timeout = match self.negotiation_timeout {
+            Some(timeout) if state == RTCSignalingState::HaveLocalOffer => timeout,
+            _ => return,
+        };
+
+        let peer = Arc::downgrade(self);
+        tokio::spawn(async move {
+            for attempt in 1.. {
+                tokio::time::sleep(timeout).await;
+                let peer = match peer.upgrade() {
+                    Some(peer) => peer,
+                    None => return,
+                };
+                if peer.negotiation_generation.load(Ordering::Acquire) != generation {
+                    return;
+                }
+                let offer = match peer.local_description().await {
+                    Some(offer) => offer,
+                    None => return,
+                };
+
+                log::warn!("No answer after {timeout:?}, re-sending the offer");
+                let offer = with_session_version_incremented(offer, attempt);
+                if let Err(e) = peer.send_sdp(offer).await {
+                    log::error!("Failed to re-send the offer: {e}");
+                }
+            }
+        });
+    }
+
+    fn close_local(&self) {
         let _ = self.ice_tx.send(RTCIceConnectionState::Closed);
         self.closed.notify_waiters();
+        if let Some(signaler_task) = self.signaler_task.lock().unwrap().take() {
+            signaler_task.abort();
+        }
     }
 
     /// Blocks until the `WebRtcPeer` has been closed.
@@ -471,6 +1748,351 @@ impl WebRtcPeer {
         self.closed.notified().await;
     }
 
+    /// Set the handler for the state changes of the DTLS transport, e.g., for applications that
+    /// derive keys from it for end-to-end encryption. The handshake only starts once ICE connects,
+    /// so a handler set right after building sees it complete. webrtc-rs does not renegotiate DTLS
+    /// on an existing transport, so the keys only change along with the state.
+    pub fn on_dtls_state_change(&self, f: OnDTLSTransportStateChangeHdlrFn) {
+        self.pc.sctp().transport().on_state_change(f);
+    }
+
+    /// Pause or resume sending on the encoder track with the given id. The transceiver is kept
+    /// alive so no renegotiation is needed. Returns `false` if no such track exists.
+    pub fn set_track_enabled(&self, id: &str, enabled: bool) -> bool {
+        let mut found = false;
+        for track in self.encoder_tracks.iter() {
+            if track.id() == id {
+                track.set_enabled(enabled);
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Returns the write metrics reported by the encoder of the track with the given id, or [None]
+    /// if no such track exists.
+    pub fn encoder_stats(&self, id: &str) -> Option<EncoderStats> {
+        self.encoder_tracks
+            .iter()
+            .find(|track| track.id() == id)
+            .map(|track| track.stats())
+    }
+
+    /// Lists the transceivers that are sending or receiving as negotiated with the remote peer, in
+    /// the order of their m-lines. Empty until the first negotiation completes.
+    pub async fn transceivers(&self) -> Vec<TransceiverInfo> {
+        // webrtc-rs keeps the direction it created the description with, even if the answer
+        // that was sent or received says otherwise, see `reject_undecodable_media`
+        let local = match self.local_description().await {
+            Some(description) => description.unmarshal().ok(),
+            None => None,
+        };
+        let remote = match self.pc.remote_description().await {
+            Some(description) => description.unmarshal().ok(),
+            None => None,
+        };
+
+        let mut transceivers = Vec::new();
+        for transceiver in self.pc.get_transceivers().await {
+            let mid = transceiver.mid().await;
+            let mut direction = transceiver.current_direction();
+            if let Some(local_direction) = media_direction(local.as_ref(), &mid) {
+                direction = direction.intersect(local_direction);
+            }
+            if let Some(remote_direction) = media_direction(remote.as_ref(), &mid) {
+                direction = direction.intersect(remote_direction.reverse());
+            }
+            if matches!(
+                direction,
+                RTCRtpTransceiverDirection::Unspecified | RTCRtpTransceiverDirection::Inactive
+            ) {
+                continue;
+            }
+
+            let mut codec = None;
+            if direction.has_send() {
+                if let Some(sender) = transceiver.sender().await {
+                    if let Some(track) = sender.track().await {
+                        if let Some(encoder_track) = self
+                            .encoder_tracks
+                            .iter()
+                            .find(|encoder_track| encoder_track.id() == track.id())
+                        {
+                            codec = encoder_track.codec_mime_type().await;
+                        }
+                    }
+                }
+            } else if let Some(receiver) = transceiver.receiver().await {
+                // Tracks that did not receive anything yet have no codec
+                if let Some(track) = receiver.track().await {
+                    let mime_type = track.codec().await.capability.mime_type;
+                    codec = (!mime_type.is_empty()).then_some(mime_type);
+                }
+            }
+            transceivers.push(TransceiverInfo {
+                mid,
+                kind: transceiver.kind(),
+                direction,
+                codec,
+            });
+        }
+        transceivers
+    }
+
+    /// Returns the RTP/NTP timestamp mapping from the latest sender report of the remote stream
+    /// with the given SSRC, e.g., `TrackRemote::ssrc` of a decoder track, or [None] if none was
+    /// received yet. Decoders of the audio and video of the same sender can convert their RTP
+    /// timestamps to the sender's wallclock through [SenderReportMapping::wallclock_time] to
+    /// synchronize their playout.
+    ///
+    /// The reports are only seen when the RTCP of the `RTCRtpReceiver` of the track is being read.
+    pub fn sender_report(&self, ssrc: u32) -> Option<SenderReportMapping> {
+        self.sender_reports.get(ssrc)
+    }
+
+    /// Renegotiate the codecs of the encoder track with the given id so that `codecs` are
+    /// preferred, in order, e.g., to switch to a higher H.264 profile once there is CPU to spare.
+    /// The offerer sends a new offer right away while an answerer applies the preference in its
+    /// answer to the next offer of the remote peer.
+    ///
+    /// The media engine is built once so `codecs` can only be among those registered on build,
+    /// i.e., the ones in [WebRtcBuilder::available_codecs], and that the remote peer accepted in
+    /// the previous negotiation. The other negotiated codecs stay negotiable after them.
+    /// webrtc-rs also binds a track only once, so the encoder keeps the codec and payload type it
+    /// was built with and has to switch within them, e.g., by changing the H.264 profile in-band
+    /// through the SPS.
+    pub async fn renegotiate_codecs(
+        &self,
+        id: &str,
+        codecs: &[Codec],
+    ) -> Result<(), webrtc::Error> {
+        let mut preferred = Vec::with_capacity(self.codecs.len());
+        for codec in codecs {
+            let registered = self
+                .codecs
+                .iter()
+                .find(|registered| {
+                    registered.codec_type() == codec.codec_type()
+                        && registered.capability_matches(&codec.parameters().capability)
+                })
+                .ok_or(webrtc::Error::ErrRTPTransceiverCodecUnsupported)?;
+            preferred.push(registered);
+        }
+
+        let mut found = None;
+        for transceiver in self.pc.get_transceivers().await {
+            if let Some(sender) = transceiver.sender().await {
+                if sender.track().await.is_some_and(|track| track.id() == id) {
+                    found = Some((transceiver, sender));
+                    break;
+                }
+            }
+        }
+        let (transceiver, sender) = found
+            .ok_or_else(|| webrtc::Error::new(format!("No encoder track with the id {id}")))?;
+
+        // Once negotiated, webrtc-rs only accepts the codecs that the remote peer also accepted
+        let negotiated = sender.get_parameters().await.rtp_parameters.codecs;
+        let kind = transceiver.kind();
+        let others = self.codecs.iter().filter(|codec| {
+            kind == codec.codec_type().into()
+                && !preferred.contains(codec)
+                && negotiated
+                    .iter()
+                    .any(|negotiated| codec.capability_matches(&negotiated.capability))
+        });
+        let preferences = preferred
+            .iter()
+            .copied()
+            .chain(others)
+            .map(|codec| codec.parameters().clone())
+            .collect();
+        transceiver.set_codec_preferences(preferences).await?;
+
+        if self.role == Role::Offerer {
+            self.start_negotiation(false).await?;
+        }
+        Ok(())
+    }
+
+    /// Send an RTCP APP packet. `ssrc` should be the SSRC of one of the local streams.
+    pub async fn send_rtcp_app(
+        &self,
+        ssrc: u32,
+        name: [u8; 4],
+        data: &[u8],
+    ) -> Result<(), webrtc::Error> {
+        let app = ApplicationDefined {
+            subtype: 0,
+            ssrc,
+            name,
+            data: Bytes::copy_from_slice(data),
+        };
+
+        // webrtc-rs routes incoming RTCP using the destination SSRCs of the packets but APP
+        // packets are left unparsed. The SDES chunk lets the remote route the compound packet to
+        // the stream of `ssrc`.
+        let sdes = SourceDescription {
+            chunks: vec![SourceDescriptionChunk {
+                source: ssrc,
+                items: Vec::new(),
+            }],
+        };
+
+        self.pc
+            .write_rtcp(&[Box::new(sdes), Box::new(app.to_raw_packet())])
+            .await?;
+        Ok(())
+    }
+
+    // webrtc-rs rejects a local description that differs from what it generated so only the copy
+    // sent to the remote peer is modified
+    fn description_to_send(&self, sdp: RTCSessionDescription) -> RTCSessionDescription {
+        let sdp = match &self.rtx_ssrcs {
+            Some(rtx_ssrcs) => add_rtx_ssrc_groups(sdp, &mut rtx_ssrcs.lock().unwrap()),
+            None => sdp,
+        };
+        let decoders = self.decoders.lock().unwrap();
+        let decoded_mids = self.decoded_mids.lock().unwrap();
+        reject_undecodable_media(sdp, |mid, capability| {
+            decoded_mids.contains(mid)
+                || decoders
+                    .iter()
+                    .any(|decoder| decoder.is_codec_supported(capability))
+        })
+    }
+
+    /// Sets the codec preferences of the transceivers to the choice of the answer codec policy.
+    /// The offered codecs are read from `offer` itself since webrtc-rs only exposes the codecs
+    /// negotiated across every media section.
+    async fn apply_answer_codec_policy(
+        &self,
+        offer: &RTCSessionDescription,
+    ) -> Result<(), webrtc::Error> {
+        let policy = match &self.answer_codec_policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let parsed = offer.unmarshal()?;
+
+        for transceiver in self.pc.get_transceivers().await {
+            let mid = transceiver.mid().await;
+            let media = parsed
+                .media_descriptions
+                .iter()
+                .find(|media| media.attribute("mid") == Some(Some(mid.as_str())));
+            let media = match media {
+                Some(media) => media,
+                None => continue,
+            };
+
+            // The registered codecs that were offered, with the offered payload types and format
+            // parameters so that they match what webrtc-rs negotiated
+            let (repair, offered): (Vec<_>, Vec<_>) = offered_codecs(media)
+                .into_iter()
+                .filter_map(|(payload_type, capability)| {
+                    let registered = self
+                        .codecs
+                        .iter()
+                        .filter_map(|codec| {
+                            codec
+                                .capability_match_score(&capability)
+                                .map(|score| (score, codec))
+                        })
+                        .max_by_key(|(score, _)| *score)?
+                        .1;
+                    let parameters = RTCRtpCodecParameters {
+                        capability: RTCRtpCodecCapability {
+                            sdp_fmtp_line: capability.sdp_fmtp_line,
+                            ..registered.parameters().capability.clone()
+                        },
+                        payload_type,
+                        ..registered.parameters().clone()
+                    };
+                    Some(Codec::new(parameters, registered.codec_type()))
+                })
+                .partition(is_repair_codec);
+            if offered.is_empty() {
+                continue;
+            }
+
+            let chosen: Vec<_> = policy(&offered)
+                .into_iter()
+                .filter_map(|choice| {
+                    offered
+                        .iter()
+                        .find(|codec| codec.capability_matches(&choice.parameters().capability))
+                })
+                .collect();
+            if chosen.is_empty() {
+                continue;
+            }
+
+            // RTX codecs are tied to their base codec through the apt parameter
+            let chosen_payload_types: Vec<_> = chosen
+                .iter()
+                .map(|codec| codec.payload_type().to_string())
+                .collect();
+            let repair = repair.iter().filter(|codec| match codec.fmtp_param("apt") {
+                Some(apt) => chosen_payload_types.iter().any(|pt| pt == apt),
+                None => true,
+            });
+
+            let preferences = chosen
+                .into_iter()
+                .chain(repair)
+                .map(|codec| codec.parameters().clone())
+                .collect();
+            transceiver.set_codec_preferences(preferences).await?;
+        }
+        Ok(())
+    }
+
+    /// Sets the local description and returns the copy of it to send to the remote peer. Without
+    /// trickle ICE, this waits for gathering to finish so that the copy includes the candidates.
+    async fn apply_local_description(
+        &self,
+        description: RTCSessionDescription,
+    ) -> Result<RTCSessionDescription, webrtc::Error> {
+        if self.trickle_ice {
+            self.pc.set_local_description(description.clone()).await?;
+            return Ok(self.description_to_send(description));
+        }
+
+        // Needs to be set up before gathering starts
+        let mut gathering_complete = self.pc.gathering_complete_promise().await;
+        self.pc.set_local_description(description.clone()).await?;
+        match self.ice_gather_timeout {
+            Some(timeout) => {
+                if tokio::time::timeout(timeout, gathering_complete.recv())
+                    .await
+                    .is_err()
+                {
+                    log::debug!("ICE gathering timed out after {timeout:?}");
+                }
+            }
+            None => {
+                gathering_complete.recv().await;
+            }
+        }
+
+        // Only the local description webrtc-rs keeps has the candidates gathered so far
+        let description = self.pc.local_description().await.unwrap_or(description);
+        Ok(self.description_to_send(description))
+    }
+
+    // Not fatal since the negotiation can still recover, e.g., through the negotiation watchdog
+    async fn add_remote_candidate(&self, candidate: RTCIceCandidateInit) {
+        let end_of_candidates = candidate.candidate.is_empty();
+        if let Err(e) = self.pc.add_ice_candidate(candidate).await {
+            if end_of_candidates {
+                log::warn!("Failed to add the end of candidates: {e}");
+            } else {
+                log::warn!("Failed to add the ICE candidate: {e}");
+            }
+        }
+    }
+
     async fn start_negotiation(&self, ice_restart: bool) -> Result<(), webrtc::Error> {
         let options = if ice_restart {
             Some(RTCOfferOptions {
@@ -482,12 +2104,33 @@ impl WebRtcPeer {
         };
 
         let offer = self.pc.create_offer(options).await?;
-        self.pc.set_local_description(offer.clone()).await?;
-        self.signaler
-            .send(Message::Sdp(offer))
-            .await
-            .map_err(|_| webrtc::Error::ErrUnknownType)?;
-        Ok(())
+        let offer = self.apply_local_description(offer).await?;
+        self.send_sdp(offer).await.map_err(|e| {
+            log::error!("Failed to send the offer: {e}");
+            webrtc::Error::new(format!("Failed to send the offer: {e}"))
+        })
+    }
+
+    /// Sends an offer or answer, retrying with exponential backoff on failure since a lost SDP
+    /// stalls the negotiation. ICE candidates are only sent once, as the other candidates can
+    /// still form a pair without the lost one. The remote peer holds on to the candidates that
+    /// arrive ahead of the SDP.
+    async fn send_sdp(
+        &self,
+        sdp: RTCSessionDescription,
+    ) -> Result<(), Box<dyn std::error::Error + Send>> {
+        let mut backoff = SDP_SEND_INITIAL_BACKOFF;
+        for _ in 1..SDP_SEND_ATTEMPTS {
+            match self.signaler.send(Message::Sdp(sdp.clone())).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    log::warn!("Failed to send the SDP, retrying in {backoff:?}: {e}");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        }
+        self.signaler.send(Message::Sdp(sdp)).await
     }
 }
 
@@ -498,3 +2141,347 @@ impl std::ops::Deref for WebRtcPeer {
         &self.pc
     }
 }
+
+impl Drop for WebRtcPeer {
+    fn drop(&mut self) {
+        if let Some(signaler_task) = self.signaler_task.get_mut().unwrap().take() {
+            signaler_task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+
+    #[test]
+    fn offered_codecs_parsed() {
+        let mut sdp = RTCSessionDescription::default();
+        sdp.sdp = "v=0\r\n\
+            o=- 0 0 IN IP4 127.0.0.1\r\n\
+            s=-\r\n\
+            t=0 0\r\n\
+            m=audio 9 UDP/TLS/RTP/SAVPF 111 11\r\n\
+            c=IN IP4 0.0.0.0\r\n\
+            a=rtpmap:111 opus/48000/2\r\n\
+            a=fmtp:111 minptime=10;useinbandfec=1\r\n\
+            a=rtpmap:11 L16/44100/1\r\n\
+            m=video 9 UDP/TLS/RTP/SAVPF 96 97 98\r\n\
+            c=IN IP4 0.0.0.0\r\n\
+            a=rtpmap:96 VP8/90000\r\n\
+            a=rtpmap:97 rtx/90000\r\n\
+            a=fmtp:97 apt=96\r\n"
+            .to_owned();
+        let parsed = sdp.unmarshal().unwrap();
+
+        let audio = offered_codecs(&parsed.media_descriptions[0]);
+        let summary: Vec<_> = audio
+            .iter()
+            .map(|(pt, c)| (*pt, c.mime_type.as_str(), c.clock_rate, c.channels))
+            .collect();
+        assert_eq!(
+            summary,
+            [(111, "audio/opus", 48000, 2), (11, "audio/L16", 44100, 1)]
+        );
+        assert_eq!(audio[0].1.sdp_fmtp_line, "minptime=10;useinbandfec=1");
+        // The fmtp of 111 also starts with 11
+        assert_eq!(audio[1].1.sdp_fmtp_line, "");
+
+        // Payload type 98 has no rtpmap
+        let video = offered_codecs(&parsed.media_descriptions[1]);
+        assert_eq!(video.len(), 2);
+        assert_eq!(video[1].1.mime_type, "video/rtx");
+        assert_eq!(video[1].1.sdp_fmtp_line, "apt=96");
+    }
+
+    #[test]
+    fn remote_b_as() {
+        let sdp = |session_bandwidth: &str, video_bandwidth: &str| {
+            let mut sdp = RTCSessionDescription::default();
+            sdp.sdp = format!(
+                "v=0\r\n\
+                o=- 0 0 IN IP4 127.0.0.1\r\n\
+                s=-\r\n\
+                {session_bandwidth}\
+                t=0 0\r\n\
+                m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+                c=IN IP4 0.0.0.0\r\n\
+                b=AS:32\r\n\
+                a=rtpmap:111 opus/48000/2\r\n\
+                m=video 9 UDP/TLS/RTP/SAVPF 96\r\n\
+                c=IN IP4 0.0.0.0\r\n\
+                {video_bandwidth}\
+                a=rtpmap:96 H264/90000\r\n"
+            );
+            sdp
+        };
+        let max_bandwidth =
+            |sdp: RTCSessionDescription| remote_max_bandwidth(&sdp).map(|b| b.bits_per_sec());
+
+        // The audio `b=AS` is ignored
+        assert_eq!(max_bandwidth(sdp("", "")), None);
+        assert_eq!(max_bandwidth(sdp("", "b=AS:300\r\n")), Some(300_000));
+        assert_eq!(max_bandwidth(sdp("b=AS:2000\r\n", "")), Some(2_000_000));
+        assert_eq!(
+            max_bandwidth(sdp("b=AS:2000\r\n", "b=AS:300\r\n")),
+            Some(300_000)
+        );
+    }
+
+    #[test]
+    fn undecodable_media_rejected() {
+        let answer = RTCSessionDescription::answer(
+            "v=0\r\n\
+            o=- 0 0 IN IP4 127.0.0.1\r\n\
+            s=-\r\n\
+            t=0 0\r\n\
+            m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+            c=IN IP4 0.0.0.0\r\n\
+            a=mid:0\r\n\
+            a=rtpmap:111 opus/48000/2\r\n\
+            a=sendrecv\r\n\
+            m=video 9 UDP/TLS/RTP/SAVPF 96 97\r\n\
+            c=IN IP4 0.0.0.0\r\n\
+            a=mid:1\r\n\
+            a=rtpmap:96 H264/90000\r\n\
+            a=fmtp:96 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f\r\n\
+            a=rtpmap:97 rtx/90000\r\n\
+            a=fmtp:97 apt=96\r\n\
+            a=recvonly\r\n\
+            m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+            c=IN IP4 0.0.0.0\r\n\
+            a=sendrecv\r\n"
+                .to_owned(),
+        )
+        .unwrap();
+        let directions = |sdp: &RTCSessionDescription| -> Vec<String> {
+            sdp.unmarshal()
+                .unwrap()
+                .media_descriptions
+                .iter()
+                .map(|media| {
+                    let direction = ["sendrecv", "sendonly", "recvonly", "inactive"]
+                        .into_iter()
+                        .find(|direction| media.attribute(direction).is_some());
+                    direction.unwrap_or_default().to_owned()
+                })
+                .collect()
+        };
+
+        // Only an audio decoder, so the audio is still received
+        let opus = |_: &str, capability: &RTCRtpCodecCapability| {
+            capability.mime_type.eq_ignore_ascii_case("audio/opus")
+        };
+        let rejected = reject_undecodable_media(answer.clone(), opus);
+        assert_eq!(directions(&rejected), ["sendrecv", "inactive", "sendrecv"]);
+
+        // Without any decoder, only sent
+        let rejected = reject_undecodable_media(answer.clone(), |_, _| false);
+        assert_eq!(directions(&rejected), ["sendonly", "inactive", "sendrecv"]);
+
+        let rejected = reject_undecodable_media(answer.clone(), |_, _| true);
+        assert_eq!(rejected.sdp, answer.sdp);
+
+        // A media section that already went to a decoder
+        let rejected = reject_undecodable_media(answer.clone(), |mid, _| mid == "1");
+        assert_eq!(directions(&rejected), ["sendonly", "recvonly", "sendrecv"]);
+    }
+
+    #[test]
+    fn session_version_incremented() {
+        let offer = RTCSessionDescription::offer(
+            "v=0\r\n\
+            o=- 123 4 IN IP4 127.0.0.1\r\n\
+            s=-\r\n\
+            t=0 0\r\n"
+                .to_owned(),
+        )
+        .unwrap();
+
+        let offer = with_session_version_incremented(offer, 2);
+        assert_eq!(offer.sdp_type, RTCSdpType::Offer);
+        let origin = offer.unmarshal().unwrap().origin;
+        assert_eq!((origin.session_id, origin.session_version), (123, 6));
+    }
+
+    #[test]
+    fn remote_max_bitrate_fmtp() {
+        let sdp = |session_bandwidth: &str, max_bitrates_kbps: &[u64]| {
+            let payload_types: Vec<_> = (96..96 + max_bitrates_kbps.len() as u8)
+                .map(|payload_type| payload_type.to_string())
+                .collect();
+            let mut sdp = RTCSessionDescription::default();
+            sdp.sdp = format!(
+                "v=0\r\n\
+                o=- 0 0 IN IP4 127.0.0.1\r\n\
+                s=-\r\n\
+                {session_bandwidth}\
+                t=0 0\r\n\
+                m=video 9 UDP/TLS/RTP/SAVPF {}\r\n\
+                c=IN IP4 0.0.0.0\r\n",
+                payload_types.join(" ")
+            );
+            for (payload_type, kbps) in payload_types.iter().zip(max_bitrates_kbps) {
+                sdp.sdp.push_str(&format!(
+                    "a=rtpmap:{payload_type} VP8/90000\r\n\
+                    a=fmtp:{payload_type} x-google-max-bitrate={kbps}\r\n"
+                ));
+            }
+            sdp
+        };
+        let max_bitrates = |sdp: RTCSessionDescription| {
+            let mut max_bitrates: Vec<_> = remote_max_bitrates(&sdp)
+                .into_iter()
+                .map(|(payload_type, max_bitrate)| (payload_type, max_bitrate.bits_per_sec()))
+                .collect();
+            max_bitrates.sort_unstable();
+            max_bitrates
+        };
+
+        assert_eq!(max_bitrates(sdp("", &[800])), [(96, 800_000)]);
+        // Kept per codec instead of capping the whole estimate
+        assert_eq!(
+            max_bitrates(sdp("", &[800, 1500])),
+            [(96, 800_000), (97, 1_500_000)]
+        );
+        assert!(remote_max_bandwidth(&sdp("", &[800])).is_none());
+        let max_bandwidth = remote_max_bandwidth(&sdp("b=AS:2000\r\n", &[800]));
+        assert_eq!(max_bandwidth.map(|b| b.bits_per_sec()), Some(2_000_000));
+    }
+
+    #[tokio::test]
+    async fn available_codecs_match_media_engine() {
+        let codecs = assign_payload_types(vec![
+            H264Codec::new(crate::codecs::h264::H264Profile::High).into(),
+            Codec::opus(),
+        ]);
+
+        let payload_types: Vec<_> = codecs.iter().map(|codec| codec.payload_type()).collect();
+        assert_eq!(payload_types, vec![96, 97, 98, 99, 100]);
+
+        let mut media_engine = MediaEngine::default();
+        for codec in codecs.iter() {
+            media_engine.register_custom_codec(codec.clone()).unwrap();
+        }
+        let api = APIBuilder::new().with_media_engine(media_engine).build();
+        let pc = api.new_peer_connection(Default::default()).await.unwrap();
+        pc.add_transceiver_from_kind(RTPCodecType::Video, &[])
+            .await
+            .unwrap();
+        pc.add_transceiver_from_kind(RTPCodecType::Audio, &[])
+            .await
+            .unwrap();
+        let offer = pc.create_offer(None).await.unwrap();
+
+        // Every codec is in the SDP with the enumerated payload type
+        let mut rtpmaps: Vec<_> = offer
+            .sdp
+            .lines()
+            .filter_map(|line| line.strip_prefix("a=rtpmap:"))
+            .map(|rtpmap| rtpmap.to_owned())
+            .collect();
+        rtpmaps.sort();
+
+        let mut expected: Vec<_> = codecs
+            .iter()
+            .map(|codec| {
+                let (_, name) = codec.mime_type().split_once('/').unwrap();
+                let mut rtpmap = format!("{} {name}/{}", codec.payload_type(), codec.clock_rate());
+                if codec.channels() > 0 {
+                    rtpmap.push_str(&format!("/{}", codec.channels()));
+                }
+                rtpmap
+            })
+            .collect();
+        expected.sort();
+
+        assert_eq!(rtpmaps, expected);
+
+        pc.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rtx_ssrc_group() {
+        let mut media_engine = MediaEngine::default();
+        let codecs = vec![H264Codec::constrained_baseline().into(), Codec::opus()];
+        for codec in assign_payload_types(codecs) {
+            media_engine.register_custom_codec(codec).unwrap();
+        }
+        let api = APIBuilder::new().with_media_engine(media_engine).build();
+        let pc = api.new_peer_connection(Default::default()).await.unwrap();
+        let h264: Codec = H264Codec::constrained_baseline().into();
+        for (codec, id) in [(h264, "video"), (Codec::opus(), "audio")] {
+            let track = Arc::new(TrackLocalStaticRTP::new(
+                codec.parameters().capability.clone(),
+                id.to_owned(),
+                "webrtc".to_owned(),
+            ));
+            pc.add_track(track).await.unwrap();
+        }
+        let offer = pc.create_offer(None).await.unwrap();
+
+        let ssrc_lines = |sdp: &RTCSessionDescription, media: &str| -> Vec<String> {
+            let parsed = sdp.unmarshal().unwrap();
+            let media = parsed
+                .media_descriptions
+                .iter()
+                .find(|m| m.media_name.media == media)
+                .unwrap();
+            media
+                .attributes
+                .iter()
+                .filter(|a| a.key.starts_with("ssrc"))
+                .map(|a| a.to_string())
+                .collect()
+        };
+        let media_ssrc: u32 = ssrc_lines(&offer, "video")[0]
+            .strip_prefix("ssrc:")
+            .and_then(|line| line.split(' ').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let mut rtx_ssrcs = HashMap::new();
+        let modified = add_rtx_ssrc_groups(offer.clone(), &mut rtx_ssrcs);
+        let rtx_ssrc = rtx_ssrcs[&media_ssrc];
+        assert_eq!(modified.sdp_type, RTCSdpType::Offer);
+
+        let lines = ssrc_lines(&modified, "video");
+        assert_eq!(lines[0], format!("ssrc-group:FID {media_ssrc} {rtx_ssrc}"));
+        assert!(lines.contains(&format!("ssrc:{media_ssrc} cname:webrtc")));
+        assert!(lines.contains(&format!("ssrc:{rtx_ssrc} cname:webrtc")));
+
+        // Audio has no RTX
+        assert!(!ssrc_lines(&modified, "audio")
+            .iter()
+            .any(|line| line.starts_with("ssrc-group")));
+
+        // The same RTX SSRC on renegotiation
+        let renegotiated = add_rtx_ssrc_groups(offer, &mut rtx_ssrcs);
+        assert_eq!(renegotiated.sdp, modified.sdp);
+        assert_eq!(rtx_ssrcs.len(), 1);
+
+        pc.close().await.unwrap();
+    }
+
+    #[test]
+    fn mdns_mode() {
+        assert_eq!(
+            ice_multicast_dns_mode(Some(true)),
+            MulticastDnsMode::QueryAndGather
+        );
+        assert_eq!(
+            ice_multicast_dns_mode(Some(false)),
+            MulticastDnsMode::Disabled
+        );
+
+        // Unset keeps the mode of each build profile
+        let default = if cfg!(debug_assertions) {
+            MulticastDnsMode::Unspecified
+        } else {
+            MulticastDnsMode::QueryAndGather
+        };
+        assert_eq!(ice_multicast_dns_mode(None), default);
+    }
+}
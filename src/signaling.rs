@@ -11,13 +11,21 @@ use webrtc::{
 pub enum Message {
     Sdp(RTCSessionDescription),
     IceCandidate(RTCIceCandidateInit),
+    /// The remote peer has finished gathering ICE candidates.
+    EndOfCandidates,
     Bye,
 }
 
 /// Trait that encapsulates the WebRTC's notion of a signaling channel.
 #[async_trait]
 pub trait Signaler: Send + Sync {
-    /// Blocks until a message is received.
+    /// Called once by `WebRtcBuilder::build` before any message is sent or received. Should
+    /// connect to the signaling server, or otherwise check that the channel works, since an error
+    /// is the only way for the build to fail on a broken channel.
+    async fn connect(&self) -> Result<(), Box<dyn std::error::Error + Send>>;
+
+    /// Blocks until a message is received. The peer is closed after `recv` fails several times
+    /// in a row.
     async fn recv(&self) -> Result<Message, Box<dyn std::error::Error + Send>>;
 
     /// Send a message through the channel.
@@ -33,6 +41,7 @@ mod tests {
         let messages = [
             Message::Sdp(RTCSessionDescription::default()),
             Message::IceCandidate(RTCIceCandidateInit::default()),
+            Message::EndOfCandidates,
             Message::Bye,
         ];
         for message in messages {
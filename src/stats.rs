@@ -0,0 +1,89 @@
+//! A summary of the statistics of a connection for dashboards and logging.
+
+use crate::{interceptor::twcc::TwccPacketStats, network::data_rate::DataRate};
+use serde::{Deserialize, Serialize};
+use webrtc::stats::{StatsReport, StatsReportType};
+
+/// Statistics of a `WebRtcPeer`, serialized by `WebRtcPeer::stats_json`.
+///
+/// The JSON field names are the Rust field names below and only ever get added to. Values that
+/// are not known yet, e.g., the RTT before the first receiver report, are `null`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerStats {
+    /// Current bandwidth estimate in bits per second. `null` if the peer has no encoders.
+    pub bandwidth_estimate_bps: Option<u64>,
+    /// Fraction of the sent packets that the remote peer reported as lost through TWCC feedback,
+    /// from 0 to 1. `null` if the peer has no encoders or no feedback was received yet.
+    pub packet_loss: Option<f64>,
+    /// Round-trip time in milliseconds from the receiver reports of the remote peer, averaged
+    /// across the sent streams. `null` if no receiver report with an RTT was received yet.
+    pub rtt_ms: Option<f64>,
+    /// Type of the local candidate of the nominated ICE candidate pair, i.e., one of `"host"`,
+    /// `"srflx"`, `"prflx"`, or `"relay"`. `null` until a pair is nominated.
+    pub local_candidate_type: Option<String>,
+    /// Type of the remote candidate of the nominated ICE candidate pair.
+    pub remote_candidate_type: Option<String>,
+    /// MIME types of the codecs of the sent and received tracks, e.g., `"video/H264"`, sorted and
+    /// without duplicates.
+    pub codecs: Vec<String>,
+    /// Number of RTCP packets from the remote peer that the bandwidth estimator did not use,
+    /// e.g., sender reports, NACKs, and PLIs. `null` if the peer has no encoders.
+    pub ignored_rtcp_packets: Option<u64>,
+}
+
+impl PeerStats {
+    pub(crate) fn new(
+        report: &StatsReport,
+        bandwidth_estimate: Option<DataRate>,
+        packet_stats: Option<TwccPacketStats>,
+        ignored_rtcp_packets: Option<u64>,
+        mut codecs: Vec<String>,
+    ) -> PeerStats {
+        // Tracks that did not receive anything yet have no codec
+        codecs.retain(|mime_type| !mime_type.is_empty());
+        codecs.sort();
+        codecs.dedup();
+
+        let mut stats = PeerStats {
+            bandwidth_estimate_bps: bandwidth_estimate.map(|rate| rate.bits_per_sec()),
+            packet_loss: packet_stats.and_then(|packet_stats| {
+                let total = packet_stats.received + packet_stats.lost;
+                (total > 0).then(|| packet_stats.lost as f64 / total as f64)
+            }),
+            codecs,
+            ignored_rtcp_packets,
+            ..Default::default()
+        };
+
+        let mut rtts_ms = Vec::new();
+        for stats_type in report.reports.values() {
+            match stats_type {
+                StatsReportType::CandidatePair(pair) if pair.nominated => {
+                    stats.local_candidate_type =
+                        candidate_type(report, &pair.local_candidate_id, true);
+                    stats.remote_candidate_type =
+                        candidate_type(report, &pair.remote_candidate_id, false);
+                }
+                // webrtc-rs reports the RTT in milliseconds
+                StatsReportType::RemoteInboundRTP(remote_inbound) => {
+                    rtts_ms.extend(remote_inbound.round_trip_time);
+                }
+                _ => (),
+            }
+        }
+        if !rtts_ms.is_empty() {
+            stats.rtt_ms = Some(rtts_ms.iter().sum::<f64>() / rtts_ms.len() as f64);
+        }
+        stats
+    }
+}
+
+fn candidate_type(report: &StatsReport, id: &str, local: bool) -> Option<String> {
+    match (report.reports.get(id)?, local) {
+        (StatsReportType::LocalCandidate(candidate), true)
+        | (StatsReportType::RemoteCandidate(candidate), false) => {
+            Some(candidate.candidate_type.to_string())
+        }
+        _ => None,
+    }
+}
@@ -11,14 +11,25 @@ use webrtc_helper::{codecs::CodecType, decoder::DecoderBuilder, Codec, WebRtcPee
 
 pub struct MockDecoderBuilder {
     codecs: Vec<Codec>,
+    required_extensions: Vec<&'static str>,
 }
 
 impl MockDecoderBuilder {
     pub fn new() -> Self {
+        Self::with_codec(super::codec::mock_codec())
+    }
+
+    pub fn with_codec(codec: Codec) -> Self {
         Self {
-            codecs: vec![super::codec::mock_codec()],
+            codecs: vec![codec],
+            required_extensions: Vec::new(),
         }
     }
+
+    pub fn with_required_extension(mut self, uri: &'static str) -> Self {
+        self.required_extensions.push(uri);
+        self
+    }
 }
 
 impl DecoderBuilder for MockDecoderBuilder {
@@ -30,13 +41,23 @@ impl DecoderBuilder for MockDecoderBuilder {
         CodecType::Video
     }
 
+    fn required_extensions(&self) -> &[&str] {
+        &self.required_extensions
+    }
+
     fn build(
         self: Box<Self>,
         track: Arc<TrackRemote>,
-        _rtp_receiver: Arc<RTCRtpReceiver>,
+        rtp_receiver: Arc<RTCRtpReceiver>,
         peer: Arc<WebRtcPeer>,
     ) {
         let handle = tokio::runtime::Handle::current();
+        // Sender reports need to be read for the receiver reports to have the RTT
+        handle.spawn(async move {
+            let mut buf = vec![0u8; 1500];
+            while let Ok((_, _)) = rtp_receiver.read(&mut buf).await {}
+        });
+
         std::thread::spawn(move || {
             handle.block_on(async move {
                 let mut data = Vec::new();
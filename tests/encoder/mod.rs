@@ -1,10 +1,10 @@
 use bytes::Bytes;
 use std::{
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
-    time::{Duration, Instant},
+    time::Duration,
 };
 use webrtc::{
     ice_transport::ice_connection_state::RTCIceConnectionState,
@@ -13,27 +13,40 @@ use webrtc::{
         packet::Packet,
         sequence::{new_random_sequencer, Sequencer},
     },
-    rtp_transceiver::{rtp_codec::RTCRtpCodecCapability, RTCRtpTransceiver},
-    track::track_local::{track_local_static_rtp::TrackLocalStaticRTP, TrackLocalWriter},
+    track::track_local::TrackLocalWriter,
 };
 use webrtc_helper::{
     codecs::{Codec, CodecType},
-    encoder::EncoderBuilder,
+    encoder::{EncoderBuilder, EncoderContext, MediaClock},
     interceptor::twcc::TwccBandwidthEstimate,
     network::data_rate::DataRate,
-    peer::IceConnectionState,
 };
 
 pub struct MockEncoderBuilder {
     codecs: Vec<Codec>,
+    clock_rate: Arc<AtomicU32>,
 }
 
 impl MockEncoderBuilder {
     pub fn new() -> Self {
+        Self::with_codec(super::codec::mock_codec())
+    }
+
+    pub fn with_codec(codec: Codec) -> Self {
+        Self::with_codecs(vec![codec])
+    }
+
+    pub fn with_codecs(codecs: Vec<Codec>) -> Self {
         Self {
-            codecs: vec![super::codec::mock_codec()],
+            codecs,
+            clock_rate: Arc::new(AtomicU32::new(0)),
         }
     }
+
+    /// Clock rate the encoder computes its timestamps with. This is 0 until the encoder is built.
+    pub fn clock_rate(&self) -> Arc<AtomicU32> {
+        self.clock_rate.clone()
+    }
 }
 
 impl EncoderBuilder for MockEncoderBuilder {
@@ -53,19 +66,23 @@ impl EncoderBuilder for MockEncoderBuilder {
         &self.codecs
     }
 
-    fn build(
-        self: Box<Self>,
-        rtp_track: Arc<TrackLocalStaticRTP>,
-        transceiver: Arc<RTCRtpTransceiver>,
-        mut ice_connection_state: IceConnectionState,
-        bandwidth_estimate: TwccBandwidthEstimate,
-        codec_capability: RTCRtpCodecCapability,
-        ssrc: u32,
-        payload_type: u8,
-    ) {
+    fn build(self: Box<Self>, context: EncoderContext) {
+        let EncoderContext {
+            rtp_track,
+            transceiver,
+            mut ice_connection_state,
+            bandwidth_estimate,
+            mut track_enabled,
+            codec_capability,
+            ssrc,
+            payload_type,
+            ..
+        } = context;
         if !self.is_codec_supported(&codec_capability) {
             panic!("Codec not supported");
         }
+        self.clock_rate
+            .store(codec_capability.clock_rate, Ordering::Release);
 
         let stopped = Arc::new(AtomicBool::new(false));
         let stopper = stopped.clone();
@@ -112,13 +129,24 @@ impl EncoderBuilder for MockEncoderBuilder {
                 }
 
                 const MTU: usize = 1200;
+                const FPS: u32 = 60;
                 const FRAME_INTERVAL_60FPS: Duration = Duration::from_nanos(16_666_667);
 
                 let mut interval = tokio::time::interval(FRAME_INTERVAL_60FPS);
-                let mut encoder = MockEncoder::new(bandwidth_estimate, ssrc, payload_type);
+                let mut encoder = MockEncoder::new(
+                    bandwidth_estimate,
+                    MediaClock::new(codec_capability.clock_rate, FPS),
+                    ssrc,
+                    payload_type,
+                );
 
                 while *ice_connection_state.borrow() == RTCIceConnectionState::Connected {
                     interval.tick().await;
+                    if !track_enabled.is_enabled()
+                        && track_enabled.wait_until_enabled().await.is_err()
+                    {
+                        break;
+                    }
                     for packet in encoder.packets(MTU, FRAME_INTERVAL_60FPS) {
                         if let Err(e) = rtp_track.write_rtp(packet).await {
                             panic!("{e}")
@@ -138,7 +166,8 @@ fn dummy_packets(ssrc: u32, payload_type: u8) -> Vec<Packet> {
     const NUM_PACKETS: usize = 1_666_667;
     let mut packets = Vec::with_capacity(NUM_PACKETS);
 
-    let data = [42u8; 1200 - 12];
+    // Shared by all the packets
+    let data = Bytes::from_static(&[42u8; 1200 - 12]);
 
     for _ in 0..NUM_PACKETS {
         let header = Header {
@@ -152,8 +181,10 @@ fn dummy_packets(ssrc: u32, payload_type: u8) -> Vec<Packet> {
             ssrc,
             ..Default::default()
         };
-        let payload = Bytes::copy_from_slice(&data);
-        let packet = Packet { header, payload };
+        let packet = Packet {
+            header,
+            payload: data.clone(),
+        };
         packets.push(packet);
     }
 
@@ -162,20 +193,23 @@ fn dummy_packets(ssrc: u32, payload_type: u8) -> Vec<Packet> {
 
 pub struct MockEncoder {
     sequencer: Box<dyn Sequencer + Send + Sync>,
-    start: (Instant, u32),
-    clock_rate: u64,
+    media_clock: MediaClock,
     bandwidth_estimate: TwccBandwidthEstimate,
     data_rate: DataRate,
     packets: Vec<Packet>,
 }
 
 impl MockEncoder {
-    fn new(bandwidth_estimate: TwccBandwidthEstimate, ssrc: u32, payload_type: u8) -> MockEncoder {
+    fn new(
+        bandwidth_estimate: TwccBandwidthEstimate,
+        media_clock: MediaClock,
+        ssrc: u32,
+        payload_type: u8,
+    ) -> MockEncoder {
         let data_rate = *bandwidth_estimate.borrow();
         MockEncoder {
             sequencer: Box::new(new_random_sequencer()),
-            start: (Instant::now(), 0),
-            clock_rate: 90000,
+            media_clock,
             bandwidth_estimate,
             data_rate,
             packets: dummy_packets(ssrc, payload_type),
@@ -190,18 +224,11 @@ impl MockEncoder {
         let payload_total_bytes = self.data_rate.bytes_per_sec_f64() * frame_interval.as_secs_f64();
         let num_packets = (payload_total_bytes as usize) / (mtu - 12);
         if num_packets == 0 {
+            self.media_clock.skip_frames(1);
             return &[];
         }
 
-        let timestamp = {
-            let duration = Instant::now().duration_since(self.start.0).as_micros() as u64;
-            // ticks is unitless:
-            // duration [us] * clock_rate [1/s] / 1_000_000 [us/s]
-            let ticks = duration
-                .wrapping_mul(self.clock_rate)
-                .wrapping_div(1_000_000);
-            self.start.1.wrapping_add(ticks as u32)
-        };
+        let timestamp = self.media_clock.next_frame();
 
         for packet in &mut self.packets[..num_packets] {
             packet.header.sequence_number = self.sequencer.next_sequence_number();
@@ -0,0 +1,143 @@
+use async_trait::async_trait;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use webrtc::{
+    interceptor::{
+        self, stream_info::StreamInfo, Attributes, Interceptor, InterceptorBuilder, RTCPReader,
+        RTCPWriter, RTPReader, RTPWriter,
+    },
+    rtp, sdp,
+};
+
+/// Number of RTP packets that went through a `CountingInterceptor`.
+#[derive(Default)]
+pub struct PacketCounts {
+    pub sent: AtomicUsize,
+    /// Sent packets that had the transport-cc extension set.
+    pub sent_with_transport_cc: AtomicUsize,
+    pub received: AtomicUsize,
+}
+
+pub struct CountingInterceptorBuilder {
+    counts: Arc<PacketCounts>,
+}
+
+impl CountingInterceptorBuilder {
+    pub fn new() -> (Self, Arc<PacketCounts>) {
+        let counts = Arc::new(PacketCounts::default());
+        let builder = Self {
+            counts: counts.clone(),
+        };
+        (builder, counts)
+    }
+}
+
+impl InterceptorBuilder for CountingInterceptorBuilder {
+    fn build(&self, _id: &str) -> Result<Arc<dyn Interceptor + Send + Sync>, interceptor::Error> {
+        Ok(Arc::new(CountingInterceptor {
+            counts: self.counts.clone(),
+        }))
+    }
+}
+
+struct CountingInterceptor {
+    counts: Arc<PacketCounts>,
+}
+
+#[async_trait]
+impl Interceptor for CountingInterceptor {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        reader
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn bind_local_stream(
+        &self,
+        info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        let transport_cc_id = info
+            .rtp_header_extensions
+            .iter()
+            .find(|ext| ext.uri == sdp::extmap::TRANSPORT_CC_URI)
+            .map(|ext| ext.id as u8);
+        Arc::new(CountingWriter {
+            counts: self.counts.clone(),
+            transport_cc_id,
+            next_writer: writer,
+        })
+    }
+
+    async fn unbind_local_stream(&self, _info: &StreamInfo) {}
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        Arc::new(CountingReader {
+            counts: self.counts.clone(),
+            next_reader: reader,
+        })
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> Result<(), interceptor::Error> {
+        Ok(())
+    }
+}
+
+struct CountingWriter {
+    counts: Arc<PacketCounts>,
+    transport_cc_id: Option<u8>,
+    next_writer: Arc<dyn RTPWriter + Send + Sync>,
+}
+
+#[async_trait]
+impl RTPWriter for CountingWriter {
+    async fn write(
+        &self,
+        pkt: &rtp::packet::Packet,
+        attributes: &Attributes,
+    ) -> Result<usize, interceptor::Error> {
+        self.counts.sent.fetch_add(1, Ordering::Relaxed);
+        if let Some(id) = self.transport_cc_id {
+            if pkt.header.get_extension(id).is_some() {
+                self.counts
+                    .sent_with_transport_cc
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.next_writer.write(pkt, attributes).await
+    }
+}
+
+struct CountingReader {
+    counts: Arc<PacketCounts>,
+    next_reader: Arc<dyn RTPReader + Send + Sync>,
+}
+
+#[async_trait]
+impl RTPReader for CountingReader {
+    async fn read(
+        &self,
+        buf: &mut [u8],
+        attributes: &Attributes,
+    ) -> Result<(usize, Attributes), interceptor::Error> {
+        let result = self.next_reader.read(buf, attributes).await?;
+        self.counts.received.fetch_add(1, Ordering::Relaxed);
+        Ok(result)
+    }
+}
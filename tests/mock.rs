@@ -1,12 +1,52 @@
 mod codec;
 mod decoder;
 mod encoder;
+mod interceptor;
 mod signaling;
 
-use self::{decoder::MockDecoderBuilder, encoder::MockEncoderBuilder, signaling::MockSignaler};
-use std::{sync::Arc, time::Duration};
+use self::{
+    decoder::MockDecoderBuilder,
+    encoder::MockEncoderBuilder,
+    interceptor::CountingInterceptorBuilder,
+    signaling::{BrokenSignaler, FlakySignaler, LossySignaler, MockSignaler},
+};
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 use tokio::sync::Notify;
-use webrtc_helper::peer::{Role, WebRtcBuilder};
+use webrtc::{
+    api::{
+        media_engine::{MediaEngine, MIME_TYPE_H264, MIME_TYPE_VP8},
+        APIBuilder,
+    },
+    dtls_transport::dtls_transport_state::RTCDtlsTransportState,
+    ice_transport::{
+        ice_candidate::RTCIceCandidateInit, ice_connection_state::RTCIceConnectionState,
+        ice_server::RTCIceServer,
+    },
+    peer_connection::{
+        configuration::RTCConfiguration,
+        sdp::{sdp_type::RTCSdpType, session_description::RTCSessionDescription},
+        signaling_state::RTCSignalingState,
+    },
+    rtp_transceiver::{
+        rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType},
+        rtp_transceiver_direction::RTCRtpTransceiverDirection,
+        RTCPFeedback, RTCRtpTransceiverInit,
+    },
+    track::track_local::track_local_static_rtp::TrackLocalStaticRTP,
+};
+use webrtc_helper::{
+    codecs::{
+        h264::{H264Codec, H264Profile},
+        Codec, CodecType,
+    },
+    encoder::PassthroughEncoderBuilder,
+    network::chunked_data_channel::{ChunkedDataChannel, SCTP_MAX_MESSAGE_SIZE},
+    peer::{InterceptorPosition, Role, TransceiverInfo, WebRtcBuilder},
+    signaling::{Message, Signaler},
+};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn mock_test() {
@@ -43,3 +83,1280 @@ async fn mock_test() {
 
     tokio::time::sleep(Duration::from_secs(1)).await;
 }
+
+#[tokio::test]
+async fn end_of_candidates() {
+    let (encoder_signaler, remote_signaler) = MockSignaler::channel();
+
+    let mut encoder_builder = WebRtcBuilder::new(encoder_signaler, Role::Offerer);
+    encoder_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let encoder = encoder_builder.build().await.unwrap();
+
+    // Plain webrtc-rs peer on the other end of the signaling channel
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs().unwrap();
+    let api = APIBuilder::new().with_media_engine(media_engine).build();
+    let pc = api
+        .new_peer_connection(RTCConfiguration::default())
+        .await
+        .unwrap();
+
+    let received_end_of_candidates = tokio::time::timeout(Duration::from_secs(30), async {
+        loop {
+            match remote_signaler.recv().await.unwrap() {
+                Message::Sdp(sdp) => pc.set_remote_description(sdp).await.unwrap(),
+                Message::IceCandidate(candidate) => pc.add_ice_candidate(candidate).await.unwrap(),
+                Message::EndOfCandidates => {
+                    // Accepted as the end-of-candidates marker
+                    pc.add_ice_candidate(RTCIceCandidateInit::default())
+                        .await
+                        .unwrap();
+                    break;
+                }
+                Message::Bye => panic!("Unexpected bye"),
+            }
+        }
+    })
+    .await;
+    assert!(received_end_of_candidates.is_ok());
+
+    encoder.close().await;
+    pc.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn closed_peers_are_released() {
+    const NUM_PEERS: usize = 20;
+
+    let mut peers = Vec::new();
+    // Keep the other ends alive so that the signaling channels stay open
+    let mut remote_signalers = Vec::new();
+    for _ in 0..NUM_PEERS {
+        let (signaler, remote_signaler) = MockSignaler::channel();
+        let peer = WebRtcBuilder::new(signaler, Role::Answerer)
+            .build()
+            .await
+            .unwrap();
+        peers.push(Arc::downgrade(&peer));
+        peer.close().await;
+        remote_signalers.push(remote_signaler);
+    }
+
+    // Give the aborted tasks a chance to be dropped
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Nothing else holds on to the peers, including the signaling tasks
+    assert!(peers.iter().all(|peer| peer.upgrade().is_none()));
+}
+
+#[tokio::test]
+async fn close_and_wait() {
+    let (signaler, remote_signaler) = MockSignaler::channel();
+    let peer = WebRtcBuilder::new(signaler, Role::Answerer)
+        .build()
+        .await
+        .unwrap();
+    let remote_peer = WebRtcBuilder::new(remote_signaler, Role::Answerer)
+        .build()
+        .await
+        .unwrap();
+
+    // The remote observes the Bye and replies with its own
+    let (acknowledged, _) = tokio::join!(
+        peer.close_and_wait(Duration::from_secs(5)),
+        remote_peer.is_closed()
+    );
+    assert!(acknowledged);
+}
+
+#[tokio::test]
+async fn close_and_wait_timeout() {
+    let (signaler, remote_signaler) = MockSignaler::channel();
+    let peer = WebRtcBuilder::new(signaler, Role::Answerer)
+        .build()
+        .await
+        .unwrap();
+
+    // Nothing on the other end replies
+    assert!(!peer.close_and_wait(Duration::from_millis(100)).await);
+    assert!(matches!(remote_signaler.recv().await, Ok(Message::Bye)));
+}
+
+#[tokio::test]
+async fn auto_role() {
+    let (encoder_signaler, decoder_signaler) = MockSignaler::channel();
+
+    let mut encoder_builder = WebRtcBuilder::new(encoder_signaler, Role::Auto);
+    encoder_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let mut decoder_builder = WebRtcBuilder::new(decoder_signaler, Role::Auto);
+    decoder_builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+
+    let (encoder, decoder) = tokio::join!(encoder_builder.build(), decoder_builder.build());
+    let (encoder, decoder) = (encoder.unwrap(), decoder.unwrap());
+
+    // Exactly one of them times out first and sends the offer
+    let roles = [encoder.role(), decoder.role()];
+    assert!(roles.contains(&Role::Offerer));
+    assert!(roles.contains(&Role::Answerer));
+
+    encoder.close().await;
+    decoder.close().await;
+}
+
+#[tokio::test]
+async fn custom_clock_rate() {
+    const CLOCK_RATE: u32 = 30000;
+
+    let codec = codec::mock_codec().with_clock_rate(CLOCK_RATE).unwrap();
+    let (encoder_signaler, decoder_signaler) = MockSignaler::channel();
+
+    let encoder_builder = MockEncoderBuilder::with_codec(codec.clone());
+    let clock_rate = encoder_builder.clock_rate();
+    let mut builder = WebRtcBuilder::new(encoder_signaler, Role::Offerer);
+    builder.with_encoder(Box::new(encoder_builder));
+    let encoder = builder.build().await.unwrap();
+
+    let mut builder = WebRtcBuilder::new(decoder_signaler, Role::Answerer);
+    builder.with_decoder(Box::new(MockDecoderBuilder::with_codec(codec)));
+    let decoder = builder.build().await.unwrap();
+
+    // The encoder is built once the codec has been negotiated
+    let negotiated = tokio::time::timeout(Duration::from_secs(10), async {
+        while clock_rate.load(Ordering::Acquire) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert!(negotiated.is_ok());
+    assert_eq!(clock_rate.load(Ordering::Acquire), CLOCK_RATE);
+
+    encoder.close().await;
+    decoder.close().await;
+}
+
+#[tokio::test]
+async fn duplicate_offer() {
+    let (signaler, remote_signaler) = MockSignaler::channel();
+    let peer = WebRtcBuilder::new(signaler, Role::Answerer)
+        .build()
+        .await
+        .unwrap();
+
+    // Plain webrtc-rs peer on the other end of the signaling channel
+    let api = APIBuilder::new().build();
+    let pc = api
+        .new_peer_connection(RTCConfiguration::default())
+        .await
+        .unwrap();
+    pc.create_data_channel("data", None).await.unwrap();
+    let offer = pc.create_offer(None).await.unwrap();
+    pc.set_local_description(offer.clone()).await.unwrap();
+
+    // Re-delivered by the signaling transport
+    remote_signaler
+        .send(Message::Sdp(offer.clone()))
+        .await
+        .unwrap();
+    remote_signaler.send(Message::Sdp(offer)).await.unwrap();
+
+    let mut num_answers = 0;
+    let _ = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            if let Message::Sdp(sdp) = remote_signaler.recv().await.unwrap() {
+                assert_eq!(sdp.sdp_type, RTCSdpType::Answer);
+                num_answers += 1;
+            }
+        }
+    })
+    .await;
+    assert_eq!(num_answers, 1);
+
+    peer.close().await;
+    pc.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn conflicting_payload_types() {
+    let (signaler, _remote_signaler) = MockSignaler::channel();
+
+    // Would otherwise be registered at the same static payload type as PCMA
+    let mut codec = codec::mock_codec();
+    codec.set_payload_type(8);
+
+    let mut builder = WebRtcBuilder::new(signaler, Role::Answerer);
+    builder
+        .with_encoder(Box::new(MockEncoderBuilder::with_codec(Codec::pcma())))
+        .with_decoder(Box::new(MockDecoderBuilder::with_codec(codec)));
+    let err = builder.build().await.err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Codecs audio/PCMA and video/mock both use the payload type 8"
+    );
+
+    // The same codec for sending and receiving is not a conflict
+    let (signaler, _remote_signaler) = MockSignaler::channel();
+    let mut builder = WebRtcBuilder::new(signaler, Role::Answerer);
+    builder
+        .with_encoder(Box::new(MockEncoderBuilder::with_codec(Codec::pcmu())))
+        .with_decoder(Box::new(MockDecoderBuilder::with_codec(Codec::pcmu())));
+    let pcmu: Vec<_> = builder
+        .available_codecs()
+        .into_iter()
+        .filter(|codec| *codec == Codec::pcmu())
+        .collect();
+    assert_eq!(pcmu.len(), 1);
+    let peer = builder.build().await.unwrap();
+    peer.close().await;
+}
+
+#[tokio::test]
+async fn ice_gather_timeout() {
+    const GATHER_TIMEOUT: Duration = Duration::from_millis(500);
+
+    let (signaler, remote_signaler) = MockSignaler::channel();
+
+    // Non-routable address that never answers, so the STUN gathering only ends on its own
+    // after the 5 s timeout of webrtc-ice
+    let mut builder = WebRtcBuilder::new(signaler, Role::Offerer);
+    builder
+        .with_encoder(Box::new(MockEncoderBuilder::new()))
+        .with_ice_servers(&[RTCIceServer {
+            urls: vec!["stun:10.255.255.1:3478".to_owned()],
+            ..Default::default()
+        }])
+        .with_ice_gather_timeout(GATHER_TIMEOUT);
+    let peer = builder.build().await.unwrap();
+
+    let gathered = tokio::time::timeout(Duration::from_secs(2), async {
+        while !matches!(remote_signaler.recv().await, Ok(Message::EndOfCandidates)) {}
+    })
+    .await;
+    assert!(gathered.is_ok());
+
+    peer.close().await;
+}
+
+#[tokio::test]
+async fn renegotiate_codecs() {
+    /// fmtp of the most preferred codec of the first video section.
+    fn preferred_video_fmtp(sdp: &RTCSessionDescription) -> Option<String> {
+        let mut lines = sdp.sdp.lines();
+        let payload_type = lines
+            .find(|line| line.starts_with("m=video"))?
+            .split_whitespace()
+            .nth(3)?;
+        let prefix = format!("a=fmtp:{payload_type} ");
+        lines
+            .find_map(|line| line.strip_prefix(&prefix))
+            .map(str::to_owned)
+    }
+
+    let baseline: Codec = H264Codec::constrained_baseline().into();
+    // Level 5 like the default codec of webrtc-rs
+    let high: Codec = H264Codec::new(H264Profile::High).with_level(0x32).into();
+    let (signaler, remote_signaler) = MockSignaler::channel();
+
+    let mut builder = WebRtcBuilder::new(signaler, Role::Offerer);
+    builder.with_encoder(Box::new(MockEncoderBuilder::with_codecs(vec![
+        baseline.clone(),
+        high.clone(),
+    ])));
+    let peer = builder.build().await.unwrap();
+
+    // Plain webrtc-rs peer on the other end of the signaling channel
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs().unwrap();
+    let api = APIBuilder::new().with_media_engine(media_engine).build();
+    let pc = api
+        .new_peer_connection(RTCConfiguration::default())
+        .await
+        .unwrap();
+
+    let answer_next_offer = || async {
+        loop {
+            match remote_signaler.recv().await.unwrap() {
+                Message::Sdp(offer) => {
+                    pc.set_remote_description(offer.clone()).await.unwrap();
+                    let answer = pc.create_answer(None).await.unwrap();
+                    pc.set_local_description(answer.clone()).await.unwrap();
+                    remote_signaler.send(Message::Sdp(answer)).await.unwrap();
+                    break offer;
+                }
+                Message::IceCandidate(candidate) => pc.add_ice_candidate(candidate).await.unwrap(),
+                _ => (),
+            }
+        }
+    };
+
+    let offer = tokio::time::timeout(Duration::from_secs(10), answer_next_offer())
+        .await
+        .unwrap();
+    assert_eq!(
+        preferred_video_fmtp(&offer).as_deref(),
+        Some(baseline.sdp_fmtp_line())
+    );
+
+    // Wait for the answer to be applied before offering again
+    while peer.signaling_state() != RTCSignalingState::Stable
+        || peer.remote_description().await.is_none()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    peer.renegotiate_codecs("mock-video", std::slice::from_ref(&high))
+        .await
+        .unwrap();
+    let offer = tokio::time::timeout(Duration::from_secs(10), answer_next_offer())
+        .await
+        .unwrap();
+    assert_eq!(
+        preferred_video_fmtp(&offer).as_deref(),
+        Some(high.sdp_fmtp_line())
+    );
+
+    // Only the registered codecs can be negotiated
+    let main: Codec = H264Codec::new(H264Profile::Main).into();
+    assert!(peer
+        .renegotiate_codecs("mock-video", &[main])
+        .await
+        .is_err());
+
+    peer.close().await;
+    pc.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn decoder_required_extensions() {
+    const VIDEO_ORIENTATION_URI: &str = "urn:3gpp:video-orientation";
+
+    async fn first_offer(decoder: MockDecoderBuilder) -> String {
+        let (signaler, remote_signaler) = MockSignaler::channel();
+        let mut builder = WebRtcBuilder::new(signaler, Role::Offerer);
+        builder.with_decoder(Box::new(decoder));
+        let peer = builder.build().await.unwrap();
+
+        let offer = loop {
+            if let Message::Sdp(offer) = remote_signaler.recv().await.unwrap() {
+                break offer;
+            }
+        };
+        peer.close().await;
+        offer.sdp
+    }
+
+    let offer = first_offer(MockDecoderBuilder::new()).await;
+    assert!(!offer.contains(VIDEO_ORIENTATION_URI));
+
+    let decoder = MockDecoderBuilder::new().with_required_extension(VIDEO_ORIENTATION_URI);
+    let offer = first_offer(decoder).await;
+    assert!(offer
+        .lines()
+        .any(|line| line.starts_with("a=extmap:") && line.ends_with(VIDEO_ORIENTATION_URI)));
+}
+
+#[tokio::test]
+async fn non_trickle_ice() {
+    let (encoder_signaler, encoder_relay) = MockSignaler::channel();
+    let (decoder_signaler, decoder_relay) = MockSignaler::channel();
+
+    let mut encoder_builder = WebRtcBuilder::new(encoder_signaler, Role::Offerer);
+    encoder_builder
+        .with_encoder(Box::new(MockEncoderBuilder::new()))
+        .with_trickle_ice(false);
+    let encoder = encoder_builder.build().await.unwrap();
+
+    let mut decoder_builder = WebRtcBuilder::new(decoder_signaler, Role::Answerer);
+    decoder_builder
+        .with_decoder(Box::new(MockDecoderBuilder::new()))
+        .with_trickle_ice(false);
+    let decoder = decoder_builder.build().await.unwrap();
+
+    // Relays the messages between the peers, keeping a copy of each
+    let (relayed_tx, mut relayed_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (msg, to) = tokio::select! {
+                Ok(msg) = encoder_relay.recv() => (msg, &decoder_relay),
+                Ok(msg) = decoder_relay.recv() => (msg, &encoder_relay),
+                else => break,
+            };
+            let _ = relayed_tx.send(msg.clone());
+            let _ = to.send(msg).await;
+        }
+    });
+
+    let connected = tokio::time::timeout(Duration::from_secs(10), async {
+        while encoder.ice_connection_state() != RTCIceConnectionState::Connected {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    assert!(connected.is_ok());
+
+    // Only the offer and the answer, each with the candidates
+    let mut sdp_types = Vec::new();
+    while let Ok(msg) = relayed_rx.try_recv() {
+        match msg {
+            Message::Sdp(sdp) => {
+                assert!(sdp.sdp.contains("a=candidate:"));
+                sdp_types.push(sdp.sdp_type);
+            }
+            msg => panic!("Unexpected {msg:?}"),
+        }
+    }
+    assert_eq!(sdp_types, [RTCSdpType::Offer, RTCSdpType::Answer]);
+
+    encoder.close().await;
+    decoder.close().await;
+}
+
+#[tokio::test]
+async fn dtls_state_change() {
+    let (encoder_signaler, decoder_signaler) = MockSignaler::channel();
+
+    let mut encoder_builder = WebRtcBuilder::new(encoder_signaler, Role::Offerer);
+    encoder_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let encoder = encoder_builder.build().await.unwrap();
+
+    let (state_tx, mut state_rx) = tokio::sync::mpsc::unbounded_channel();
+    encoder.on_dtls_state_change(Box::new(move |state| {
+        let _ = state_tx.send(state);
+        Box::pin(async {})
+    }));
+
+    let mut decoder_builder = WebRtcBuilder::new(decoder_signaler, Role::Answerer);
+    decoder_builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let decoder = decoder_builder.build().await.unwrap();
+
+    let mut states = Vec::new();
+    let handshake = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(state) = state_rx.recv().await {
+            states.push(state);
+            if state == RTCDtlsTransportState::Connected {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(handshake.is_ok());
+    assert_eq!(
+        states,
+        [
+            RTCDtlsTransportState::Connecting,
+            RTCDtlsTransportState::Connected
+        ]
+    );
+
+    encoder.close().await;
+    decoder.close().await;
+}
+
+#[tokio::test]
+async fn stats_json() {
+    let (encoder_signaler, decoder_signaler) = MockSignaler::channel();
+
+    let mut encoder_builder = WebRtcBuilder::new(encoder_signaler, Role::Offerer);
+    encoder_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let encoder = encoder_builder.build().await.unwrap();
+
+    let mut decoder_builder = WebRtcBuilder::new(decoder_signaler, Role::Answerer);
+    decoder_builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let decoder = decoder_builder.build().await.unwrap();
+
+    // The RTT comes with the first receiver report of the decoder
+    let json = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let json = encoder.stats_json().await;
+            let stats: serde_json::Value = serde_json::from_str(&json).unwrap();
+            if !stats["rtt_ms"].is_null() && !stats["packet_loss"].is_null() {
+                break stats;
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    assert!(json["bandwidth_estimate_bps"].as_u64().unwrap() > 0);
+    let packet_loss = json["packet_loss"].as_f64().unwrap();
+    assert!((0.0..=1.0).contains(&packet_loss));
+    let rtt_ms = json["rtt_ms"].as_f64().unwrap();
+    assert!((0.0..1000.0).contains(&rtt_ms));
+    assert_eq!(json["local_candidate_type"], "host");
+    assert_eq!(json["remote_candidate_type"], "host");
+    assert_eq!(json["codecs"], serde_json::json!(["video/mock"]));
+    assert!(json["ignored_rtcp_packets"].is_u64());
+
+    // Without encoders there is no bandwidth estimator
+    let json = decoder.stats_json().await;
+    let stats: webrtc_helper::PeerStats = serde_json::from_str(&json).unwrap();
+    assert_eq!(stats.bandwidth_estimate_bps, None);
+    assert_eq!(stats.packet_loss, None);
+    assert_eq!(stats.ignored_rtcp_packets, None);
+    assert_eq!(stats.codecs, ["video/mock"]);
+
+    encoder.close().await;
+    decoder.close().await;
+}
+
+#[tokio::test]
+async fn chunked_data_channel() {
+    let (offerer_signaler, answerer_signaler) = MockSignaler::channel();
+    let message: Vec<u8> = (0..3 * SCTP_MAX_MESSAGE_SIZE + 123)
+        .map(|i| i as u8)
+        .collect();
+
+    let (sent_tx, mut sent_rx) = tokio::sync::mpsc::unbounded_channel();
+    let sent_message = message.clone();
+    let mut offerer_builder = WebRtcBuilder::new(offerer_signaler, Role::Offerer);
+    offerer_builder.with_data_channel_handler(Box::new(move |data_channel| {
+        let sent_tx = sent_tx.clone();
+        let sent_message = sent_message.clone();
+        let on_open_channel = data_channel.clone();
+        Box::pin(async move {
+            data_channel.on_open(Box::new(move || {
+                Box::pin(async move {
+                    let data_channel = on_open_channel.detach().await.unwrap();
+                    // Too large to be sent as is
+                    let too_large = data_channel.write(&sent_message.clone().into()).await;
+                    assert!(too_large.is_err());
+
+                    let data_channel =
+                        ChunkedDataChannel::new(data_channel).with_max_message_size(16384);
+                    // Then one byte over the limit of the other side and a small message
+                    let mut too_large = sent_message.clone();
+                    too_large.push(0);
+                    let mut sent = data_channel.send(&sent_message).await.is_ok();
+                    sent &= data_channel.send(&too_large).await.is_ok();
+                    sent &= data_channel.send(b"small").await.is_ok();
+                    let _ = sent_tx.send(sent);
+                    // Keep the data channel open until the message is received
+                    std::future::pending::<()>().await;
+                })
+            }));
+        })
+    }));
+    let offerer = offerer_builder.build().await.unwrap();
+
+    let (received_tx, mut received_rx) = tokio::sync::mpsc::unbounded_channel();
+    let max_received_size = message.len();
+    let mut answerer_builder = WebRtcBuilder::new(answerer_signaler, Role::Answerer);
+    answerer_builder.with_data_channel_handler(Box::new(move |data_channel| {
+        let received_tx = received_tx.clone();
+        let on_open_channel = data_channel.clone();
+        Box::pin(async move {
+            data_channel.on_open(Box::new(move || {
+                Box::pin(async move {
+                    let data_channel = on_open_channel.detach().await.unwrap();
+                    let mut data_channel = ChunkedDataChannel::new(data_channel)
+                        .with_max_received_size(max_received_size);
+                    for _ in 0..3 {
+                        let _ = received_tx.send(data_channel.recv().await);
+                    }
+                })
+            }));
+        })
+    }));
+    let answerer = answerer_builder.build().await.unwrap();
+
+    let received = tokio::time::timeout(Duration::from_secs(10), async {
+        assert!(sent_rx.recv().await.unwrap());
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(received_rx.recv().await.unwrap());
+        }
+        received
+    })
+    .await
+    .unwrap();
+    let mut received = received.into_iter();
+    assert!(received.next().unwrap().unwrap().unwrap() == message);
+    assert!(received.next().unwrap().is_err());
+    assert_eq!(received.next().unwrap().unwrap().unwrap(), b"small");
+
+    offerer.close().await;
+    answerer.close().await;
+}
+
+#[tokio::test]
+async fn sdp_send_retries() {
+    let (signaler, remote_signaler) = MockSignaler::channel();
+    let signaler = FlakySignaler::new(signaler, 2);
+
+    let mut offerer_builder = WebRtcBuilder::new(signaler, Role::Offerer);
+    offerer_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let offerer = offerer_builder.build().await.unwrap();
+
+    // The offer is delivered on the third attempt, after the ICE candidates sent in the meantime
+    let offer = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Message::Sdp(sdp) = remote_signaler.recv().await.unwrap() {
+                break sdp;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(offer.sdp_type, RTCSdpType::Offer);
+    offerer.close().await;
+
+    // Same with the answer, and with the candidates arriving ahead of the descriptions on both
+    // sides
+    let (offerer_signaler, answerer_signaler) = MockSignaler::channel();
+    let mut offerer_builder =
+        WebRtcBuilder::new(FlakySignaler::new(offerer_signaler, 2), Role::Offerer);
+    offerer_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let offerer = offerer_builder.build().await.unwrap();
+    let mut answerer_builder =
+        WebRtcBuilder::new(FlakySignaler::new(answerer_signaler, 2), Role::Answerer);
+    answerer_builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let answerer = answerer_builder.build().await.unwrap();
+
+    let connected = tokio::time::timeout(Duration::from_secs(10), async {
+        while offerer.ice_connection_state() != RTCIceConnectionState::Connected
+            || answerer.ice_connection_state() != RTCIceConnectionState::Connected
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    assert!(connected.is_ok());
+
+    offerer.close().await;
+    answerer.close().await;
+}
+
+#[tokio::test]
+async fn broken_signaler() {
+    let mut offerer_builder = WebRtcBuilder::new(BrokenSignaler, Role::Offerer);
+    offerer_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let err = offerer_builder.build().await.err().unwrap();
+    assert!(
+        err.to_string()
+            .starts_with("Failed to connect the signaler"),
+        "{err}"
+    );
+
+    let mut answerer_builder = WebRtcBuilder::new(BrokenSignaler, Role::Answerer);
+    answerer_builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let err = answerer_builder.build().await.err().unwrap();
+    assert!(
+        err.to_string()
+            .starts_with("Failed to connect the signaler"),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn signaler_recv_retries() {
+    // Failed receives are retried instead of ending the signaling
+    let (offerer_signaler, answerer_signaler) = MockSignaler::channel();
+    let offerer_signaler = FlakySignaler::new(offerer_signaler, 0).with_recv_failures(2);
+    let mut offerer_builder = WebRtcBuilder::new(offerer_signaler, Role::Offerer);
+    offerer_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let offerer = offerer_builder.build().await.unwrap();
+    let answerer_signaler = FlakySignaler::new(answerer_signaler, 0).with_recv_failures(2);
+    let mut answerer_builder = WebRtcBuilder::new(answerer_signaler, Role::Answerer);
+    answerer_builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let answerer = answerer_builder.build().await.unwrap();
+
+    let connected = tokio::time::timeout(Duration::from_secs(10), async {
+        while offerer.ice_connection_state() != RTCIceConnectionState::Connected
+            || answerer.ice_connection_state() != RTCIceConnectionState::Connected
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    assert!(connected.is_ok());
+
+    offerer.close().await;
+    answerer.close().await;
+}
+
+#[tokio::test]
+async fn signaler_recv_gives_up() {
+    // Five failed receives in a row close the peer instead of retrying forever
+    let (signaler, _remote_signaler) = MockSignaler::channel();
+    let signaler = FlakySignaler::new(signaler, 0).with_recv_failures(5);
+    let mut builder = WebRtcBuilder::new(signaler, Role::Answerer);
+    builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let peer = builder.build().await.unwrap();
+
+    let closed = tokio::time::timeout(Duration::from_secs(10), peer.is_closed()).await;
+    assert!(closed.is_ok());
+}
+
+#[tokio::test]
+async fn nothing_to_offer() {
+    let (signaler, _remote_signaler) = MockSignaler::channel();
+    let err = WebRtcBuilder::new(signaler, Role::Offerer)
+        .build()
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Nothing to offer without an encoder, a decoder, or a data channel handler"
+    );
+
+    // A data channel handler alone is enough
+    let (signaler, remote_signaler) = MockSignaler::channel();
+    let mut builder = WebRtcBuilder::new(signaler, Role::Offerer);
+    builder.with_data_channel_handler(Box::new(|_| Box::pin(async {})));
+    let peer = builder.build().await.unwrap();
+    let offer = loop {
+        if let Message::Sdp(sdp) = remote_signaler.recv().await.unwrap() {
+            break sdp;
+        }
+    };
+    assert!(offer.sdp.contains("m=application"));
+    assert!(!offer.sdp.contains("m=video"));
+    peer.close().await;
+}
+
+#[tokio::test]
+async fn negotiated_descriptions() {
+    let (encoder_signaler, decoder_signaler) = MockSignaler::channel();
+
+    let mut encoder_builder = WebRtcBuilder::new(encoder_signaler, Role::Offerer);
+    encoder_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let encoder = encoder_builder.build().await.unwrap();
+
+    let mut decoder_builder = WebRtcBuilder::new(decoder_signaler, Role::Answerer);
+    decoder_builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let decoder = decoder_builder.build().await.unwrap();
+
+    let connected = tokio::time::timeout(Duration::from_secs(10), async {
+        while encoder.ice_connection_state() != RTCIceConnectionState::Connected {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    assert!(connected.is_ok());
+
+    let offer = encoder.local_description().await.unwrap();
+    let answer = decoder.local_description().await.unwrap();
+    assert_eq!(offer.sdp_type, RTCSdpType::Offer);
+    assert_eq!(answer.sdp_type, RTCSdpType::Answer);
+    assert!(offer.sdp.contains("m=video"));
+
+    // The local descriptions also have the candidates gathered after they were sent
+    let ice_ufrag = |sdp: &str| {
+        sdp.lines()
+            .find(|line| line.starts_with("a=ice-ufrag:"))
+            .unwrap()
+            .to_owned()
+    };
+    let remote_offer = decoder.remote_description().await.unwrap();
+    let remote_answer = encoder.remote_description().await.unwrap();
+    assert_eq!(ice_ufrag(&remote_offer.sdp), ice_ufrag(&offer.sdp));
+    assert_eq!(ice_ufrag(&remote_answer.sdp), ice_ufrag(&answer.sdp));
+    assert_ne!(ice_ufrag(&offer.sdp), ice_ufrag(&answer.sdp));
+
+    encoder.close().await;
+    decoder.close().await;
+}
+
+#[tokio::test]
+async fn answer_codec_policy() {
+    let vp8 = Codec::new(
+        RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_VP8.to_owned(),
+                clock_rate: 90000,
+                ..Default::default()
+            },
+            payload_type: 96,
+            ..Default::default()
+        },
+        CodecType::Video,
+    );
+    let h264: Codec = H264Codec::constrained_baseline().into();
+    let (signaler, remote_signaler) = MockSignaler::channel();
+
+    let mut builder = WebRtcBuilder::new(signaler, Role::Answerer);
+    builder
+        .with_encoder(Box::new(MockEncoderBuilder::with_codecs(vec![
+            vp8.clone(),
+            h264.clone(),
+        ])))
+        .with_answer_codec_policy(Box::new(|offered| {
+            offered
+                .iter()
+                .filter(|codec| codec.mime_type().eq_ignore_ascii_case(MIME_TYPE_H264))
+                .cloned()
+                .collect()
+        }));
+    let peer = builder.build().await.unwrap();
+
+    // Plain webrtc-rs peer offering VP8 before H.264
+    let mut media_engine = MediaEngine::default();
+    let mut h264_parameters = h264.parameters().clone();
+    h264_parameters.payload_type = 102;
+    for parameters in [vp8.parameters().clone(), h264_parameters] {
+        media_engine
+            .register_codec(parameters, RTPCodecType::Video)
+            .unwrap();
+    }
+    let api = APIBuilder::new().with_media_engine(media_engine).build();
+    let pc = api
+        .new_peer_connection(RTCConfiguration::default())
+        .await
+        .unwrap();
+    pc.add_transceiver_from_kind(
+        RTPCodecType::Video,
+        &[RTCRtpTransceiverInit {
+            direction: RTCRtpTransceiverDirection::Recvonly,
+            send_encodings: Vec::new(),
+        }],
+    )
+    .await
+    .unwrap();
+    let offer = pc.create_offer(None).await.unwrap();
+    pc.set_local_description(offer.clone()).await.unwrap();
+    assert!(offer.sdp.contains("m=video 9 UDP/TLS/RTP/SAVPF 96 102"));
+    remote_signaler.send(Message::Sdp(offer)).await.unwrap();
+
+    let answer = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Message::Sdp(answer) = remote_signaler.recv().await.unwrap() {
+                break answer;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    let video_section = answer.sdp.split("m=video").nth(1).unwrap();
+    let payload_types: Vec<_> = video_section
+        .lines()
+        .next()
+        .unwrap()
+        .split_whitespace()
+        .skip(2)
+        .collect();
+    assert_eq!(payload_types, ["102"]);
+    assert!(!video_section.contains("VP8"));
+
+    peer.close().await;
+    pc.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn custom_interceptors() {
+    let (encoder_signaler, decoder_signaler) = MockSignaler::channel();
+
+    let (before_twcc, before_twcc_counts) = CountingInterceptorBuilder::new();
+    let (after_twcc, after_twcc_counts) = CountingInterceptorBuilder::new();
+    let mut encoder_builder = WebRtcBuilder::new(encoder_signaler, Role::Offerer);
+    encoder_builder
+        .with_encoder(Box::new(MockEncoderBuilder::new()))
+        .with_interceptor(Box::new(before_twcc), InterceptorPosition::BeforeTwcc)
+        .with_interceptor(Box::new(after_twcc), InterceptorPosition::AfterTwcc);
+    let encoder = encoder_builder.build().await.unwrap();
+
+    let (receiving, receiving_counts) = CountingInterceptorBuilder::new();
+    let mut decoder_builder = WebRtcBuilder::new(decoder_signaler, Role::Answerer);
+    decoder_builder
+        .with_decoder(Box::new(MockDecoderBuilder::new()))
+        .with_interceptor(Box::new(receiving), InterceptorPosition::AfterTwcc);
+    let decoder = decoder_builder.build().await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while receiving_counts.received.load(Ordering::Relaxed) < 10 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap();
+    encoder.close().await;
+    decoder.close().await;
+
+    // Only the interceptor registered before the TWCC interceptors sees the transport-cc
+    // sequence numbers
+    let sent = before_twcc_counts.sent.load(Ordering::Relaxed);
+    assert!(sent > 0);
+    assert_eq!(
+        before_twcc_counts
+            .sent_with_transport_cc
+            .load(Ordering::Relaxed),
+        sent
+    );
+    assert!(after_twcc_counts.sent.load(Ordering::Relaxed) > 0);
+    assert_eq!(
+        after_twcc_counts
+            .sent_with_transport_cc
+            .load(Ordering::Relaxed),
+        0
+    );
+}
+
+#[tokio::test]
+async fn negotiation_watchdog() {
+    const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(1);
+
+    let (encoder_signaler, decoder_signaler) = MockSignaler::channel();
+    // The first answer never arrives
+    let decoder_signaler = LossySignaler::new(decoder_signaler, 1);
+
+    let mut encoder_builder = WebRtcBuilder::new(encoder_signaler, Role::Offerer);
+    encoder_builder
+        .with_encoder(Box::new(MockEncoderBuilder::new()))
+        .with_negotiation_timeout(NEGOTIATION_TIMEOUT);
+    let encoder = encoder_builder.build().await.unwrap();
+
+    let mut decoder_builder = WebRtcBuilder::new(decoder_signaler, Role::Answerer);
+    decoder_builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let decoder = decoder_builder.build().await.unwrap();
+
+    // Stuck until the watchdog re-sends the offer
+    tokio::time::sleep(NEGOTIATION_TIMEOUT / 2).await;
+    assert_eq!(encoder.signaling_state(), RTCSignalingState::HaveLocalOffer);
+
+    let recovered = tokio::time::timeout(NEGOTIATION_TIMEOUT * 2, async {
+        while encoder.signaling_state() != RTCSignalingState::Stable {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert!(recovered.is_ok());
+
+    let connected = tokio::time::timeout(Duration::from_secs(10), async {
+        while encoder.ice_connection_state() != RTCIceConnectionState::Connected {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    assert!(connected.is_ok());
+
+    encoder.close().await;
+    decoder.close().await;
+}
+
+#[tokio::test]
+async fn undecodable_media_inactive() {
+    let (signaler, remote_signaler) = MockSignaler::channel();
+    let mut builder = WebRtcBuilder::new(signaler, Role::Answerer);
+    builder.with_decoder(Box::new(MockDecoderBuilder::new()));
+    let peer = builder.build().await.unwrap();
+
+    // Plain webrtc-rs peer sending H.264, which is registered as the fallback codec but has no
+    // decoder
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs().unwrap();
+    let api = APIBuilder::new().with_media_engine(media_engine).build();
+    let pc = api
+        .new_peer_connection(RTCConfiguration::default())
+        .await
+        .unwrap();
+    let track = Arc::new(TrackLocalStaticRTP::new(
+        RTCRtpCodecCapability {
+            mime_type: MIME_TYPE_H264.to_owned(),
+            clock_rate: 90000,
+            ..Default::default()
+        },
+        "video".to_owned(),
+        "webrtc".to_owned(),
+    ));
+    pc.add_track(track).await.unwrap();
+    let offer = pc.create_offer(None).await.unwrap();
+    pc.set_local_description(offer.clone()).await.unwrap();
+    assert!(offer.sdp.contains("a=sendrecv"));
+    remote_signaler.send(Message::Sdp(offer)).await.unwrap();
+
+    let answer = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Message::Sdp(answer) = remote_signaler.recv().await.unwrap() {
+                break answer;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    let video_section = answer.sdp.split("m=video").nth(1).unwrap();
+    // Still answered with H.264, but not received
+    assert!(video_section.contains("H264/90000"));
+    assert!(video_section.contains("a=inactive"));
+    assert!(!video_section.contains("a=recvonly"));
+    let local_description = peer.local_description().await.unwrap();
+    assert!(local_description.sdp.contains("a=inactive"));
+    assert!(peer.transceivers().await.is_empty());
+
+    peer.close().await;
+    pc.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn shared_stream_id() {
+    let (signaler, remote_signaler) = MockSignaler::channel();
+
+    // Audio in the same stream as the video of `MockEncoderBuilder`, and another in its own
+    let (audio, _audio_tx) =
+        PassthroughEncoderBuilder::new("mock-audio", "mock-webrtc", Codec::opus(), 1);
+    let (other_audio, _other_audio_tx) =
+        PassthroughEncoderBuilder::new("other-audio", "other-webrtc", Codec::opus(), 1);
+    let mut builder = WebRtcBuilder::new(signaler, Role::Offerer);
+    builder
+        .with_encoder(Box::new(MockEncoderBuilder::new()))
+        .with_encoder(Box::new(audio))
+        .with_encoder(Box::new(other_audio));
+    let peer = builder.build().await.unwrap();
+
+    let offer = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Message::Sdp(sdp) = remote_signaler.recv().await.unwrap() {
+                break sdp;
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    // The `a=msid` and the CNAME of each media section, in order. Receivers also synchronize the
+    // playout of the streams with the same CNAME.
+    let groups: Vec<_> = offer
+        .sdp
+        .split("\r\nm=")
+        .skip(1)
+        .map(|media| {
+            let msid = media
+                .lines()
+                .find_map(|line| line.strip_prefix("a=msid:"))
+                .map(|msid| msid.to_owned())
+                .unwrap();
+            let cname = media
+                .lines()
+                .find_map(|line| line.split_once(" cname:"))
+                .map(|(_, cname)| cname.to_owned())
+                .unwrap();
+            (msid, cname)
+        })
+        .collect();
+    assert_eq!(
+        groups,
+        [
+            (
+                "mock-webrtc mock-video".to_owned(),
+                "mock-webrtc".to_owned()
+            ),
+            (
+                "mock-webrtc mock-audio".to_owned(),
+                "mock-webrtc".to_owned()
+            ),
+            (
+                "other-webrtc other-audio".to_owned(),
+                "other-webrtc".to_owned()
+            ),
+        ]
+    );
+
+    peer.close().await;
+}
+
+#[tokio::test]
+async fn per_track_rtcp_feedback() {
+    let (signaler, remote_signaler) = MockSignaler::channel();
+
+    // The same codec on two tracks, one with only keyframe requests and one with the usual
+    // best-effort video feedback
+    let feedback = |feedback: &[(&str, &str)]| {
+        feedback
+            .iter()
+            .map(|(typ, parameter)| RTCPFeedback {
+                typ: (*typ).to_owned(),
+                parameter: (*parameter).to_owned(),
+            })
+            .collect()
+    };
+    let codec: Codec = H264Codec::constrained_baseline().into();
+    let (critical, _critical_tx) = PassthroughEncoderBuilder::new(
+        "critical-video",
+        "mock-webrtc",
+        codec
+            .clone()
+            .with_rtcp_feedback(feedback(&[("nack", "pli")])),
+        1,
+    );
+    let (best_effort, _best_effort_tx) = PassthroughEncoderBuilder::new(
+        "best-effort-video",
+        "mock-webrtc",
+        codec.with_rtcp_feedback(feedback(&[
+            ("nack", ""),
+            ("nack", "pli"),
+            ("transport-cc", ""),
+        ])),
+        1,
+    );
+    let mut builder = WebRtcBuilder::new(signaler, Role::Offerer);
+    builder
+        .with_encoder(Box::new(critical))
+        .with_encoder(Box::new(best_effort));
+    let peer = builder.build().await.unwrap();
+
+    let offer = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Message::Sdp(sdp) = remote_signaler.recv().await.unwrap() {
+                break sdp;
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    // The `a=rtcp-fb` lines of the first payload type of each media section
+    let rtcp_feedbacks: Vec<Vec<_>> = offer
+        .sdp
+        .split("\r\nm=")
+        .skip(1)
+        .map(|media| {
+            let payload_type = media.lines().next().unwrap().split(' ').nth(3).unwrap();
+            let prefix = format!("a=rtcp-fb:{payload_type} ");
+            media
+                .lines()
+                .filter_map(|line| line.strip_prefix(&prefix))
+                .map(|feedback| feedback.trim_end().to_owned())
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        rtcp_feedbacks,
+        [
+            vec!["nack pli".to_owned()],
+            vec![
+                "nack".to_owned(),
+                "nack pli".to_owned(),
+                "transport-cc".to_owned()
+            ],
+        ]
+    );
+
+    // The header extension is still negotiated for both, as documented
+    let twcc_extmaps = offer
+        .sdp
+        .split("\r\nm=")
+        .skip(1)
+        .filter(|media| media.contains("transport-wide-cc"))
+        .count();
+    assert_eq!(twcc_extmaps, 2);
+
+    peer.close().await;
+}
+
+#[tokio::test]
+async fn transceivers() {
+    let (offerer_signaler, answerer_signaler) = MockSignaler::channel();
+
+    // The encoders and decoders each get their own transceiver
+    let mut offerer_builder = WebRtcBuilder::new(offerer_signaler, Role::Offerer);
+    offerer_builder
+        .with_encoder(Box::new(MockEncoderBuilder::new()))
+        .with_decoder(Box::new(MockDecoderBuilder::new()));
+    let offerer = offerer_builder.build().await.unwrap();
+
+    let mut answerer_builder = WebRtcBuilder::new(answerer_signaler, Role::Answerer);
+    answerer_builder
+        .with_encoder(Box::new(MockEncoderBuilder::new()))
+        .with_decoder(Box::new(MockDecoderBuilder::new()));
+    let answerer = answerer_builder.build().await.unwrap();
+
+    let connected = tokio::time::timeout(Duration::from_secs(10), async {
+        while offerer.ice_connection_state() != RTCIceConnectionState::Connected
+            || answerer.ice_connection_state() != RTCIceConnectionState::Connected
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    assert!(connected.is_ok());
+
+    let count = |transceivers: &[TransceiverInfo], direction| {
+        transceivers
+            .iter()
+            .filter(|transceiver| transceiver.direction == direction)
+            .count()
+    };
+    for peer in [&offerer, &answerer] {
+        let transceivers = peer.transceivers().await;
+        assert_eq!(transceivers.len(), 2, "{transceivers:?}");
+        assert!(transceivers
+            .iter()
+            .all(|transceiver| transceiver.kind == RTPCodecType::Video));
+        assert_eq!(
+            count(&transceivers, RTCRtpTransceiverDirection::Sendonly),
+            1
+        );
+        assert_eq!(
+            count(&transceivers, RTCRtpTransceiverDirection::Recvonly),
+            1
+        );
+
+        let sent = transceivers
+            .iter()
+            .find(|transceiver| transceiver.direction == RTCRtpTransceiverDirection::Sendonly)
+            .unwrap();
+        assert_eq!(sent.codec.as_deref(), Some("video/mock"));
+    }
+
+    offerer.close().await;
+    answerer.close().await;
+}
+
+#[tokio::test]
+async fn rtcp_app() {
+    let (offerer_signaler, answerer_signaler) = MockSignaler::channel();
+
+    let mut offerer_builder = WebRtcBuilder::new(offerer_signaler, Role::Offerer);
+    offerer_builder.with_encoder(Box::new(MockEncoderBuilder::new()));
+    let offerer = offerer_builder.build().await.unwrap();
+
+    // The mock decoder reads the RTCP of its receiver
+    let (app_tx, mut app_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut answerer_builder = WebRtcBuilder::new(answerer_signaler, Role::Answerer);
+    answerer_builder
+        .with_decoder(Box::new(MockDecoderBuilder::new()))
+        .with_rtcp_app_handler(Box::new(move |app| {
+            let _ = app_tx.send(app);
+        }));
+    let answerer = answerer_builder.build().await.unwrap();
+
+    let ssrc = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(description) = offerer.local_description().await {
+                let ssrc = description.sdp.lines().find_map(|line| {
+                    line.strip_prefix("a=ssrc:")?
+                        .split(' ')
+                        .next()?
+                        .parse::<u32>()
+                        .ok()
+                });
+                if let Some(ssrc) = ssrc {
+                    break ssrc;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // Packets sent before the remote track is set up are dropped so keep sending
+    let app = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let _ = offerer.send_rtcp_app(ssrc, *b"TEST", b"hello").await;
+            if let Ok(Some(app)) =
+                tokio::time::timeout(Duration::from_millis(100), app_rx.recv()).await
+            {
+                break app;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(app.ssrc, ssrc);
+    assert_eq!(&app.name, b"TEST");
+    // Padded to a multiple of 4 bytes
+    assert_eq!(&app.data[..5], b"hello");
+
+    offerer.close().await;
+    answerer.close().await;
+}
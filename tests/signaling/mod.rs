@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::sync::{
     mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
     Mutex,
@@ -12,6 +13,10 @@ pub struct MockSignaler {
 
 #[async_trait]
 impl Signaler for MockSignaler {
+    async fn connect(&self) -> Result<(), Box<dyn std::error::Error + Send>> {
+        Ok(())
+    }
+
     async fn recv(&self) -> Result<Message, Box<dyn std::error::Error + Send>> {
         let mut lock = self.rx.lock().await;
         let msg = lock.recv().await;
@@ -46,6 +51,135 @@ impl MockSignaler {
     }
 }
 
+/// Fails the first SDP sends and receives, for testing the retries.
+pub struct FlakySignaler {
+    inner: MockSignaler,
+    failures_left: AtomicUsize,
+    recv_failures_left: AtomicUsize,
+}
+
+impl FlakySignaler {
+    pub fn new(inner: MockSignaler, failures: usize) -> Self {
+        FlakySignaler {
+            inner,
+            failures_left: AtomicUsize::new(failures),
+            recv_failures_left: AtomicUsize::new(0),
+        }
+    }
+
+    /// Also fail the first `failures` receives, without taking a message.
+    pub fn with_recv_failures(self, failures: usize) -> Self {
+        FlakySignaler {
+            recv_failures_left: AtomicUsize::new(failures),
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl Signaler for FlakySignaler {
+    async fn connect(&self) -> Result<(), Box<dyn std::error::Error + Send>> {
+        self.inner.connect().await
+    }
+
+    async fn recv(&self) -> Result<Message, Box<dyn std::error::Error + Send>> {
+        let failed = self
+            .recv_failures_left
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok();
+        if failed {
+            return Err(Box::new(std::io::Error::from(
+                std::io::ErrorKind::ConnectionReset,
+            )));
+        }
+        self.inner.recv().await
+    }
+
+    async fn send(&self, msg: Message) -> Result<(), Box<dyn std::error::Error + Send>> {
+        if let Message::Sdp(_) = msg {
+            let failed = self
+                .failures_left
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok();
+            if failed {
+                return Err(Box::new(std::io::Error::from(
+                    std::io::ErrorKind::ConnectionReset,
+                )));
+            }
+        }
+        self.inner.send(msg).await
+    }
+}
+
+/// Silently drops the first SDPs sent, as if lost by the signaling channel.
+pub struct LossySignaler {
+    inner: MockSignaler,
+    losses_left: AtomicUsize,
+}
+
+impl LossySignaler {
+    pub fn new(inner: MockSignaler, losses: usize) -> Self {
+        LossySignaler {
+            inner,
+            losses_left: AtomicUsize::new(losses),
+        }
+    }
+}
+
+#[async_trait]
+impl Signaler for LossySignaler {
+    async fn connect(&self) -> Result<(), Box<dyn std::error::Error + Send>> {
+        self.inner.connect().await
+    }
+
+    async fn recv(&self) -> Result<Message, Box<dyn std::error::Error + Send>> {
+        self.inner.recv().await
+    }
+
+    async fn send(&self, msg: Message) -> Result<(), Box<dyn std::error::Error + Send>> {
+        if let Message::Sdp(_) = msg {
+            let lost = self
+                .losses_left
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok();
+            if lost {
+                return Ok(());
+            }
+        }
+        self.inner.send(msg).await
+    }
+}
+
+/// Fails to connect, send, and receive, as if the connection to the signaling server was refused.
+pub struct BrokenSignaler;
+
+#[async_trait]
+impl Signaler for BrokenSignaler {
+    async fn connect(&self) -> Result<(), Box<dyn std::error::Error + Send>> {
+        Err(Box::new(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        )))
+    }
+
+    async fn recv(&self) -> Result<Message, Box<dyn std::error::Error + Send>> {
+        Err(Box::new(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        )))
+    }
+
+    async fn send(&self, _msg: Message) -> Result<(), Box<dyn std::error::Error + Send>> {
+        Err(Box::new(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        )))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;